@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use wot_serve::{
+    servient::*,
+    thing::{actions, EventChannel, Thing},
+};
+
+/// A dimmable lamp
+#[derive(Thing)]
+#[thing(title = "Lamp", actions)]
+struct Lamp {
+    /// Brightness in percent
+    brightness: u8,
+    /// Whether the lamp is lit
+    #[thing(read_only)]
+    on: bool,
+    /// Emitted when the lamp is switched
+    #[event]
+    switched: EventChannel<bool>,
+}
+
+#[actions]
+impl Lamp {
+    /// Switch the lamp on or off
+    #[action]
+    fn toggle(&mut self) -> bool {
+        self.on = !self.on;
+        self.switched.emit(self.on);
+        self.on
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let lamp = Lamp {
+        brightness: 100,
+        on: false,
+        switched: EventChannel::new(),
+    };
+
+    let servient = lamp
+        .builder()
+        .http_bind("127.0.0.1:8080".parse().unwrap())
+        .build_servient()
+        .unwrap();
+
+    println!("Running the servient for 10 seconds.");
+    let _ = tokio::time::timeout(Duration::from_secs(10), async {
+        servient.serve().await.unwrap()
+    })
+    .await;
+}
@@ -3,7 +3,10 @@ use std::time::Duration;
 use serde::{Deserialize, Serialize};
 use wot_serve::servient::*;
 use wot_td::{
-    builder::{affordance::BuildableInteractionAffordance, data_schema::SpecializableDataSchema},
+    builder::{
+        affordance::BuildableInteractionAffordance,
+        data_schema::{ReadableWriteableDataSchema, SpecializableDataSchema},
+    },
     extend::ExtendableThing,
 };
 
@@ -39,6 +42,7 @@ async fn main() {
                         .href("/hello")
                 })
                 .string()
+                .read_only()
         })
         .action("say_hello", |b| {
             b.ext(())
@@ -38,7 +38,7 @@ pub enum ThingType {
 }
 
 impl ThingType {
-    fn to_service_type(self) -> &'static str {
+    pub(crate) fn to_service_type(self) -> &'static str {
         use ThingType::*;
         match self {
             Thing => "_wot",
@@ -63,10 +63,10 @@ pub struct Advertiser {
     /// Default set of ips for the system
     ips: Vec<Ipv4Addr>,
     /// Default hostname
-    hostname: String,
+    pub(crate) hostname: String,
 }
 
-const WELL_KNOWN: &str = "/.well-known/wot";
+pub(crate) const WELL_KNOWN: &str = "/.well-known/wot";
 
 /// Builder to create a service
 ///
@@ -76,6 +76,7 @@ pub struct ServiceBuilder<'a> {
     ips: Vec<Ipv4Addr>,
     hostname: String,
     ty: ThingType,
+    udp: bool,
     port: u16,
     path: String,
     name: String,
@@ -89,6 +90,7 @@ impl<'a> ServiceBuilder<'a> {
             ips: ad.ips.clone(),
             hostname: ad.hostname.clone(),
             ty: ThingType::Thing,
+            udp: false,
             port: 8080,
             path: WELL_KNOWN.to_string(),
         }
@@ -103,6 +105,13 @@ impl<'a> ServiceBuilder<'a> {
         self
     }
 
+    /// Advertise a service listening on UDP, e.g. CoAP, instead of TCP.
+    pub fn udp(mut self) -> Self {
+        self.udp = true;
+
+        self
+    }
+
     /// The listening port for the advertised `Thing`.
     pub fn port(mut self, port: u16) -> Self {
         self.port = port;
@@ -142,13 +151,15 @@ impl<'a> ServiceBuilder<'a> {
             ips,
             hostname,
             ty,
+            udp,
             path,
             port,
             name,
         } = self;
 
         let service_type = ty.to_service_type();
-        let domain = format!("{service_type}._tcp.local.");
+        let transport = if udp { "_udp" } else { "_tcp" };
+        let domain = format!("{service_type}.{transport}.local.");
         let mut props = HashMap::new();
 
         props.insert("td".to_string(), path);
@@ -201,7 +212,7 @@ impl Advertiser {
     }
 
     /// Register and Advertise a new service.
-    pub fn add_service(&self, name: impl Into<String>) -> ServiceBuilder {
+    pub fn add_service(&self, name: impl Into<String>) -> ServiceBuilder<'_> {
         ServiceBuilder::new(self, name)
     }
 }
@@ -242,6 +253,20 @@ mod test {
         );
     }
 
+    #[test]
+    fn set_udp() {
+        test_feature(
+            "TestLampUdp",
+            "_wot._udp.local.",
+            |b| b.udp().port(5683),
+            |info| {
+                assert_eq!(info.get_port(), 5683);
+                let props = info.get_properties();
+                assert_eq!(props.get_property_val_str("td"), Some(WELL_KNOWN));
+            },
+        );
+    }
+
     #[test]
     fn set_type() {
         test_feature(
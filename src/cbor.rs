@@ -0,0 +1,339 @@
+//! CBOR encoding of json values
+//!
+//! Only the CBOR data items with a json representation are supported: byte strings,
+//! non-text map keys and the simple values other than `false`, `true`, `null` and
+//! `undefined` are rejected. Tags are skipped, their content is kept.
+
+use serde_json::{Map, Number, Value};
+
+/// Nesting depth allowed when decoding
+const MAX_DEPTH: usize = 128;
+
+/// Error type for the CBOR decoding.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The input ends in the middle of a data item.
+    #[error("unexpected end of input")]
+    Eof,
+
+    /// The input continues after the data item.
+    #[error("trailing bytes after the data item")]
+    Trailing,
+
+    /// The initial byte of a data item is not well formed.
+    #[error("invalid initial byte {0:#04x}")]
+    Invalid(u8),
+
+    /// The data item has no json representation.
+    #[error("{0} cannot be represented as json")]
+    Unsupported(&'static str),
+
+    /// The data items are nested too deeply.
+    #[error("data items nested deeper than {MAX_DEPTH}")]
+    Depth,
+}
+
+/// Result type for the CBOR decoding.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Encode a json value as CBOR.
+pub fn to_vec(value: &Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    encode(value, &mut out);
+    out
+}
+
+/// Decode a CBOR data item as a json value.
+pub fn from_slice(bytes: &[u8]) -> Result<Value> {
+    let mut decoder = Decoder { bytes, pos: 0 };
+    let value = decoder.value(0)?;
+
+    if decoder.pos != bytes.len() {
+        return Err(Error::Trailing);
+    }
+
+    Ok(value)
+}
+
+fn head(major: u8, arg: u64, out: &mut Vec<u8>) {
+    let major = major << 5;
+
+    if arg < 24 {
+        out.push(major | arg as u8);
+    } else if let Ok(arg) = u8::try_from(arg) {
+        out.extend([major | 24, arg]);
+    } else if let Ok(arg) = u16::try_from(arg) {
+        out.push(major | 25);
+        out.extend(arg.to_be_bytes());
+    } else if let Ok(arg) = u32::try_from(arg) {
+        out.push(major | 26);
+        out.extend(arg.to_be_bytes());
+    } else {
+        out.push(major | 27);
+        out.extend(arg.to_be_bytes());
+    }
+}
+
+fn encode(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Null => out.push(0xf6),
+        Value::Bool(false) => out.push(0xf4),
+        Value::Bool(true) => out.push(0xf5),
+        Value::Number(n) => {
+            if let Some(n) = n.as_u64() {
+                head(0, n, out);
+            } else if let Some(n) = n.as_i64() {
+                // Negative integers are encoded as -1 - n.
+                head(1, !n as u64, out);
+            } else {
+                let n = n.as_f64().unwrap_or(f64::NAN);
+                let single = n as f32;
+                if f64::from(single) == n {
+                    out.push(0xfa);
+                    out.extend(single.to_be_bytes());
+                } else {
+                    out.push(0xfb);
+                    out.extend(n.to_be_bytes());
+                }
+            }
+        }
+        Value::String(s) => {
+            head(3, s.len() as u64, out);
+            out.extend(s.as_bytes());
+        }
+        Value::Array(values) => {
+            head(4, values.len() as u64, out);
+            for value in values {
+                encode(value, out);
+            }
+        }
+        Value::Object(map) => {
+            head(5, map.len() as u64, out);
+            for (key, value) in map {
+                head(3, key.len() as u64, out);
+                out.extend(key.as_bytes());
+                encode(value, out);
+            }
+        }
+    }
+}
+
+/// Value of a half precision float.
+fn half(bits: u16) -> f64 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = i32::from((bits >> 10) & 0x1f);
+    let mantissa = f64::from(bits & 0x3ff);
+
+    sign * match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f64.powi(exponent - 15),
+    }
+}
+
+fn float(n: f64) -> Result<Value> {
+    Number::from_f64(n)
+        .map(Value::Number)
+        .ok_or(Error::Unsupported("a non finite float"))
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Decoder<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8]> {
+        let end = self.pos.checked_add(len).ok_or(Error::Eof)?;
+        let bytes = self.bytes.get(self.pos..end).ok_or(Error::Eof)?;
+        self.pos = end;
+
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn peek(&self) -> Result<u8> {
+        self.bytes.get(self.pos).copied().ok_or(Error::Eof)
+    }
+
+    /// Argument of the head, `None` for the indefinite lengths.
+    fn argument(&mut self, initial: u8) -> Result<Option<u64>> {
+        let info = initial & 0x1f;
+
+        let arg = match info {
+            0..=23 => u64::from(info),
+            24 => u64::from(self.byte()?),
+            25 => u64::from(u16::from_be_bytes(self.take(2)?.try_into().unwrap())),
+            26 => u64::from(u32::from_be_bytes(self.take(4)?.try_into().unwrap())),
+            27 => u64::from_be_bytes(self.take(8)?.try_into().unwrap()),
+            31 => return Ok(None),
+            _ => return Err(Error::Invalid(initial)),
+        };
+
+        Ok(Some(arg))
+    }
+
+    fn len(&mut self, initial: u8) -> Result<usize> {
+        let len = self.argument(initial)?.ok_or(Error::Invalid(initial))?;
+
+        usize::try_from(len).map_err(|_| Error::Eof)
+    }
+
+    fn text(&mut self, initial: u8) -> Result<String> {
+        if initial & 0x1f != 31 {
+            let len = self.len(initial)?;
+            let bytes = self.take(len)?;
+            return std::str::from_utf8(bytes)
+                .map(str::to_string)
+                .map_err(|_| Error::Unsupported("an invalid utf-8 text string"));
+        }
+
+        // Indefinite length strings are sequences of definite length chunks.
+        let mut text = String::new();
+        loop {
+            let initial = self.byte()?;
+            match initial {
+                0xff => return Ok(text),
+                0x60..=0x7b => text.push_str(&self.text(initial)?),
+                _ => return Err(Error::Invalid(initial)),
+            }
+        }
+    }
+
+    /// Number of items of an array or a map, unbounded until the break byte.
+    fn items(&mut self, initial: u8) -> Result<usize> {
+        match self.argument(initial)? {
+            Some(len) => usize::try_from(len).map_err(|_| Error::Eof),
+            None => Ok(usize::MAX),
+        }
+    }
+
+    /// Whether an indefinite length array or map ends here.
+    fn end(&mut self, initial: u8) -> Result<bool> {
+        if initial & 0x1f == 31 && self.peek()? == 0xff {
+            self.pos += 1;
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value> {
+        if depth > MAX_DEPTH {
+            return Err(Error::Depth);
+        }
+
+        let initial = self.byte()?;
+
+        match initial >> 5 {
+            0 => Ok(self
+                .argument(initial)?
+                .ok_or(Error::Invalid(initial))?
+                .into()),
+            1 => {
+                let n = self.argument(initial)?.ok_or(Error::Invalid(initial))?;
+                match i64::try_from(n) {
+                    Ok(n) => Ok((-1 - n).into()),
+                    Err(_) => float(-1.0 - n as f64),
+                }
+            }
+            2 => Err(Error::Unsupported("a byte string")),
+            3 => self.text(initial).map(Value::String),
+            4 => {
+                let mut values = Vec::new();
+                for _ in 0..self.items(initial)? {
+                    if self.end(initial)? {
+                        break;
+                    }
+                    values.push(self.value(depth + 1)?);
+                }
+                Ok(Value::Array(values))
+            }
+            5 => {
+                let mut map = Map::new();
+                for _ in 0..self.items(initial)? {
+                    if self.end(initial)? {
+                        break;
+                    }
+                    let key = match self.byte()? {
+                        initial @ 0x60..=0x7f => self.text(initial)?,
+                        _ => return Err(Error::Unsupported("a non text map key")),
+                    };
+                    map.insert(key, self.value(depth + 1)?);
+                }
+                Ok(Value::Object(map))
+            }
+            6 => {
+                self.argument(initial)?.ok_or(Error::Invalid(initial))?;
+                self.value(depth + 1)
+            }
+            _ => match initial {
+                0xf4 => Ok(false.into()),
+                0xf5 => Ok(true.into()),
+                0xf6 | 0xf7 => Ok(Value::Null),
+                0xf9 => float(half(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))),
+                0xfa => float(f64::from(f32::from_be_bytes(
+                    self.take(4)?.try_into().unwrap(),
+                ))),
+                0xfb => float(f64::from_be_bytes(self.take(8)?.try_into().unwrap())),
+                0xe0..=0xf3 | 0xf8 => Err(Error::Unsupported("a simple value")),
+                _ => Err(Error::Invalid(initial)),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn encode_values() {
+        assert_eq!(to_vec(&json!(0)), [0x00]);
+        assert_eq!(to_vec(&json!(500)), [0x19, 0x01, 0xf4]);
+        assert_eq!(to_vec(&json!(-1)), [0x20]);
+        assert_eq!(to_vec(&json!(-1000)), [0x39, 0x03, 0xe7]);
+        assert_eq!(to_vec(&json!(1.5)), [0xfa, 0x3f, 0xc0, 0x00, 0x00]);
+        assert_eq!(to_vec(&json!("a")), [0x61, 0x61]);
+        assert_eq!(
+            to_vec(&json!({ "a": [true, null] })),
+            [0xa1, 0x61, 0x61, 0x82, 0xf5, 0xf6]
+        );
+    }
+
+    #[test]
+    fn decode_values() {
+        let value = json!({
+            "on": false,
+            "level": 18446744073709551615u64,
+            "offset": -42,
+            "ratio": 0.1,
+            "name": "lamp ✓",
+            "tags": [[], {}],
+        });
+        assert_eq!(from_slice(&to_vec(&value)), Ok(value));
+
+        // Half float, tagged value, indefinite array and string.
+        assert_eq!(from_slice(&[0xf9, 0x3c, 0x00]), Ok(json!(1.0)));
+        assert_eq!(from_slice(&[0xc1, 0x1a, 0, 0, 0, 1]), Ok(json!(1)));
+        assert_eq!(from_slice(&[0x9f, 0x01, 0x02, 0xff]), Ok(json!([1, 2])));
+        assert_eq!(
+            from_slice(&[0x7f, 0x61, 0x61, 0x61, 0x62, 0xff]),
+            Ok(json!("ab"))
+        );
+
+        assert_eq!(from_slice(&[0x82, 0x01]), Err(Error::Eof));
+        assert_eq!(from_slice(&[0x01, 0x02]), Err(Error::Trailing));
+        assert_eq!(
+            from_slice(&[0x41, 0x00]),
+            Err(Error::Unsupported("a byte string"))
+        );
+        assert_eq!(from_slice(&[0x81; 200]), Err(Error::Depth));
+    }
+}
@@ -0,0 +1,1085 @@
+//! CoAP protocol binding
+//!
+//! The Forms with `coap://` hrefs are served over UDP by the [`Servient`] configured with
+//! [`ServientSettings::coap_bind`]. The requests are translated to http and handled by the
+//! same handlers as the http Forms, the Thing Description is served at `/.well-known/wot`.
+//!
+//! The `GET` requests with the `Observe` option on the Forms observing a property or
+//! subscribing an event register an observation: the handler is called again every time it
+//! replies, each reply is a notification. The handlers are expected to wait for the next
+//! value, as for long-polling, the notifications are paced otherwise.
+//!
+//! A notification is sent as Confirmable once in a while, the observation is dropped if the
+//! client does not acknowledge it. Each client holds a limited number of observations, the
+//! requests beyond it are answered once.
+//!
+//! Block-wise transfers and DTLS are not supported.
+//!
+//! [`Servient`]: crate::Servient
+//! [`ServientSettings::coap_bind`]: crate::servient::ServientSettings::coap_bind
+
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use axum::{
+    body::Body,
+    http::{
+        self,
+        header::{ACCEPT, CONTENT_TYPE},
+        Request, StatusCode,
+    },
+    response::Response,
+    Router,
+};
+use tokio::{
+    net::UdpSocket,
+    sync::oneshot,
+    task::JoinSet,
+    time::{sleep_until, Instant},
+};
+use tower::Service;
+
+use crate::{
+    advertise::WELL_KNOWN,
+    servient::{content::body_bytes, Description},
+};
+
+/// Default CoAP port
+pub const DEFAULT_PORT: u16 = 5683;
+
+/// Time after which a Confirmable request is acknowledged before its response is ready
+const ACK_DELAY: Duration = Duration::from_millis(500);
+
+/// Number of recent Confirmable requests remembered to detect their retransmissions
+const RECENT_REQUESTS: usize = 64;
+
+/// Minimum time between two notifications of an observation
+const NOTIFY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Time after which the next notification is sent as Confirmable, to detect the gone clients
+const CONFIRM_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Time waited for the acknowledgement of a Confirmable notification, doubled on each
+/// retransmission
+const ACK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Number of retransmissions of an unacknowledged Confirmable notification
+const MAX_RETRANSMIT: u32 = 4;
+
+/// Number of observations a client may hold
+const MAX_OBSERVATIONS: usize = 16;
+
+/// Error type for the module
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// Network-specific error
+    #[error("I/O error {0}")]
+    Io(#[from] std::io::Error),
+
+    /// The datagram is not a well formed CoAP message.
+    #[error("malformed message: {0}")]
+    Malformed(&'static str),
+}
+
+/// Result type for the module
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Type of a CoAP message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    /// Message requiring an acknowledgement
+    Confirmable,
+    /// Message not requiring an acknowledgement
+    NonConfirmable,
+    /// Acknowledgement of a Confirmable message
+    Acknowledgement,
+    /// Rejection of a message
+    Reset,
+}
+
+/// Code of a CoAP message, as `class.detail`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Code(pub u8);
+
+impl Code {
+    /// Empty message
+    pub const EMPTY: Code = Code::new(0, 0);
+    /// GET request
+    pub const GET: Code = Code::new(0, 1);
+    /// POST request
+    pub const POST: Code = Code::new(0, 2);
+    /// PUT request
+    pub const PUT: Code = Code::new(0, 3);
+    /// DELETE request
+    pub const DELETE: Code = Code::new(0, 4);
+    /// PATCH request
+    pub const PATCH: Code = Code::new(0, 6);
+    /// 2.01 Created
+    pub const CREATED: Code = Code::new(2, 1);
+    /// 2.02 Deleted
+    pub const DELETED: Code = Code::new(2, 2);
+    /// 2.03 Valid
+    pub const VALID: Code = Code::new(2, 3);
+    /// 2.04 Changed
+    pub const CHANGED: Code = Code::new(2, 4);
+    /// 2.05 Content
+    pub const CONTENT: Code = Code::new(2, 5);
+    /// 4.00 Bad Request
+    pub const BAD_REQUEST: Code = Code::new(4, 0);
+    /// 4.04 Not Found
+    pub const NOT_FOUND: Code = Code::new(4, 4);
+    /// 4.05 Method Not Allowed
+    pub const METHOD_NOT_ALLOWED: Code = Code::new(4, 5);
+    /// 5.00 Internal Server Error
+    pub const INTERNAL_SERVER_ERROR: Code = Code::new(5, 0);
+
+    /// The code of the class and detail.
+    pub const fn new(class: u8, detail: u8) -> Self {
+        Code(class << 5 | detail)
+    }
+
+    /// The class, 0 for the requests, 2 for the successful responses.
+    pub fn class(self) -> u8 {
+        self.0 >> 5
+    }
+
+    /// The detail within the class.
+    pub fn detail(self) -> u8 {
+        self.0 & 0x1f
+    }
+}
+
+impl std::fmt::Display for Code {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{:02}", self.class(), self.detail())
+    }
+}
+
+/// Numbers of the CoAP options used by the binding
+pub mod option {
+    /// Observe, registering or deregistering an observation
+    pub const OBSERVE: u16 = 6;
+    /// Uri-Path, one path segment per option
+    pub const URI_PATH: u16 = 11;
+    /// Content-Format of the payload
+    pub const CONTENT_FORMAT: u16 = 12;
+    /// Uri-Query, one query argument per option
+    pub const URI_QUERY: u16 = 15;
+    /// Accept, the Content-Format expected in the response
+    pub const ACCEPT: u16 = 17;
+}
+
+/// Registered Content-Formats of the media types
+const CONTENT_FORMATS: &[(&str, u16)] = &[
+    ("text/plain", 0),
+    ("application/link-format", 40),
+    ("application/xml", 41),
+    ("application/octet-stream", 42),
+    ("application/json", 50),
+    ("application/cbor", 60),
+    ("application/td+json", 432),
+];
+
+/// The Content-Format of a media type, its parameters are ignored.
+pub fn content_format(media_type: &str) -> Option<u16> {
+    let essence = media_type.split(';').next()?.trim();
+
+    CONTENT_FORMATS
+        .iter()
+        .find(|(media_type, _)| media_type.eq_ignore_ascii_case(essence))
+        .map(|(_, format)| *format)
+}
+
+/// The media type of a Content-Format.
+pub fn media_type(content_format: u16) -> Option<&'static str> {
+    match content_format {
+        0 => Some("text/plain; charset=utf-8"),
+        format => CONTENT_FORMATS
+            .iter()
+            .find(|(_, f)| *f == format)
+            .map(|(media_type, _)| *media_type),
+    }
+}
+
+/// A CoAP message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    /// Message type
+    pub ty: MessageType,
+    /// Request method or response code
+    pub code: Code,
+    /// Message ID, matching the Acknowledgement to the Confirmable message
+    pub id: u16,
+    /// Token, matching the response to the request
+    pub token: Vec<u8>,
+    /// Options, by number
+    pub options: Vec<(u16, Vec<u8>)>,
+    /// Payload
+    pub payload: Vec<u8>,
+}
+
+/// Encode an unsigned integer option value, in as few bytes as possible.
+pub fn uint(value: u32) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count();
+
+    bytes[skip..].to_vec()
+}
+
+fn option_nibble(value: usize, extended: &mut Vec<u8>) -> u8 {
+    match value {
+        0..=12 => value as u8,
+        13..=268 => {
+            extended.push((value - 13) as u8);
+            13
+        }
+        _ => {
+            extended.extend(((value - 269) as u16).to_be_bytes());
+            14
+        }
+    }
+}
+
+impl Message {
+    /// A message without options nor payload.
+    pub fn new(ty: MessageType, code: Code, id: u16, token: Vec<u8>) -> Self {
+        Self {
+            ty,
+            code,
+            id,
+            token,
+            options: Vec::new(),
+            payload: Vec::new(),
+        }
+    }
+
+    /// Add an option.
+    pub fn with_option(mut self, number: u16, value: impl Into<Vec<u8>>) -> Self {
+        self.options.push((number, value.into()));
+        self
+    }
+
+    /// Set the payload.
+    pub fn with_payload(mut self, payload: impl Into<Vec<u8>>) -> Self {
+        self.payload = payload.into();
+        self
+    }
+
+    /// The values of an option, in order.
+    pub fn options(&self, number: u16) -> impl Iterator<Item = &[u8]> {
+        self.options
+            .iter()
+            .filter(move |(n, _)| *n == number)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// The value of an unsigned integer option.
+    pub fn uint_option(&self, number: u16) -> Option<u32> {
+        let value = self.options(number).next()?;
+        if value.len() > 4 {
+            return None;
+        }
+
+        Some(value.iter().fold(0, |n, b| n << 8 | u32::from(*b)))
+    }
+
+    /// Encode the message as a datagram.
+    pub fn encode(&self) -> Vec<u8> {
+        let ty = match self.ty {
+            MessageType::Confirmable => 0,
+            MessageType::NonConfirmable => 1,
+            MessageType::Acknowledgement => 2,
+            MessageType::Reset => 3,
+        };
+        let token = &self.token[..self.token.len().min(8)];
+
+        let mut out = vec![0x40 | ty << 4 | token.len() as u8, self.code.0];
+        out.extend(self.id.to_be_bytes());
+        out.extend(token);
+
+        let mut options: Vec<_> = self.options.iter().collect();
+        // The sort is stable, the repeated options keep their order.
+        options.sort_by_key(|(number, _)| *number);
+
+        let mut last = 0;
+        for (number, value) in options {
+            let mut extended = Vec::new();
+            let delta = option_nibble(usize::from(number - last), &mut extended);
+            let len = option_nibble(value.len(), &mut extended);
+
+            out.push(delta << 4 | len);
+            out.extend(extended);
+            out.extend(value);
+            last = *number;
+        }
+
+        if !self.payload.is_empty() {
+            out.push(0xff);
+            out.extend(&self.payload);
+        }
+
+        out
+    }
+
+    /// Decode a datagram.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let [first, code, id @ ..] = bytes else {
+            return Err(Error::Malformed("shorter than the header"));
+        };
+        if first >> 6 != 1 {
+            return Err(Error::Malformed("unknown version"));
+        }
+        let ty = match first >> 4 & 0x3 {
+            0 => MessageType::Confirmable,
+            1 => MessageType::NonConfirmable,
+            2 => MessageType::Acknowledgement,
+            _ => MessageType::Reset,
+        };
+        let token_len = usize::from(first & 0xf);
+        if token_len > 8 || id.len() < 2 + token_len {
+            return Err(Error::Malformed("invalid token length"));
+        }
+
+        let id_bytes = [id[0], id[1]];
+        let token = id[2..2 + token_len].to_vec();
+        let mut rest = &id[2 + token_len..];
+
+        let extended = |rest: &mut &[u8], nibble: u8| -> Result<usize> {
+            let (value, len) = match nibble {
+                0..=12 => (usize::from(nibble), 0),
+                13 => (
+                    usize::from(*rest.first().ok_or(Error::Malformed("truncated"))?) + 13,
+                    1,
+                ),
+                14 => {
+                    let bytes = rest.get(..2).ok_or(Error::Malformed("truncated"))?;
+                    (
+                        usize::from(u16::from_be_bytes([bytes[0], bytes[1]])) + 269,
+                        2,
+                    )
+                }
+                _ => return Err(Error::Malformed("reserved option nibble")),
+            };
+            *rest = &rest[len..];
+            Ok(value)
+        };
+
+        let mut options = Vec::new();
+        let mut number = 0usize;
+        let mut payload = Vec::new();
+        while let Some((&header, tail)) = rest.split_first() {
+            rest = tail;
+            if header == 0xff {
+                if rest.is_empty() {
+                    return Err(Error::Malformed("empty payload after the marker"));
+                }
+                payload = rest.to_vec();
+                break;
+            }
+
+            number += extended(&mut rest, header >> 4)?;
+            let len = extended(&mut rest, header & 0xf)?;
+            let value = rest
+                .get(..len)
+                .ok_or(Error::Malformed("truncated option"))?;
+            let number = u16::try_from(number).map_err(|_| Error::Malformed("option number"))?;
+            options.push((number, value.to_vec()));
+            rest = &rest[len..];
+        }
+
+        Ok(Self {
+            ty,
+            code: Code(*code),
+            id: u16::from_be_bytes(id_bytes),
+            token,
+            options,
+            payload,
+        })
+    }
+}
+
+/// CoAP endpoint of a [`Servient`]
+///
+/// [`Servient`]: crate::Servient
+#[derive(Debug, Clone)]
+pub struct CoapServer {
+    /// Address the UDP socket binds to
+    pub addr: SocketAddr,
+    /// The routes of the `coap://` Forms
+    pub router: Router,
+    /// Routes of the Forms serving observations
+    pub(crate) observable: Vec<String>,
+    notify_interval: Duration,
+    confirm_interval: Duration,
+    ack_timeout: Duration,
+}
+
+/// Observation registered by a client
+struct Observer {
+    task: tokio::task::AbortHandle,
+    /// Message ID of the last notification, a Reset to it cancels the observation
+    last_id: u16,
+    /// Message ID of the Confirmable notification waiting for its acknowledgement
+    pending: Option<(u16, oneshot::Sender<()>)>,
+}
+
+/// Confirmable request, by peer and Message ID, and its piggybacked response once sent
+type Exchange = ((SocketAddr, u16), Option<Vec<u8>>);
+
+/// State shared by the tasks handling the requests
+struct Exchanges {
+    socket: UdpSocket,
+    next_id: AtomicU16,
+    /// Recent Confirmable requests
+    recent: Mutex<VecDeque<Exchange>>,
+    observers: Mutex<HashMap<(SocketAddr, Vec<u8>), Observer>>,
+}
+
+impl Exchanges {
+    fn next_id(&self) -> u16 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    async fn send(&self, message: &Message, peer: SocketAddr) {
+        let bytes = message.encode();
+        if message.ty == MessageType::Acknowledgement {
+            let mut recent = self.recent.lock().unwrap();
+            if let Some((_, sent)) = recent
+                .iter_mut()
+                .find(|(key, _)| *key == (peer, message.id))
+            {
+                *sent = Some(bytes.clone());
+            }
+        }
+
+        // The client retransmits the Confirmable requests whose response is lost.
+        let _ = self.socket.send_to(&bytes, peer).await;
+    }
+
+    /// Send a Confirmable notification until acknowledged, returns whether it is.
+    async fn confirm(&self, message: &Message, peer: SocketAddr, ack_timeout: Duration) -> bool {
+        let (ack, mut acked) = oneshot::channel();
+        {
+            let mut observers = self.observers.lock().unwrap();
+            let Some(observer) = observers.get_mut(&(peer, message.token.clone())) else {
+                return false;
+            };
+            observer.pending = Some((message.id, ack));
+        }
+
+        let mut timeout = ack_timeout;
+        for _ in 0..=MAX_RETRANSMIT {
+            self.send(message, peer).await;
+            if let Ok(acked) = tokio::time::timeout(timeout, &mut acked).await {
+                return acked.is_ok();
+            }
+            timeout *= 2;
+        }
+
+        false
+    }
+
+    /// Whether a Confirmable request is a retransmission, resending its response if sent.
+    async fn retransmitted(&self, request: &Message, peer: SocketAddr) -> bool {
+        if request.ty != MessageType::Confirmable {
+            return false;
+        }
+
+        let sent = {
+            let mut recent = self.recent.lock().unwrap();
+            match recent.iter().find(|(key, _)| *key == (peer, request.id)) {
+                Some((_, sent)) => Some(sent.clone()),
+                None => {
+                    if recent.len() == RECENT_REQUESTS {
+                        recent.pop_front();
+                    }
+                    recent.push_back(((peer, request.id), None));
+                    None
+                }
+            }
+        };
+
+        match sent {
+            Some(Some(bytes)) => {
+                let _ = self.socket.send_to(&bytes, peer).await;
+                true
+            }
+            Some(None) => true,
+            None => false,
+        }
+    }
+}
+
+/// Reply to a request, piggybacked on the Acknowledgement if not sent yet.
+struct Reply {
+    peer: SocketAddr,
+    request: Message,
+    acknowledged: bool,
+}
+
+impl Reply {
+    fn message(&mut self, code: Code, exchanges: &Exchanges) -> Message {
+        let (ty, id) = if self.request.ty == MessageType::Confirmable && !self.acknowledged {
+            self.acknowledged = true;
+            (MessageType::Acknowledgement, self.request.id)
+        } else {
+            (MessageType::NonConfirmable, exchanges.next_id())
+        };
+
+        Message::new(ty, code, id, self.request.token.clone())
+    }
+
+    /// Acknowledge the request before its response is ready.
+    async fn acknowledge(&mut self, exchanges: &Exchanges) {
+        if self.request.ty == MessageType::Confirmable && !self.acknowledged {
+            let ack = Message::new(
+                MessageType::Acknowledgement,
+                Code::EMPTY,
+                self.request.id,
+                Vec::new(),
+            );
+            exchanges.send(&ack, self.peer).await;
+            self.acknowledged = true;
+        }
+    }
+}
+
+/// Percent-encode an Uri-Path or Uri-Query option.
+fn percent_encode(value: &[u8], keep: &[u8]) -> String {
+    value
+        .iter()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b"-._~!$'()*+,;:@".contains(b) || keep.contains(b) {
+                char::from(*b).to_string()
+            } else {
+                format!("%{b:02X}")
+            }
+        })
+        .collect()
+}
+
+fn request_path(request: &Message) -> String {
+    let path: String = request
+        .options(option::URI_PATH)
+        .map(|segment| format!("/{}", percent_encode(segment, b"")))
+        .collect();
+
+    if path.is_empty() {
+        "/".into()
+    } else {
+        path
+    }
+}
+
+/// Whether the path matches an axum route.
+fn matches_route(route: &str, path: &str) -> bool {
+    let mut route = route.split('/');
+    let mut path = path.split('/');
+
+    loop {
+        match (route.next(), path.next()) {
+            (None, None) => return true,
+            (Some(r), _) if r.starts_with('*') => return true,
+            (Some(r), Some(p)) if r == p || (r.starts_with(':') && !p.is_empty()) => {}
+            _ => return false,
+        }
+    }
+}
+
+/// The CoAP response code of an http response.
+fn response_code(status: StatusCode, method: Code) -> Code {
+    let status = status.as_u16();
+
+    match status {
+        201 => Code::CREATED,
+        304 => Code::VALID,
+        200..=299 if method == Code::GET => Code::CONTENT,
+        200..=299 if method == Code::DELETE => Code::DELETED,
+        200..=299 => Code::CHANGED,
+        // The error codes defined by CoAP share their detail with the http ones.
+        400..=406 | 409 | 412 | 413 | 415 | 422 | 500..=505 => {
+            Code::new((status / 100) as u8, (status % 100) as u8)
+        }
+        400..=499 => Code::BAD_REQUEST,
+        _ => Code::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn format_media_type(format: u32) -> Option<&'static str> {
+    u16::try_from(format).ok().and_then(media_type)
+}
+
+/// The http request of a CoAP request.
+fn http_request(request: &Message) -> Option<Request<Body>> {
+    let method = match request.code {
+        Code::GET => http::Method::GET,
+        Code::POST => http::Method::POST,
+        Code::PUT => http::Method::PUT,
+        Code::DELETE => http::Method::DELETE,
+        Code::PATCH => http::Method::PATCH,
+        _ => return None,
+    };
+
+    let mut uri = request_path(request);
+    let query: Vec<_> = request
+        .options(option::URI_QUERY)
+        .map(|arg| percent_encode(arg, b"=/?"))
+        .collect();
+    if !query.is_empty() {
+        uri.push('?');
+        uri.push_str(&query.join("&"));
+    }
+
+    let mut builder = Request::builder().method(method).uri(uri);
+    if let Some(format) = request.uint_option(option::CONTENT_FORMAT) {
+        builder = builder.header(CONTENT_TYPE, format_media_type(format)?);
+    }
+    if let Some(format) = request.uint_option(option::ACCEPT) {
+        builder = builder.header(ACCEPT, format_media_type(format)?);
+    }
+
+    builder.body(Body::from(request.payload.clone())).ok()
+}
+
+async fn call(mut router: Router, request: Request<Body>) -> Response {
+    // Routers are always ready.
+    let Ok(response) = router.call(request).await;
+
+    response
+}
+
+/// The code, options and payload of the response to a request.
+async fn respond(router: Router, request: &Message, description: &Description) -> Message {
+    // The type and the ID are set once the response is sent.
+    let empty = || Message::new(MessageType::NonConfirmable, Code::CONTENT, 0, Vec::new());
+
+    if request.code == Code::GET && request_path(request) == WELL_KNOWN {
+        let td = serde_json::to_vec(&description.get()).expect("json values serialize");
+        return empty()
+            .with_option(option::CONTENT_FORMAT, uint(432))
+            .with_payload(td);
+    }
+
+    let Some(http_request) = http_request(request) else {
+        let code = match request.code {
+            Code::GET | Code::POST | Code::PUT | Code::DELETE | Code::PATCH => Code::BAD_REQUEST,
+            _ => Code::METHOD_NOT_ALLOWED,
+        };
+        return Message { code, ..empty() };
+    };
+
+    let response = call(router, http_request).await;
+    let code = response_code(response.status(), request.code);
+    let format = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(content_format);
+
+    let Ok(payload) = body_bytes(response.into_body()).await else {
+        return Message {
+            code: Code::INTERNAL_SERVER_ERROR,
+            ..empty()
+        };
+    };
+
+    let mut message = Message { code, ..empty() }.with_payload(payload.to_vec());
+    if let Some(format) = format.filter(|_| !payload.is_empty()) {
+        message = message.with_option(option::CONTENT_FORMAT, uint(format.into()));
+    }
+
+    message
+}
+
+impl CoapServer {
+    pub(crate) fn new(addr: SocketAddr, router: Router, observable: Vec<String>) -> Self {
+        Self {
+            addr,
+            router,
+            observable,
+            notify_interval: NOTIFY_INTERVAL,
+            confirm_interval: CONFIRM_INTERVAL,
+            ack_timeout: ACK_TIMEOUT,
+        }
+    }
+
+    /// Handle a request, answering with the response or the notifications.
+    async fn handle(
+        self,
+        exchanges: Arc<Exchanges>,
+        description: Description,
+        request: Message,
+        peer: SocketAddr,
+        observe: bool,
+    ) {
+        let mut reply = Reply {
+            peer,
+            request,
+            acknowledged: false,
+        };
+
+        let mut sequence = 0u32;
+        let mut next = Instant::now();
+        let mut confirmed = Instant::now();
+        loop {
+            // Handlers answering right away would flood the client otherwise.
+            sleep_until(next).await;
+
+            let request = reply.request.clone();
+            let response = respond(self.router.clone(), &request, &description);
+            tokio::pin!(response);
+
+            let response = tokio::select! {
+                response = &mut response => response,
+                _ = tokio::time::sleep(ACK_DELAY) => {
+                    reply.acknowledge(&exchanges).await;
+                    response.await
+                }
+            };
+
+            let mut message = reply.message(response.code, &exchanges);
+            message.options = response.options;
+            message.payload = response.payload;
+
+            let observing = observe && response.code.class() == 2;
+            if observing {
+                message = message.with_option(option::OBSERVE, uint(sequence & 0xff_ffff));
+                sequence = sequence.wrapping_add(1);
+                if message.ty == MessageType::NonConfirmable
+                    && confirmed.elapsed() >= self.confirm_interval
+                {
+                    message.ty = MessageType::Confirmable;
+                }
+                if let Some(observer) = exchanges
+                    .observers
+                    .lock()
+                    .unwrap()
+                    .get_mut(&(peer, reply.request.token.clone()))
+                {
+                    observer.last_id = message.id;
+                }
+            }
+
+            if message.ty == MessageType::Confirmable {
+                // The client is gone.
+                if !exchanges.confirm(&message, peer, self.ack_timeout).await {
+                    break;
+                }
+                confirmed = Instant::now();
+            } else {
+                exchanges.send(&message, peer).await;
+            }
+
+            if !observing {
+                break;
+            }
+            next = Instant::now() + self.notify_interval;
+        }
+
+        if observe {
+            exchanges
+                .observers
+                .lock()
+                .unwrap()
+                .remove(&(peer, reply.request.token));
+        }
+    }
+
+    /// Serve the CoAP requests received by `socket` until `signal` resolves.
+    pub(crate) async fn serve(
+        &self,
+        socket: UdpSocket,
+        description: Description,
+        signal: impl Future<Output = ()>,
+    ) -> Result<()> {
+        let seed = uuid::Uuid::new_v4().as_u128() as u16;
+        let exchanges = Arc::new(Exchanges {
+            socket,
+            next_id: AtomicU16::new(seed),
+            recent: Mutex::new(VecDeque::new()),
+            observers: Mutex::new(HashMap::new()),
+        });
+        // Dropping the tasks aborts them, the observations included.
+        let mut tasks = JoinSet::new();
+        let mut buf = vec![0; 65535];
+
+        tokio::pin!(signal);
+        loop {
+            let (len, peer) = tokio::select! {
+                _ = &mut signal => return Ok(()),
+                Some(_) = tasks.join_next(), if !tasks.is_empty() => continue,
+                received = exchanges.socket.recv_from(&mut buf) => received?,
+            };
+            let Ok(message) = Message::decode(&buf[..len]) else {
+                continue;
+            };
+
+            match (message.ty, message.code.class()) {
+                (MessageType::Reset, _) => {
+                    exchanges
+                        .observers
+                        .lock()
+                        .unwrap()
+                        .retain(|(observer, _), o| {
+                            let cancel = *observer == peer && o.last_id == message.id;
+                            if cancel {
+                                o.task.abort();
+                            }
+                            !cancel
+                        });
+                }
+                (MessageType::Acknowledgement, _) => {
+                    let mut observers = exchanges.observers.lock().unwrap();
+                    let acked = observers.iter_mut().find_map(|((observer, _), o)| {
+                        o.pending
+                            .take_if(|(id, _)| *observer == peer && *id == message.id)
+                    });
+                    if let Some((_, ack)) = acked {
+                        let _ = ack.send(());
+                    }
+                }
+                // Pings and responses to requests never sent are rejected.
+                (MessageType::Confirmable, _) if message.code == Code::EMPTY => {
+                    let reset = Message::new(MessageType::Reset, Code::EMPTY, message.id, vec![]);
+                    exchanges.send(&reset, peer).await;
+                }
+                (MessageType::Confirmable, class) if class != 0 => {
+                    let reset = Message::new(MessageType::Reset, Code::EMPTY, message.id, vec![]);
+                    exchanges.send(&reset, peer).await;
+                }
+                (_, 0) if message.code != Code::EMPTY => {
+                    if exchanges.retransmitted(&message, peer).await {
+                        continue;
+                    }
+
+                    let key = (peer, message.token.clone());
+                    if let Some(observer) = exchanges.observers.lock().unwrap().remove(&key) {
+                        observer.task.abort();
+                    }
+
+                    let observe = message.code == Code::GET
+                        && message.uint_option(option::OBSERVE) == Some(0)
+                        && self
+                            .observable
+                            .iter()
+                            .any(|route| matches_route(route, &request_path(&message)));
+
+                    // The observation is registered before its task may complete.
+                    let mut observers = exchanges.observers.lock().unwrap();
+                    // The requests beyond the limit are answered once, as plain GETs.
+                    let observe = observe
+                        && observers.keys().filter(|(p, _)| *p == peer).count() < MAX_OBSERVATIONS;
+                    let task = tasks.spawn(self.clone().handle(
+                        exchanges.clone(),
+                        description.clone(),
+                        message,
+                        peer,
+                        observe,
+                    ));
+                    if observe {
+                        let observer = Observer {
+                            task,
+                            last_id: 0,
+                            pending: None,
+                        };
+                        observers.insert(key, observer);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn message_roundtrip() {
+        let message = Message::new(MessageType::Confirmable, Code::GET, 0x1234, vec![1, 2])
+            .with_option(option::URI_PATH, "properties")
+            .with_option(option::URI_PATH, "level")
+            .with_option(option::OBSERVE, uint(0))
+            .with_option(option::ACCEPT, uint(60))
+            .with_option(300, vec![0; 300])
+            .with_payload("hello");
+
+        let bytes = message.encode();
+        assert_eq!(&bytes[..6], [0x42, 0x01, 0x12, 0x34, 1, 2]);
+        // Observe comes first, with an empty value.
+        assert_eq!(bytes[6], 0x60);
+
+        let decoded = Message::decode(&bytes).unwrap();
+        assert_eq!(decoded.code, Code::GET);
+        assert_eq!(decoded.uint_option(option::OBSERVE), Some(0));
+        assert_eq!(decoded.uint_option(option::ACCEPT), Some(60));
+        assert_eq!(request_path(&decoded), "/properties/level");
+        assert_eq!(decoded.options(300).next().unwrap().len(), 300);
+        assert_eq!(decoded.payload, b"hello");
+
+        assert!(Message::decode(&[0x40, 0x01]).is_err());
+        assert!(Message::decode(&[0x49, 0x01, 0, 0]).is_err());
+        assert!(Message::decode(&[0x40, 0x01, 0, 0, 0xff]).is_err());
+    }
+
+    #[test]
+    fn map_requests() {
+        assert_eq!(Code::CONTENT.to_string(), "2.05");
+        assert_eq!(response_code(StatusCode::OK, Code::GET), Code::CONTENT);
+        assert_eq!(
+            response_code(StatusCode::NO_CONTENT, Code::PUT),
+            Code::CHANGED
+        );
+        assert_eq!(
+            response_code(StatusCode::UNSUPPORTED_MEDIA_TYPE, Code::PUT),
+            Code::new(4, 15)
+        );
+        assert_eq!(
+            response_code(StatusCode::UNPROCESSABLE_ENTITY, Code::PUT),
+            Code::new(4, 22)
+        );
+        assert_eq!(
+            response_code(StatusCode::TOO_MANY_REQUESTS, Code::PUT),
+            Code::BAD_REQUEST
+        );
+
+        assert!(matches_route("/things/:id", "/things/lamp"));
+        assert!(!matches_route("/things/:id", "/things"));
+        assert!(matches_route("/files/*rest", "/files/a/b"));
+
+        let request = Message::new(MessageType::NonConfirmable, Code::PUT, 1, vec![])
+            .with_option(option::URI_PATH, "a b")
+            .with_option(option::URI_QUERY, "x=1")
+            .with_option(option::CONTENT_FORMAT, uint(60));
+        let http = http_request(&request).unwrap();
+        assert_eq!(http.uri(), "/a%20b?x=1");
+        assert_eq!(http.headers()[CONTENT_TYPE], "application/cbor");
+        assert_eq!(content_format("text/plain; charset=utf-8"), Some(0));
+    }
+
+    /// Run `checks` with a client of `router` served over CoAP with short observation timings.
+    async fn with_server<F, Fut>(router: Router, notify_interval: Duration, checks: F)
+    where
+        F: FnOnce(UdpSocket) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let server = CoapServer {
+            notify_interval,
+            confirm_interval: Duration::from_millis(200),
+            ack_timeout: Duration::from_millis(20),
+            ..CoapServer::new(addr, router, vec!["/level".into()])
+        };
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(addr).await.unwrap();
+
+        let description = Description::new(serde_json::json!({}));
+        tokio::select! {
+            served = server.serve(socket, description, std::future::pending()) => {
+                panic!("the server stopped: {served:?}")
+            }
+            () = checks(client) => {}
+        }
+    }
+
+    async fn recv(client: &UdpSocket, timeout: Duration) -> Option<Message> {
+        let mut buf = vec![0; 65535];
+        let len = tokio::time::timeout(timeout, client.recv(&mut buf))
+            .await
+            .ok()?
+            .unwrap();
+
+        Some(Message::decode(&buf[..len]).unwrap())
+    }
+
+    fn observe(token: u8) -> Message {
+        Message::new(
+            MessageType::NonConfirmable,
+            Code::GET,
+            token.into(),
+            vec![token],
+        )
+        .with_option(option::URI_PATH, "level")
+        .with_option(option::OBSERVE, uint(0))
+    }
+
+    fn level() -> Router {
+        Router::new().route("/level", axum::routing::get(|| async { "5" }))
+    }
+
+    #[tokio::test]
+    async fn drop_unacknowledged_observations() {
+        with_server(level(), Duration::from_millis(50), |client| async move {
+            client.send(&observe(1).encode()).await.unwrap();
+
+            let start = Instant::now();
+            let mut notifications = Vec::new();
+            while let Some(message) = recv(&client, Duration::from_secs(1)).await {
+                notifications.push(message);
+            }
+
+            // The notifications are paced until the first Confirmable one.
+            let confirmable: Vec<_> = notifications
+                .iter()
+                .filter(|m| m.ty == MessageType::Confirmable)
+                .collect();
+            let paced = notifications.len() - confirmable.len();
+            assert!((2..=6).contains(&paced), "{paced} notifications");
+
+            // Retransmitted until given up, then the observation is dropped.
+            assert_eq!(confirmable.len(), MAX_RETRANSMIT as usize + 1);
+            assert!(confirmable.iter().all(|m| m.id == confirmable[0].id));
+            assert!(start.elapsed() < Duration::from_secs(3));
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn acknowledged_observations() {
+        with_server(level(), Duration::from_millis(50), |client| async move {
+            client.send(&observe(1).encode()).await.unwrap();
+
+            let mut confirmed = 0;
+            while confirmed < 2 {
+                let message = recv(&client, Duration::from_secs(1)).await.unwrap();
+                if message.ty == MessageType::Confirmable {
+                    let ack = Message::new(
+                        MessageType::Acknowledgement,
+                        Code::EMPTY,
+                        message.id,
+                        vec![],
+                    );
+                    client.send(&ack.encode()).await.unwrap();
+                    confirmed += 1;
+                }
+            }
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn limit_observations() {
+        with_server(level(), Duration::from_secs(10), |client| async move {
+            for token in 0..=MAX_OBSERVATIONS as u8 {
+                client.send(&observe(token).encode()).await.unwrap();
+                let message = recv(&client, Duration::from_secs(1)).await.unwrap();
+                assert_eq!(message.token, [token]);
+
+                let observing = message.uint_option(option::OBSERVE).is_some();
+                assert_eq!(observing, usize::from(token) < MAX_OBSERVATIONS);
+            }
+        })
+        .await;
+    }
+}
@@ -0,0 +1,952 @@
+//! Consumer side
+//!
+//! A [`ConsumedThing`] interacts with a remote Thing through the http Forms of its Thing
+//! Description, fetched by url or from a Thing [discovered](crate::discovery) through DNS-SD.
+//!
+//! The Forms are picked by operation, json ones first, skipping the subprotocols other than
+//! long polling and Server-Sent Events. Their hrefs are expanded as URI
+//! templates and resolved against the base of the description, the credentials of the
+//! security schemes they require are sent along.
+//!
+//! Observing a property and subscribing an event use long-polling, or Server-Sent Events
+//! when the Thing replies with an event stream.
+
+use std::{collections::HashMap, marker::PhantomData, sync::Arc, time::Duration};
+
+use reqwest::{header::CONTENT_TYPE, Client, Method, RequestBuilder, Response, StatusCode, Url};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Value};
+use tokio::{sync::mpsc, task::JoinHandle};
+use wot_td::{
+    builder::AffordanceType,
+    protocol::http::{self, HttpProtocol},
+    thing::{
+        ComboSecurityScheme, Form, FormOperation, KnownSecuritySchemeSubtype,
+        SecurityAuthenticationLocation, SecuritySchemeSubtype, Thing,
+    },
+};
+
+use crate::{
+    discovery::DiscoveredThing,
+    servient::{default_method, form_ops, MediaType},
+};
+
+/// Error type for the module
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// The url cannot be used to reach the Thing.
+    #[error("invalid url {0}")]
+    Url(String),
+    /// Error communicating with the Thing.
+    #[error("http client error {0}")]
+    Http(#[from] reqwest::Error),
+    /// The Thing refused the request.
+    #[error("the thing replied with status {0}")]
+    Status(StatusCode),
+    /// The Thing Description or a payload cannot be parsed.
+    #[error("invalid json {0}")]
+    Json(#[from] serde_json::Error),
+    /// The Thing Description has no such affordance.
+    #[error("no {0} named {1}")]
+    UnknownAffordance(AffordanceType, String),
+    /// No http Form of the affordance supports the operation.
+    #[error("the {0} {1} has no http form for {2}")]
+    NoForm(AffordanceType, String, FormOperation),
+    /// The security scheme has no credentials set.
+    #[error("no credentials for the security scheme {0}")]
+    MissingCredentials(String),
+    /// The security scheme is not supported.
+    #[error("unsupported security scheme {0}")]
+    UnsupportedSecurity(String),
+}
+
+/// Result type for the module
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Minimum time between two long-polling requests
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Credentials for a security scheme of the Thing Description
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Credentials {
+    /// Username and password of the `basic` scheme
+    Basic {
+        /// User name
+        username: String,
+        /// Password
+        password: String,
+    },
+    /// Token of the `bearer` scheme
+    Bearer(String),
+    /// Key of the `apikey` scheme
+    ApiKey(String),
+}
+
+/// Options of an interaction
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InteractionOptions {
+    /// Values of the variables of the URI templates of the Forms
+    pub uri_variables: Map<String, Value>,
+}
+
+impl InteractionOptions {
+    /// Set the value of a URI template variable.
+    pub fn uri_variable(mut self, name: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.uri_variables.insert(name.into(), value.into());
+        self
+    }
+}
+
+/// Request resolved from a Form
+struct Target {
+    method: Method,
+    url: Url,
+    security: Vec<String>,
+}
+
+/// A remote Thing, interacted with through its Thing Description
+#[derive(Debug, Clone)]
+pub struct ConsumedThing {
+    client: Client,
+    td: Arc<Thing<HttpProtocol>>,
+    base: Url,
+    credentials: HashMap<String, Credentials>,
+}
+
+impl ConsumedThing {
+    /// Fetch the Thing Description at `url`.
+    pub async fn fetch(url: &str) -> Result<Self> {
+        let client = Client::new();
+        let url = Url::parse(url).map_err(|_| Error::Url(url.to_string()))?;
+
+        let response = check(client.get(url.clone()).send().await?)?;
+        let td = serde_json::from_slice(&response.bytes().await?)?;
+
+        Ok(Self::with_client(client, td, url))
+    }
+
+    /// Fetch the Thing Description of a Thing discovered through DNS-SD.
+    pub async fn discovered(thing: &DiscoveredThing) -> Result<Self> {
+        let url = thing
+            .td_url()
+            .ok_or_else(|| Error::Url(thing.fullname.clone()))?;
+
+        Self::fetch(&url).await
+    }
+
+    /// Consume the Thing Description `td`, fetched from `url`.
+    ///
+    /// The relative Forms are resolved against the `base` of the description, or `url`.
+    pub fn new(td: Thing<HttpProtocol>, url: Url) -> Self {
+        Self::with_client(Client::new(), td, url)
+    }
+
+    fn with_client(client: Client, td: Thing<HttpProtocol>, url: Url) -> Self {
+        let base = td
+            .base
+            .as_deref()
+            .and_then(|base| url.join(base).ok())
+            .unwrap_or(url);
+
+        Self {
+            client,
+            td: Arc::new(td),
+            base,
+            credentials: HashMap::new(),
+        }
+    }
+
+    /// Set the credentials of the security scheme defined as `name`.
+    pub fn credentials(mut self, name: impl Into<String>, credentials: Credentials) -> Self {
+        self.credentials.insert(name.into(), credentials);
+        self
+    }
+
+    /// The Thing Description.
+    pub fn td(&self) -> &Thing<HttpProtocol> {
+        &self.td
+    }
+
+    /// Read the value of a property.
+    pub async fn read_property<T: DeserializeOwned>(&self, name: &str) -> Result<T> {
+        self.read_property_with(name, &Default::default()).await
+    }
+
+    /// Read the value of a property, with options.
+    pub async fn read_property_with<T: DeserializeOwned>(
+        &self,
+        name: &str,
+        options: &InteractionOptions,
+    ) -> Result<T> {
+        let target = self.target(
+            AffordanceType::Property,
+            name,
+            FormOperation::ReadProperty,
+            options,
+        )?;
+
+        value(self.send(target, None).await?).await
+    }
+
+    /// Write the value of a property.
+    pub async fn write_property<T: Serialize>(&self, name: &str, value: &T) -> Result<()> {
+        self.write_property_with(name, value, &Default::default())
+            .await
+    }
+
+    /// Write the value of a property, with options.
+    pub async fn write_property_with<T: Serialize>(
+        &self,
+        name: &str,
+        value: &T,
+        options: &InteractionOptions,
+    ) -> Result<()> {
+        let target = self.target(
+            AffordanceType::Property,
+            name,
+            FormOperation::WriteProperty,
+            options,
+        )?;
+        let value = serde_json::to_value(value)?;
+
+        self.send(target, Some(value)).await?;
+
+        Ok(())
+    }
+
+    /// Invoke an action and return its output.
+    ///
+    /// An input serialized as `null`, e.g. `()`, is not sent. An empty output is read as
+    /// `null`, e.g. as `()`.
+    pub async fn invoke_action<I, O>(&self, name: &str, input: &I) -> Result<O>
+    where
+        I: Serialize,
+        O: DeserializeOwned,
+    {
+        self.invoke_action_with(name, input, &Default::default())
+            .await
+    }
+
+    /// Invoke an action and return its output, with options.
+    pub async fn invoke_action_with<I, O>(
+        &self,
+        name: &str,
+        input: &I,
+        options: &InteractionOptions,
+    ) -> Result<O>
+    where
+        I: Serialize,
+        O: DeserializeOwned,
+    {
+        let target = self.target(
+            AffordanceType::Action,
+            name,
+            FormOperation::InvokeAction,
+            options,
+        )?;
+        let input = Some(serde_json::to_value(input)?).filter(|input| !input.is_null());
+
+        value(self.send(target, input).await?).await
+    }
+
+    /// Receive the values of a property as it changes.
+    pub async fn observe_property<T>(&self, name: &str) -> Result<Subscription<T>>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        self.observe_property_with(name, &Default::default()).await
+    }
+
+    /// Receive the values of a property as it changes, with options.
+    pub async fn observe_property_with<T>(
+        &self,
+        name: &str,
+        options: &InteractionOptions,
+    ) -> Result<Subscription<T>>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        let target = self.target(
+            AffordanceType::Property,
+            name,
+            FormOperation::ObserveProperty,
+            options,
+        )?;
+
+        Ok(self.subscribe(target))
+    }
+
+    /// Receive the occurrences of an event.
+    pub async fn subscribe_event<T>(&self, name: &str) -> Result<Subscription<T>>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        self.subscribe_event_with(name, &Default::default()).await
+    }
+
+    /// Receive the occurrences of an event, with options.
+    pub async fn subscribe_event_with<T>(
+        &self,
+        name: &str,
+        options: &InteractionOptions,
+    ) -> Result<Subscription<T>>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        let target = self.target(
+            AffordanceType::Event,
+            name,
+            FormOperation::SubscribeEvent,
+            options,
+        )?;
+
+        Ok(self.subscribe(target))
+    }
+
+    pub(crate) fn forms(
+        &self,
+        affordance: AffordanceType,
+        name: &str,
+    ) -> Option<&[Form<HttpProtocol>]> {
+        let forms = match affordance {
+            AffordanceType::Property => &self.td.properties.as_ref()?.get(name)?.interaction.forms,
+            AffordanceType::Action => &self.td.actions.as_ref()?.get(name)?.interaction.forms,
+            AffordanceType::Event => &self.td.events.as_ref()?.get(name)?.interaction.forms,
+        };
+
+        Some(forms)
+    }
+
+    /// Index of the http Form of the affordance serving the operation, the json ones first.
+    pub(crate) fn form_index(
+        &self,
+        affordance: AffordanceType,
+        name: &str,
+        op: FormOperation,
+    ) -> Result<usize> {
+        let forms = self
+            .forms(affordance, name)
+            .ok_or_else(|| Error::UnknownAffordance(affordance, name.to_string()))?;
+
+        let (read_only, write_only) = match affordance {
+            AffordanceType::Property => self.td.properties.as_ref().and_then(|properties| {
+                let schema = &properties.get(name)?.data_schema;
+                Some((schema.read_only, schema.write_only))
+            }),
+            _ => None,
+        }
+        .unwrap_or_default();
+
+        let is_json = |form: &Form<HttpProtocol>| {
+            form.content_type
+                .as_deref()
+                .is_none_or(|ty| MediaType::parse(ty) == Some(MediaType::Json))
+        };
+
+        forms
+            .iter()
+            .enumerate()
+            .filter(|(_, form)| form_ops(&form.op, affordance, read_only, write_only).contains(&op))
+            .filter(|(_, form)| {
+                form.subprotocol
+                    .as_deref()
+                    .is_none_or(|subprotocol| matches!(subprotocol, "longpoll" | "sse"))
+            })
+            .filter(|(_, form)| self.form_target(form, op, &Default::default()).is_some())
+            .min_by_key(|(index, form)| (!is_json(form), *index))
+            .map(|(index, _)| index)
+            .ok_or_else(|| Error::NoForm(affordance, name.to_string(), op))
+    }
+
+    /// The request of the operation through the Form, `None` if it does not use http.
+    fn form_target(
+        &self,
+        form: &Form<HttpProtocol>,
+        op: FormOperation,
+        options: &InteractionOptions,
+    ) -> Option<Target> {
+        let url = self.base.join(&expand(&form.href, options)).ok()?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return None;
+        }
+
+        let method = form.other.method_name.unwrap_or_else(|| default_method(op));
+        Some(Target {
+            method: http_method(method),
+            url,
+            security: form
+                .security
+                .clone()
+                .unwrap_or_else(|| self.td.security.clone()),
+        })
+    }
+
+    /// Resolve the request of the operation, from the json Forms first.
+    fn target(
+        &self,
+        affordance: AffordanceType,
+        name: &str,
+        op: FormOperation,
+        options: &InteractionOptions,
+    ) -> Result<Target> {
+        let index = self.form_index(affordance, name, op)?;
+        let form = &self
+            .forms(affordance, name)
+            .expect("the form has just been found")[index];
+
+        self.form_target(form, op, options)
+            .ok_or_else(|| Error::Url(form.href.clone()))
+    }
+
+    /// The request of the operation through the Form at `index`.
+    ///
+    /// It carries the credentials of the security schemes of the Form if `authorize`.
+    pub(crate) fn form_request(
+        &self,
+        affordance: AffordanceType,
+        name: &str,
+        index: usize,
+        op: FormOperation,
+        options: &InteractionOptions,
+        authorize: bool,
+    ) -> Result<RequestBuilder> {
+        let form = self
+            .forms(affordance, name)
+            .and_then(|forms| forms.get(index))
+            .ok_or_else(|| Error::UnknownAffordance(affordance, name.to_string()))?;
+        let target = self
+            .form_target(form, op, options)
+            .ok_or_else(|| Error::Url(form.href.clone()))?;
+
+        if authorize {
+            self.request(&target, None)
+        } else {
+            Ok(self.client.request(target.method, target.url))
+        }
+    }
+
+    /// Add the credentials of the security scheme to the request.
+    fn authorize(&self, request: RequestBuilder, scheme: &str) -> Result<RequestBuilder> {
+        self.authorize_within(request, scheme, &[])
+    }
+
+    /// Add the credentials of the security scheme, part of the combo schemes of `within`.
+    fn authorize_within(
+        &self,
+        request: RequestBuilder,
+        scheme: &str,
+        within: &[&str],
+    ) -> Result<RequestBuilder> {
+        use KnownSecuritySchemeSubtype::*;
+
+        let unsupported = || Error::UnsupportedSecurity(scheme.to_string());
+        let missing = || Error::MissingCredentials(scheme.to_string());
+
+        // A combo scheme including itself would never be satisfied.
+        if within.contains(&scheme) {
+            return Err(unsupported());
+        }
+        let within = [within, &[scheme]].concat();
+
+        let definition = self
+            .td
+            .security_definitions
+            .get(scheme)
+            .ok_or_else(unsupported)?;
+        let SecuritySchemeSubtype::Known(subtype) = &definition.subtype else {
+            return Err(unsupported());
+        };
+
+        let request = match subtype {
+            NoSec | Auto => request,
+            Combo(ComboSecurityScheme::AllOf(schemes)) => {
+                schemes.iter().try_fold(request, |request, scheme| {
+                    self.authorize_within(request, scheme, &within)
+                })?
+            }
+            Combo(ComboSecurityScheme::OneOf(schemes)) => {
+                let scheme = schemes
+                    .iter()
+                    .find(|scheme| self.satisfiable(scheme, &within))
+                    .ok_or_else(missing)?;
+                self.authorize_within(request, scheme, &within)?
+            }
+            Basic(basic) => match (self.credentials.get(scheme), &basic.location) {
+                (
+                    Some(Credentials::Basic { username, password }),
+                    SecurityAuthenticationLocation::Header,
+                ) if basic.name.is_none() => request.basic_auth(username, Some(password)),
+                (Some(Credentials::Basic { .. }), _) => return Err(unsupported()),
+                _ => return Err(missing()),
+            },
+            Bearer(bearer) => match self.credentials.get(scheme) {
+                Some(Credentials::Bearer(token)) => credential(
+                    request,
+                    &bearer.location,
+                    bearer.name.as_deref(),
+                    token,
+                    true,
+                )
+                .ok_or_else(unsupported)?,
+                _ => return Err(missing()),
+            },
+            ApiKey(apikey) => match (self.credentials.get(scheme), apikey.name.as_deref()) {
+                (Some(Credentials::ApiKey(key)), Some(name)) => {
+                    credential(request, &apikey.location, Some(name), key, false)
+                        .ok_or_else(unsupported)?
+                }
+                (Some(Credentials::ApiKey(_)), None) => return Err(unsupported()),
+                _ => return Err(missing()),
+            },
+            _ => return Err(unsupported()),
+        };
+
+        Ok(request)
+    }
+
+    /// Whether the security scheme can be satisfied with the credentials set.
+    fn satisfiable(&self, scheme: &str, within: &[&str]) -> bool {
+        self.authorize_within(self.client.get(self.base.clone()), scheme, within)
+            .is_ok()
+    }
+
+    fn request(&self, target: &Target, body: Option<&Value>) -> Result<RequestBuilder> {
+        let mut request = self
+            .client
+            .request(target.method.clone(), target.url.clone());
+        if let Some(body) = body {
+            request = request
+                .header(CONTENT_TYPE, MediaType::Json.as_str())
+                .body(serde_json::to_vec(body)?);
+        }
+
+        target
+            .security
+            .iter()
+            .try_fold(request, |request, scheme| self.authorize(request, scheme))
+    }
+
+    async fn send(&self, target: Target, body: Option<Value>) -> Result<Response> {
+        let response = self.request(&target, body.as_ref())?.send().await?;
+
+        check(response)
+    }
+
+    fn subscribe<T>(&self, target: Target) -> Subscription<T>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel(16);
+        let request = self.request(&target, None);
+
+        let task = tokio::spawn(async move {
+            let result = match request {
+                Ok(request) => poll(request, &tx).await,
+                Err(err) => Err(err),
+            };
+            if let Err(err) = result {
+                let _ = tx.send(Err(err)).await;
+            }
+        });
+
+        Subscription {
+            rx,
+            task,
+            values: PhantomData,
+        }
+    }
+}
+
+/// Values received from an observed property or a subscribed event
+///
+/// The subscription ends when dropped.
+#[derive(Debug)]
+pub struct Subscription<T> {
+    rx: mpsc::Receiver<Result<Value>>,
+    task: JoinHandle<()>,
+    values: PhantomData<fn() -> T>,
+}
+
+impl<T: DeserializeOwned> Subscription<T> {
+    /// Wait for the next value.
+    ///
+    /// Returns `None` once the Thing stops sending them, after an error.
+    pub async fn next(&mut self) -> Option<Result<T>> {
+        let value = self.rx.recv().await?;
+
+        Some(value.and_then(|value| Ok(serde_json::from_value(value)?)))
+    }
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Request the values until the receiver is dropped or the Thing fails.
+async fn poll(request: RequestBuilder, tx: &mpsc::Sender<Result<Value>>) -> Result<()> {
+    let mut next = tokio::time::Instant::now();
+    loop {
+        // Things answering right away would be flooded with requests otherwise.
+        tokio::time::sleep_until(next).await;
+        next = tokio::time::Instant::now() + POLL_INTERVAL;
+
+        let Some(request) = request.try_clone() else {
+            return Ok(());
+        };
+        let mut response = check(request.send().await?)?;
+
+        let is_stream = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("text/event-stream"));
+
+        if !is_stream {
+            let value = serde_json::from_slice(&response.bytes().await?)?;
+            if tx.send(Ok(value)).await.is_err() {
+                return Ok(());
+            }
+            continue;
+        }
+
+        let mut events = EventStream::default();
+        while let Some(chunk) = response.chunk().await? {
+            for data in events.feed(&chunk) {
+                let value = serde_json::from_str(&data)?;
+                if tx.send(Ok(value)).await.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Parser of the `data` of a Server-Sent Events stream
+#[derive(Debug, Default)]
+struct EventStream {
+    buffer: Vec<u8>,
+    data: Vec<String>,
+}
+
+impl EventStream {
+    /// Parse a chunk of the stream, returning the data of the events it completes.
+    fn feed(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+
+            if line.is_empty() {
+                if !self.data.is_empty() {
+                    events.push(std::mem::take(&mut self.data).join("\n"));
+                }
+            } else if let Some(data) = line.strip_prefix("data:") {
+                self.data
+                    .push(data.strip_prefix(' ').unwrap_or(data).to_string());
+            }
+        }
+
+        events
+    }
+}
+
+fn check(response: Response) -> Result<Response> {
+    if response.status().is_success() {
+        Ok(response)
+    } else {
+        Err(Error::Status(response.status()))
+    }
+}
+
+/// The json value of the response, `null` if empty.
+async fn value<T: DeserializeOwned>(response: Response) -> Result<T> {
+    let bytes = response.bytes().await?;
+    let value = if bytes.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_slice(&bytes)?
+    };
+
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Add a credential to the request, `None` if the location is not supported.
+fn credential(
+    request: RequestBuilder,
+    location: &SecurityAuthenticationLocation,
+    name: Option<&str>,
+    value: &str,
+    bearer: bool,
+) -> Option<RequestBuilder> {
+    match (location, name) {
+        (SecurityAuthenticationLocation::Header, None) if bearer => {
+            Some(request.bearer_auth(value))
+        }
+        (SecurityAuthenticationLocation::Header, Some(name)) => Some(request.header(name, value)),
+        (SecurityAuthenticationLocation::Query, Some(name)) => {
+            Some(request.query(&[(name, value)]))
+        }
+        _ => None,
+    }
+}
+
+fn http_method(method: http::Method) -> Method {
+    match method {
+        http::Method::Get => Method::GET,
+        http::Method::Put => Method::PUT,
+        http::Method::Post => Method::POST,
+        http::Method::Delete => Method::DELETE,
+        http::Method::Patch => Method::PATCH,
+    }
+}
+
+/// Expand the URI template of an href.
+fn expand(href: &str, options: &InteractionOptions) -> String {
+    if !href.contains('{') {
+        return href.to_string();
+    }
+
+    let mut template = datta::UriTemplate::new(href);
+    for (name, value) in &options.uri_variables {
+        let value = match value {
+            Value::String(s) => s.clone(),
+            value => value.to_string(),
+        };
+        template.set(name, value);
+    }
+
+    template.build()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            atomic::{AtomicI64, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use axum::{
+        extract::{Query, State},
+        http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+        Json,
+    };
+    use tokio::sync::broadcast;
+    use wot_td::builder::{affordance::*, data_schema::*};
+
+    use crate::{
+        servient::{BuildServient, HttpRouter, ServientSettings},
+        test_util::{local_listener, serve_until},
+        Servient,
+    };
+
+    use super::*;
+
+    type AppState = (Arc<AtomicI64>, broadcast::Sender<u8>);
+
+    #[test]
+    fn parse_event_stream() {
+        let mut events = EventStream::default();
+
+        assert!(events.feed(b"event: update\r\ndata: 1").is_empty());
+        assert_eq!(events.feed(b"\r\n\r\ndata: {\ndata:\"a\": 2}\n"), ["1"]);
+        assert_eq!(events.feed(b": comment\n\n"), ["{\n\"a\": 2}"]);
+        assert!(events.feed(b"\n").is_empty());
+
+        let options = InteractionOptions::default()
+            .uri_variable("unit", "celsius")
+            .uri_variable("id", 3);
+        assert_eq!(
+            expand("/things/{id}/reading{?unit}", &options),
+            "/things/3/reading?unit=celsius"
+        );
+    }
+
+    #[test]
+    fn security_cycles() {
+        let consume = |definitions| {
+            let td = serde_json::json!({
+                "@context": "https://www.w3.org/2022/wot/td/v1.1",
+                "title": "cyclic",
+                "security": ["a"],
+                "securityDefinitions": definitions,
+            });
+            ConsumedThing::new(
+                serde_json::from_value(td).unwrap(),
+                Url::parse("http://localhost/").unwrap(),
+            )
+        };
+
+        let thing = consume(serde_json::json!({
+            "a": { "scheme": "combo", "allOf": ["b"] },
+            "b": { "scheme": "combo", "oneOf": ["a"] },
+        }));
+        assert!(matches!(
+            thing.authorize(thing.client.get("http://localhost/"), "a"),
+            Err(Error::MissingCredentials(scheme)) if scheme == "b"
+        ));
+        assert!(matches!(
+            thing.authorize_within(thing.client.get("http://localhost/"), "a", &["b"]),
+            Err(Error::UnsupportedSecurity(scheme)) if scheme == "b"
+        ));
+
+        // The alternatives leading back to the cycle are skipped.
+        let thing = consume(serde_json::json!({
+            "a": { "scheme": "combo", "allOf": ["b"] },
+            "b": { "scheme": "combo", "oneOf": ["a", "nosec"] },
+            "nosec": { "scheme": "nosec" },
+        }));
+        assert!(thing
+            .authorize(thing.client.get("http://localhost/"), "a")
+            .is_ok());
+    }
+
+    #[test]
+    fn read_only_forms() {
+        let td = serde_json::json!({
+            "@context": "https://www.w3.org/2022/wot/td/v1.1",
+            "title": "flags",
+            "security": ["nosec"],
+            "securityDefinitions": { "nosec": { "scheme": "nosec" } },
+            "properties": {
+                "level": {
+                    "type": "integer",
+                    "readOnly": true,
+                    "forms": [{ "href": "/level" }],
+                },
+                "target": {
+                    "type": "integer",
+                    "writeOnly": true,
+                    "forms": [{ "href": "/target" }],
+                },
+            },
+        });
+        let thing = ConsumedThing::new(
+            serde_json::from_value(td).unwrap(),
+            Url::parse("http://localhost/").unwrap(),
+        );
+
+        let index = |name, op| thing.form_index(AffordanceType::Property, name, op);
+        assert_eq!(index("level", FormOperation::ReadProperty).unwrap(), 0);
+        assert!(matches!(
+            index("level", FormOperation::WriteProperty),
+            Err(Error::NoForm(_, name, FormOperation::WriteProperty)) if name == "level"
+        ));
+        assert_eq!(index("target", FormOperation::WriteProperty).unwrap(), 0);
+        assert!(matches!(
+            index("target", FormOperation::ReadProperty),
+            Err(Error::NoForm(_, name, FormOperation::ReadProperty)) if name == "target"
+        ));
+    }
+
+    #[tokio::test]
+    async fn consume_servient() {
+        let (listener, addr) = local_listener();
+        let (events, _) = broadcast::channel::<u8>(4);
+
+        let servient = Servient::stateful_builder("consumed")
+            .security(|b| b.basic().with_key("basic").required())
+            .finish_extend()
+            .http_bind(addr)
+            .with_state((Arc::new(AtomicI64::new(5)), events.clone()))
+            .property("level", |b| {
+                b.finish_extend_data_schema().integer().form(|f| {
+                    f.href("/level")
+                        .http_get(
+                            |State((level, _)): State<AppState>, headers: HeaderMap| async move {
+                                // user:pass
+                                if headers[AUTHORIZATION] != "Basic dXNlcjpwYXNz" {
+                                    return Err(StatusCode::UNAUTHORIZED);
+                                }
+                                Ok(Json(level.load(Ordering::SeqCst)))
+                            },
+                        )
+                        .http_put(
+                            |State((level, _)): State<AppState>, Json(value): Json<i64>| async move {
+                                level.store(value, Ordering::SeqCst);
+                            },
+                        )
+                })
+            })
+            .property("reading", |b| {
+                b.finish_extend_data_schema().string().form(|f| {
+                    f.href("/reading{?unit}")
+                        .http_get(|Query(query): Query<HashMap<String, String>>| async move {
+                            Json(query.get("unit").cloned().unwrap_or_default())
+                        })
+                        .op(wot_td::thing::FormOperation::ReadProperty)
+                })
+            })
+            .action("double", |b| {
+                b.input(|b| b.finish_extend().integer()).form(|f| {
+                    f.href("/double")
+                        .http_post(|Json(n): Json<i64>| async move { Json(n * 2) })
+                })
+            })
+            .event("changed", |b| {
+                b.form(|f| {
+                    f.href("/changed")
+                        .http_get(|State((_, events)): State<AppState>| async move {
+                            Json(events.subscribe().recv().await.unwrap())
+                        })
+                        .op(wot_td::thing::FormOperation::SubscribeEvent)
+                })
+            })
+            .build_servient()
+            .unwrap();
+
+        let checks = async {
+            let url = format!("http://{addr}/");
+            let thing = ConsumedThing::fetch(&url).await.unwrap();
+
+            assert!(matches!(
+                thing.read_property::<i64>("level").await,
+                Err(Error::MissingCredentials(scheme)) if scheme == "basic"
+            ));
+            assert!(matches!(
+                thing.read_property::<i64>("missing").await,
+                Err(Error::UnknownAffordance(AffordanceType::Property, _))
+            ));
+
+            let thing = thing.credentials(
+                "basic",
+                Credentials::Basic {
+                    username: "user".into(),
+                    password: "pass".into(),
+                },
+            );
+            assert_eq!(thing.read_property::<i64>("level").await.unwrap(), 5);
+            thing.write_property("level", &7).await.unwrap();
+            assert_eq!(thing.read_property::<i64>("level").await.unwrap(), 7);
+            assert!(matches!(
+                thing.write_property("reading", &"kelvin").await,
+                Err(Error::NoForm(..))
+            ));
+
+            let options = InteractionOptions::default().uri_variable("unit", "celsius");
+            let unit: String = thing.read_property_with("reading", &options).await.unwrap();
+            assert_eq!(unit, "celsius");
+
+            let doubled: i64 = thing.invoke_action("double", &21).await.unwrap();
+            assert_eq!(doubled, 42);
+
+            let mut changes = thing.subscribe_event::<u8>("changed").await.unwrap();
+            for value in [1, 2] {
+                while events.receiver_count() == 0 {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                events.send(value).unwrap();
+                assert_eq!(changes.next().await.unwrap().unwrap(), value);
+            }
+        };
+
+        serve_until(&servient, listener, checks).await;
+    }
+}
@@ -0,0 +1,374 @@
+//! Thing Directory interaction
+//!
+//! A Thing may [register](https://www.w3.org/TR/wot-discovery/#exploration-directory-api-registration)
+//! its own Thing Description with a remote Thing Directory, so Consumers that cannot rely on
+//! DNS-SD may still find it. The directory may be known in advance or found through
+//! [DNS-SD](crate::discovery).
+//!
+//! The registration is kept alive for as long as the [`Servient`] is served.
+//!
+//! A [`ThingDirectory`] may be served as well, to collect the registrations of the Things on
+//! the local network.
+//!
+//! [`Servient`]: crate::Servient
+
+use std::time::Duration;
+
+use reqwest::{Client, StatusCode, Url};
+use serde_json::Value;
+use tokio::sync::watch;
+
+use crate::{
+    advertise::{Advertiser, ThingType},
+    discovery::Event,
+};
+
+mod server;
+
+pub use server::*;
+
+/// Error type for the module
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// The directory url cannot be used as base for the registration.
+    #[error("invalid directory url {0}")]
+    Url(String),
+    /// Error browsing for directories.
+    #[error("discovery error {0}")]
+    Discovery(#[from] crate::advertise::Error),
+    /// Error communicating with the directory.
+    #[error("http client error {0}")]
+    Http(#[from] reqwest::Error),
+    /// The directory refused the request.
+    #[error("the directory replied with status {0}")]
+    Status(StatusCode),
+    /// The time-to-live of the registration is zero.
+    #[error("the registration time-to-live must not be zero")]
+    ZeroTtl,
+}
+
+/// Result type for the module
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Default time-to-live of a registration.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60);
+
+/// Thing Directory to register with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Directory {
+    /// The directory at the given url.
+    Url(String),
+    /// The directories advertised through DNS-SD.
+    ///
+    /// The most recently announced directory is used, the registration moves to
+    /// another one when it disappears or changes address.
+    Discover,
+}
+
+/// Registration of a Thing Description with a Thing Directory
+///
+/// The Thing Description is stored with a `PUT` on `things/{id}`, relative to the directory url,
+/// and removed with a `DELETE` on the same resource.
+#[derive(Debug, Clone)]
+pub struct Registration {
+    client: Client,
+    directory: Directory,
+    ttl: Duration,
+    base: String,
+    id: String,
+}
+
+impl Registration {
+    /// Prepare the registration of the Thing `id` with `directory`.
+    ///
+    /// `base` is used to resolve the relative Forms when the Thing Description does not
+    /// provide one. The time-to-live is advertised in whole seconds, rounded up, and must
+    /// not be zero.
+    pub fn new(
+        directory: Directory,
+        id: impl Into<String>,
+        base: impl Into<String>,
+        ttl: Duration,
+    ) -> Result<Self> {
+        let id = id.into();
+
+        if ttl.is_zero() {
+            return Err(Error::ZeroTtl);
+        }
+
+        if let Directory::Url(url) = &directory {
+            endpoint(url, &id)?;
+        }
+
+        Ok(Self {
+            client: Client::new(),
+            directory,
+            ttl,
+            base: base.into(),
+            id,
+        })
+    }
+
+    /// The directory the Thing registers with.
+    pub fn directory(&self) -> &Directory {
+        &self.directory
+    }
+
+    /// The url of the Thing Description within the directory at `directory`.
+    pub fn url(&self, directory: &str) -> Result<Url> {
+        endpoint(directory, &self.id)
+    }
+
+    /// The time-to-live of the registration.
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Store or update the Thing Description at `url`.
+    pub async fn put(&self, url: &Url, td: &Value) -> Result<()> {
+        let td = self.enrich(td);
+
+        let res = self
+            .client
+            .put(url.clone())
+            .header(reqwest::header::CONTENT_TYPE, "application/td+json")
+            .body(serde_json::to_vec(&td).expect("a json value is always serializable"))
+            .send()
+            .await?;
+
+        check_status(res.status())
+    }
+
+    /// Remove the Thing Description at `url`.
+    pub async fn delete(&self, url: &Url) -> Result<()> {
+        let res = self.client.delete(url.clone()).send().await?;
+
+        check_status(res.status())
+    }
+
+    /// Register the Thing Description with a known directory.
+    ///
+    /// Directories found through DNS-SD are registered with once [`Registration::run`] starts.
+    pub(crate) async fn register(&self, td: &Value) -> Result<()> {
+        match &self.directory {
+            Directory::Url(url) => self.put(&self.url(url)?, td).await,
+            Directory::Discover => Ok(()),
+        }
+    }
+
+    /// Keep the registration alive until `shutdown` resolves, then remove it.
+    ///
+    /// The Thing Description is registered again once half of its time-to-live elapsed or
+    /// as soon as it changes.
+    pub(crate) async fn run(
+        &self,
+        sd: &Advertiser,
+        mut td: watch::Receiver<Value>,
+        shutdown: impl std::future::Future<Output = ()>,
+    ) -> Result<()> {
+        tokio::pin!(shutdown);
+
+        let (mut current, mut browser) = match &self.directory {
+            Directory::Url(url) => (Some((String::new(), self.url(url)?)), None),
+            Directory::Discover => (None, Some(sd.browse(ThingType::Directory)?)),
+        };
+        let mut known = Vec::new();
+
+        // Created once so that the browse events and the changes do not delay the refreshes.
+        let period = Duration::from_secs(self.ttl_secs()) / 2;
+        let mut refresh = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        refresh.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            let next = async {
+                match browser.as_mut() {
+                    Some(browser) => browser.next().await,
+                    None => std::future::pending().await,
+                }
+            };
+
+            tokio::select! {
+                _ = &mut shutdown => break,
+                _ = refresh.tick() => {}
+                changed = td.changed() => {
+                    if changed.is_err() {
+                        break;
+                    }
+                }
+                ev = next => {
+                    let Some(ev) = ev else {
+                        browser = None;
+                        continue;
+                    };
+
+                    let previous = current.clone();
+                    match ev {
+                        Event::Found(dir) => {
+                            known.retain(|(name, _)| name != &dir.fullname);
+                            if let Some(url) = dir.base_url().and_then(|url| self.url(&url).ok()) {
+                                known.push((dir.fullname, url));
+                            }
+                        }
+                        Event::Lost(fullname) => known.retain(|(name, _)| name != &fullname),
+                    }
+                    current = known.last().cloned();
+
+                    if current == previous {
+                        continue;
+                    }
+
+                    // Leave the previous directory if it is still around.
+                    if let Some((name, url)) = previous {
+                        if known.iter().any(|(n, _)| n == &name) {
+                            let _ = self.delete(&url).await;
+                        }
+                    }
+                }
+            }
+
+            // A failed refresh is retried at the next round, meanwhile any other known
+            // directory accepting the registration is used.
+            if let Some((_, url)) = &current {
+                let current_td = td.borrow().clone();
+                if let Err(err) = self.put(url, &current_td).await {
+                    tracing::warn!(%url, %err, "registration not refreshed");
+
+                    for candidate in known.iter().rev() {
+                        if Some(candidate) == current.as_ref() {
+                            continue;
+                        }
+
+                        match self.put(&candidate.1, &current_td).await {
+                            Ok(()) => {
+                                current = Some(candidate.clone());
+                                break;
+                            }
+                            Err(err) => {
+                                tracing::warn!(url = %candidate.1, %err, "registration refused");
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        match current {
+            Some((_, url)) => self.delete(&url).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Complete the Thing Description with the information the directory needs.
+    fn enrich(&self, td: &Value) -> Value {
+        let mut td = td.clone();
+
+        if let Some(obj) = td.as_object_mut() {
+            obj.entry("id").or_insert_with(|| self.id.clone().into());
+            obj.entry("base")
+                .or_insert_with(|| self.base.clone().into());
+            obj.insert(
+                "registration".to_string(),
+                serde_json::json!({ "ttl": self.ttl_secs() }),
+            );
+        }
+
+        td
+    }
+
+    /// The time-to-live in whole seconds, rounded up.
+    fn ttl_secs(&self) -> u64 {
+        self.ttl.as_secs() + u64::from(self.ttl.subsec_nanos() > 0)
+    }
+}
+
+fn endpoint(directory: &str, id: &str) -> Result<Url> {
+    let mut url = Url::parse(directory).map_err(|_| Error::Url(directory.to_string()))?;
+
+    url.path_segments_mut()
+        .map_err(|_| Error::Url(directory.to_string()))?
+        .pop_if_empty()
+        .extend(["things", id]);
+
+    Ok(url)
+}
+
+fn check_status(status: StatusCode) -> Result<()> {
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(Error::Status(status))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn registration_url() {
+        let r = Registration::new(
+            Directory::Discover,
+            "urn:dev:test",
+            "http://host:8080/",
+            DEFAULT_TTL,
+        )
+        .unwrap();
+
+        assert_eq!(
+            r.url("http://localhost:8081/").unwrap().as_str(),
+            "http://localhost:8081/things/urn:dev:test"
+        );
+        assert_eq!(
+            r.url("http://localhost:8081/dir").unwrap().as_str(),
+            "http://localhost:8081/dir/things/urn:dev:test"
+        );
+
+        assert!(Registration::new(
+            Directory::Url("not a url".into()),
+            "urn:dev:test",
+            "http://host:8080/",
+            DEFAULT_TTL,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn registration_enrich() {
+        let r = Registration::new(
+            Directory::Url("http://localhost:8081/".into()),
+            "urn:dev:test",
+            "http://host:8080/",
+            DEFAULT_TTL,
+        )
+        .unwrap();
+
+        let td = r.enrich(&serde_json::json!({ "title": "test" }));
+
+        assert_eq!(td["id"], "urn:dev:test");
+        assert_eq!(td["base"], "http://host:8080/");
+        assert_eq!(td["registration"]["ttl"], 60);
+    }
+
+    #[test]
+    fn registration_ttl() {
+        let register = |ttl| {
+            Registration::new(
+                Directory::Discover,
+                "urn:dev:test",
+                "http://host:8080/",
+                ttl,
+            )
+        };
+
+        assert!(matches!(register(Duration::ZERO), Err(Error::ZeroTtl)));
+
+        let td = serde_json::json!({ "title": "test" });
+        for (ttl, secs) in [
+            (Duration::from_millis(1), 1),
+            (Duration::from_millis(2500), 3),
+        ] {
+            let r = register(ttl).unwrap();
+            assert_eq!(r.enrich(&td)["registration"]["ttl"], secs);
+        }
+    }
+}
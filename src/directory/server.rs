@@ -0,0 +1,406 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Bytes,
+    extract::Path,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::Value;
+use uuid::Uuid;
+use wot_td::{
+    builder::{
+        affordance::BuildableInteractionAffordance,
+        data_schema::{ReadableWriteableDataSchema, SpecializableDataSchema},
+        Extended, ThingBuilder,
+    },
+    thing::FormOperation,
+};
+
+use crate::{
+    advertise::ThingType,
+    hlist::NilPlus,
+    servient::{HttpRouter, ServientExtension, ServientSettings},
+    Servient,
+};
+
+/// A stored Thing Description
+#[derive(Debug, Clone)]
+struct Entry {
+    td: Value,
+    expires: Option<Instant>,
+}
+
+/// In-memory Thing Directory
+///
+/// It implements the [Directory Things API](https://www.w3.org/TR/wot-discovery/#exploration-directory-api-things)
+/// over http: Thing Descriptions are stored with a `PUT` on `/things/{id}` or, if anonymous,
+/// with a `POST` on `/things`, and listed by reading the `things` property.
+///
+/// Registrations carrying a `registration.ttl` expire once it elapses.
+#[derive(Debug, Clone, Default)]
+pub struct ThingDirectory {
+    things: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+impl ThingDirectory {
+    /// Create an empty directory.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The Thing Description stored as `id`.
+    pub fn get(&self, id: &str) -> Option<Value> {
+        self.purge();
+
+        self.things.lock().unwrap().get(id).map(|e| e.td.clone())
+    }
+
+    /// The stored Thing Descriptions.
+    pub fn things(&self) -> Vec<Value> {
+        self.purge();
+
+        let things = self.things.lock().unwrap();
+        let mut ids: Vec<_> = things.keys().collect();
+        ids.sort();
+
+        ids.into_iter().map(|id| things[id].td.clone()).collect()
+    }
+
+    /// Store a Thing Description, returns whether it replaced another one.
+    ///
+    /// A `registration.ttl` that is not a valid duration is ignored.
+    pub fn insert(&self, id: impl Into<String>, td: Value) -> bool {
+        let expires = expiration(&td).unwrap_or_default();
+
+        self.store(id.into(), td, expires)
+    }
+
+    fn store(&self, id: String, td: Value, expires: Option<Instant>) -> bool {
+        self.purge();
+
+        self.things
+            .lock()
+            .unwrap()
+            .insert(id, Entry { td, expires })
+            .is_some()
+    }
+
+    /// Remove a Thing Description, returns whether it was stored.
+    pub fn remove(&self, id: &str) -> bool {
+        self.purge();
+
+        self.things.lock().unwrap().remove(id).is_some()
+    }
+
+    /// Drop the expired registrations.
+    fn purge(&self) {
+        let now = Instant::now();
+
+        self.things
+            .lock()
+            .unwrap()
+            .retain(|_, e| e.expires.is_none_or(|expires| expires > now));
+    }
+
+    /// Prepare a [`Servient`] builder exposing the directory.
+    ///
+    /// The Servient is advertised as [`ThingType::Directory`].
+    pub fn builder(
+        &self,
+        title: impl Into<String>,
+    ) -> ThingBuilder<NilPlus<ServientExtension>, Extended> {
+        let list = self.clone();
+        let create = self.clone();
+        let retrieve = self.clone();
+        let update = self.clone();
+        let patch = self.clone();
+        let delete = self.clone();
+
+        Servient::builder(title)
+            .finish_extend()
+            .thing_type(ThingType::Directory)
+            .property("things", |b| {
+                b.finish_extend_data_schema()
+                    .form(|f| {
+                        f.href("/things")
+                            .http_get(move || async move { Json(list.things()) })
+                            .op(FormOperation::ReadProperty)
+                    })
+                    .array()
+                    .read_only()
+            })
+            .action("createThing", |b| {
+                b.input(|i| i.finish_extend().object()).form(|f| {
+                    f.href("/things")
+                        .http_post(move |body: Bytes| async move { create.create(&body) })
+                })
+            })
+            .action("retrieveThing", |b| {
+                b.output(|o| o.finish_extend().object())
+                    .form(|f| {
+                        f.href("/things/{id}")
+                            .http_get(move |Path(id): Path<String>| async move {
+                                match retrieve.get(&id) {
+                                    Some(td) => Json(td).into_response(),
+                                    None => StatusCode::NOT_FOUND.into_response(),
+                                }
+                            })
+                    })
+                    .uri_variable("id", |b| b.finish_extend().string())
+            })
+            .action("updateThing", |b| {
+                b.input(|i| i.finish_extend().object())
+                    .form(|f| {
+                        f.href("/things/{id}").http_put(
+                            move |Path(id): Path<String>, body: Bytes| async move {
+                                update.update(id, &body)
+                            },
+                        )
+                    })
+                    .uri_variable("id", |b| b.finish_extend().string())
+            })
+            .action("partiallyUpdateThing", |b| {
+                b.input(|i| i.finish_extend().object())
+                    .form(|f| {
+                        f.href("/things/{id}").http_patch(
+                            move |Path(id): Path<String>, body: Bytes| async move {
+                                patch.patch(&id, &body)
+                            },
+                        )
+                    })
+                    .uri_variable("id", |b| b.finish_extend().string())
+            })
+            .action("deleteThing", |b| {
+                b.form(|f| {
+                    f.href("/things/{id}")
+                        .http_delete(move |Path(id): Path<String>| async move {
+                            if delete.remove(&id) {
+                                StatusCode::NO_CONTENT
+                            } else {
+                                StatusCode::NOT_FOUND
+                            }
+                        })
+                })
+                .uri_variable("id", |b| b.finish_extend().string())
+            })
+    }
+
+    fn create(&self, body: &[u8]) -> Response {
+        let Ok(mut td @ Value::Object(_)) = serde_json::from_slice::<Value>(body) else {
+            return StatusCode::BAD_REQUEST.into_response();
+        };
+
+        // Only anonymous Thing Descriptions are created, the others are stored by id.
+        if td.get("id").is_some() {
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+
+        let Some(expires) = expiration(&td) else {
+            return StatusCode::BAD_REQUEST.into_response();
+        };
+
+        let id = format!("urn:uuid:{}", Uuid::new_v4().as_hyphenated());
+        td["id"] = id.clone().into();
+        self.store(id.clone(), td, expires);
+
+        (
+            StatusCode::CREATED,
+            [(header::LOCATION, format!("/things/{id}"))],
+        )
+            .into_response()
+    }
+
+    fn update(&self, id: String, body: &[u8]) -> StatusCode {
+        let Ok(td @ Value::Object(_)) = serde_json::from_slice::<Value>(body) else {
+            return StatusCode::BAD_REQUEST;
+        };
+        let Some(expires) = expiration(&td) else {
+            return StatusCode::BAD_REQUEST;
+        };
+
+        if self.store(id, td, expires) {
+            StatusCode::NO_CONTENT
+        } else {
+            StatusCode::CREATED
+        }
+    }
+
+    fn patch(&self, id: &str, body: &[u8]) -> StatusCode {
+        let Ok(patch @ Value::Object(_)) = serde_json::from_slice::<Value>(body) else {
+            return StatusCode::BAD_REQUEST;
+        };
+        let Some(mut td) = self.get(id) else {
+            return StatusCode::NOT_FOUND;
+        };
+
+        merge_patch(&mut td, patch);
+        let Some(expires) = expiration(&td) else {
+            return StatusCode::BAD_REQUEST;
+        };
+        self.store(id.to_string(), td, expires);
+
+        StatusCode::NO_CONTENT
+    }
+}
+
+/// When the registration of the Thing Description expires, from its `registration.ttl` in
+/// seconds.
+///
+/// `None` if the ttl is not a valid duration.
+fn expiration(td: &Value) -> Option<Option<Instant>> {
+    let Some(ttl) = td.pointer("/registration/ttl") else {
+        return Some(None);
+    };
+
+    ttl.as_f64()
+        .and_then(|ttl| Duration::try_from_secs_f64(ttl).ok())
+        .and_then(|ttl| Instant::now().checked_add(ttl))
+        .map(Some)
+}
+
+/// Apply a [JSON Merge Patch](https://www.rfc-editor.org/rfc/rfc7396).
+fn merge_patch(target: &mut Value, patch: Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch;
+        return;
+    };
+
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+    let target = target.as_object_mut().expect("just made an object");
+
+    for (k, v) in patch {
+        if v.is_null() {
+            target.remove(&k);
+        } else {
+            merge_patch(target.entry(k).or_insert(Value::Null), v);
+        }
+    }
+}
+
+#[cfg(all(test, not(miri)))]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::servient::BuildServient;
+    use crate::test_util::{local_listener, serve_until};
+
+    #[test]
+    fn merge_patch_td() {
+        let mut td = json!({ "title": "a", "description": "b", "links": [1] });
+
+        merge_patch(
+            &mut td,
+            json!({ "title": "c", "description": null, "links": [2] }),
+        );
+
+        assert_eq!(td, json!({ "title": "c", "links": [2] }));
+    }
+
+    #[tokio::test]
+    async fn serve_directory() {
+        let (listener, addr) = local_listener();
+
+        let directory = ThingDirectory::new();
+        let servient = directory
+            .builder("directory")
+            .http_bind(addr)
+            .build_servient()
+            .unwrap();
+
+        assert_eq!(servient.thing_type, ThingType::Directory);
+
+        let checks = async {
+            let client = reqwest::Client::new();
+            let url = |path: &str| format!("http://{addr}{path}");
+
+            let res = client
+                .put(url("/things/urn:dev:a"))
+                .body(r#"{ "title": "a" }"#)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::CREATED);
+
+            let res = client
+                .patch(url("/things/urn:dev:a"))
+                .body(r#"{ "description": "patched" }"#)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::NO_CONTENT);
+
+            let td: Value = client
+                .get(url("/things/urn:dev:a"))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            assert_eq!(td, json!({ "title": "a", "description": "patched" }));
+
+            for ttl in ["1e300", "-1", "\"soon\""] {
+                let res = client
+                    .patch(url("/things/urn:dev:a"))
+                    .body(format!(r#"{{ "registration": {{ "ttl": {ttl} }} }}"#))
+                    .send()
+                    .await
+                    .unwrap();
+                assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+            }
+
+            let res = client
+                .post(url("/things"))
+                .body(r#"{ "title": "anonymous" }"#)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::CREATED);
+            let location = res.headers()[reqwest::header::LOCATION].to_str().unwrap();
+            assert!(location.starts_with("/things/urn:uuid:"));
+
+            let things: Vec<Value> = client
+                .get(url("/things"))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            assert_eq!(things.len(), 2);
+
+            let res = client
+                .delete(url("/things/urn:dev:a"))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::NO_CONTENT);
+            assert!(directory.get("urn:dev:a").is_none());
+        };
+
+        serve_until(&servient, listener, checks).await;
+    }
+
+    #[test]
+    fn expire_registration() {
+        let directory = ThingDirectory::new();
+
+        directory.insert("urn:dev:a", json!({ "registration": { "ttl": 0 } }));
+        directory.insert("urn:dev:b", json!({ "registration": { "ttl": 60 } }));
+
+        assert!(directory.get("urn:dev:a").is_none());
+        assert!(directory.get("urn:dev:b").is_some());
+
+        directory.insert("urn:dev:c", json!({ "registration": { "ttl": 1e300 } }));
+        assert!(directory.get("urn:dev:c").is_some());
+    }
+}
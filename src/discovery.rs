@@ -0,0 +1,197 @@
+//! Service Discovery
+//!
+//! Find the Things and the Thing Directories advertised through
+//! [DNS-SD](https://www.w3.org/TR/wot-discovery/#introduction-dns-sd), as done by the
+//! [`Advertiser`].
+
+use std::{net::Ipv4Addr, time::Duration};
+
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use tokio::sync::mpsc;
+
+use crate::advertise::{Advertiser, Result, ThingType, WELL_KNOWN};
+
+/// A Thing advertised on the network
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredThing {
+    /// Full DNS-SD name of the service
+    pub fullname: String,
+    /// Type of the advertised Thing
+    pub thing_type: ThingType,
+    /// Hostname of the Thing
+    pub hostname: String,
+    /// Addresses the Thing is reachable at
+    pub addresses: Vec<Ipv4Addr>,
+    /// Listening port
+    pub port: u16,
+    /// Path of the Thing Description
+    pub path: String,
+}
+
+impl DiscoveredThing {
+    fn from_info(info: &ServiceInfo) -> Self {
+        let props = info.get_properties();
+        let thing_type = match props.get_property_val_str("type") {
+            Some("Directory") => ThingType::Directory,
+            _ => ThingType::Thing,
+        };
+        let path = props
+            .get_property_val_str("td")
+            .unwrap_or(WELL_KNOWN)
+            .to_string();
+
+        let mut addresses: Vec<_> = info.get_addresses().iter().copied().collect();
+        addresses.sort();
+
+        Self {
+            fullname: info.get_fullname().to_string(),
+            thing_type,
+            hostname: info.get_hostname().trim_end_matches('.').to_string(),
+            addresses,
+            port: info.get_port(),
+            path,
+        }
+    }
+
+    /// The instance name of the service.
+    pub fn name(&self) -> &str {
+        self.fullname
+            .split_once('.')
+            .map_or(&self.fullname, |(name, _)| name)
+    }
+
+    /// Base url of the Thing, using its first address.
+    pub fn base_url(&self) -> Option<String> {
+        self.addresses
+            .first()
+            .map(|ip| format!("http://{}:{}/", ip, self.port))
+    }
+
+    /// Url of the Thing Description, using its first address.
+    pub fn td_url(&self) -> Option<String> {
+        self.addresses.first().map(|ip| {
+            let path = self.path.trim_start_matches('/');
+            format!("http://{}:{}/{}", ip, self.port, path)
+        })
+    }
+}
+
+/// Discovery event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// A Thing has been found or its advertisement changed.
+    Found(DiscoveredThing),
+    /// A Thing is not advertised anymore, by full name.
+    Lost(String),
+}
+
+/// Stream of [`Event`]s for a [`ThingType`]
+///
+/// Call [`Advertiser::browse`] to get one.
+pub struct Browser {
+    rx: mpsc::UnboundedReceiver<Event>,
+}
+
+impl Browser {
+    pub(crate) fn new(mdns: &ServiceDaemon, ty: ThingType) -> Result<Self> {
+        let events = mdns.browse(&format!("{}._tcp.local.", ty.to_service_type()))?;
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        // The mdns daemon provides a blocking channel, forward it from a thread until the
+        // Browser is dropped.
+        std::thread::spawn(move || {
+            while !tx.is_closed() {
+                let ev = match events.recv_timeout(Duration::from_secs(1)) {
+                    Ok(ServiceEvent::ServiceResolved(info)) => {
+                        Event::Found(DiscoveredThing::from_info(&info))
+                    }
+                    Ok(ServiceEvent::ServiceRemoved(_, fullname)) => Event::Lost(fullname),
+                    Ok(_) => continue,
+                    Err(_) if events.is_disconnected() => break,
+                    Err(_) => continue,
+                };
+
+                if tx.send(ev).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self { rx })
+    }
+
+    /// Wait for the next event.
+    ///
+    /// Returns `None` once the mdns daemon shut down.
+    pub async fn next(&mut self) -> Option<Event> {
+        self.rx.recv().await
+    }
+
+    /// Collect the Things found within `timeout`.
+    pub async fn collect(mut self, timeout: Duration) -> Vec<DiscoveredThing> {
+        let mut found: Vec<DiscoveredThing> = Vec::new();
+
+        let _ = tokio::time::timeout(timeout, async {
+            while let Some(ev) = self.next().await {
+                match ev {
+                    Event::Found(thing) => {
+                        found.retain(|t| t.fullname != thing.fullname);
+                        found.push(thing);
+                    }
+                    Event::Lost(fullname) => found.retain(|t| t.fullname != fullname),
+                }
+            }
+        })
+        .await;
+
+        found
+    }
+}
+
+impl Advertiser {
+    /// Browse the network for Things of the given type.
+    pub fn browse(&self, ty: ThingType) -> Result<Browser> {
+        Browser::new(&self.mdns, ty)
+    }
+}
+
+#[cfg(all(test, not(miri)))]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn browse_directory() {
+        let ad = Advertiser::new().unwrap();
+
+        ad.add_service("TestBrowseDirectory")
+            .thing_type(ThingType::Directory)
+            .port(8123)
+            .path("/td")
+            .build()
+            .unwrap();
+
+        let mut browser = ad.browse(ThingType::Directory).unwrap();
+
+        let found = tokio::time::timeout(Duration::from_secs(2), async {
+            while let Some(ev) = browser.next().await {
+                match ev {
+                    Event::Found(thing) if thing.name() == "TestBrowseDirectory" => {
+                        return Some(thing)
+                    }
+                    _ => {}
+                }
+            }
+            None
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(found.thing_type, ThingType::Directory);
+        assert_eq!(found.port, 8123);
+        assert_eq!(found.path, "/td");
+        if let Some(ip) = found.addresses.first() {
+            assert_eq!(found.td_url().unwrap(), format!("http://{ip}:8123/td"));
+        }
+    }
+}
@@ -0,0 +1,725 @@
+//! Things exposed through the WoT Scripting API
+//!
+//! An [`ExposedThing`] serves a Thing Description through handlers exchanging json values,
+//! set and replaced at any time as in the [Scripting API](https://www.w3.org/TR/wot-scripting-api/).
+//! The handlers do not depend on the protocol, every binding configured on the [`Servient`]
+//! builder routes to them.
+//!
+//! - property reads and writes, action invocations are routed to the handlers set, the
+//!   operations without one are not supported.
+//! - the property changes are delivered on [`ExposedThing::emit_property_change`], through
+//!   the Forms of the description or the ones a [`PropertyNotifier`] adds.
+//! - the events are delivered through long polling on [`ExposedThing::emit_event`].
+//!
+//! ```no_run
+//! use serde_json::json;
+//! use wot_serve::{exposed::ExposedThing, servient::ServientSettings};
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let td = std::fs::read_to_string("lamp.td.json")?;
+//! let lamp = ExposedThing::from_td(&td)?
+//!     .configure(|builder| builder.http_bind("0.0.0.0:8080".parse().unwrap()));
+//!
+//! lamp.set_property_read_handler("on", |_| async { Ok(json!(true)) })?;
+//! lamp.expose()?;
+//!
+//! lamp.emit_event("overheating", 90.5)?;
+//!
+//! lamp.destroy().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Servient`]: crate::Servient
+//! [`PropertyNotifier`]: crate::servient::PropertyNotifier
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use axum::{
+    body::Bytes,
+    extract::Query,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tokio::{
+    runtime::Handle,
+    sync::{broadcast, oneshot},
+    task::JoinHandle,
+};
+use wot_td::{
+    builder::{AffordanceType, Extended, ThingBuilder},
+    thing::{FormOperation, Thing},
+};
+
+use crate::{
+    consumer::InteractionOptions,
+    hlist::NilPlus,
+    servient::{
+        build_servient, description_builder, AffordanceRouter, PropertyNotifier, ServientExtension,
+        ServientSettings,
+    },
+    simulator::{affordances, declared_ops, default_ops, DEFAULT_OPS},
+};
+
+/// Error type for the module
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// The Thing Description cannot be parsed.
+    #[error("invalid thing description {0}")]
+    Description(#[from] serde_json::Error),
+
+    /// The affordance is not present in the Thing Description.
+    #[error("no {0} named {1}")]
+    UnknownAffordance(AffordanceType, String),
+
+    /// No handler is set for the operation.
+    #[error("the {0} {1} has no handler for {2}")]
+    NotSupported(AffordanceType, String, FormOperation),
+
+    /// A handler rejects the value it is given.
+    #[error("invalid value {0}")]
+    InvalidValue(String),
+
+    /// A handler fails.
+    #[error("handler error {0}")]
+    Handler(String),
+
+    /// [`ExposedThing::expose`] is called on a Thing already exposed.
+    #[error("the thing is already exposed")]
+    Exposed,
+
+    /// [`ExposedThing::destroy`] is called on a Thing not exposed.
+    #[error("the thing is not exposed")]
+    NotExposed,
+
+    /// The [`Servient`] cannot be built from the configured builder.
+    ///
+    /// [`Servient`]: crate::Servient
+    #[error("cannot build the servient {0}")]
+    Build(#[from] Box<dyn std::error::Error + Send + Sync>),
+
+    /// The http address of the [`Servient`] cannot be bound.
+    ///
+    /// [`Servient`]: crate::Servient
+    #[error("cannot bind the http address {0}")]
+    Bind(#[from] std::io::Error),
+
+    /// [`ExposedThing::expose`] is called outside of a tokio runtime.
+    #[error("no runtime to serve the thing {0}")]
+    Runtime(#[from] tokio::runtime::TryCurrentError),
+
+    /// The [`Servient`] stops with an error.
+    ///
+    /// [`Servient`]: crate::Servient
+    #[error("servient error {0}")]
+    Servient(#[from] crate::servient::Error),
+}
+
+/// Result type for the module
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Error::UnknownAffordance(..) => StatusCode::NOT_FOUND,
+            Error::NotSupported(..) => StatusCode::NOT_IMPLEMENTED,
+            Error::InvalidValue(_) | Error::Description(_) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        (status, self.to_string()).into_response()
+    }
+}
+
+/// [`Servient`] builder of an [`ExposedThing`]
+///
+/// [`Servient`]: crate::Servient
+pub type Builder = ThingBuilder<NilPlus<ServientExtension>, Extended>;
+
+type Reply<T> = Pin<Box<dyn Future<Output = Result<T>> + Send>>;
+type ReadHandler = Arc<dyn Fn(InteractionOptions) -> Reply<Value> + Send + Sync>;
+type WriteHandler = Arc<dyn Fn(Value, InteractionOptions) -> Reply<()> + Send + Sync>;
+type ActionHandler = Arc<dyn Fn(Value, InteractionOptions) -> Reply<Value> + Send + Sync>;
+/// URI variables of a request
+type Variables = Query<Map<String, Value>>;
+type Settings = Arc<dyn Fn(Builder) -> Builder + Send + Sync>;
+
+/// Handlers and notifications shared by the routes
+struct Handlers {
+    reads: RwLock<HashMap<String, ReadHandler>>,
+    writes: RwLock<HashMap<String, WriteHandler>>,
+    actions: RwLock<HashMap<String, ActionHandler>>,
+    changes: PropertyNotifier,
+    events: broadcast::Sender<(String, Value)>,
+}
+
+/// Servient running on the runtime of [`ExposedThing::expose`]
+struct Running {
+    stop: oneshot::Sender<()>,
+    done: JoinHandle<std::result::Result<(), crate::servient::Error>>,
+}
+
+/// Thing served through protocol independent handlers
+///
+/// Dropping it stops serving the Thing.
+pub struct ExposedThing {
+    td: Value,
+    settings: Settings,
+    handlers: Arc<Handlers>,
+    running: Mutex<Option<Running>>,
+}
+
+impl std::fmt::Debug for ExposedThing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExposedThing")
+            .field("td", &self.td)
+            .field("exposed", &self.running.lock().unwrap().is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl ExposedThing {
+    /// Expose the Thing Description `td`.
+    pub fn from_td(td: &str) -> Result<Self> {
+        Self::from_json(serde_json::from_str(td)?)
+    }
+
+    /// Expose the Thing Description `td`, as json.
+    pub fn from_json(td: Value) -> Result<Self> {
+        // Check the description is well formed before taking it apart.
+        serde_json::from_value::<Thing>(td.clone())?;
+
+        let (events, _) = broadcast::channel(16);
+
+        Ok(Self {
+            td,
+            settings: Arc::new(|builder| builder),
+            handlers: Arc::new(Handlers {
+                reads: Default::default(),
+                writes: Default::default(),
+                actions: Default::default(),
+                changes: PropertyNotifier::new(),
+                events,
+            }),
+            running: Mutex::default(),
+        })
+    }
+
+    /// Configure the [`Servient`] builder, e.g. its bindings, before exposing the Thing.
+    ///
+    /// [`Servient`]: crate::Servient
+    pub fn configure(
+        mut self,
+        settings: impl Fn(Builder) -> Builder + Send + Sync + 'static,
+    ) -> Self {
+        self.settings = Arc::new(settings);
+        self
+    }
+
+    /// The exposed Thing Description.
+    pub fn td(&self) -> &Value {
+        &self.td
+    }
+
+    fn check(&self, affordance: AffordanceType, name: &str) -> Result<()> {
+        let key = DEFAULT_OPS
+            .iter()
+            .find_map(|(a, key, _)| (*a == affordance).then_some(*key))
+            .unwrap_or_default();
+
+        if self.td[key].get(name).is_some() {
+            Ok(())
+        } else {
+            Err(Error::UnknownAffordance(affordance, name.to_string()))
+        }
+    }
+
+    /// Reply to the reads of the property `name` with the value returned by `handler`.
+    pub fn set_property_read_handler<F, Fut>(&self, name: &str, handler: F) -> Result<()>
+    where
+        F: Fn(InteractionOptions) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Value>> + Send + 'static,
+    {
+        self.check(AffordanceType::Property, name)?;
+
+        let handler: ReadHandler = Arc::new(move |options| Box::pin(handler(options)));
+        self.handlers
+            .reads
+            .write()
+            .unwrap()
+            .insert(name.to_string(), handler);
+
+        Ok(())
+    }
+
+    /// Pass the values written to the property `name` to `handler`.
+    pub fn set_property_write_handler<F, Fut>(&self, name: &str, handler: F) -> Result<()>
+    where
+        F: Fn(Value, InteractionOptions) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.check(AffordanceType::Property, name)?;
+
+        let handler: WriteHandler =
+            Arc::new(move |value, options| Box::pin(handler(value, options)));
+        self.handlers
+            .writes
+            .write()
+            .unwrap()
+            .insert(name.to_string(), handler);
+
+        Ok(())
+    }
+
+    /// Invoke `handler` with the input of the action `name`, `null` if none, and reply with
+    /// its output, no content if `null`.
+    pub fn set_action_handler<F, Fut>(&self, name: &str, handler: F) -> Result<()>
+    where
+        F: Fn(Value, InteractionOptions) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Value>> + Send + 'static,
+    {
+        self.check(AffordanceType::Action, name)?;
+
+        let handler: ActionHandler =
+            Arc::new(move |input, options| Box::pin(handler(input, options)));
+        self.handlers
+            .actions
+            .write()
+            .unwrap()
+            .insert(name.to_string(), handler);
+
+        Ok(())
+    }
+
+    /// Deliver `data` to the subscribers of the event `name`, returns how many receive it.
+    pub fn emit_event(&self, name: &str, data: impl Serialize) -> Result<usize> {
+        self.check(AffordanceType::Event, name)?;
+
+        let data = serde_json::to_value(data)?;
+
+        Ok(self
+            .handlers
+            .events
+            .send((name.to_string(), data))
+            .unwrap_or(0))
+    }
+
+    /// Deliver the value of the property `name`, as read by its read handler, to its observers.
+    pub async fn emit_property_change(&self, name: &str) -> Result<usize> {
+        self.check(AffordanceType::Property, name)?;
+
+        let value = self
+            .handlers
+            .read(name, InteractionOptions::default())
+            .await?;
+
+        Ok(self.handlers.changes.notify(name, value)?)
+    }
+
+    /// Prepare the [`Servient`] builder with every operation of the description routed to
+    /// the handlers.
+    ///
+    /// [`Servient`]: crate::Servient
+    pub fn builder(&self) -> Result<Builder> {
+        let mut builder = description_builder(self.td.clone())?
+            .with_state(())
+            .property_notifier(self.handlers.changes.clone());
+
+        for op in declared_ops(self.td.get("forms"), &[]) {
+            builder = bind_thing(builder, op, self.handlers.clone());
+        }
+
+        for (affordance, key, default) in DEFAULT_OPS {
+            for (name, a) in affordances(&self.td, key) {
+                for op in declared_ops(a.get("forms"), &default_ops(a, default)) {
+                    builder = bind_affordance(builder, affordance, name, op, self.handlers.clone());
+                }
+            }
+        }
+
+        Ok((self.settings)(builder))
+    }
+
+    /// Start serving the Thing.
+    ///
+    /// The http address is bound before returning, the [`Servient`] then runs on the tokio
+    /// runtime of the caller until [`ExposedThing::destroy`].
+    ///
+    /// [`Servient`]: crate::Servient
+    pub fn expose(&self) -> Result<()> {
+        let mut running = self.running.lock().unwrap();
+        if running.is_some() {
+            return Err(Error::Exposed);
+        }
+
+        let runtime = Handle::try_current()?;
+        let servient = build_servient(self.builder()?)?;
+        let listener = std::net::TcpListener::bind(servient.http_addr)?;
+
+        let (stop, stopped) = oneshot::channel::<()>();
+        // The Servient is not Sync, serving it borrows it across awaits.
+        let done = runtime.spawn_blocking({
+            let runtime = runtime.clone();
+            move || {
+                runtime.block_on(servient.serve_listener(listener, async move {
+                    let _ = stopped.await;
+                }))
+            }
+        });
+
+        *running = Some(Running { stop, done });
+
+        Ok(())
+    }
+
+    /// Stop serving the Thing, it may be exposed again.
+    pub async fn destroy(&self) -> Result<()> {
+        let Some(Running { stop, done }) = self.running.lock().unwrap().take() else {
+            return Err(Error::NotExposed);
+        };
+
+        let _ = stop.send(());
+        match done.await {
+            Ok(served) => Ok(served?),
+            Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+impl Handlers {
+    async fn read(&self, name: &str, options: InteractionOptions) -> Result<Value> {
+        let handler = self.reads.read().unwrap().get(name).cloned();
+        let Some(handler) = handler else {
+            return Err(Error::NotSupported(
+                AffordanceType::Property,
+                name.to_string(),
+                FormOperation::ReadProperty,
+            ));
+        };
+
+        handler(options).await
+    }
+
+    async fn write(&self, name: &str, value: Value, options: InteractionOptions) -> Result<()> {
+        let handler = self.writes.read().unwrap().get(name).cloned();
+        let Some(handler) = handler else {
+            return Err(Error::NotSupported(
+                AffordanceType::Property,
+                name.to_string(),
+                FormOperation::WriteProperty,
+            ));
+        };
+
+        handler(value, options).await
+    }
+
+    async fn invoke(&self, name: &str, input: Value, options: InteractionOptions) -> Result<Value> {
+        let handler = self.actions.read().unwrap().get(name).cloned();
+        let Some(handler) = handler else {
+            return Err(Error::NotSupported(
+                AffordanceType::Action,
+                name.to_string(),
+                FormOperation::InvokeAction,
+            ));
+        };
+
+        handler(input, options).await
+    }
+
+    async fn read_all(&self) -> Result<Value> {
+        let names: Vec<_> = self.reads.read().unwrap().keys().cloned().collect();
+
+        let mut values = Map::new();
+        for name in names {
+            let value = self.read(&name, InteractionOptions::default()).await?;
+            values.insert(name, value);
+        }
+
+        Ok(Value::Object(values))
+    }
+
+    /// Wait for the next notification named `name` on `channel`.
+    async fn next(channel: &broadcast::Sender<(String, Value)>, name: &str) -> Response {
+        let mut notifications = channel.subscribe();
+
+        loop {
+            match notifications.recv().await {
+                Ok((n, value)) if n == name => return Json(value).into_response(),
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => {
+                    return StatusCode::NO_CONTENT.into_response()
+                }
+            }
+        }
+    }
+}
+
+fn options(Query(uri_variables): Variables) -> InteractionOptions {
+    InteractionOptions { uri_variables }
+}
+
+fn value(body: &[u8]) -> Result<Value> {
+    if body.is_empty() {
+        return Ok(Value::Null);
+    }
+
+    serde_json::from_slice(body).map_err(|err| Error::InvalidValue(err.to_string()))
+}
+
+fn reply(output: Result<Value>) -> Response {
+    match output {
+        Ok(Value::Null) => StatusCode::NO_CONTENT.into_response(),
+        Ok(output) => Json(output).into_response(),
+        Err(err) => err.into_response(),
+    }
+}
+
+fn bind_affordance(
+    builder: Builder,
+    affordance: AffordanceType,
+    name: &str,
+    op: FormOperation,
+    handlers: Arc<Handlers>,
+) -> Builder {
+    use FormOperation::*;
+
+    let n = name.to_string();
+
+    match (affordance, op) {
+        (AffordanceType::Property, ReadProperty) => {
+            builder.on_read_property(name, move |query: Variables| async move {
+                match handlers.read(&n, options(query)).await {
+                    Ok(value) => Json(value).into_response(),
+                    Err(err) => err.into_response(),
+                }
+            })
+        }
+        (AffordanceType::Property, WriteProperty) => {
+            builder.on_write_property(name, move |query: Variables, body: Bytes| async move {
+                let written = match value(&body) {
+                    Ok(value) => handlers.write(&n, value, options(query)).await,
+                    Err(err) => Err(err),
+                };
+                match written {
+                    Ok(()) => StatusCode::NO_CONTENT.into_response(),
+                    Err(err) => err.into_response(),
+                }
+            })
+        }
+        (AffordanceType::Property, ObserveProperty) => {
+            builder.on_observe_property(name, move || async move {
+                let mut changes = handlers.changes.subscribe_property(&n);
+                match PropertyNotifier::next(&mut changes).await {
+                    Some(value) => Json(value).into_response(),
+                    None => StatusCode::NO_CONTENT.into_response(),
+                }
+            })
+        }
+        (AffordanceType::Property, UnobserveProperty) => {
+            builder.on_unobserve_property(name, no_content)
+        }
+        (AffordanceType::Action, InvokeAction) => {
+            builder.on_invoke_action(name, move |query: Variables, body: Bytes| async move {
+                match value(&body) {
+                    Ok(input) => reply(handlers.invoke(&n, input, options(query)).await),
+                    Err(err) => err.into_response(),
+                }
+            })
+        }
+        (AffordanceType::Action, QueryAction) => builder.on_query_action(name, not_supported),
+        (AffordanceType::Action, CancelAction) => builder.on_cancel_action(name, not_supported),
+        (AffordanceType::Event, SubscribeEvent) => builder
+            .on_subscribe_event(name, move || async move {
+                Handlers::next(&handlers.events, &n).await
+            }),
+        (AffordanceType::Event, UnsubscribeEvent) => builder.on_unsubscribe_event(name, no_content),
+        // Leave the inconsistent operations to the Servient checks.
+        _ => builder,
+    }
+}
+
+fn bind_thing(builder: Builder, op: FormOperation, handlers: Arc<Handlers>) -> Builder {
+    use FormOperation::*;
+
+    match op {
+        ReadAllProperties => builder.on_thing_operation(op, move || async move {
+            match handlers.read_all().await {
+                Ok(values) => Json(values).into_response(),
+                Err(err) => err.into_response(),
+            }
+        }),
+        UnobserveAllProperties | UnsubscribeAllEvents => builder.on_thing_operation(op, no_content),
+        _ => builder.on_thing_operation(op, not_supported),
+    }
+}
+
+async fn no_content() -> StatusCode {
+    StatusCode::NO_CONTENT
+}
+
+async fn not_supported() -> StatusCode {
+    StatusCode::NOT_IMPLEMENTED
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use crate::consumer::{self, ConsumedThing};
+    use crate::test_util::local_listener;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn expose_thing() {
+        // The Thing binds the address itself once exposed.
+        let addr = local_listener().1;
+        let td = json!({
+            "@context": "https://www.w3.org/2022/wot/td/v1.1",
+            "title": "lamp",
+            "securityDefinitions": { "nosec_sc": { "scheme": "nosec" } },
+            "security": "nosec_sc",
+            "properties": {
+                "level": {
+                    "type": "integer",
+                    "forms": [{ "href": "/level", "op": ["readproperty", "writeproperty"] }],
+                },
+                "reading": {
+                    "type": "string",
+                    "forms": [{ "href": "/reading{?unit}", "op": "readproperty" }],
+                },
+            },
+            "actions": {
+                "double": { "forms": [{ "href": "/double" }] },
+            },
+            "events": {
+                "changed": { "forms": [{ "href": "/changed", "op": "subscribeevent" }] },
+            },
+        });
+
+        let lamp = ExposedThing::from_json(td)
+            .unwrap()
+            .configure(move |builder| builder.http_bind(addr));
+
+        let level = Arc::new(std::sync::atomic::AtomicI64::new(5));
+        {
+            let level = level.clone();
+            lamp.set_property_read_handler("level", move |_| {
+                let level = level.clone();
+                async move { Ok(level.load(std::sync::atomic::Ordering::SeqCst).into()) }
+            })
+            .unwrap();
+        }
+        lamp.set_property_read_handler("reading", |options| async move {
+            Ok(options.uri_variables["unit"].clone())
+        })
+        .unwrap();
+        lamp.set_action_handler("double", |input, _| async move {
+            match input.as_i64() {
+                Some(n) => Ok((n * 2).into()),
+                None => Err(Error::InvalidValue(input.to_string())),
+            }
+        })
+        .unwrap();
+        assert!(matches!(
+            lamp.set_action_handler("missing", |input, _| async move { Ok(input) }),
+            Err(Error::UnknownAffordance(AffordanceType::Action, _))
+        ));
+
+        lamp.expose().unwrap();
+        assert!(matches!(lamp.expose(), Err(Error::Exposed)));
+
+        let url = format!("http://{addr}/");
+        let thing = ConsumedThing::fetch(&url).await.unwrap();
+
+        assert_eq!(thing.read_property::<i64>("level").await.unwrap(), 5);
+        assert!(matches!(
+            thing.write_property("level", &7).await,
+            Err(consumer::Error::Status(StatusCode::NOT_IMPLEMENTED))
+        ));
+
+        {
+            let level = level.clone();
+            lamp.set_property_write_handler("level", move |value, _| {
+                let level = level.clone();
+                async move {
+                    let value = value
+                        .as_i64()
+                        .ok_or_else(|| Error::InvalidValue(value.to_string()))?;
+                    level.store(value, std::sync::atomic::Ordering::SeqCst);
+                    Ok(())
+                }
+            })
+            .unwrap();
+        }
+        thing.write_property("level", &7).await.unwrap();
+        assert_eq!(thing.read_property::<i64>("level").await.unwrap(), 7);
+
+        let options = consumer::InteractionOptions::default().uri_variable("unit", "celsius");
+        let unit: String = thing.read_property_with("reading", &options).await.unwrap();
+        assert_eq!(unit, "celsius");
+
+        let doubled: i64 = thing.invoke_action("double", &21).await.unwrap();
+        assert_eq!(doubled, 42);
+        assert!(matches!(
+            thing.invoke_action::<_, i64>("double", &"a").await,
+            Err(consumer::Error::Status(StatusCode::BAD_REQUEST))
+        ));
+
+        let mut changes = thing.observe_property::<i64>("level").await.unwrap();
+        while lamp.emit_property_change("level").await.unwrap() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(changes.next().await.unwrap().unwrap(), 7);
+
+        let mut events = thing.subscribe_event::<u8>("changed").await.unwrap();
+        while lamp.emit_event("changed", 3).unwrap() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(events.next().await.unwrap().unwrap(), 3);
+
+        drop((changes, events));
+        lamp.destroy().await.unwrap();
+        assert!(matches!(lamp.destroy().await, Err(Error::NotExposed)));
+        assert!(ConsumedThing::fetch(&url).await.is_err());
+    }
+
+    #[test]
+    fn expose_errors() {
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = taken.local_addr().unwrap();
+        let td = json!({
+            "@context": "https://www.w3.org/2022/wot/td/v1.1",
+            "title": "lamp",
+            "securityDefinitions": { "nosec_sc": { "scheme": "nosec" } },
+            "security": "nosec_sc",
+        });
+        let lamp = ExposedThing::from_json(td)
+            .unwrap()
+            .configure(move |builder| builder.http_bind(addr));
+
+        assert!(matches!(lamp.expose(), Err(Error::Runtime(_))));
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let _entered = runtime.enter();
+        assert!(matches!(lamp.expose(), Err(Error::Bind(_))));
+        assert!(matches!(
+            runtime.block_on(lamp.destroy()),
+            Err(Error::NotExposed)
+        ));
+    }
+}
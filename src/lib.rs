@@ -2,9 +2,27 @@
 //!
 //! Provides all the building blocks to serve [Web Of Things](https://www.w3.org/WoT/) Things.
 
+// The derived code refers to the crate by name, from within it as well.
+extern crate self as wot_serve;
+
 pub mod advertise;
+pub(crate) mod cbor;
+pub mod coap;
+pub mod consumer;
+pub mod directory;
+pub mod discovery;
+pub mod exposed;
 #[doc(hidden)]
 pub mod hlist;
+pub mod model;
+pub mod mqtt;
+pub mod proxy;
+pub mod schema;
 pub mod servient;
+pub mod simulator;
+pub mod thing;
+
+#[cfg(test)]
+mod test_util;
 
 pub use servient::Servient;
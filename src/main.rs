@@ -0,0 +1,302 @@
+//! wot-serve command line
+//!
+//! Serve simulated Things, run a Thing Directory, advertise services and browse for them.
+
+use std::{net::SocketAddr, time::Duration};
+
+use wot_serve::{
+    advertise::{Advertiser, ThingType},
+    directory::ThingDirectory,
+    servient::*,
+    simulator::Simulator,
+};
+use wot_td::{
+    builder::{Extended, ThingBuilder},
+    extend::ExtendableThing,
+};
+
+const USAGE: &str = "\
+Usage: wot-serve <command> [options]
+
+Commands:
+  serve <td.json>        Serve a Thing Description with simulated handlers
+  directory              Run a Thing Directory
+  advertise <name>       Advertise a service through DNS-SD until stopped
+  browse                 List the Things advertised on the local network
+
+Servient options (serve, directory):
+  --bind <addr>          Address the http server binds to [default: 0.0.0.0:8080]
+  --thing-type <type>    Advertise as `thing` or `directory`
+  --no-cors              Disable the permissive CORS headers
+  --register <url>       Register with the Thing Directory at <url>
+  --register-discovered  Register with the Thing Directories found through DNS-SD
+  --title <title>        Title of the Thing Directory [default: Thing Directory]
+
+Simulator options (serve):
+  --random               Produce random values instead of the schema defaults
+  --seed <n>             Produce random values from the given seed
+  --event-interval <s>   Seconds between simulated events [default: 5]
+  --persist <file>       Keep the written property values in a json file across restarts
+
+Advertise options:
+  --port <port>          Port of the advertised service [default: 8080]
+  --path <path>          Path of the Thing Description [default: /.well-known/wot]
+  --thing-type <type>    Advertise as `thing` or `directory`
+
+Browse options:
+  --thing-type <type>    Look for `thing` or `directory` [default: thing]
+  --timeout <s>          Seconds to wait for answers [default: 3]
+
+  -h, --help             Print this help
+";
+
+type Error = Box<dyn std::error::Error>;
+
+/// Command line arguments, as parsed
+#[derive(Debug, Default)]
+struct Args {
+    command: String,
+    operand: Option<String>,
+    bind: Option<SocketAddr>,
+    thing_type: Option<ThingType>,
+    no_cors: bool,
+    register: Option<String>,
+    register_discovered: bool,
+    title: Option<String>,
+    random: bool,
+    seed: Option<u64>,
+    event_interval: Option<Duration>,
+    persist: Option<String>,
+    port: Option<u16>,
+    path: Option<String>,
+    timeout: Option<Duration>,
+}
+
+impl Args {
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+
+        fn value<T: std::str::FromStr>(
+            args: &mut impl Iterator<Item = String>,
+            flag: &str,
+        ) -> Result<T, String> {
+            args.next()
+                .ok_or_else(|| format!("{flag} requires a value"))?
+                .parse()
+                .map_err(|_| format!("invalid value for {flag}"))
+        }
+
+        let seconds =
+            |s: f64| Duration::try_from_secs_f64(s).map_err(|_| "invalid duration".to_string());
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => {
+                    parsed.command = "help".into();
+                    return Ok(parsed);
+                }
+                "--bind" => parsed.bind = Some(value(&mut args, &arg)?),
+                "--thing-type" => {
+                    parsed.thing_type = Some(match value::<String>(&mut args, &arg)?.as_str() {
+                        "thing" => ThingType::Thing,
+                        "directory" => ThingType::Directory,
+                        _ => return Err("the thing type is either thing or directory".into()),
+                    })
+                }
+                "--no-cors" => parsed.no_cors = true,
+                "--register" => parsed.register = Some(value(&mut args, &arg)?),
+                "--register-discovered" => parsed.register_discovered = true,
+                "--title" => parsed.title = Some(value(&mut args, &arg)?),
+                "--random" => parsed.random = true,
+                "--seed" => parsed.seed = Some(value(&mut args, &arg)?),
+                "--event-interval" => {
+                    parsed.event_interval = Some(seconds(value(&mut args, &arg)?)?)
+                }
+                "--persist" => parsed.persist = Some(value(&mut args, &arg)?),
+                "--port" => parsed.port = Some(value(&mut args, &arg)?),
+                "--path" => parsed.path = Some(value(&mut args, &arg)?),
+                "--timeout" => parsed.timeout = Some(seconds(value(&mut args, &arg)?)?),
+                flag if flag.starts_with('-') => return Err(format!("unknown option {flag}")),
+                _ if parsed.command.is_empty() => parsed.command = arg,
+                _ if parsed.operand.is_none() => parsed.operand = Some(arg),
+                _ => return Err(format!("unexpected argument {arg}")),
+            }
+        }
+
+        if parsed.register.is_some() && parsed.register_discovered {
+            return Err("--register and --register-discovered are exclusive".into());
+        }
+
+        Ok(parsed)
+    }
+
+    fn operand(&self, name: &str) -> Result<&str, String> {
+        self.operand
+            .as_deref()
+            .ok_or_else(|| format!("{} requires the {name}", self.command))
+    }
+
+    /// Apply the [`ServientSettings`] given on the command line.
+    fn configure<O>(&self, mut builder: ThingBuilder<O, Extended>) -> ThingBuilder<O, Extended>
+    where
+        O: ExtendableThing + wot_serve::hlist::Holder<ServientExtension>,
+    {
+        builder = builder.http_bind(self.bind.unwrap_or_else(|| ([0, 0, 0, 0], 8080).into()));
+        if let Some(ty) = self.thing_type {
+            builder = builder.thing_type(ty);
+        }
+        if self.no_cors {
+            builder = builder.http_disable_permissive_cors();
+        }
+        if let Some(url) = &self.register {
+            builder = builder.register_with(url);
+        }
+        if self.register_discovered {
+            builder = builder.register_with_discovered();
+        }
+
+        builder
+    }
+}
+
+async fn serve(servient: wot_serve::Servient<impl ExtendableThing>) -> Result<(), Error> {
+    println!(
+        "Serving {} on http://{}/ as {}",
+        servient.thing.title, servient.http_addr, servient.name
+    );
+
+    servient.serve().await?;
+
+    Ok(())
+}
+
+async fn run(args: Args) -> Result<(), Error> {
+    match args.command.as_str() {
+        "serve" => {
+            let td = std::fs::read_to_string(args.operand("thing description file")?)?;
+
+            let mut simulator = Simulator::from_td(&td)?;
+            if let Some(seed) = args.seed {
+                simulator = simulator.random_with_seed(seed);
+            } else if args.random {
+                simulator = simulator.random();
+            }
+            if let Some(interval) = args.event_interval {
+                simulator = simulator.event_interval(interval);
+            }
+
+            let mut builder = args.configure(simulator.builder()?);
+            if let Some(path) = &args.persist {
+                builder = builder.persist_properties(JsonFile::new(path));
+            }
+            let servient = builder.build_servient()?;
+
+            serve(servient).await
+        }
+        "directory" => {
+            let title = args.title.as_deref().unwrap_or("Thing Directory");
+            let builder = ThingDirectory::new().builder(title);
+
+            let servient = args.configure(builder).build_servient()?;
+
+            serve(servient).await
+        }
+        "advertise" => {
+            let name = args.operand("service name")?;
+            let port = args.port.unwrap_or(8080);
+
+            let ad = Advertiser::new()?;
+            let mut service = ad
+                .add_service(name)
+                .thing_type(args.thing_type.unwrap_or_default())
+                .port(port);
+            if let Some(path) = &args.path {
+                service = service.path(path);
+            }
+            service.build()?;
+
+            println!("Advertising {name} on port {port}");
+
+            std::future::pending::<()>().await;
+
+            Ok(())
+        }
+        "browse" => {
+            let ad = Advertiser::new()?;
+            let found = ad
+                .browse(args.thing_type.unwrap_or_default())?
+                .collect(args.timeout.unwrap_or(Duration::from_secs(3)))
+                .await;
+
+            for thing in found {
+                let td = thing.td_url().unwrap_or_else(|| "-".into());
+                println!(
+                    "{}\t{:?}\t{}\t{}",
+                    thing.name(),
+                    thing.thing_type,
+                    thing.hostname,
+                    td
+                );
+            }
+
+            Ok(())
+        }
+        "help" => {
+            print!("{USAGE}");
+
+            Ok(())
+        }
+        "" => Err(USAGE.into()),
+        command => Err(format!("unknown command {command}\n\n{USAGE}").into()),
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{err}\n\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(err) = run(args).await {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Args, String> {
+        Args::parse(args.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn parse_serve() {
+        let args = parse(
+            "serve lamp.td.json --bind 127.0.0.1:9000 --no-cors --seed 3 --persist lamp.json",
+        )
+        .unwrap();
+
+        assert_eq!(args.command, "serve");
+        assert_eq!(args.operand.as_deref(), Some("lamp.td.json"));
+        assert_eq!(args.bind, Some("127.0.0.1:9000".parse().unwrap()));
+        assert!(args.no_cors);
+        assert_eq!(args.seed, Some(3));
+        assert_eq!(args.persist.as_deref(), Some("lamp.json"));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse("browse --thing-type lamp").is_err());
+        assert!(parse("browse --timeout").is_err());
+        assert!(parse("serve a b").is_err());
+        assert!(parse("serve --tls").is_err());
+        assert!(parse("serve --register http://localhost:8081 --register-discovered").is_err());
+    }
+}
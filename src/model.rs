@@ -0,0 +1,598 @@
+//! Thing Models
+//!
+//! A [Thing Model](https://www.w3.org/TR/wot-thing-description11/#thing-model) describes a class
+//! of Things, from which many Thing Descriptions can be instantiated.
+//!
+//! [`ThingModel`] loads a model resolving its `tm:extends` links and `tm:ref` references and,
+//! once its `{{placeholders}}` are substituted, provides a [`Servient`] builder whose affordances
+//! are routed by name through [`AffordanceRouter`].
+//!
+//! [`Servient`]: crate::Servient
+//! [`AffordanceRouter`]: crate::servient::AffordanceRouter
+
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use serde_json::{Map, Value};
+use wot_td::builder::{Extended, ThingBuilder};
+
+use crate::{
+    hlist::NilPlus,
+    servient::{description_builder, ServientExtension, ServientSettings},
+};
+
+/// Error type for the module
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// Error reading a model.
+    #[error("I/O error {0}")]
+    Io(#[from] std::io::Error),
+    /// Malformed model.
+    #[error("json error {0}")]
+    Json(#[from] serde_json::Error),
+    /// The document `@type` does not include `tm:ThingModel`.
+    #[error("the document is not a Thing Model")]
+    NotAModel,
+    /// A `tm:ref` or `tm:extends` target cannot be found.
+    #[error("cannot resolve the reference {0}")]
+    Reference(String),
+    /// No value provided for a placeholder.
+    #[error("missing value for the placeholder {0}")]
+    Placeholder(String),
+}
+
+/// Result type for the module
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The JSON-LD context of the Thing Model vocabulary
+pub const TM_CONTEXT: &str = "https://www.w3.org/2022/wot/tm";
+
+const THING_MODEL: &str = "tm:ThingModel";
+
+/// Maximum depth of nested references, to spot the circular ones.
+const MAX_DEPTH: usize = 32;
+
+/// A resolved Thing Model
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThingModel {
+    json: Value,
+}
+
+impl ThingModel {
+    /// Load a model from a file.
+    ///
+    /// Relative `tm:extends` and `tm:ref` targets are resolved from the directory of the file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let json = load(path.as_ref(), 0)?;
+
+        Self::checked(json)
+    }
+
+    /// Use a model from its JSON representation.
+    ///
+    /// Relative `tm:extends` and `tm:ref` targets are resolved from the current directory.
+    pub fn from_json(json: Value) -> Result<Self> {
+        let json = resolve(json, Path::new("."), 0)?;
+
+        Self::checked(json)
+    }
+
+    fn checked(json: Value) -> Result<Self> {
+        let is_model = match json.get("@type") {
+            Some(Value::String(ty)) => ty == THING_MODEL,
+            Some(Value::Array(tys)) => tys.iter().any(|ty| ty == THING_MODEL),
+            _ => false,
+        };
+
+        if is_model {
+            Ok(Self { json })
+        } else {
+            Err(Error::NotAModel)
+        }
+    }
+
+    /// The model with all its references resolved.
+    pub fn as_json(&self) -> &Value {
+        &self.json
+    }
+
+    /// The names of the placeholders in the model.
+    pub fn placeholders(&self) -> BTreeSet<String> {
+        let mut names = BTreeSet::new();
+
+        visit_strings(&self.json, &mut |s| {
+            for (_, name) in placeholders(s) {
+                names.insert(name.to_string());
+            }
+        });
+
+        names
+    }
+
+    /// Produce a Thing Description, substituting the placeholders with the given values.
+    ///
+    /// A placeholder taking a whole JSON string is replaced with its value as is, otherwise
+    /// the value is formatted within the string.
+    ///
+    /// The affordances without Forms get one, routed to `/properties/{name}`, `/actions/{name}`
+    /// or `/events/{name}`.
+    pub fn instantiate<I, K, V>(&self, values: I) -> Result<Value>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<Value>,
+    {
+        let values: Map<String, Value> = values
+            .into_iter()
+            .map(|(k, v)| (k.into(), v.into()))
+            .collect();
+
+        let mut td = substitute(self.json.clone(), &values)?;
+
+        if let Some(td) = td.as_object_mut() {
+            match td.get_mut("@type") {
+                Some(Value::Array(tys)) => {
+                    tys.retain(|ty| ty != THING_MODEL);
+                    if tys.is_empty() {
+                        td.remove("@type");
+                    }
+                }
+                Some(Value::String(_)) => {
+                    td.remove("@type");
+                }
+                _ => {}
+            }
+            td.remove("tm:required");
+            td.remove("tm:optional");
+
+            for (affordance, ops) in [
+                ("properties", None),
+                ("actions", Some(&["invokeaction"][..])),
+                ("events", Some(&["subscribeevent"][..])),
+            ] {
+                let Some(Value::Object(affordances)) = td.get_mut(affordance) else {
+                    continue;
+                };
+
+                for (name, a) in affordances.iter_mut() {
+                    let Some(a) = a.as_object_mut() else {
+                        continue;
+                    };
+
+                    if a.get("forms").is_some_and(Value::is_array) {
+                        continue;
+                    }
+
+                    let ops = match ops {
+                        Some(ops) => ops.to_vec(),
+                        None => property_ops(a),
+                    };
+
+                    a.insert(
+                        "forms".to_string(),
+                        serde_json::json!([{
+                            "href": format!("/{affordance}/{name}"),
+                            "op": ops,
+                        }]),
+                    );
+                }
+            }
+        }
+
+        Ok(td)
+    }
+
+    /// Instantiate the model and prepare a [`Servient`] builder for it.
+    ///
+    /// The builder carries the affordances of the model, bind them to handlers by name
+    /// through [`AffordanceRouter`]: every Form must have one when the Servient is built.
+    ///
+    /// [`Servient`]: crate::Servient
+    /// [`AffordanceRouter`]: crate::servient::AffordanceRouter
+    pub fn builder<I, K, V>(
+        &self,
+        values: I,
+    ) -> Result<ThingBuilder<NilPlus<ServientExtension>, Extended>>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<Value>,
+    {
+        let td = self.instantiate(values)?;
+
+        Ok(description_builder(td)?.with_state(()))
+    }
+}
+
+/// Default property operations, according to `readOnly` and `writeOnly`.
+fn property_ops(property: &Map<String, Value>) -> Vec<&'static str> {
+    let flag = |name| property.get(name).and_then(Value::as_bool).unwrap_or(false);
+
+    let mut ops = Vec::new();
+    if !flag("writeOnly") {
+        ops.push("readproperty");
+    }
+    if !flag("readOnly") {
+        ops.push("writeproperty");
+    }
+    ops
+}
+
+fn load(path: &Path, depth: usize) -> Result<Value> {
+    if depth > MAX_DEPTH {
+        return Err(Error::Reference(path.display().to_string()));
+    }
+
+    let data = std::fs::read(path)?;
+    let json = serde_json::from_slice(&data)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+    resolve(json, dir, depth)
+}
+
+/// Resolve the `tm:ref` references and the `tm:extends` links of a document.
+fn resolve(json: Value, dir: &Path, depth: usize) -> Result<Value> {
+    let root = json.clone();
+    let mut json = resolve_refs(json, &root, dir, depth)?;
+
+    let mut parents = Vec::new();
+    if let Some(Value::Array(links)) = json.get_mut("links") {
+        links.retain(|link| {
+            if link.get("rel").and_then(Value::as_str) == Some("tm:extends") {
+                if let Some(href) = link.get("href").and_then(Value::as_str) {
+                    parents.push(href.to_string());
+                }
+                false
+            } else {
+                true
+            }
+        });
+
+        if links.is_empty() {
+            json.as_object_mut().map(|obj| obj.remove("links"));
+        }
+    }
+
+    let mut base = Value::Object(Map::new());
+    for parent in parents {
+        let parent = load(&target_path(dir, &parent), depth + 1)?;
+        base = merge(base, parent);
+    }
+
+    Ok(merge(base, json))
+}
+
+fn resolve_refs(json: Value, root: &Value, dir: &Path, depth: usize) -> Result<Value> {
+    match json {
+        Value::Object(mut obj) => {
+            let reference = match obj.remove("tm:ref") {
+                Some(Value::String(reference)) => Some(reference),
+                Some(_) => return Err(Error::Reference("tm:ref".to_string())),
+                None => None,
+            };
+
+            let obj = obj
+                .into_iter()
+                .map(|(k, v)| Ok((k, resolve_refs(v, root, dir, depth)?)))
+                .collect::<Result<Map<_, _>>>()?;
+
+            match reference {
+                Some(reference) => {
+                    let target = dereference(&reference, root, dir, depth)?;
+                    Ok(merge(target, Value::Object(obj)))
+                }
+                None => Ok(Value::Object(obj)),
+            }
+        }
+        Value::Array(values) => values
+            .into_iter()
+            .map(|v| resolve_refs(v, root, dir, depth))
+            .collect::<Result<Vec<_>>>()
+            .map(Value::Array),
+        json => Ok(json),
+    }
+}
+
+fn dereference(reference: &str, root: &Value, dir: &Path, depth: usize) -> Result<Value> {
+    if depth > MAX_DEPTH {
+        return Err(Error::Reference(reference.to_string()));
+    }
+
+    let (file, pointer) = reference.split_once('#').unwrap_or((reference, ""));
+
+    let target = if file.is_empty() {
+        let target = root
+            .pointer(pointer)
+            .cloned()
+            .ok_or_else(|| Error::Reference(reference.to_string()))?;
+        // The fragment may hold references on its own.
+        resolve_refs(target, root, dir, depth + 1)?
+    } else {
+        load(&target_path(dir, file), depth + 1)?
+            .pointer(pointer)
+            .cloned()
+            .ok_or_else(|| Error::Reference(reference.to_string()))?
+    };
+
+    Ok(target)
+}
+
+fn target_path(dir: &Path, href: &str) -> PathBuf {
+    let href = href.strip_prefix("file://").unwrap_or(href);
+
+    dir.join(href)
+}
+
+/// Merge `overlay` over `base`, the objects are merged recursively.
+fn merge(base: Value, overlay: Value) -> Value {
+    match (base, overlay) {
+        (Value::Object(mut base), Value::Object(overlay)) => {
+            for (k, v) in overlay {
+                let merged = match base.remove(&k) {
+                    Some(b) => merge(b, v),
+                    None => v,
+                };
+                base.insert(k, merged);
+            }
+            Value::Object(base)
+        }
+        (_, overlay) => overlay,
+    }
+}
+
+fn visit_strings(json: &Value, f: &mut impl FnMut(&str)) {
+    match json {
+        Value::String(s) => f(s),
+        Value::Array(values) => values.iter().for_each(|v| visit_strings(v, f)),
+        Value::Object(obj) => obj.iter().for_each(|(k, v)| {
+            f(k);
+            visit_strings(v, f)
+        }),
+        _ => {}
+    }
+}
+
+/// The placeholders within a string, with their position.
+fn placeholders(s: &str) -> Vec<(std::ops::Range<usize>, &str)> {
+    let mut found = Vec::new();
+    let mut start = 0;
+
+    while let Some(open) = s[start..].find("{{").map(|i| i + start) {
+        let Some(close) = s[open + 2..].find("}}").map(|i| i + open + 2) else {
+            break;
+        };
+        found.push((open..close + 2, s[open + 2..close].trim()));
+        start = close + 2;
+    }
+
+    found
+}
+
+fn substitute_str(s: &str, values: &Map<String, Value>) -> Result<Value> {
+    let found = placeholders(s);
+
+    let value = |name: &str| {
+        values
+            .get(name)
+            .ok_or_else(|| Error::Placeholder(name.to_string()))
+    };
+
+    match found.as_slice() {
+        [] => Ok(Value::String(s.to_string())),
+        [(range, name)] if range.len() == s.len() => value(name).cloned(),
+        _ => {
+            let mut out = String::with_capacity(s.len());
+            let mut last = 0;
+            for (range, name) in found {
+                out.push_str(&s[last..range.start]);
+                match value(name)? {
+                    Value::String(v) => out.push_str(v),
+                    v => out.push_str(&v.to_string()),
+                }
+                last = range.end;
+            }
+            out.push_str(&s[last..]);
+
+            Ok(Value::String(out))
+        }
+    }
+}
+
+fn substitute(json: Value, values: &Map<String, Value>) -> Result<Value> {
+    match json {
+        Value::String(s) => substitute_str(&s, values),
+        Value::Array(a) => a
+            .into_iter()
+            .map(|v| substitute(v, values))
+            .collect::<Result<_>>()
+            .map(Value::Array),
+        Value::Object(obj) => obj
+            .into_iter()
+            .map(|(k, v)| {
+                let k = match substitute_str(&k, values)? {
+                    Value::String(k) => k,
+                    k => k.to_string(),
+                };
+                Ok((k, substitute(v, values)?))
+            })
+            .collect::<Result<_>>()
+            .map(Value::Object),
+        json => Ok(json),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use wot_td::thing::FormOperation;
+
+    use super::*;
+    use crate::servient::{AffordanceRouter, BuildServient};
+
+    /// Temporary directory removed once dropped
+    struct ModelDir(PathBuf);
+
+    impl std::ops::Deref for ModelDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for ModelDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn write_models(models: &[(&str, Value)]) -> ModelDir {
+        let dir = std::env::temp_dir().join(format!("wot-serve-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        for (name, model) in models {
+            std::fs::write(dir.join(name), serde_json::to_vec(model).unwrap()).unwrap();
+        }
+
+        ModelDir(dir)
+    }
+
+    fn lamp() -> ModelDir {
+        write_models(&[
+            (
+                "base.tm.json",
+                json!({
+                    "@context": ["https://www.w3.org/2022/wot/td/v1.1", { "tm": TM_CONTEXT }],
+                    "@type": "tm:ThingModel",
+                    "title": "Base",
+                    "properties": {
+                        "on": { "type": "boolean" }
+                    }
+                }),
+            ),
+            (
+                "schemas.json",
+                json!({
+                    "level": { "type": "integer", "minimum": 0, "maximum": 100 }
+                }),
+            ),
+            (
+                "lamp.tm.json",
+                json!({
+                    "@context": ["https://www.w3.org/2022/wot/td/v1.1", { "tm": TM_CONTEXT }],
+                    "@type": ["tm:ThingModel", "Light"],
+                    "title": "Lamp {{SERIAL}}",
+                    "id": "urn:dev:lamp:{{SERIAL}}",
+                    "links": [{ "rel": "tm:extends", "href": "base.tm.json" }],
+                    "properties": {
+                        "brightness": {
+                            "tm:ref": "schemas.json#/level",
+                            "maximum": "{{MAX}}"
+                        },
+                        "status": {
+                            "tm:ref": "#/properties/brightness",
+                            "readOnly": true
+                        }
+                    },
+                    "actions": {
+                        "toggle": {}
+                    }
+                }),
+            ),
+        ])
+    }
+
+    #[test]
+    fn resolve_model() {
+        let dir = lamp();
+        let model = ThingModel::from_file(dir.join("lamp.tm.json")).unwrap();
+
+        assert_eq!(
+            model.placeholders(),
+            BTreeSet::from(["MAX".to_string(), "SERIAL".to_string()])
+        );
+
+        let td = model
+            .instantiate([("SERIAL", json!("1234")), ("MAX", json!(255))])
+            .unwrap();
+
+        assert_eq!(td["title"], "Lamp 1234");
+        assert_eq!(td["id"], "urn:dev:lamp:1234");
+        assert_eq!(td["@type"], json!(["Light"]));
+        assert!(td.get("links").is_none());
+        assert_eq!(td["properties"]["on"]["type"], "boolean");
+        assert_eq!(td["properties"]["brightness"]["type"], "integer");
+        assert_eq!(td["properties"]["brightness"]["minimum"], 0);
+        assert_eq!(td["properties"]["brightness"]["maximum"], 255);
+        assert_eq!(td["properties"]["status"]["maximum"], 255);
+        assert_eq!(
+            td["properties"]["status"]["forms"],
+            json!([{ "href": "/properties/status", "op": ["readproperty"] }])
+        );
+        assert_eq!(
+            td["actions"]["toggle"]["forms"],
+            json!([{ "href": "/actions/toggle", "op": ["invokeaction"] }])
+        );
+
+        assert!(matches!(
+            model.instantiate([("SERIAL", "1234")]),
+            Err(Error::Placeholder(p)) if p == "MAX"
+        ));
+    }
+
+    #[test]
+    fn not_a_model() {
+        assert!(matches!(
+            ThingModel::from_json(json!({ "title": "test" })),
+            Err(Error::NotAModel)
+        ));
+    }
+
+    #[test]
+    fn build_servient_from_model() {
+        let dir = lamp();
+        let model = ThingModel::from_file(dir.join("lamp.tm.json")).unwrap();
+        let values = [("SERIAL", json!("1234")), ("MAX", json!(100))];
+
+        let servient = model
+            .builder(values.clone())
+            .unwrap()
+            .on_read_property("on", || async { "true" })
+            .on_write_property("on", || async {})
+            .on_read_property("brightness", || async { "50" })
+            .on_write_property("brightness", || async {})
+            .on_read_property("status", || async { "50" })
+            .on_invoke_action("toggle", || async {})
+            .build_servient()
+            .unwrap();
+
+        assert_eq!(servient.thing.title, "Lamp 1234");
+        assert_eq!(servient.thing.id.as_deref(), Some("urn:dev:lamp:1234"));
+        assert_eq!(servient.thing.properties.as_ref().unwrap().len(), 3);
+        let status = &servient.thing.properties.as_ref().unwrap()["status"];
+        assert_eq!(
+            status.interaction.forms[0].op,
+            wot_td::thing::DefaultedFormOperations::Custom(vec![FormOperation::ReadProperty])
+        );
+
+        let Err(err) = model
+            .builder(values)
+            .unwrap()
+            .on_read_property("on", || async { "true" })
+            .on_read_property("brightness", || async { "50" })
+            .on_write_property("brightness", || async {})
+            .on_read_property("status", || async { "50" })
+            .on_invoke_action("toggle", || async {})
+            .build_servient()
+        else {
+            panic!("the servient should not build");
+        };
+
+        assert_eq!(
+            err.to_string(),
+            "the form /properties/on has no handler for writeproperty"
+        );
+    }
+}
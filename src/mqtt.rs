@@ -0,0 +1,906 @@
+//! MQTT protocol binding
+//!
+//! The [`Servient`] configured with [`ServientSettings::mqtt_broker`] connects to an MQTT
+//! broker and exposes its affordances on topics named after the Thing: its id or, without
+//! one, its title, with the `/`, `+`, `#` and whitespace characters replaced by `-`.
+//!
+//! - the value of each readable property is published, retained, to
+//!   `thing/<name>/properties/<property>`, and again every time its `observeproperty`
+//!   handler replies;
+//! - each reply of the `subscribeevent` handlers is published to `thing/<name>/events/<event>`;
+//! - the messages published to `thing/<name>/actions/<action>` invoke the action, their
+//!   payload is its input.
+//!
+//! The handlers of the http Forms are used, the observing and subscribing ones are expected
+//! to wait for the next value, as for long-polling, the publications are paced otherwise.
+//! The Thing Description describes the topics through Forms using the `mqv` vocabulary.
+//!
+//! MQTT 3.1.1 is spoken, with the quality of service 0 only and without TLS.
+//!
+//! [`Servient`]: crate::Servient
+//! [`ServientSettings::mqtt_broker`]: crate::servient::ServientSettings::mqtt_broker
+
+use std::{future::Future, time::Duration};
+
+use axum::{
+    body::Body,
+    http::{self, header::CONTENT_TYPE, Request},
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::mpsc,
+    task::JoinSet,
+};
+use tower::Service;
+
+use crate::servient::content::body_bytes;
+
+/// Default MQTT port
+pub const DEFAULT_PORT: u16 = 1883;
+
+/// The JSON-LD context of the `mqv` vocabulary
+pub const MQV_CONTEXT: &str = "http://www.example.org/mqtt-binding#";
+
+/// Keep alive interval requested to the broker
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+/// Minimum time between two publications of an observation
+const PUBLISH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Error type for the module
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// Network-specific error
+    #[error("I/O error {0}")]
+    Io(#[from] std::io::Error),
+
+    /// The bytes received are not a well formed MQTT packet.
+    #[error("malformed packet: {0}")]
+    Malformed(&'static str),
+
+    /// The broker url is not a `mqtt://` one.
+    #[error("invalid broker url {0}")]
+    Url(String),
+
+    /// The broker refused the connection, with the CONNACK return code.
+    #[error("connection refused with code {0}")]
+    Refused(u8),
+}
+
+/// Result type for the module
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// MQTT control packet used by the operations of a Form
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ControlPacket {
+    /// Publish to the `mqv:topic`
+    #[serde(rename = "mqv:publish")]
+    Publish,
+    /// Subscribe to the `mqv:filter`
+    #[serde(rename = "mqv:subscribe")]
+    Subscribe,
+    /// Unsubscribe from the `mqv:filter`
+    #[serde(rename = "mqv:unsubscribe")]
+    Unsubscribe,
+}
+
+/// An MQTT control packet
+///
+/// Only the packets used with the quality of service 0 are supported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    /// Connection request, with a clean session
+    Connect {
+        /// Client identifier
+        client_id: String,
+        /// Keep alive interval, in seconds
+        keep_alive: u16,
+    },
+    /// Connection acknowledgement
+    ConnAck {
+        /// Whether the broker resumed a session
+        session_present: bool,
+        /// 0 if the connection is accepted
+        return_code: u8,
+    },
+    /// Application message
+    Publish {
+        /// Topic name
+        topic: String,
+        /// Payload
+        payload: Vec<u8>,
+        /// Whether the broker keeps the message for the future subscribers
+        retain: bool,
+    },
+    /// Subscription request
+    Subscribe {
+        /// Packet identifier
+        id: u16,
+        /// Topic filters
+        filters: Vec<String>,
+    },
+    /// Subscription acknowledgement
+    SubAck {
+        /// Packet identifier of the request
+        id: u16,
+        /// Granted quality of service of each filter, `0x80` on failure
+        return_codes: Vec<u8>,
+    },
+    /// Ping request
+    PingReq,
+    /// Ping response
+    PingResp,
+    /// Disconnection notice
+    Disconnect,
+}
+
+fn put_string(value: &str, out: &mut Vec<u8>) {
+    out.extend((value.len() as u16).to_be_bytes());
+    out.extend(value.as_bytes());
+}
+
+/// Reader of the variable header and payload of a packet
+struct Fields<'a>(&'a [u8]);
+
+impl Fields<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8]> {
+        if self.0.len() < len {
+            return Err(Error::Malformed("truncated packet"));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.u16()?.into();
+        let bytes = self.take(len)?;
+
+        String::from_utf8(bytes.to_vec()).map_err(|_| Error::Malformed("invalid utf-8 string"))
+    }
+
+    fn rest(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.0).to_vec()
+    }
+}
+
+impl Packet {
+    /// Encode the packet.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+
+        let first = match self {
+            Packet::Connect {
+                client_id,
+                keep_alive,
+            } => {
+                put_string("MQTT", &mut body);
+                // Protocol level 4, clean session.
+                body.extend([4, 0x02]);
+                body.extend(keep_alive.to_be_bytes());
+                put_string(client_id, &mut body);
+                0x10
+            }
+            Packet::ConnAck {
+                session_present,
+                return_code,
+            } => {
+                body.extend([u8::from(*session_present), *return_code]);
+                0x20
+            }
+            Packet::Publish {
+                topic,
+                payload,
+                retain,
+            } => {
+                put_string(topic, &mut body);
+                body.extend(payload);
+                0x30 | u8::from(*retain)
+            }
+            Packet::Subscribe { id, filters } => {
+                body.extend(id.to_be_bytes());
+                for filter in filters {
+                    put_string(filter, &mut body);
+                    body.push(0);
+                }
+                0x82
+            }
+            Packet::SubAck { id, return_codes } => {
+                body.extend(id.to_be_bytes());
+                body.extend(return_codes);
+                0x90
+            }
+            Packet::PingReq => 0xc0,
+            Packet::PingResp => 0xd0,
+            Packet::Disconnect => 0xe0,
+        };
+
+        let mut out = vec![first];
+        let mut len = body.len();
+        loop {
+            let byte = (len % 128) as u8;
+            len /= 128;
+            if len == 0 {
+                out.push(byte);
+                break;
+            }
+            out.push(byte | 0x80);
+        }
+        out.extend(body);
+
+        out
+    }
+
+    /// Decode a packet from its first byte and the rest of its bytes after the length.
+    pub fn decode(first: u8, body: &[u8]) -> Result<Self> {
+        let mut body = Fields(body);
+
+        let packet = match first >> 4 {
+            1 => {
+                if body.string()? != "MQTT" || body.u8()? != 4 {
+                    return Err(Error::Malformed("unsupported protocol"));
+                }
+                let flags = body.u8()?;
+                let keep_alive = body.u16()?;
+                let client_id = body.string()?;
+                // The will and the credentials are not supported.
+                if flags & 0xc4 != 0 {
+                    return Err(Error::Malformed("unsupported connect flags"));
+                }
+                Packet::Connect {
+                    client_id,
+                    keep_alive,
+                }
+            }
+            2 => Packet::ConnAck {
+                session_present: body.u8()? & 1 != 0,
+                return_code: body.u8()?,
+            },
+            3 => {
+                let topic = body.string()?;
+                // The packet identifier of the higher qualities of service is skipped.
+                if first & 0x06 != 0 {
+                    body.u16()?;
+                }
+                Packet::Publish {
+                    topic,
+                    payload: body.rest(),
+                    retain: first & 1 != 0,
+                }
+            }
+            8 => {
+                let id = body.u16()?;
+                let mut filters = Vec::new();
+                while !body.0.is_empty() {
+                    filters.push(body.string()?);
+                    body.u8()?;
+                }
+                Packet::Subscribe { id, filters }
+            }
+            9 => Packet::SubAck {
+                id: body.u16()?,
+                return_codes: body.rest(),
+            },
+            12 => Packet::PingReq,
+            13 => Packet::PingResp,
+            14 => Packet::Disconnect,
+            _ => return Err(Error::Malformed("unsupported packet type")),
+        };
+
+        Ok(packet)
+    }
+
+    /// Read a packet.
+    pub async fn read<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Self> {
+        let first = reader.read_u8().await?;
+
+        let mut len = 0;
+        for shift in [0, 7, 14, 21] {
+            let byte = reader.read_u8().await?;
+            len |= usize::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                let mut body = vec![0; len];
+                reader.read_exact(&mut body).await?;
+                return Packet::decode(first, &body);
+            }
+        }
+
+        Err(Error::Malformed("remaining length too long"))
+    }
+}
+
+/// The host and port of a `mqtt://` broker url.
+pub(crate) fn broker_addr(url: &str) -> Result<(String, u16)> {
+    let invalid = || Error::Url(url.to_string());
+    let uri: http::Uri = url.parse().map_err(|_| invalid())?;
+
+    if uri.scheme_str() != Some("mqtt") {
+        return Err(invalid());
+    }
+    let host = uri
+        .host()
+        .filter(|host| !host.is_empty())
+        .ok_or_else(invalid)?;
+
+    Ok((host.to_string(), uri.port_u16().unwrap_or(DEFAULT_PORT)))
+}
+
+/// Name of the topics of the Thing, stable across restarts.
+pub(crate) fn topic_name(id: Option<&str>, title: &str) -> String {
+    id.unwrap_or(title)
+        .chars()
+        .map(|c| match c {
+            '/' | '+' | '#' => '-',
+            c if c.is_whitespace() || c.is_control() => '-',
+            c => c,
+        })
+        .collect()
+}
+
+/// Http request handled by a Form
+#[derive(Debug, Clone)]
+pub(crate) struct Route {
+    pub(crate) path: String,
+    pub(crate) method: http::Method,
+}
+
+impl Route {
+    /// Call the handler, the body of the successful responses is returned.
+    async fn call(&self, mut router: Router, topic: &str, payload: Vec<u8>) -> Option<Vec<u8>> {
+        let mut request = Request::builder()
+            .method(self.method.clone())
+            .uri(&self.path);
+        if !payload.is_empty() {
+            request = request.header(CONTENT_TYPE, "application/json");
+        }
+        let request = request.body(Body::from(payload)).ok()?;
+
+        // Routers are always ready.
+        let Ok(response) = router.call(request).await;
+        if !response.status().is_success() {
+            tracing::warn!(%topic, status = %response.status(), "mqtt handler failed");
+            return None;
+        }
+
+        body_bytes(response.into_body()).await.ok().map(Into::into)
+    }
+}
+
+/// Topic the replies of the handlers are published to
+#[derive(Debug, Clone)]
+pub(crate) struct Publication {
+    pub(crate) topic: String,
+    pub(crate) retain: bool,
+    /// Handler replying with the current value
+    pub(crate) read: Option<Route>,
+    /// Handler replying with the next value
+    pub(crate) observe: Option<Route>,
+}
+
+impl Publication {
+    async fn run(self, router: Router, outgoing: mpsc::UnboundedSender<Packet>) {
+        let publish = |payload| {
+            let publish = Packet::Publish {
+                topic: self.topic.clone(),
+                payload,
+                retain: self.retain,
+            };
+            outgoing.send(publish).is_ok()
+        };
+
+        if let Some(read) = &self.read {
+            if let Some(payload) = read.call(router.clone(), &self.topic, Vec::new()).await {
+                if !publish(payload) {
+                    return;
+                }
+            }
+        }
+
+        let Some(observe) = &self.observe else {
+            return;
+        };
+        // A failing observation would fail again right away.
+        loop {
+            // Handlers answering right away would flood the broker otherwise.
+            let next = tokio::time::Instant::now() + PUBLISH_INTERVAL;
+            let Some(payload) = observe.call(router.clone(), &self.topic, Vec::new()).await else {
+                return;
+            };
+            if !publish(payload) {
+                return;
+            }
+            tokio::time::sleep_until(next).await;
+        }
+    }
+}
+
+/// Topic whose messages are handled
+#[derive(Debug, Clone)]
+pub(crate) struct Subscription {
+    pub(crate) topic: String,
+    pub(crate) route: Route,
+}
+
+/// MQTT client exposing the affordances of a [`Servient`]
+///
+/// [`Servient`]: crate::Servient
+#[derive(Debug, Clone)]
+pub struct MqttClient {
+    /// Broker host
+    pub host: String,
+    /// Broker port
+    pub port: u16,
+    /// Client identifier
+    pub client_id: String,
+    /// The router serving the handlers
+    pub router: Router,
+    pub(crate) publications: Vec<Publication>,
+    pub(crate) subscriptions: Vec<Subscription>,
+}
+
+impl MqttClient {
+    pub(crate) fn new(
+        (host, port): (String, u16),
+        client_id: String,
+        router: Router,
+        publications: Vec<Publication>,
+        subscriptions: Vec<Subscription>,
+    ) -> Self {
+        Self {
+            host,
+            port,
+            client_id,
+            router,
+            publications,
+            subscriptions,
+        }
+    }
+
+    /// Connect to the broker and expose the affordances until `signal` resolves.
+    pub(crate) async fn serve(&self, signal: impl Future<Output = ()>) -> Result<()> {
+        let stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+        let (mut reader, mut writer) = stream.into_split();
+
+        let connect = Packet::Connect {
+            client_id: self.client_id.clone(),
+            keep_alive: KEEP_ALIVE.as_secs() as u16,
+        };
+        writer.write_all(&connect.encode()).await?;
+        match Packet::read(&mut reader).await? {
+            Packet::ConnAck { return_code: 0, .. } => {}
+            Packet::ConnAck { return_code, .. } => return Err(Error::Refused(return_code)),
+            _ => return Err(Error::Malformed("expected a CONNACK")),
+        }
+
+        // Subscribe before publishing, the messages reacting to the publications are handled.
+        if !self.subscriptions.is_empty() {
+            let subscribe = Packet::Subscribe {
+                id: 1,
+                filters: self.subscriptions.iter().map(|s| s.topic.clone()).collect(),
+            };
+            writer.write_all(&subscribe.encode()).await?;
+        }
+
+        // Dropping the tasks aborts them.
+        let mut tasks = JoinSet::new();
+
+        // Reading is not cancel safe, it happens in its own task.
+        let (incoming_tx, mut incoming) = mpsc::channel(16);
+        tasks.spawn(async move {
+            loop {
+                let packet = Packet::read(&mut reader).await;
+                let failed = packet.is_err();
+                if incoming_tx.send(packet).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        let (outgoing_tx, mut outgoing) = mpsc::unbounded_channel();
+        for publication in &self.publications {
+            tasks.spawn(
+                publication
+                    .clone()
+                    .run(self.router.clone(), outgoing_tx.clone()),
+            );
+        }
+
+        let mut ping =
+            tokio::time::interval_at(tokio::time::Instant::now() + KEEP_ALIVE / 2, KEEP_ALIVE / 2);
+
+        tokio::pin!(signal);
+        loop {
+            tokio::select! {
+                _ = &mut signal => {
+                    writer.write_all(&Packet::Disconnect.encode()).await?;
+                    return Ok(());
+                }
+                Some(_) = tasks.join_next(), if !tasks.is_empty() => {}
+                Some(packet) = outgoing.recv() => writer.write_all(&packet.encode()).await?,
+                _ = ping.tick() => writer.write_all(&Packet::PingReq.encode()).await?,
+                packet = incoming.recv() => {
+                    let packet = packet.unwrap_or_else(|| {
+                        Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into())
+                    })?;
+                    let Packet::Publish { topic, payload, .. } = packet else {
+                        continue;
+                    };
+                    if let Some(subscription) = self.subscriptions.iter().find(|s| s.topic == topic) {
+                        let route = subscription.route.clone();
+                        let router = self.router.clone();
+                        tasks.spawn(async move {
+                            route.call(router, &topic, payload).await;
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{extract::State, Json};
+    use serde_json::Value;
+    use tokio::{
+        net::{tcp::OwnedWriteHalf, TcpListener},
+        sync::broadcast,
+    };
+    use wot_td::{builder::data_schema::*, builder::*, thing::FormOperation};
+
+    use crate::servient::{BuildServient, HttpRouter, ServientSettings};
+    use crate::test_util::{local_listener, serve_until};
+    use crate::Servient;
+
+    use super::*;
+
+    /// Retained messages and subscriptions of the test broker
+    #[derive(Default)]
+    struct Topics {
+        retained: HashMap<String, Vec<u8>>,
+        subscribers: Vec<(String, mpsc::UnboundedSender<Packet>)>,
+    }
+
+    async fn write_packets(
+        mut writer: OwnedWriteHalf,
+        mut packets: mpsc::UnboundedReceiver<Packet>,
+    ) {
+        while let Some(packet) = packets.recv().await {
+            if writer.write_all(&packet.encode()).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    async fn session(stream: TcpStream, topics: Arc<Mutex<Topics>>) {
+        let (mut reader, writer) = stream.into_split();
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(write_packets(writer, rx));
+
+        while let Ok(packet) = Packet::read(&mut reader).await {
+            let mut topics = topics.lock().unwrap();
+            let reply = match packet {
+                Packet::Connect { .. } => Packet::ConnAck {
+                    session_present: false,
+                    return_code: 0,
+                },
+                Packet::Subscribe { id, filters } => {
+                    let _ = tx.send(Packet::SubAck {
+                        id,
+                        return_codes: vec![0; filters.len()],
+                    });
+                    for topic in filters {
+                        if let Some(payload) = topics.retained.get(&topic) {
+                            let _ = tx.send(Packet::Publish {
+                                topic: topic.clone(),
+                                payload: payload.clone(),
+                                retain: true,
+                            });
+                        }
+                        topics.subscribers.push((topic, tx.clone()));
+                    }
+                    continue;
+                }
+                Packet::Publish {
+                    topic,
+                    payload,
+                    retain,
+                } => {
+                    if retain {
+                        topics.retained.insert(topic.clone(), payload.clone());
+                    }
+                    for (_, subscriber) in topics.subscribers.iter().filter(|(t, _)| *t == topic) {
+                        let _ = subscriber.send(Packet::Publish {
+                            topic: topic.clone(),
+                            payload: payload.clone(),
+                            retain: false,
+                        });
+                    }
+                    continue;
+                }
+                Packet::PingReq => Packet::PingResp,
+                _ => break,
+            };
+            let _ = tx.send(reply);
+        }
+    }
+
+    /// Start a broker matching the topics exactly, without wildcards.
+    async fn broker() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let topics = Arc::new(Mutex::new(Topics::default()));
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(session(stream, topics.clone()));
+            }
+        });
+
+        addr
+    }
+
+    async fn next_publish(stream: &mut TcpStream) -> (String, Vec<u8>) {
+        loop {
+            if let Packet::Publish { topic, payload, .. } = Packet::read(stream).await.unwrap() {
+                return (topic, payload);
+            }
+        }
+    }
+
+    #[test]
+    fn packet_roundtrip() {
+        let packets = [
+            Packet::Connect {
+                client_id: "lamp".into(),
+                keep_alive: 60,
+            },
+            Packet::ConnAck {
+                session_present: false,
+                return_code: 0,
+            },
+            Packet::Publish {
+                topic: "thing/lamp/properties/on".into(),
+                payload: vec![b'x'; 200],
+                retain: true,
+            },
+            Packet::Subscribe {
+                id: 1,
+                filters: vec!["a".into(), "b/+".into()],
+            },
+            Packet::SubAck {
+                id: 1,
+                return_codes: vec![0, 0x80],
+            },
+            Packet::PingReq,
+            Packet::PingResp,
+            Packet::Disconnect,
+        ];
+
+        for packet in packets {
+            let bytes = packet.encode();
+            // The length takes two bytes past 127.
+            let body = if bytes[1] & 0x80 != 0 { 3 } else { 2 };
+            assert_eq!(Packet::decode(bytes[0], &bytes[body..]).unwrap(), packet);
+        }
+
+        assert_eq!(
+            Packet::Publish {
+                topic: "t".into(),
+                payload: b"1".to_vec(),
+                retain: false
+            }
+            .encode(),
+            [0x30, 4, 0, 1, b't', b'1']
+        );
+        assert!(Packet::decode(0x30, &[0, 5, b't']).is_err());
+        assert!(Packet::decode(0x50, &[0, 1]).is_err());
+
+        assert_eq!(
+            broker_addr("mqtt://localhost").unwrap(),
+            ("localhost".into(), DEFAULT_PORT)
+        );
+        assert_eq!(
+            broker_addr("mqtt://127.0.0.1:1884/").unwrap(),
+            ("127.0.0.1".into(), 1884)
+        );
+        assert!(broker_addr("http://localhost").is_err());
+    }
+
+    #[test]
+    fn topic_names() {
+        assert_eq!(topic_name(Some("urn:dev:lamp/1"), "Lamp"), "urn:dev:lamp-1");
+        assert_eq!(topic_name(None, "Living room #2"), "Living-room--2");
+    }
+
+    #[tokio::test]
+    async fn serve_mqtt() {
+        type AppState = (broadcast::Sender<u8>, mpsc::UnboundedSender<bool>);
+
+        let broker = broker().await;
+        let (events, _) = broadcast::channel::<u8>(4);
+        let (invoked_tx, mut invoked) = mpsc::unbounded_channel();
+
+        let (listener, _) = local_listener();
+        let servient = Servient::stateful_builder("mqtt")
+            .finish_extend()
+            .mqtt_broker(format!("mqtt://{broker}"))
+            .with_state((events.clone(), invoked_tx))
+            .property("level", |b| {
+                b.finish_extend_data_schema().integer().form(|f| {
+                    f.href("/level")
+                        .http_get(|| async { Json(5) })
+                        .op(FormOperation::ReadProperty)
+                })
+            })
+            .action("toggle", |b| {
+                b.input(|b| b.finish_extend().bool()).form(|f| {
+                    f.href("/toggle").http_post(
+                        |State((_, invoked)): State<AppState>, Json(on): Json<bool>| async move {
+                            invoked.send(on).unwrap();
+                        },
+                    )
+                })
+            })
+            .event("changed", |b| {
+                b.form(|f| {
+                    f.href("/changed")
+                        .http_get(|State((events, _)): State<AppState>| async move {
+                            Json(events.subscribe().recv().await.unwrap())
+                        })
+                        .op(FormOperation::SubscribeEvent)
+                })
+            })
+            .build_servient()
+            .unwrap();
+
+        let td = servient.description.get();
+        let prefix = "thing/mqtt";
+        assert_eq!(td["@context"][1]["mqv"], MQV_CONTEXT);
+        assert_eq!(
+            td["properties"]["level"]["forms"][1],
+            serde_json::json!({
+                "href": format!("mqtt://{broker}"),
+                "op": ["readproperty"],
+                "contentType": "application/json",
+                "mqv:filter": format!("{prefix}/properties/level"),
+                "mqv:controlPacket": "mqv:subscribe",
+                "mqv:retain": true,
+            })
+        );
+        assert_eq!(
+            td["actions"]["toggle"]["forms"][1]["mqv:topic"],
+            Value::from(format!("{prefix}/actions/toggle"))
+        );
+        assert_eq!(
+            td["events"]["changed"]["forms"][1]["mqv:filter"],
+            Value::from(format!("{prefix}/events/changed"))
+        );
+
+        let checks = async {
+            let mut client = TcpStream::connect(broker).await.unwrap();
+            let connect = Packet::Connect {
+                client_id: "test".into(),
+                keep_alive: 60,
+            };
+            client.write_all(&connect.encode()).await.unwrap();
+            Packet::read(&mut client).await.unwrap();
+
+            let filters = vec![
+                format!("{prefix}/properties/level"),
+                format!("{prefix}/events/changed"),
+            ];
+            let subscribe = Packet::Subscribe { id: 1, filters };
+            client.write_all(&subscribe.encode()).await.unwrap();
+
+            // Retained or published once the servient is connected, after its subscriptions.
+            let (topic, payload) = next_publish(&mut client).await;
+            assert_eq!(topic, format!("{prefix}/properties/level"));
+            assert_eq!(payload, b"5");
+
+            while events.receiver_count() == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            events.send(3).unwrap();
+            let (topic, payload) = next_publish(&mut client).await;
+            assert_eq!(topic, format!("{prefix}/events/changed"));
+            assert_eq!(payload, b"3");
+
+            let publish = Packet::Publish {
+                topic: format!("{prefix}/actions/toggle"),
+                payload: b"true".to_vec(),
+                retain: false,
+            };
+            client.write_all(&publish.encode()).await.unwrap();
+            assert_eq!(invoked.recv().await, Some(true));
+        };
+
+        serve_until(&servient, listener, checks).await;
+    }
+
+    #[tokio::test]
+    async fn pace_publications() {
+        let broker = broker().await;
+
+        let (listener, _) = local_listener();
+        let servient = Servient::builder("paced")
+            .finish_extend()
+            .mqtt_broker(format!("mqtt://{broker}"))
+            .property("level", |b| {
+                b.finish_extend_data_schema().integer().form(|f| {
+                    f.href("/level")
+                        .http_get(|| async { Json(5) })
+                        .op(FormOperation::ObserveProperty)
+                })
+            })
+            .build_servient()
+            .unwrap();
+
+        let checks = async {
+            let mut client = TcpStream::connect(broker).await.unwrap();
+            let connect = Packet::Connect {
+                client_id: "test".into(),
+                keep_alive: 60,
+            };
+            client.write_all(&connect.encode()).await.unwrap();
+            Packet::read(&mut client).await.unwrap();
+
+            let filters = vec!["thing/paced/properties/level".into()];
+            let subscribe = Packet::Subscribe { id: 1, filters };
+            client.write_all(&subscribe.encode()).await.unwrap();
+
+            next_publish(&mut client).await;
+            let mut published = 0;
+            let window = tokio::time::sleep(Duration::from_millis(500));
+            tokio::pin!(window);
+            loop {
+                tokio::select! {
+                    _ = &mut window => break,
+                    _ = next_publish(&mut client) => published += 1,
+                }
+            }
+            assert!((2..=6).contains(&published), "{published} publications");
+        };
+
+        serve_until(&servient, listener, checks).await;
+    }
+
+    #[tokio::test]
+    async fn unreachable_broker() {
+        // Nothing listens on the port once the listener is dropped.
+        let broker = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let servient = Servient::builder("unreachable")
+            .finish_extend()
+            .http_bind("127.0.0.1:0".parse().unwrap())
+            .mqtt_broker(format!("mqtt://{broker}"))
+            .build_servient()
+            .unwrap();
+
+        // The http server stops as well instead of serving until shut down.
+        let served = tokio::time::timeout(Duration::from_secs(5), servient.serve()).await;
+        assert!(matches!(served, Ok(Err(crate::servient::Error::Mqtt(_)))));
+    }
+}
@@ -0,0 +1,924 @@
+//! Reverse proxy
+//!
+//! A [`Proxy`] re-exposes remote Things through a local [`Servient`], e.g. a gateway to the
+//! Things of isolated networks. Each remote Thing gets its own path prefix: its description is
+//! served at the prefix, with Forms routed under it forwarding the requests to the original
+//! Forms through a [`ConsumedThing`]. The description of the gateway itself, at `/`, links the
+//! re-exposed ones.
+//!
+//! Each operation of an affordance is forwarded to the http Form the [`ConsumedThing`] would
+//! use, the Forms of the Thing itself and the ones using other protocols are not re-exposed.
+//! The property reads may be cached.
+//!
+//! The re-exposed descriptions keep the security of the remote Things and the credentials the
+//! clients send are forwarded as they are. With [`Proxy::security`], the clients authenticate
+//! with the proxy instead, and the requests it lets through are forwarded with the credentials
+//! of the [`ConsumedThing`]s.
+//!
+//! ```no_run
+//! use wot_serve::{
+//!     consumer::ConsumedThing,
+//!     proxy::Proxy,
+//!     servient::{BuildServient, ServientSettings},
+//! };
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let lamp = ConsumedThing::fetch("http://10.0.1.2:8080/").await?;
+//! let sensor = ConsumedThing::fetch("http://10.0.2.5:8080/").await?;
+//!
+//! let servient = Proxy::new("gateway")
+//!     .thing("/lamp", lamp)
+//!     .thing("/sensor", sensor)
+//!     .builder()?
+//!     .http_bind("0.0.0.0:8080".parse()?)
+//!     .build_servient()?;
+//!
+//! servient.serve().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Servient`]: crate::Servient
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::{boxed, Body, Bytes},
+    extract::Query,
+    http::{
+        header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, COOKIE},
+        HeaderName, HeaderValue, Request, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    routing::{MethodRouter, Route},
+    Json, Router,
+};
+use serde_json::{json, Map, Value};
+use tower::{Layer, Service};
+use wot_td::{
+    builder::{AffordanceType, Extended, ThingBuilder},
+    protocol::http::{HttpProtocol, Method},
+    thing::{
+        FormOperation, KnownSecuritySchemeSubtype, SecurityAuthenticationLocation, SecurityScheme,
+        SecuritySchemeSubtype, Thing,
+    },
+};
+
+use crate::{
+    consumer::{ConsumedThing, InteractionOptions},
+    hlist::NilPlus,
+    servient::{
+        content::{request_bytes, BODY_LIMIT},
+        default_method, method_filter, Error, Middleware, ServientExtension, ServientSettings,
+    },
+    Servient,
+};
+
+/// Media type of the re-exposed Thing Descriptions, as linked by the gateway
+const TD_MEDIA_TYPE: &str = "application/td+json";
+
+/// Operations forwarded for each affordance type
+const OPERATIONS: &[(AffordanceType, &str, &[FormOperation])] = &[
+    (
+        AffordanceType::Property,
+        "properties",
+        &[
+            FormOperation::ReadProperty,
+            FormOperation::WriteProperty,
+            FormOperation::ObserveProperty,
+            FormOperation::UnobserveProperty,
+        ],
+    ),
+    (
+        AffordanceType::Action,
+        "actions",
+        &[
+            FormOperation::InvokeAction,
+            FormOperation::QueryAction,
+            FormOperation::CancelAction,
+        ],
+    ),
+    (
+        AffordanceType::Event,
+        "events",
+        &[
+            FormOperation::SubscribeEvent,
+            FormOperation::UnsubscribeEvent,
+        ],
+    ),
+];
+
+/// Property read replied from the cache
+#[derive(Debug, Clone)]
+struct Cached {
+    at: Instant,
+    status: StatusCode,
+    content_type: Option<HeaderValue>,
+    body: Bytes,
+}
+
+/// Property reads, by property name, query, `Accept` header and forwarded credentials
+type Cache = Mutex<HashMap<CacheKey, Cached>>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    property: String,
+    query: String,
+    accept: String,
+    credentials: String,
+}
+
+/// Most property reads cached, the oldest one is evicted past it
+const CACHE_CAPACITY: usize = 256;
+
+/// Cache the property read `key`, dropping the expired reads and the oldest one once full.
+fn cache_read(
+    cache: &mut HashMap<CacheKey, Cached>,
+    ttl: Duration,
+    capacity: usize,
+    key: CacheKey,
+    cached: Cached,
+) {
+    cache.retain(|_, cached| cached.at.elapsed() < ttl);
+
+    if cache.len() >= capacity && !cache.contains_key(&key) {
+        let oldest = cache
+            .iter()
+            .min_by_key(|(_, cached)| cached.at)
+            .map(|(key, _)| key.clone());
+        if let Some(oldest) = oldest {
+            cache.remove(&oldest);
+        }
+    }
+
+    cache.insert(key, cached);
+}
+
+/// State shared by the forwarding handlers of a remote Thing
+struct Remote {
+    thing: ConsumedThing,
+    cache: Option<(Duration, Cache)>,
+    /// Whether the requests carry the credentials of the [`ConsumedThing`], instead of the
+    /// client ones
+    authorize: bool,
+    /// Headers the clients send their credentials in
+    credential_headers: Vec<HeaderName>,
+    /// Query parameters the clients send their credentials in
+    credential_queries: Vec<String>,
+}
+
+/// Security the clients of the proxy satisfy instead of the remote one
+#[derive(Debug, Clone)]
+struct Authentication {
+    name: String,
+    scheme: SecurityScheme,
+    layer: Middleware<Router>,
+}
+
+/// Builder of a [`Servient`] re-exposing remote Things
+///
+/// [`Servient`]: crate::Servient
+#[derive(Debug, Clone)]
+pub struct Proxy {
+    title: String,
+    things: Vec<(String, ConsumedThing)>,
+    cache: Option<Duration>,
+    authentication: Option<Authentication>,
+}
+
+impl Proxy {
+    /// Create a gateway described as `title`, re-exposing no Thing yet.
+    pub fn new(title: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            things: Vec::new(),
+            cache: None,
+            authentication: None,
+        }
+    }
+
+    /// Re-expose the remote Thing under `prefix`, e.g. `/lamp`.
+    ///
+    /// Its description is served at the prefix, its Forms are routed under it.
+    pub fn thing(mut self, prefix: impl Into<String>, thing: ConsumedThing) -> Self {
+        let prefix = prefix.into();
+        let prefix = format!("/{}", prefix.trim_matches('/'));
+        self.things.push((prefix, thing));
+        self
+    }
+
+    /// Reply to the property reads from a cache for `ttl`.
+    ///
+    /// Writing a property through the proxy clears its cached values. Up to 256 reads are
+    /// cached per Thing, the oldest one is evicted first. The reads are cached by the
+    /// credentials they forward, if any.
+    pub fn cache_reads(mut self, ttl: Duration) -> Self {
+        self.cache = Some(ttl);
+        self
+    }
+
+    /// Require the clients to satisfy the security `scheme`, checked by `layer`.
+    ///
+    /// The re-exposed descriptions declare `scheme` as `name` instead of the security of the
+    /// remote Things. The requests `layer` lets through are forwarded with the credentials of
+    /// the [`ConsumedThing`]s, the descriptions themselves are served without it.
+    pub fn security<L>(mut self, name: impl Into<String>, scheme: SecurityScheme, layer: L) -> Self
+    where
+        L: Layer<Route> + Clone + Send + 'static,
+        L::Service: Service<Request<Body>> + Clone + Send + 'static,
+        <L::Service as Service<Request<Body>>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request<Body>>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+    {
+        self.authentication = Some(Authentication {
+            name: name.into(),
+            scheme,
+            layer: Middleware::new(move |router: Router| router.layer(layer.clone())),
+        });
+        self
+    }
+
+    /// Prepare a builder serving the description of the gateway and the re-exposed ones.
+    ///
+    /// The gateway links each re-exposed description, with the `item` relation. A Thing
+    /// re-exposed at `/` would overlap with the description of the gateway.
+    pub fn builder(self) -> Result<ThingBuilder<NilPlus<ServientExtension>, Extended>, Error> {
+        let mut builder = Servient::builder(self.title).finish_extend();
+
+        for (prefix, thing) in self.things {
+            if prefix == "/" {
+                return Err(Error::OverlappingRoute(prefix));
+            }
+
+            let router = reexpose(&prefix, thing, self.cache, self.authentication.as_ref())?;
+            builder = builder
+                .link_with(|b| b.href(prefix.clone()).rel("item").ty(TD_MEDIA_TYPE))
+                .http_nest(prefix, router);
+        }
+
+        Ok(builder)
+    }
+}
+
+/// Route the re-exposed description of `thing` and its forwarding Forms, under `prefix`.
+fn reexpose(
+    prefix: &str,
+    thing: ConsumedThing,
+    cache: Option<Duration>,
+    authentication: Option<&Authentication>,
+) -> Result<Router, Error> {
+    let mut td = serde_json::to_value(thing.td())?;
+    let (credential_headers, credential_queries) = credential_locations(thing.td());
+
+    let remote = Arc::new(Remote {
+        thing,
+        cache: cache.map(|ttl| (ttl, Mutex::default())),
+        authorize: authentication.is_some(),
+        credential_headers,
+        credential_queries,
+    });
+    let mut forwarding = Router::new();
+
+    for (affordance, key, ops) in OPERATIONS {
+        let Some(affordances) = td.get_mut(*key).and_then(Value::as_object_mut) else {
+            continue;
+        };
+
+        affordances.retain(|name, description| {
+            let Some(remote_forms) = remote.thing.forms(*affordance, name) else {
+                return false;
+            };
+
+            // The operations grouped by the remote Form serving them, a method each.
+            let mut forms: Vec<(usize, Vec<(FormOperation, Method)>)> = Vec::new();
+            for op in *ops {
+                let Ok(index) = remote.thing.form_index(*affordance, name, *op) else {
+                    continue;
+                };
+                let method = remote_forms[index]
+                    .other
+                    .method_name
+                    .unwrap_or_else(|| default_method(*op));
+
+                let group = forms.iter_mut().find(|(i, form_ops)| {
+                    *i == index && form_ops.iter().all(|(_, m)| *m != method)
+                });
+                match group {
+                    Some((_, form_ops)) => form_ops.push((*op, method)),
+                    None => forms.push((index, vec![(*op, method)])),
+                }
+            }
+
+            let remote_descriptions = description["forms"].take();
+            let mut local = Vec::new();
+            for (n, (index, form_ops)) in forms.into_iter().enumerate() {
+                let path = path(key, name, n);
+                let mut form = remote_descriptions[index].clone();
+                let href = href(prefix, &path, form["href"].as_str());
+                let form_map = form.as_object_mut().expect("forms are objects");
+                if authentication.is_some() {
+                    form_map.remove("security");
+                    form_map.remove("scopes");
+                }
+                form_map.insert("href".into(), href.into());
+                let op_names: Vec<_> = form_ops.iter().map(|(op, _)| op).collect();
+                form_map.insert("op".into(), json!(op_names));
+                local.push(form);
+
+                let mut route = MethodRouter::new();
+                for (op, method) in form_ops {
+                    let remote = remote.clone();
+                    let name = name.clone();
+                    let affordance = *affordance;
+                    let handler = move |request: Request<Body>| {
+                        forward(remote.clone(), affordance, name.clone(), index, op, request)
+                    };
+                    route = route.on(method_filter(method), handler);
+                }
+                forwarding = std::mem::take(&mut forwarding).route(&path, route);
+            }
+
+            let exposed = !local.is_empty();
+            description["forms"] = local.into();
+            exposed
+        });
+    }
+
+    let td_map = td.as_object_mut().expect("the description is an object");
+    td_map.remove("forms");
+    td_map.remove("base");
+
+    if let Some(authentication) = authentication {
+        td_map.insert(
+            "securityDefinitions".into(),
+            json!({ &authentication.name: authentication.scheme }),
+        );
+        td_map.insert("security".into(), authentication.name.clone().into());
+        forwarding = authentication.layer.apply(forwarding);
+    }
+
+    let description = Router::new().route(
+        "/",
+        axum::routing::get(move || async move { Json(td.clone()) }),
+    );
+
+    Ok(description.merge(forwarding))
+}
+
+/// Headers and query parameters the credentials of the security schemes of `td` are sent in.
+fn credential_locations(td: &Thing<HttpProtocol>) -> (Vec<HeaderName>, Vec<String>) {
+    use KnownSecuritySchemeSubtype::*;
+
+    let mut headers = vec![AUTHORIZATION];
+    let mut queries = Vec::new();
+
+    for definition in td.security_definitions.values() {
+        let SecuritySchemeSubtype::Known(subtype) = &definition.subtype else {
+            continue;
+        };
+        let (location, name) = match subtype {
+            Basic(scheme) => (&scheme.location, &scheme.name),
+            Digest(scheme) => (&scheme.location, &scheme.name),
+            Bearer(scheme) => (&scheme.location, &scheme.name),
+            ApiKey(scheme) => (&scheme.location, &scheme.name),
+            _ => continue,
+        };
+
+        match (location, name) {
+            (SecurityAuthenticationLocation::Header, Some(name)) => {
+                if let Ok(name) = HeaderName::try_from(name.as_str()) {
+                    headers.push(name);
+                }
+            }
+            (SecurityAuthenticationLocation::Query, Some(name)) => queries.push(name.clone()),
+            (SecurityAuthenticationLocation::Cookie, _) => headers.push(COOKIE),
+            _ => {}
+        }
+    }
+
+    (headers, queries)
+}
+
+/// Path of the `n`th re-exposed Form of an affordance, under the prefix of its Thing.
+fn path(key: &str, name: &str, n: usize) -> String {
+    match n {
+        0 => format!("/{key}/{name}"),
+        n => format!("/{key}/{name}/{n}"),
+    }
+}
+
+/// Href of the re-exposed Form at `path`, keeping the variables of the remote one.
+fn href(prefix: &str, path: &str, remote: Option<&str>) -> String {
+    use datta::TemplateComponent::VarList;
+
+    let mut href = format!("{prefix}{path}");
+
+    let template = datta::UriTemplate::new(remote.unwrap_or_default());
+    let variables: Vec<_> = template
+        .components()
+        .iter()
+        .filter_map(|component| match component {
+            VarList(_, varspecs) => Some(varspecs.iter().map(|v| v.name.clone())),
+            _ => None,
+        })
+        .flatten()
+        .collect();
+    if !variables.is_empty() {
+        href.push_str(&format!("{{?{}}}", variables.join(",")));
+    }
+
+    href
+}
+
+fn response(
+    status: StatusCode,
+    content_type: Option<HeaderValue>,
+    body: impl Into<Body>,
+) -> Response {
+    let mut response = Response::builder().status(status);
+    if let Some(content_type) = content_type {
+        response = response.header(CONTENT_TYPE, content_type);
+    }
+
+    response
+        .body(boxed(body.into()))
+        .unwrap_or_else(|_| StatusCode::BAD_GATEWAY.into_response())
+}
+
+/// Forward a request to the Form at `index` of the remote affordance.
+async fn forward(
+    remote: Arc<Remote>,
+    affordance: AffordanceType,
+    name: String,
+    index: usize,
+    op: FormOperation,
+    request: Request<Body>,
+) -> Response {
+    let Ok(Query(query)) = Query::<HashMap<String, String>>::try_from_uri(request.uri()) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    // Without credentials of its own, the proxy forwards the client ones.
+    let (credential_headers, credential_queries) = if remote.authorize {
+        (Vec::new(), Vec::new())
+    } else {
+        let headers: Vec<_> = remote
+            .credential_headers
+            .iter()
+            .filter_map(|name| Some((name.clone(), request.headers().get(name)?.clone())))
+            .collect();
+        let queries: Vec<_> = remote
+            .credential_queries
+            .iter()
+            .filter_map(|name| Some((name.clone(), query.get(name)?.clone())))
+            .collect();
+        (headers, queries)
+    };
+
+    let options = InteractionOptions {
+        uri_variables: query
+            .into_iter()
+            .map(|(k, v)| (k, v.into()))
+            .collect::<Map<_, _>>(),
+    };
+
+    let header = |name| request.headers().get(name).cloned();
+    let (content_type, accept) = (header(CONTENT_TYPE), header(ACCEPT));
+
+    let cache = remote
+        .cache
+        .as_ref()
+        .filter(|_| op == FormOperation::ReadProperty);
+    let key = CacheKey {
+        property: name.clone(),
+        query: request.uri().query().unwrap_or_default().to_string(),
+        accept: accept
+            .as_ref()
+            .and_then(|accept| accept.to_str().ok())
+            .unwrap_or_default()
+            .to_string(),
+        credentials: credential_headers
+            .iter()
+            .map(|(name, value)| format!("{name}: {}", value.to_str().unwrap_or_default()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    };
+    if let Some((ttl, cache)) = cache {
+        if let Some(cached) = cache.lock().unwrap().get(&key) {
+            if cached.at.elapsed() < *ttl {
+                let cached = cached.clone();
+                return response(cached.status, cached.content_type, cached.body);
+            }
+        }
+    }
+
+    let body = match request_bytes(request.into_body(), BODY_LIMIT).await {
+        Ok(body) => body,
+        Err(rejection) => return rejection,
+    };
+
+    let mut forwarded =
+        match remote
+            .thing
+            .form_request(affordance, &name, index, op, &options, remote.authorize)
+        {
+            Ok(forwarded) => forwarded,
+            Err(err) => return (StatusCode::BAD_GATEWAY, err.to_string()).into_response(),
+        };
+    for (name, value) in credential_headers {
+        forwarded = forwarded.header(name, value);
+    }
+    if !credential_queries.is_empty() {
+        forwarded = forwarded.query(&credential_queries);
+    }
+    if let Some(content_type) = content_type {
+        forwarded = forwarded.header(CONTENT_TYPE, content_type);
+    }
+    if let Some(accept) = accept {
+        forwarded = forwarded.header(ACCEPT, accept);
+    }
+    if !body.is_empty() {
+        forwarded = forwarded.body(body);
+    }
+
+    let mut replied = match forwarded.send().await {
+        Ok(replied) => replied,
+        Err(err) => return (StatusCode::BAD_GATEWAY, err.to_string()).into_response(),
+    };
+    let status = replied.status();
+    let content_type = replied.headers().get(CONTENT_TYPE).cloned();
+
+    if op == FormOperation::WriteProperty && status.is_success() {
+        if let Some((_, cache)) = &remote.cache {
+            cache.lock().unwrap().retain(|key, _| key.property != name);
+        }
+    }
+
+    if let Some((ttl, cache)) = cache {
+        let Ok(body) = replied.bytes().await else {
+            return StatusCode::BAD_GATEWAY.into_response();
+        };
+        if status.is_success() {
+            let cached = Cached {
+                at: Instant::now(),
+                status,
+                content_type: content_type.clone(),
+                body: body.clone(),
+            };
+            cache_read(
+                &mut cache.lock().unwrap(),
+                *ttl,
+                CACHE_CAPACITY,
+                key,
+                cached,
+            );
+        }
+        return response(status, content_type, body);
+    }
+
+    // Streamed, the events may keep coming.
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        loop {
+            match replied.chunk().await {
+                Ok(Some(chunk)) => {
+                    if sender.send_data(chunk).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(_) => {
+                    sender.abort();
+                    break;
+                }
+            }
+        }
+    });
+
+    response(status, content_type, body)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::{
+        extract::State,
+        http::{header::AUTHORIZATION, HeaderMap},
+        middleware::Next,
+    };
+    use tokio::sync::broadcast;
+    use tower::ServiceExt;
+    use wot_td::builder::{affordance::*, data_schema::*};
+
+    use crate::{
+        consumer::Credentials,
+        servient::{content::body_bytes, BuildServient, HttpRouter},
+        test_util::{local_listener, serve_until},
+        Servient,
+    };
+
+    use super::*;
+
+    type AppState = (Arc<AtomicUsize>, broadcast::Sender<u8>);
+
+    /// A remote lamp requiring basic authentication, counting the reads of its level.
+    fn remote_lamp(
+        reads: Arc<AtomicUsize>,
+        events: broadcast::Sender<u8>,
+    ) -> Servient<NilPlus<ServientExtension<AppState>>, AppState> {
+        Servient::stateful_builder("lamp")
+            .security(|b| b.basic().with_key("basic").required())
+            .finish_extend()
+            .with_state((reads, events))
+            .property("level", |b| {
+                b.finish_extend_data_schema().integer().form(|f| {
+                    f.href("/level")
+                        .http_get(
+                            |State((reads, _)): State<AppState>, headers: HeaderMap| async move {
+                                // user:pass
+                                if headers
+                                    .get(AUTHORIZATION)
+                                    .is_none_or(|auth| auth != "Basic dXNlcjpwYXNz")
+                                {
+                                    return Err(StatusCode::UNAUTHORIZED);
+                                }
+                                Ok(Json(reads.fetch_add(1, Ordering::SeqCst) + 1))
+                            },
+                        )
+                        .http_put(|Json(_): Json<usize>| async move {})
+                })
+            })
+            .property("reading", |b| {
+                b.finish_extend_data_schema().string().form(|f| {
+                    f.href("/reading{?unit}")
+                        .http_get(|Query(query): Query<HashMap<String, String>>| async move {
+                            Json(query.get("unit").cloned().unwrap_or_default())
+                        })
+                        .op(FormOperation::ReadProperty)
+                })
+            })
+            .action("double", |b| {
+                b.input(|b| b.finish_extend().integer()).form(|f| {
+                    f.href("/double")
+                        .http_post(|Json(n): Json<i64>| async move { Json(n * 2) })
+                })
+            })
+            .event("changed", |b| {
+                b.form(|f| {
+                    f.href("/changed")
+                        .http_get(|State((_, events)): State<AppState>| async move {
+                            Json(events.subscribe().recv().await.unwrap())
+                        })
+                        .op(FormOperation::SubscribeEvent)
+                })
+            })
+            .build_servient()
+            .unwrap()
+    }
+
+    async fn fetch(url: String) -> ConsumedThing {
+        ConsumedThing::fetch(&url).await.unwrap()
+    }
+
+    fn basic(password: &str) -> Credentials {
+        Credentials::Basic {
+            username: "user".into(),
+            password: password.into(),
+        }
+    }
+
+    #[test]
+    fn rewrite_hrefs() {
+        assert_eq!(path("properties", "on", 0), "/properties/on");
+        assert_eq!(path("actions", "fade", 1), "/actions/fade/1");
+        assert_eq!(
+            href("/lamp", "/properties/on", Some("http://10.0.0.2/on")),
+            "/lamp/properties/on"
+        );
+        assert_eq!(
+            href(
+                "/lamp",
+                "/actions/fade/1",
+                Some("/things/{id}/fade{?speed}")
+            ),
+            "/lamp/actions/fade/1{?id,speed}"
+        );
+    }
+
+    #[test]
+    fn prune_cache() {
+        let ttl = Duration::from_secs(60);
+        let key = |name: &str| CacheKey {
+            property: name.to_string(),
+            query: String::new(),
+            accept: String::new(),
+            credentials: String::new(),
+        };
+        let cached = |age| Cached {
+            at: Instant::now() - Duration::from_secs(age),
+            status: StatusCode::OK,
+            content_type: None,
+            body: Bytes::new(),
+        };
+
+        let mut cache = HashMap::new();
+        cache_read(&mut cache, ttl, 2, key("expired"), cached(120));
+        cache_read(&mut cache, ttl, 2, key("old"), cached(10));
+        assert!(!cache.contains_key(&key("expired")));
+
+        // Once full, the oldest read is evicted.
+        cache_read(&mut cache, ttl, 2, key("recent"), cached(5));
+        assert_eq!(cache.len(), 2);
+        cache_read(&mut cache, ttl, 2, key("latest"), cached(0));
+        assert!(!cache.contains_key(&key("old")));
+        assert_eq!(cache.len(), 2);
+
+        // Refreshing a cached read evicts nothing.
+        cache_read(&mut cache, ttl, 2, key("recent"), cached(0));
+        assert!(cache.contains_key(&key("latest")));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn proxy_servient() {
+        let reads = Arc::new(AtomicUsize::new(0));
+        let (events, _) = broadcast::channel::<u8>(4);
+        let remote = remote_lamp(reads.clone(), events.clone());
+        let (remote_listener, remote_addr) = local_listener();
+
+        let (sensor_listener, sensor_addr) = local_listener();
+        let sensor = Servient::builder("sensor")
+            .finish_extend()
+            .property("temperature", |b| {
+                b.finish_extend_data_schema().number().form(|f| {
+                    f.href("/temperature")
+                        .http_get(|| async { Json(21.5) })
+                        .op(FormOperation::ReadProperty)
+                })
+            })
+            .build_servient()
+            .unwrap();
+
+        let checks = async {
+            // The credentials of the remote Things are left to the clients.
+            let lamp = fetch(format!("http://{remote_addr}/")).await;
+            let sensor_thing = fetch(format!("http://{sensor_addr}/")).await;
+
+            let (proxy_listener, proxy_addr) = local_listener();
+            let proxy = Proxy::new("gateway")
+                .thing("lamp/", lamp)
+                .thing("/sensor", sensor_thing)
+                .cache_reads(Duration::from_secs(3600))
+                .builder()
+                .unwrap()
+                .build_servient()
+                .unwrap();
+
+            let proxy_checks = async {
+                let gateway = fetch(format!("http://{proxy_addr}/")).await;
+                let links = serde_json::to_value(&gateway.td().links).unwrap();
+                assert_eq!(links[0]["href"], "/lamp");
+                assert_eq!(links[0]["rel"], "item");
+                assert_eq!(links[1]["href"], "/sensor");
+
+                let url = format!("http://{proxy_addr}/lamp");
+                let thing = fetch(url.clone()).await.credentials("basic", basic("pass"));
+                let intruder = fetch(url).await.credentials("basic", basic("guess"));
+
+                let td = serde_json::to_value(thing.td()).unwrap();
+                assert_eq!(td["security"], "basic");
+                assert_eq!(td["securityDefinitions"]["basic"]["scheme"], "basic");
+                assert_eq!(
+                    td["properties"]["level"]["forms"][0]["href"],
+                    "/lamp/properties/level"
+                );
+                assert_eq!(
+                    td["properties"]["reading"]["forms"][0]["href"],
+                    "/lamp/properties/reading{?unit}"
+                );
+
+                assert_eq!(thing.read_property::<usize>("level").await.unwrap(), 1);
+                assert_eq!(thing.read_property::<usize>("level").await.unwrap(), 1);
+                // The cached reads are not shared with other credentials.
+                assert!(matches!(
+                    intruder.read_property::<usize>("level").await,
+                    Err(crate::consumer::Error::Status(StatusCode::UNAUTHORIZED))
+                ));
+                thing.write_property("level", &7).await.unwrap();
+                assert_eq!(thing.read_property::<usize>("level").await.unwrap(), 2);
+                assert_eq!(reads.load(Ordering::SeqCst), 2);
+
+                let options = InteractionOptions::default().uri_variable("unit", "celsius");
+                let unit: String = thing.read_property_with("reading", &options).await.unwrap();
+                assert_eq!(unit, "celsius");
+
+                let doubled: i64 = thing.invoke_action("double", &21).await.unwrap();
+                assert_eq!(doubled, 42);
+
+                let mut changes = thing.subscribe_event::<u8>("changed").await.unwrap();
+                while events.receiver_count() == 0 {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                events.send(3).unwrap();
+                assert_eq!(changes.next().await.unwrap().unwrap(), 3);
+
+                let sensor = fetch(format!("http://{proxy_addr}/sensor")).await;
+                let temperature: f64 = sensor.read_property("temperature").await.unwrap();
+                assert_eq!(temperature, 21.5);
+            };
+
+            serve_until(&proxy, proxy_listener, proxy_checks).await;
+        };
+
+        let checks = async { serve_until(&sensor, sensor_listener, checks).await };
+        serve_until(&remote, remote_listener, checks).await;
+    }
+
+    #[tokio::test]
+    async fn proxy_security() {
+        let (events, _) = broadcast::channel::<u8>(4);
+        let remote = remote_lamp(Arc::default(), events);
+        let (listener, addr) = local_listener();
+
+        let checks = async {
+            let lamp = fetch(format!("http://{addr}/"))
+                .await
+                .credentials("basic", basic("pass"));
+
+            let bearer = serde_json::from_value(json!({ "scheme": "bearer" })).unwrap();
+            let authenticated =
+                axum::middleware::from_fn(|request: Request<Body>, next: Next<Body>| async move {
+                    if request
+                        .headers()
+                        .get(AUTHORIZATION)
+                        .is_none_or(|auth| auth != "Bearer token")
+                    {
+                        return StatusCode::UNAUTHORIZED.into_response();
+                    }
+                    next.run(request).await
+                });
+            let proxy = Proxy::new("gateway")
+                .thing("/lamp", lamp)
+                .security("bearer_sc", bearer, authenticated)
+                .builder()
+                .unwrap()
+                .build_servient()
+                .unwrap();
+
+            let get = |uri: &str, token: Option<&str>| {
+                let mut request = Request::get(uri);
+                if let Some(token) = token {
+                    request = request.header(AUTHORIZATION, format!("Bearer {token}"));
+                }
+                proxy
+                    .router
+                    .clone()
+                    .oneshot(request.body(Body::empty()).unwrap())
+            };
+
+            // The description is served to anyone, declaring the security of the proxy.
+            let res = get("/lamp", None).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            let td: Value =
+                serde_json::from_slice(&body_bytes(res.into_body()).await.unwrap()).unwrap();
+            assert_eq!(td["security"], "bearer_sc");
+            assert_eq!(td["securityDefinitions"]["bearer_sc"]["scheme"], "bearer");
+
+            let res = get("/lamp/properties/level", None).await.unwrap();
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+            let res = get("/lamp/properties/level", Some("guess")).await.unwrap();
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+            // The remote Thing gets the credentials of the proxy.
+            let res = get("/lamp/properties/level", Some("token")).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(body_bytes(res.into_body()).await.unwrap(), "1");
+        };
+
+        serve_until(&remote, listener, checks).await;
+    }
+
+    #[test]
+    fn gateway_root() {
+        let td = json!({
+            "title": "root",
+            "security": "nosec_sc",
+            "securityDefinitions": { "nosec_sc": { "scheme": "nosec" } },
+        });
+        let thing = ConsumedThing::new(
+            serde_json::from_value(td).unwrap(),
+            "http://localhost/".parse().unwrap(),
+        );
+
+        assert!(matches!(
+            Proxy::new("gateway").thing("/", thing).builder(),
+            Err(Error::OverlappingRoute(_))
+        ));
+    }
+}
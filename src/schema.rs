@@ -0,0 +1,644 @@
+//! Data schemas of Rust types
+//!
+//! [`ToDataSchema`] describes the values of a type as a WoT [`DataSchema`], so the schemas of
+//! the affordances follow the types their handlers use:
+//!
+//! - it is implemented for the primitive types and the standard containers.
+//! - `#[derive(ToDataSchema)]` maps structs to objects and enums to strings or `oneOf`
+//!   alternatives, following the default serde representation. The `rename`, `skip` and
+//!   `default` serde attributes are taken into account.
+//!
+//! [`validate`] checks json values against the schemas of any Thing Description.
+//!
+//! ```
+//! use wot_serve::schema::ToDataSchema;
+//!
+//! /// Fading transition
+//! #[derive(ToDataSchema)]
+//! struct Fade {
+//!     /// Target brightness
+//!     to: u8,
+//!     /// Duration in milliseconds
+//!     duration: Option<u32>,
+//! }
+//!
+//! let schema = serde_json::to_value(Fade::data_schema()).unwrap();
+//!
+//! assert_eq!(schema["type"], "object");
+//! assert_eq!(schema["required"], serde_json::json!(["to"]));
+//! ```
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    sync::Arc,
+};
+
+use serde_json::Value;
+use wot_td::{
+    hlist::Nil,
+    thing::{
+        ArraySchema, DataSchemaSubtype, IntegerSchema, Maximum, Minimum, NumberSchema,
+        ObjectSchema, StringSchema,
+    },
+};
+
+pub use wot_serve_derive::ToDataSchema;
+
+/// Data schema without extensions
+pub type DataSchema = wot_td::thing::DataSchema<Nil, Nil, Nil>;
+
+/// A type whose values are described by a [`DataSchema`]
+///
+/// Derive it with `#[derive(ToDataSchema)]`.
+pub trait ToDataSchema {
+    /// The data schema of the values of the type.
+    fn data_schema() -> DataSchema;
+}
+
+fn subtype(subtype: DataSchemaSubtype<Nil, Nil, Nil>) -> DataSchema {
+    DataSchema {
+        subtype: Some(subtype),
+        ..Default::default()
+    }
+}
+
+fn array(items: Vec<DataSchema>, len: Option<u32>) -> DataSchema {
+    subtype(DataSchemaSubtype::Array(ArraySchema {
+        items: Some(items),
+        min_items: len,
+        max_items: len,
+        other: Nil,
+    }))
+}
+
+impl ToDataSchema for bool {
+    fn data_schema() -> DataSchema {
+        subtype(DataSchemaSubtype::Boolean)
+    }
+}
+
+impl ToDataSchema for () {
+    fn data_schema() -> DataSchema {
+        subtype(DataSchemaSubtype::Null)
+    }
+}
+
+macro_rules! impl_integer {
+    ($($ty:ty: $minimum:expr, $maximum:expr;)*) => {
+        $(
+            impl ToDataSchema for $ty {
+                fn data_schema() -> DataSchema {
+                    subtype(DataSchemaSubtype::Integer(IntegerSchema {
+                        minimum: $minimum.map(Minimum::Inclusive),
+                        maximum: $maximum.map(Maximum::Inclusive),
+                        multiple_of: None,
+                    }))
+                }
+            }
+        )*
+    };
+}
+
+// The integer schemas cannot express negative bounds.
+impl_integer! {
+    u8: Some(0), Some(u8::MAX as usize);
+    u16: Some(0), Some(u16::MAX as usize);
+    u32: Some(0), Some(u32::MAX as usize);
+    u64: Some(0), None;
+    u128: Some(0), None;
+    usize: Some(0), None;
+    i8: None, Some(i8::MAX as usize);
+    i16: None, Some(i16::MAX as usize);
+    i32: None, Some(i32::MAX as usize);
+    i64: None, None;
+    i128: None, None;
+    isize: None, None;
+}
+
+impl ToDataSchema for f32 {
+    fn data_schema() -> DataSchema {
+        subtype(DataSchemaSubtype::Number(NumberSchema::default()))
+    }
+}
+
+impl ToDataSchema for f64 {
+    fn data_schema() -> DataSchema {
+        subtype(DataSchemaSubtype::Number(NumberSchema::default()))
+    }
+}
+
+impl ToDataSchema for String {
+    fn data_schema() -> DataSchema {
+        subtype(DataSchemaSubtype::String(StringSchema::default()))
+    }
+}
+
+impl ToDataSchema for str {
+    fn data_schema() -> DataSchema {
+        String::data_schema()
+    }
+}
+
+impl ToDataSchema for char {
+    fn data_schema() -> DataSchema {
+        subtype(DataSchemaSubtype::String(StringSchema {
+            min_length: Some(1),
+            max_length: Some(1),
+            ..Default::default()
+        }))
+    }
+}
+
+/// Any json value
+impl ToDataSchema for Value {
+    fn data_schema() -> DataSchema {
+        DataSchema::default()
+    }
+}
+
+impl<T: ToDataSchema> ToDataSchema for Option<T> {
+    fn data_schema() -> DataSchema {
+        DataSchema {
+            one_of: Some(vec![T::data_schema(), <()>::data_schema()]),
+            ..Default::default()
+        }
+    }
+}
+
+impl<T: ToDataSchema + ?Sized> ToDataSchema for &T {
+    fn data_schema() -> DataSchema {
+        T::data_schema()
+    }
+}
+
+impl<T: ToDataSchema + ?Sized> ToDataSchema for Box<T> {
+    fn data_schema() -> DataSchema {
+        T::data_schema()
+    }
+}
+
+impl<T: ToDataSchema + ?Sized> ToDataSchema for Arc<T> {
+    fn data_schema() -> DataSchema {
+        T::data_schema()
+    }
+}
+
+impl<T: ToDataSchema> ToDataSchema for [T] {
+    fn data_schema() -> DataSchema {
+        array(vec![T::data_schema()], None)
+    }
+}
+
+impl<T: ToDataSchema, const N: usize> ToDataSchema for [T; N] {
+    fn data_schema() -> DataSchema {
+        array(vec![T::data_schema()], u32::try_from(N).ok())
+    }
+}
+
+macro_rules! impl_sequence {
+    ($($ty:ident),*) => {
+        $(
+            impl<T: ToDataSchema> ToDataSchema for $ty<T> {
+                fn data_schema() -> DataSchema {
+                    array(vec![T::data_schema()], None)
+                }
+            }
+        )*
+    };
+}
+
+impl_sequence!(Vec, VecDeque, HashSet, BTreeSet);
+
+macro_rules! impl_map {
+    ($($ty:ident),*) => {
+        $(
+            impl<K, V> ToDataSchema for $ty<K, V> {
+                fn data_schema() -> DataSchema {
+                    subtype(DataSchemaSubtype::Object(ObjectSchema::default()))
+                }
+            }
+        )*
+    };
+}
+
+impl_map!(HashMap, BTreeMap);
+
+macro_rules! impl_tuple {
+    ($($ty:ident),*) => {
+        impl<$($ty: ToDataSchema),*> ToDataSchema for ($($ty,)*) {
+            fn data_schema() -> DataSchema {
+                let items = vec![$($ty::data_schema()),*];
+                let len = items.len() as u32;
+
+                array(items, Some(len))
+            }
+        }
+    };
+}
+
+impl_tuple!(A);
+impl_tuple!(A, B);
+impl_tuple!(A, B, C);
+impl_tuple!(A, B, C, D);
+
+/// A value not matching its data schema
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[error("the value at /{path} {reason}")]
+pub struct Violation {
+    /// Location of the offending value, as the segments of a json pointer
+    pub path: String,
+    /// Constraint the value breaks
+    pub reason: String,
+}
+
+/// Check a json value against a data schema, given as json.
+///
+/// The schema is taken as json to accept the schemas of any Thing Description, extensions
+/// included. The constraints not expressible in json, e.g. `contentMediaType`, and the
+/// unknown types are not checked.
+///
+/// ```
+/// use serde_json::json;
+/// use wot_serve::schema::validate;
+///
+/// let schema = json!({ "type": "integer", "maximum": 10 });
+///
+/// assert!(validate(&schema, &json!(5)).is_ok());
+/// assert_eq!(
+///     validate(&schema, &json!("5")).unwrap_err().to_string(),
+///     "the value at / is not an integer"
+/// );
+/// ```
+pub fn validate(schema: &Value, value: &Value) -> Result<(), Violation> {
+    check(schema, value, &mut Vec::new())
+}
+
+fn violation(path: &[String], reason: impl Into<String>) -> Violation {
+    Violation {
+        path: path.join("/"),
+        reason: reason.into(),
+    }
+}
+
+fn check(schema: &Value, value: &Value, path: &mut Vec<String>) -> Result<(), Violation> {
+    let Some(schema) = schema.as_object() else {
+        return Ok(());
+    };
+
+    if let Some(constant) = schema.get("const") {
+        if constant != value {
+            return Err(violation(path, format!("is not {constant}")));
+        }
+    }
+    if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+        if !values.contains(value) {
+            return Err(violation(path, "is not one of the enumerated values"));
+        }
+    }
+    if let Some(schemas) = schema.get("oneOf").and_then(Value::as_array) {
+        let matching = schemas
+            .iter()
+            .filter(|schema| check(schema, value, path).is_ok())
+            .count();
+        if matching != 1 {
+            return Err(violation(
+                path,
+                format!("matches {matching} of the oneOf schemas instead of one"),
+            ));
+        }
+    }
+
+    let number = |key| schema.get(key).and_then(Value::as_f64);
+    // wot-td serializes the array bounds in snake case.
+    let length = |key, alias| {
+        schema
+            .get(key)
+            .or_else(|| schema.get(alias))
+            .and_then(Value::as_u64)
+    };
+
+    match schema.get("type").and_then(Value::as_str) {
+        Some("null") if !value.is_null() => Err(violation(path, "is not null")),
+        Some("boolean") if !value.is_boolean() => Err(violation(path, "is not a boolean")),
+        Some(ty @ ("integer" | "number")) => {
+            let Some(n) = value.as_f64() else {
+                return Err(violation(path, format!("is not {}", article(ty))));
+            };
+            if ty == "integer" && n.fract() != 0.0 {
+                return Err(violation(path, "is not an integer"));
+            }
+
+            if let Some(min) = number("minimum").filter(|min| n < *min) {
+                return Err(violation(path, format!("is less than {min}")));
+            }
+            if let Some(min) = number("exclusiveMinimum").filter(|min| n <= *min) {
+                return Err(violation(path, format!("is not greater than {min}")));
+            }
+            if let Some(max) = number("maximum").filter(|max| n > *max) {
+                return Err(violation(path, format!("is greater than {max}")));
+            }
+            if let Some(max) = number("exclusiveMaximum").filter(|max| n >= *max) {
+                return Err(violation(path, format!("is not less than {max}")));
+            }
+            if let Some(step) = number("multipleOf").filter(|step| *step > 0.0) {
+                // Compared to the rounded quotient, as decimal steps are not exact in binary.
+                let quotient = n / step;
+                if (quotient - quotient.round()).abs() > 1e-9 * quotient.abs().max(1.0) {
+                    return Err(violation(path, format!("is not a multiple of {step}")));
+                }
+            }
+
+            Ok(())
+        }
+        Some("string") => {
+            let Some(s) = value.as_str() else {
+                return Err(violation(path, "is not a string"));
+            };
+            let len = s.chars().count() as u64;
+
+            if let Some(min) = length("minLength", "min_length").filter(|min| len < *min) {
+                return Err(violation(path, format!("is shorter than {min} characters")));
+            }
+            if let Some(max) = length("maxLength", "max_length").filter(|max| len > *max) {
+                return Err(violation(path, format!("is longer than {max} characters")));
+            }
+
+            Ok(())
+        }
+        Some("array") => {
+            let Some(values) = value.as_array() else {
+                return Err(violation(path, "is not an array"));
+            };
+            let len = values.len() as u64;
+
+            if let Some(min) = length("minItems", "min_items").filter(|min| len < *min) {
+                return Err(violation(path, format!("has less than {min} items")));
+            }
+            if let Some(max) = length("maxItems", "max_items").filter(|max| len > *max) {
+                return Err(violation(path, format!("has more than {max} items")));
+            }
+
+            for (idx, value) in values.iter().enumerate() {
+                let items = match schema.get("items") {
+                    Some(Value::Array(items)) => items.get(idx),
+                    items => items,
+                };
+                if let Some(items) = items {
+                    path.push(idx.to_string());
+                    check(items, value, path)?;
+                    path.pop();
+                }
+            }
+
+            Ok(())
+        }
+        Some("object") => {
+            let Some(values) = value.as_object() else {
+                return Err(violation(path, "is not an object"));
+            };
+
+            let required = schema.get("required").and_then(Value::as_array);
+            for name in required.into_iter().flatten().filter_map(Value::as_str) {
+                if !values.contains_key(name) {
+                    return Err(violation(path, format!("has no {name} property")));
+                }
+            }
+
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for (name, schema) in properties.into_iter().flatten() {
+                if let Some(value) = values.get(name) {
+                    path.push(name.clone());
+                    check(schema, value, path)?;
+                    path.pop();
+                }
+            }
+
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn article(ty: &str) -> String {
+    match ty {
+        "integer" => "an integer".into(),
+        ty => format!("a {ty}"),
+    }
+}
+
+#[doc(hidden)]
+/// Runtime support of the derived code
+pub mod __private {
+    use wot_td::thing::{DataSchemaSubtype, ObjectSchema};
+
+    use super::{array, subtype, DataSchema, ToDataSchema};
+
+    pub fn described(mut schema: DataSchema, description: Option<&str>) -> DataSchema {
+        if let Some(description) = description {
+            schema.description = Some(description.into());
+        }
+        schema
+    }
+
+    pub fn object(properties: Vec<(&str, DataSchema, bool)>) -> DataSchema {
+        let required: Vec<_> = properties
+            .iter()
+            .filter(|(_, _, required)| *required)
+            .map(|(name, _, _)| name.to_string())
+            .collect();
+        let properties = properties
+            .into_iter()
+            .map(|(name, schema, _)| (name.to_string(), schema))
+            .collect();
+
+        subtype(DataSchemaSubtype::Object(ObjectSchema {
+            properties: Some(properties),
+            required: (!required.is_empty()).then_some(required),
+            ..Default::default()
+        }))
+    }
+
+    pub fn tuple(items: Vec<DataSchema>) -> DataSchema {
+        let len = items.len() as u32;
+
+        array(items, Some(len))
+    }
+
+    pub fn string_enum(names: &[&str]) -> DataSchema {
+        DataSchema {
+            enumeration: Some(names.iter().map(|name| (*name).into()).collect()),
+            ..String::data_schema()
+        }
+    }
+
+    pub fn constant(name: &str) -> DataSchema {
+        DataSchema {
+            constant: Some(name.into()),
+            ..String::data_schema()
+        }
+    }
+
+    pub fn one_of(alternatives: Vec<DataSchema>) -> DataSchema {
+        DataSchema {
+            one_of: Some(alternatives),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    fn schema<T: ToDataSchema + ?Sized>() -> Value {
+        serde_json::to_value(T::data_schema()).unwrap()
+    }
+
+    /// A color
+    #[derive(ToDataSchema)]
+    #[allow(dead_code)]
+    enum Color {
+        Red,
+        #[serde(rename = "verde")]
+        Green,
+    }
+
+    #[derive(ToDataSchema)]
+    #[allow(dead_code)]
+    enum Shape {
+        Point,
+        Circle(f64),
+        Rect { w: f64, h: f64 },
+    }
+
+    #[derive(ToDataSchema)]
+    #[allow(dead_code)]
+    struct Meters(f64);
+
+    #[derive(ToDataSchema)]
+    #[allow(dead_code)]
+    struct Light<T> {
+        /// Light color
+        color: Color,
+        #[serde(rename = "level")]
+        brightness: T,
+        #[serde(default)]
+        on: bool,
+        #[serde(skip)]
+        hidden: (),
+        range: Option<Meters>,
+    }
+
+    #[test]
+    fn primitive_schemas() {
+        assert_eq!(
+            schema::<u8>(),
+            json!({
+                "type": "integer",
+                "minimum": 0,
+                "maximum": 255,
+                "readOnly": false,
+                "writeOnly": false,
+            })
+        );
+        assert_eq!(schema::<str>()["type"], "string");
+        assert_eq!(schema::<Vec<bool>>()["items"]["type"], "boolean");
+        assert!(matches!(
+            <(u8, String)>::data_schema().subtype,
+            Some(DataSchemaSubtype::Array(ArraySchema {
+                max_items: Some(2),
+                ..
+            }))
+        ));
+        assert_eq!(schema::<Option<f64>>()["oneOf"][1]["type"], "null");
+        assert!(schema::<Value>().get("type").is_none());
+    }
+
+    #[test]
+    fn derived_schemas() {
+        let color = schema::<Color>();
+        assert_eq!(color["type"], "string");
+        assert_eq!(color["enum"], json!(["Red", "verde"]));
+        assert_eq!(color["description"], "A color");
+
+        let shape = schema::<Shape>();
+        assert_eq!(shape["oneOf"][0]["const"], "Point");
+        assert_eq!(shape["oneOf"][1]["properties"]["Circle"]["type"], "number");
+        assert_eq!(shape["oneOf"][1]["required"], json!(["Circle"]));
+        assert_eq!(
+            shape["oneOf"][2]["properties"]["Rect"]["required"],
+            json!(["w", "h"])
+        );
+
+        assert_eq!(schema::<Meters>()["type"], "number");
+
+        let light = schema::<Light<u8>>();
+        assert_eq!(light["type"], "object");
+        assert_eq!(light["properties"]["color"]["description"], "Light color");
+        assert_eq!(light["properties"]["level"]["maximum"], 255);
+        assert!(light["properties"].get("hidden").is_none());
+        assert_eq!(light["required"], json!(["color", "level"]));
+    }
+
+    #[test]
+    fn validate_values() {
+        let light = schema::<Light<u8>>();
+        let reason = |value| validate(&light, &value).unwrap_err().to_string();
+
+        assert_eq!(
+            validate(&light, &json!({ "color": "Red", "level": 3 })),
+            Ok(())
+        );
+        assert_eq!(
+            reason(json!({ "color": "Red" })),
+            "the value at / has no level property"
+        );
+        assert_eq!(
+            reason(json!({ "color": "Blue", "level": 3 })),
+            "the value at /color is not one of the enumerated values"
+        );
+        assert_eq!(
+            reason(json!({ "color": "Red", "level": 300 })),
+            "the value at /level is greater than 255"
+        );
+        assert_eq!(
+            reason(json!({ "color": "Red", "level": 3, "range": "far" })),
+            "the value at /range matches 0 of the oneOf schemas instead of one"
+        );
+
+        let pair = schema::<(u8, String)>();
+        assert!(validate(&pair, &json!([1, "a"])).is_ok());
+        assert_eq!(
+            validate(&pair, &json!([1, 2])).unwrap_err().to_string(),
+            "the value at /1 is not a string"
+        );
+        assert_eq!(
+            validate(&pair, &json!([1, "a", 3]))
+                .unwrap_err()
+                .to_string(),
+            "the value at / has more than 2 items"
+        );
+        assert!(validate(&schema::<Value>(), &json!("anything")).is_ok());
+    }
+
+    #[test]
+    fn validate_decimal_steps() {
+        let step = json!({ "type": "number", "multipleOf": 0.1 });
+
+        for valid in [0.3, 0.7, 1.1, -2.3, 12345.6, 0.] {
+            assert_eq!(validate(&step, &json!(valid)), Ok(()), "{valid}");
+        }
+        assert_eq!(
+            validate(&step, &json!(0.35)).unwrap_err().to_string(),
+            "the value at / is not a multiple of 0.1"
+        );
+        assert!(validate(
+            &json!({ "multipleOf": 0.01, "type": "number" }),
+            &json!(1.15)
+        )
+        .is_ok());
+        assert!(validate(&json!({ "multipleOf": 3, "type": "integer" }), &json!(10)).is_err());
+    }
+}
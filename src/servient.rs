@@ -1,19 +1,41 @@
 //! Web of Thing Servient
 
-use std::net::SocketAddr;
+use std::{future::Future, net::SocketAddr, sync::Arc};
 
-use crate::{advertise::Advertiser, advertise::ThingType, hlist::NilPlus};
+use crate::{
+    advertise::Advertiser, advertise::ThingType, coap::CoapServer, directory::Registration,
+    hlist::NilPlus, mqtt::MqttClient,
+};
 use axum::Router;
+use serde_json::Value;
+use tokio::sync::watch;
 use wot_td::{
-    builder::{ThingBuilder, ToExtend},
+    builder::{AffordanceType, Extended, ThingBuilder, ToExtend},
     extend::ExtendableThing,
     hlist::*,
-    thing::Thing,
+    protocol::http::Method,
+    thing::{FormOperation, Thing},
 };
 
 mod builder;
+pub(crate) mod content;
+mod events;
+mod history;
+mod notifier;
+mod persistence;
+mod typed;
+mod validation;
+mod webhooks;
 
 pub use builder::*;
+pub use content::{MediaType, Payload};
+pub use events::EventSubscriptions;
+pub use history::{HistoryQuery, PropertyHistory, Retention, Sample};
+pub use notifier::PropertyNotifier;
+pub use persistence::{JsonFile, PersistedProperties, PropertyStore};
+pub use typed::*;
+pub use validation::ResponseValidation;
+pub use webhooks::EventWebhooks;
 
 /// Error type for the Servient.
 #[derive(thiserror::Error, Debug)]
@@ -25,6 +47,99 @@ pub enum Error {
     /// Error setting up the mDNS advertiser.
     #[error("mdns internal error {0}")]
     Advertise(#[from] crate::advertise::Error),
+
+    /// Error registering with the Thing Directory.
+    #[error("directory registration error {0}")]
+    Directory(#[from] crate::directory::Error),
+
+    /// Error serving CoAP.
+    #[error("coap error {0}")]
+    Coap(#[from] crate::coap::Error),
+
+    /// Error exposing the affordances over MQTT.
+    #[error("mqtt error {0}")]
+    Mqtt(#[from] crate::mqtt::Error),
+
+    /// A handler is bound to an affordance not present in the Thing Description.
+    #[error("no {0} named {1}")]
+    UnknownAffordance(AffordanceType, String),
+
+    /// A handler is bound to an operation no Form of the affordance declares.
+    #[error("the {0} {1} has no form for {2}")]
+    UnknownOperation(AffordanceType, String, FormOperation),
+
+    /// A handler is bound to a Thing operation no Form of the Thing declares.
+    #[error("the thing has no form for {0}")]
+    UnknownThingOperation(FormOperation),
+
+    /// Two operations routed to the same path are bound to handlers for the same http method.
+    #[error("the http method of {1} is already handled on {0}")]
+    OverlappingOperation(String, FormOperation),
+
+    /// The application state of a [`Servient::stateful_builder`] is not set.
+    #[error("the application state is not set")]
+    MissingState,
+
+    /// The Thing Description cannot be parsed.
+    #[error("invalid thing description {0}")]
+    Description(#[from] serde_json::Error),
+
+    /// An operation of a Form of the Thing Description has no handler.
+    #[error("the form {0} has no handler for {1}")]
+    UnboundForm(String, FormOperation),
+
+    /// The persisted property values cannot be loaded.
+    #[error("property persistence error {0}")]
+    Persistence(std::io::Error),
+
+    /// A Form uses CoAP but the Servient has no [`ServientSettings::coap_bind`] address.
+    #[error("the form {0} uses coap without a coap address to bind")]
+    UnboundCoap(String),
+
+    /// The routes of an extra router overlap with the other ones.
+    #[error("overlapping http routes: {0}")]
+    OverlappingRoute(String),
+
+    /// A Form routes an http method none of its operations use.
+    #[error("the form {0} handles {method} requests without an operation for them", method = builder::method_str(*.1))]
+    UndeclaredMethod(String, Method),
+}
+
+/// Shared handle to the Thing Description served by a [`Servient`]
+///
+/// Updating it changes the description served over http and refreshes the
+/// Thing Directory registration, if any.
+#[derive(Debug, Clone)]
+pub struct Description {
+    tx: Arc<watch::Sender<Value>>,
+}
+
+impl Description {
+    pub(crate) fn new(td: Value) -> Self {
+        let (tx, _) = watch::channel(td);
+
+        Self { tx: Arc::new(tx) }
+    }
+
+    /// Current Thing Description.
+    pub fn get(&self) -> Value {
+        self.tx.borrow().clone()
+    }
+
+    /// Replace the Thing Description.
+    pub fn set(&self, td: Value) {
+        self.tx.send_replace(td);
+    }
+
+    /// Modify the Thing Description in place.
+    pub fn update(&self, f: impl FnOnce(&mut Value)) {
+        self.tx.send_modify(f);
+    }
+
+    /// Receive the Thing Description every time it changes.
+    pub fn subscribe(&self) -> watch::Receiver<Value> {
+        self.tx.subscribe()
+    }
 }
 
 /// WoT Servient serving a Thing
@@ -33,7 +148,7 @@ pub enum Error {
 /// time using [`Servient::builder`].
 ///
 /// [`Thing`]: wot_td::thing::Thing
-pub struct Servient<Other: ExtendableThing = Nil> {
+pub struct Servient<Other: ExtendableThing = Nil, S = ()> {
     /// hostname for the thing
     ///
     /// Used in the DNS-SD advertisement by default
@@ -48,6 +163,18 @@ pub struct Servient<Other: ExtendableThing = Nil> {
     pub http_addr: SocketAddr,
     /// The type of thing advertised
     pub thing_type: ThingType,
+    /// The Thing Description as served
+    pub description: Description,
+    /// Thing Directory registration
+    pub registration: Option<Registration>,
+    /// CoAP endpoint, if the Forms are served over CoAP as well
+    pub coap: Option<CoapServer>,
+    /// MQTT client, if the affordances are exposed over MQTT as well
+    pub mqtt: Option<MqttClient>,
+    /// Property values persisted across restarts, restored before serving
+    pub persisted: Option<PersistedProperties>,
+    /// Application state shared by the handlers
+    pub state: S,
 }
 
 impl Servient<Nil> {
@@ -56,23 +183,186 @@ impl Servient<Nil> {
     /// By default it sets the CORS headers to allow any origin, you may disable the behaviour
     /// by calling [ServientSettings::http_disable_permissive_cors].
     pub fn builder(title: impl Into<String>) -> ThingBuilder<NilPlus<ServientExtension>, ToExtend> {
-        ThingBuilder::<NilPlus<ServientExtension>, ToExtend>::new(title)
+        let mut builder = ThingBuilder::<NilPlus<ServientExtension>, ToExtend>::new(title);
+        builder.other.field.state = Some(());
+        builder
+    }
+
+    /// Instantiate a ThingBuilder whose handlers share an application state.
+    ///
+    /// The handlers access the state through the [`State`] extractor, its value must be set
+    /// with [`ServientSettings::with_state`] before building the Servient.
+    ///
+    /// [`State`]: axum::extract::State
+    pub fn stateful_builder<S>(
+        title: impl Into<String>,
+    ) -> ThingBuilder<NilPlus<ServientExtension<S>>, ToExtend>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        ThingBuilder::<NilPlus<ServientExtension<S>>, ToExtend>::new(title)
+    }
+
+    /// Instantiate a ThingBuilder serving the Thing Description `td`.
+    ///
+    /// The Forms of the description are routed by affordance name and operation through the
+    /// [`AffordanceRouter`] methods, every http Form must have a handler for each of its
+    /// operations once the Servient is built.
+    ///
+    /// Absolute hrefs are routed by their path, the Forms using other protocols are kept
+    /// in the description but not served.
+    pub fn from_td(td: &str) -> Result<ThingBuilder<NilPlus<ServientExtension>, Extended>, Error> {
+        let td: Value = serde_json::from_str(td)?;
+
+        // Check the description is well formed before taking it apart.
+        serde_json::from_value::<Thing>(td.clone())?;
+
+        Ok(builder::description_builder(td)?.with_state(()))
     }
 }
 
-impl<O: ExtendableThing> Servient<O> {
+impl<O: ExtendableThing, S> Servient<O, S> {
     /// Start a listening server and advertise for it.
     pub async fn serve(&self) -> Result<(), Error> {
+        self.serve_with_shutdown(std::future::pending()).await
+    }
+
+    /// Start a listening server and advertise for it, until `signal` resolves.
+    ///
+    /// If the Servient is registered with a Thing Directory, the registration
+    /// is removed once the server stops.
+    ///
+    /// The http server stops as well as soon as serving CoAP, MQTT or the registration fails.
+    pub async fn serve_with_shutdown(&self, signal: impl Future<Output = ()>) -> Result<(), Error> {
+        let listener = std::net::TcpListener::bind(self.http_addr).map_err(axum::Error::new)?;
+
+        self.serve_listener(listener, signal).await
+    }
+
+    /// Serve the http requests accepted by `listener`, until `signal` resolves.
+    pub(crate) async fn serve_listener(
+        &self,
+        listener: std::net::TcpListener,
+        signal: impl Future<Output = ()>,
+    ) -> Result<(), Error> {
+        let http_port = listener.local_addr().map_err(axum::Error::new)?.port();
+        let server = axum::Server::from_tcp(listener).map_err(axum::Error::new)?;
+
+        if let Some(persisted) = &self.persisted {
+            persisted.restore().await;
+        }
+
         self.sd
             .add_service(&self.name)
             .thing_type(self.thing_type)
-            .port(self.http_addr.port())
+            .port(http_port)
             .build()?;
 
-        axum::Server::bind(&self.http_addr)
-            .serve(self.router.clone().into_make_service())
-            .await
-            .map_err(axum::Error::new)?;
+        let coap_socket = match &self.coap {
+            Some(coap) => {
+                let socket = tokio::net::UdpSocket::bind(coap.addr)
+                    .await
+                    .map_err(crate::coap::Error::from)?;
+                self.sd
+                    .add_service(&self.name)
+                    .thing_type(self.thing_type)
+                    .udp()
+                    .port(
+                        socket
+                            .local_addr()
+                            .map_err(crate::coap::Error::from)?
+                            .port(),
+                    )
+                    .build()?;
+                Some(socket)
+            }
+            None => None,
+        };
+
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let (stop_coap, coap_stopped) = tokio::sync::oneshot::channel::<()>();
+        let (stop_mqtt, mqtt_stopped) = tokio::sync::oneshot::channel::<()>();
+
+        if let Some(registration) = &self.registration {
+            registration.register(&self.description.get()).await?;
+        }
+
+        // Notified once the CoAP, MQTT or registration task fails.
+        let failed = tokio::sync::Notify::new();
+        let failing = |res: Result<(), Error>| {
+            if res.is_err() {
+                failed.notify_one();
+            }
+            res
+        };
+
+        let registered = async {
+            let registered = match &self.registration {
+                Some(registration) => {
+                    let shutdown = async move {
+                        let _ = stopped.await;
+                    };
+                    registration
+                        .run(&self.sd, self.description.subscribe(), shutdown)
+                        .await
+                        .map_err(Error::from)
+                }
+                None => Ok(()),
+            };
+            failing(registered)
+        };
+
+        let served = async {
+            let served = server
+                .serve(self.router.clone().into_make_service())
+                .with_graceful_shutdown(async {
+                    tokio::select! {
+                        _ = signal => {}
+                        _ = failed.notified() => {}
+                    }
+                })
+                .await;
+            let _ = stop.send(());
+            let _ = stop_coap.send(());
+            let _ = stop_mqtt.send(());
+            served
+        };
+
+        let coap_served = async {
+            let served = match (&self.coap, coap_socket) {
+                (Some(coap), Some(socket)) => {
+                    let shutdown = async move {
+                        let _ = coap_stopped.await;
+                    };
+                    coap.serve(socket, self.description.clone(), shutdown)
+                        .await
+                        .map_err(Error::from)
+                }
+                _ => Ok(()),
+            };
+            failing(served)
+        };
+
+        let mqtt_served = async {
+            let served = match &self.mqtt {
+                Some(mqtt) => {
+                    let shutdown = async move {
+                        let _ = mqtt_stopped.await;
+                    };
+                    mqtt.serve(shutdown).await.map_err(Error::from)
+                }
+                None => Ok(()),
+            };
+            failing(served)
+        };
+
+        let (served, registered, coap_served, mqtt_served) =
+            tokio::join!(served, registered, coap_served, mqtt_served);
+
+        served.map_err(axum::Error::new)?;
+        registered?;
+        coap_served?;
+        mqtt_served?;
 
         Ok(())
     }
@@ -80,9 +370,13 @@ impl<O: ExtendableThing> Servient<O> {
 
 #[cfg(test)]
 mod test {
-    use wot_td::{builder::affordance::*, builder::data_schema::*, thing::FormOperation};
+    use wot_td::{
+        builder::{affordance::*, data_schema::*, BuildableHumanReadableInfo},
+        thing::FormOperation,
+    };
 
     use crate::advertise::ThingType;
+    use crate::test_util::{local_listener, serve_until};
 
     use super::*;
 
@@ -153,6 +447,155 @@ mod test {
         dbg!(&servient.router);
     }
 
+    #[test]
+    fn form_methods() {
+        let build =
+            |servient: Result<Servient<NilPlus<ServientExtension>>, Box<dyn std::error::Error>>| {
+                servient
+                    .err()
+                    .expect("the servient should not build")
+                    .to_string()
+            };
+
+        let servient = Servient::builder("test")
+            .finish_extend()
+            .property("hello", |b| {
+                b.finish_extend_data_schema().null().form(|f| {
+                    f.href("/hello")
+                        .http_get(|| async { "Hello, World!" })
+                        .op(FormOperation::WriteProperty)
+                })
+            })
+            .property("bye", |b| {
+                b.finish_extend_data_schema().null().form(|f| {
+                    f.href("/bye")
+                        .http_handler(FormOperation::ReadProperty, || async { "Bye!" })
+                })
+            })
+            .build_servient();
+        assert_eq!(
+            build(servient),
+            "the form /hello has no handler for writeproperty"
+        );
+
+        let servient = Servient::builder("test")
+            .finish_extend()
+            .property("hello", |b| {
+                b.finish_extend_data_schema().null().form(|f| {
+                    f.href("/hello")
+                        .http_get(|| async { "Hello, World!" })
+                        .http_post(|| async { "Hello, World!" })
+                        .op(FormOperation::ReadProperty)
+                })
+            })
+            .build_servient();
+        assert_eq!(
+            build(servient),
+            "the form /hello handles POST requests without an operation for them"
+        );
+
+        let servient = Servient::builder("test")
+            .finish_extend()
+            .property("hello", |b| {
+                b.finish_extend_data_schema().null().form(|f| {
+                    f.href("/hello")
+                        .http_handler(FormOperation::ReadProperty, || async { "Hello" })
+                        .http_handler(FormOperation::WriteProperty, || async { "World" })
+                })
+            })
+            .action("hello", |b| {
+                b.form(|f| f.href("/say_hello").http_put(|| async { "Hello, World!" }))
+            })
+            .build_servient()
+            .unwrap();
+
+        let td = servient.description.get();
+        assert_eq!(
+            td["properties"]["hello"]["forms"][0],
+            serde_json::json!({ "href": "/hello", "op": ["readproperty", "writeproperty"] })
+        );
+        assert_eq!(
+            td["actions"]["hello"]["forms"][0],
+            serde_json::json!({ "href": "/say_hello", "htv:methodName": "PUT" })
+        );
+    }
+
+    #[test]
+    fn property_flag_ops() {
+        let servient = Servient::builder("test")
+            .finish_extend()
+            .property("hello", |b| {
+                b.finish_extend_data_schema()
+                    .form(|f| f.href("/hello").http_get(|| async { "Hello, World!" }))
+                    .string()
+                    .read_only()
+            })
+            .property("bye", |b| {
+                b.finish_extend_data_schema()
+                    .form(|f| f.href("/bye").http_put(|| async {}))
+                    .null()
+                    .write_only()
+            })
+            .build_servient()
+            .unwrap();
+
+        let td = servient.description.get();
+        assert_eq!(
+            td["properties"]["hello"]["forms"][0],
+            serde_json::json!({ "href": "/hello", "op": ["readproperty"] })
+        );
+        assert_eq!(
+            td["properties"]["bye"]["forms"][0],
+            serde_json::json!({ "href": "/bye", "op": ["writeproperty"] })
+        );
+
+        // A writable property still needs a handler for writeproperty.
+        let servient = Servient::builder("test")
+            .finish_extend()
+            .property("hello", |b| {
+                b.finish_extend_data_schema()
+                    .form(|f| f.href("/hello").http_get(|| async { "Hello, World!" }))
+                    .string()
+            })
+            .build_servient();
+        assert_eq!(
+            servient.err().unwrap().to_string(),
+            "the form /hello has no handler for writeproperty"
+        );
+    }
+
+    #[test]
+    fn typed_forms() {
+        let servient = Servient::builder("test")
+            .finish_extend()
+            .property("level", |b| {
+                b.finish_extend_data_schema()
+                    .null()
+                    .title("Level")
+                    .form(|f| {
+                        f.href("/level")
+                            .http_get_typed(|| async { 5u8 })
+                            .op(FormOperation::ReadProperty)
+                    })
+            })
+            .action("list", |b| {
+                b.form(|f| {
+                    f.href("/list")
+                        .http_post_typed(|| async { vec![String::from("a")] })
+                })
+            })
+            .build_servient()
+            .unwrap();
+
+        let td = servient.description.get();
+        let level = &td["properties"]["level"];
+        assert_eq!(level["title"], "Level");
+        assert_eq!(level["type"], "integer");
+        assert_eq!(level["maximum"], 255);
+        assert_eq!(level["readOnly"], false);
+        assert_eq!(td["actions"]["list"]["output"]["items"]["type"], "string");
+    }
+
     #[test]
     fn servient_setup() {
         let addr = "0.0.0.0:3000".parse().unwrap();
@@ -167,4 +610,1399 @@ mod test {
         assert_eq!(servient.http_addr, addr);
         assert_eq!(servient.thing_type, ThingType::Directory);
     }
+
+    async fn eventually(check: impl Fn() -> bool) -> bool {
+        for _ in 0..50 {
+            if check() {
+                return true;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+
+        false
+    }
+
+    type Things = Arc<std::sync::Mutex<std::collections::HashMap<String, Value>>>;
+
+    /// Minimal Thing Directory storing the registered Thing Descriptions
+    fn directory_stand_in(listener: std::net::TcpListener) -> Things {
+        use axum::{
+            extract::{Path, State},
+            routing::put,
+        };
+
+        let things = Things::default();
+        let dir = Router::new()
+            .route(
+                "/things/:id",
+                put(
+                    |things: State<Things>, Path(id): Path<String>, body: String| async move {
+                        let td = serde_json::from_str(&body).unwrap();
+                        things.lock().unwrap().insert(id, td);
+                    },
+                )
+                .delete(
+                    |things: State<Things>, Path(id): Path<String>| async move {
+                        things.lock().unwrap().remove(&id);
+                    },
+                ),
+            )
+            .with_state(things.clone());
+        let server = axum::Server::from_tcp(listener).unwrap();
+        tokio::spawn(server.serve(dir.into_make_service()));
+
+        things
+    }
+
+    #[tokio::test]
+    async fn register_with_directory() {
+        use std::time::Duration;
+
+        let (dir_listener, dir_addr) = local_listener();
+        let things = directory_stand_in(dir_listener);
+
+        let (listener, addr) = local_listener();
+        let servient = Servient::builder("test registration")
+            .id("urn:dev:test-registration")
+            .finish_extend()
+            .http_bind(addr)
+            .register_with(format!("http://{dir_addr}/"))
+            .registration_ttl(Duration::from_secs(30))
+            .build_servient()
+            .unwrap();
+
+        let get = || {
+            things
+                .lock()
+                .unwrap()
+                .get("urn:dev:test-registration")
+                .cloned()
+        };
+
+        let checks = async {
+            assert!(eventually(|| get().is_some()).await);
+
+            let td = get().unwrap();
+            assert_eq!(td["title"], "test registration");
+            assert_eq!(td["registration"]["ttl"], 30);
+            assert!(td["base"]
+                .as_str()
+                .unwrap()
+                .starts_with("http://127.0.0.1:"));
+
+            servient
+                .description
+                .update(|td| td["title"] = "updated".into());
+
+            assert!(eventually(|| get().unwrap()["title"] == "updated").await);
+        };
+
+        serve_until(&servient, listener, checks).await;
+
+        assert!(get().is_none());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs multicast DNS on the local network"]
+    async fn register_with_discovered_directory() {
+        let dir_listener = std::net::TcpListener::bind("0.0.0.0:0").unwrap();
+        let port = dir_listener.local_addr().unwrap().port();
+        let things = directory_stand_in(dir_listener);
+
+        let ad = Advertiser::new().unwrap();
+        ad.add_service("TestDiscoveredDirectory")
+            .thing_type(ThingType::Directory)
+            .port(port)
+            .build()
+            .unwrap();
+
+        let (listener, addr) = local_listener();
+        let servient = Servient::builder("test discovered registration")
+            .id("urn:dev:test-discovered-registration")
+            .finish_extend()
+            .http_bind(addr)
+            .register_with_discovered()
+            .build_servient()
+            .unwrap();
+
+        let get = || {
+            things
+                .lock()
+                .unwrap()
+                .get("urn:dev:test-discovered-registration")
+                .cloned()
+        };
+
+        let checks = async {
+            for _ in 0..5 {
+                if eventually(|| get().is_some()).await {
+                    break;
+                }
+            }
+            assert_eq!(get().unwrap()["title"], "test discovered registration");
+        };
+
+        serve_until(&servient, listener, checks).await;
+
+        assert!(get().is_none());
+    }
+
+    const TD: &str = r#"{
+        "@context": "https://www.w3.org/2022/wot/td/v1.1",
+        "title": "Thermometer",
+        "securityDefinitions": { "nosec_sc": { "scheme": "nosec" } },
+        "security": "nosec_sc",
+        "forms": [{ "href": "/properties", "op": "readallproperties" }],
+        "properties": {
+            "temp": {
+                "type": "number",
+                "forms": [
+                    {
+                        "href": "http://thermometer.local/things/t/properties/temp",
+                        "op": "readproperty"
+                    },
+                    {
+                        "href": "properties/temp",
+                        "op": "writeproperty",
+                        "htv:methodName": "POST"
+                    }
+                ]
+            }
+        },
+        "events": {
+            "overheat": {
+                "forms": [{ "href": "coap://thermometer.local/overheat", "op": "subscribeevent" }]
+            }
+        }
+    }"#;
+
+    #[tokio::test]
+    async fn serve_from_td() {
+        let (listener, addr) = local_listener();
+        let servient = Servient::from_td(TD)
+            .unwrap()
+            .http_bind(addr)
+            .on_read_property("temp", || async { "21" })
+            .on_write_property("temp", |body: String| async move { body })
+            .on_thing_operation(FormOperation::ReadAllProperties, || async {
+                axum::Json(serde_json::json!({ "temp": 21 }))
+            })
+            .build_servient()
+            .unwrap();
+
+        assert_eq!(servient.thing.title, "Thermometer");
+        assert_eq!(servient.thing.security, ["nosec_sc"]);
+
+        let checks = async {
+            let client = reqwest::Client::new();
+            let url = |path: &str| format!("http://{addr}{path}");
+
+            let res = client
+                .get(url("/things/t/properties/temp"))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.text().await.unwrap(), "21");
+
+            let res = client
+                .post(url("/properties/temp"))
+                .body("22")
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.text().await.unwrap(), "22");
+
+            let res = client.put(url("/properties/temp")).send().await.unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::METHOD_NOT_ALLOWED);
+
+            let all: Value = client
+                .get(url("/properties"))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            assert_eq!(all["temp"], 21);
+
+            let td: Value = client
+                .get(url("/"))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            assert_eq!(
+                td["properties"]["temp"]["forms"][1]["htv:methodName"],
+                "POST"
+            );
+            assert_eq!(
+                td["events"]["overheat"]["forms"][0]["href"],
+                "coap://thermometer.local/overheat"
+            );
+        };
+
+        serve_until(&servient, listener, checks).await;
+    }
+
+    #[test]
+    fn from_td_unbound() {
+        let build = |builder: ThingBuilder<NilPlus<ServientExtension>, Extended>| {
+            builder
+                .build_servient()
+                .err()
+                .expect("the servient should not build")
+                .to_string()
+        };
+
+        let builder = Servient::from_td(TD)
+            .unwrap()
+            .on_read_property("temp", || async { "21" })
+            .on_thing_operation(FormOperation::ReadAllProperties, || async { "{}" });
+        assert_eq!(
+            build(builder),
+            "the form properties/temp has no handler for writeproperty"
+        );
+
+        let builder = Servient::from_td(TD)
+            .unwrap()
+            .on_read_property("humidity", || async { "50" });
+        assert_eq!(build(builder), "no property named humidity");
+
+        let builder = Servient::from_td(TD)
+            .unwrap()
+            .on_observe_property("temp", || async { "21" });
+        assert_eq!(
+            build(builder),
+            "the property temp has no form for observeproperty"
+        );
+
+        let builder = Servient::from_td(TD)
+            .unwrap()
+            .on_read_property("temp", || async { "21" })
+            .on_write_property("temp", || async {})
+            .on_thing_operation(FormOperation::ReadAllProperties, || async { "{}" })
+            .on_subscribe_event("overheat", || async { "true" });
+        assert_eq!(
+            build(builder),
+            "the form coap://thermometer.local/overheat uses coap without a coap address to bind"
+        );
+
+        assert!(Servient::from_td(r#"{ "title": "no security" }"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn serve_with_state() {
+        use axum::extract::State;
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        type Counter = Arc<AtomicU32>;
+
+        let (listener, addr) = local_listener();
+        let servient = Servient::stateful_builder("counter")
+            .finish_extend()
+            .with_state(Counter::default())
+            .http_bind(addr)
+            .property("count", |b| {
+                b.finish_extend_data_schema().integer().form(|f| {
+                    f.href("/count")
+                        .http_get(|State(count): State<Counter>| async move {
+                            count.load(Ordering::SeqCst).to_string()
+                        })
+                        .op(FormOperation::ReadProperty)
+                })
+            })
+            .action("increment", |b| {
+                b.form(|f| {
+                    f.href("/increment")
+                        .http_post(|State(count): State<Counter>| async move {
+                            count.fetch_add(1, Ordering::SeqCst);
+                        })
+                })
+            })
+            .build_servient()
+            .unwrap();
+
+        let checks = async {
+            let client = reqwest::Client::new();
+
+            for _ in 0..2 {
+                client
+                    .post(format!("http://{addr}/increment"))
+                    .send()
+                    .await
+                    .unwrap();
+            }
+            let res = client
+                .get(format!("http://{addr}/count"))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.text().await.unwrap(), "2");
+            assert_eq!(servient.state.load(Ordering::SeqCst), 2);
+        };
+
+        serve_until(&servient, listener, checks).await;
+    }
+
+    #[test]
+    fn missing_state() {
+        let Err(err) = Servient::stateful_builder::<Arc<String>>("stateless")
+            .finish_extend()
+            .build_servient()
+        else {
+            panic!("the servient should not build");
+        };
+
+        assert_eq!(err.to_string(), "the application state is not set");
+    }
+
+    #[tokio::test]
+    async fn serve_extra_routes_and_layers() {
+        use axum::{http::HeaderValue, middleware::map_response, response::Response};
+
+        fn tag(name: &'static str) -> impl Fn(Response) -> std::future::Ready<Response> + Clone {
+            move |mut res: Response| {
+                res.headers_mut()
+                    .insert(name, HeaderValue::from_static("1"));
+                std::future::ready(res)
+            }
+        }
+
+        let (listener, addr) = local_listener();
+        let servient = Servient::builder("extra")
+            .finish_extend()
+            .http_bind(addr)
+            .property("on", |b| {
+                b.finish_extend_data_schema().bool().form(|f| {
+                    f.href("/on")
+                        .http_get(|| async { "true" })
+                        .op(FormOperation::ReadProperty)
+                })
+            })
+            .property("off", |b| {
+                b.finish_extend_data_schema().bool().form(|f| {
+                    f.href("/off")
+                        .http_get(|| async { "false" })
+                        .op(FormOperation::ReadProperty)
+                })
+            })
+            .http_route("/health", axum::routing::get(|| async { "ok" }))
+            .http_nest(
+                "/admin",
+                Router::new().route("/stats", axum::routing::get(|| async { "stats" })),
+            )
+            .http_layer(map_response(tag("x-global")))
+            .http_affordance_layer(AffordanceType::Property, "on", map_response(tag("x-on")))
+            .build_servient()
+            .unwrap();
+
+        let checks = async {
+            let client = reqwest::Client::new();
+            let get = |path: &str| {
+                client
+                    .get(format!("http://{addr}{path}"))
+                    .header("origin", "http://example.com")
+                    .send()
+            };
+
+            let res = get("/health").await.unwrap();
+            assert!(res.headers().contains_key("x-global"));
+            assert!(res.headers().contains_key("access-control-allow-origin"));
+            assert_eq!(res.text().await.unwrap(), "ok");
+
+            let res = get("/admin/stats").await.unwrap();
+            assert_eq!(res.text().await.unwrap(), "stats");
+
+            let res = get("/on").await.unwrap();
+            assert!(res.headers().contains_key("x-global"));
+            assert!(res.headers().contains_key("x-on"));
+
+            let res = get("/off").await.unwrap();
+            assert!(!res.headers().contains_key("x-on"));
+        };
+
+        serve_until(&servient, listener, checks).await;
+    }
+
+    #[test]
+    fn overlapping_routes() {
+        let builder = || {
+            Servient::builder("extra")
+                .finish_extend()
+                .property("on", |b| {
+                    b.finish_extend_data_schema().bool().form(|f| {
+                        f.href("/things/{id}/on")
+                            .http_get(|| async { "true" })
+                            .op(FormOperation::ReadProperty)
+                    })
+                })
+        };
+        let get = || axum::routing::get(|| async { "ok" });
+        let router = || Router::new().route("/stats", get());
+        let overlapping =
+            |servient: Result<Servient<NilPlus<ServientExtension>>, Box<dyn std::error::Error>>,
+             path: &str| {
+                let Err(err) = servient else {
+                    panic!("{path} should overlap");
+                };
+                assert!(matches!(
+                    err.downcast_ref::<Error>(),
+                    Some(Error::OverlappingRoute(p)) if p == path
+                ));
+            };
+
+        for path in [
+            "/",
+            "/.well-known/wot",
+            "/things/3/on",
+            "/things/:name/on",
+            "/*rest",
+        ] {
+            overlapping(builder().http_route(path, get()).build_servient(), path);
+        }
+        for path in ["/", "/things", "/things/3"] {
+            overlapping(builder().http_nest(path, router()).build_servient(), path);
+        }
+        overlapping(
+            builder()
+                .http_route("/admin/stats", get())
+                .http_nest("/admin", router())
+                .build_servient(),
+            "/admin",
+        );
+        overlapping(
+            builder()
+                .http_nest("/admin", router())
+                .http_nest("/admin/more", router())
+                .build_servient(),
+            "/admin/more",
+        );
+
+        let servient = builder()
+            .http_route("/health", get())
+            .http_route("/things/3", get())
+            .http_nest("/admin", router())
+            .http_nest("/administration", router())
+            .build_servient();
+        assert!(servient.is_ok());
+    }
+
+    #[tokio::test]
+    async fn serve_form_layers() {
+        use axum::{error_handling::HandleErrorLayer, http::StatusCode, BoxError};
+        use std::time::Duration;
+        use tower::{timeout::TimeoutLayer, ServiceBuilder};
+
+        let timeout = || {
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(|_: BoxError| async {
+                    StatusCode::REQUEST_TIMEOUT
+                }))
+                .layer(TimeoutLayer::new(Duration::from_millis(50)))
+        };
+        let slow = || async {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        };
+
+        let (listener, addr) = local_listener();
+        let servient = Servient::builder("layers")
+            .finish_extend()
+            .http_bind(addr)
+            .action("slow", |b| {
+                b.form(|f| f.href("/slow").http_post(slow).layer(timeout()))
+            })
+            .action("bound", |b| b.form(|f| f.href("/bound").layer(timeout())))
+            .action("patient", |b| {
+                b.form(|f| {
+                    f.href("/patient").http_post(|| async {
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                    })
+                })
+            })
+            .on_invoke_action("bound", slow)
+            .build_servient()
+            .unwrap();
+
+        let checks = async {
+            let client = reqwest::Client::new();
+            let post = |path: &str| client.post(format!("http://{addr}{path}")).send();
+
+            let res = post("/slow").await.unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::REQUEST_TIMEOUT);
+
+            let res = post("/bound").await.unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::REQUEST_TIMEOUT);
+
+            let res = post("/patient").await.unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::OK);
+        };
+
+        serve_until(&servient, listener, checks).await;
+    }
+
+    #[tokio::test]
+    async fn serve_validated_responses() {
+        use axum::Json;
+
+        let (listener, addr) = local_listener();
+        let servient = Servient::builder("validation")
+            .finish_extend()
+            .http_bind(addr)
+            .http_validate_responses(ResponseValidation::Strict)
+            .property("level", |b| {
+                b.finish_extend_data_schema()
+                    .integer()
+                    .maximum(10)
+                    .form(|f| {
+                        f.href("/level")
+                            .http_get(|| async { Json(5) })
+                            .http_put(|| async { Json("accepted") })
+                    })
+            })
+            .property("broken", |b| {
+                b.finish_extend_data_schema().integer().form(|f| {
+                    f.href("/broken")
+                        .http_get(|| async { Json("five") })
+                        .op(FormOperation::ReadProperty)
+                })
+            })
+            .property("text", |b| {
+                b.finish_extend_data_schema().integer().form(|f| {
+                    f.href("/text")
+                        .http_get(|| async { "five" })
+                        .op(FormOperation::ReadProperty)
+                })
+            })
+            .action("count", |b| {
+                b.output(|o| o.finish_extend().integer())
+                    .form(|f| f.href("/count").http_post(|| async { Json(11.5) }))
+            })
+            .build_servient()
+            .unwrap();
+
+        let checks = async {
+            let client = reqwest::Client::new();
+            let url = |path: &str| format!("http://{addr}{path}");
+
+            let res = client.get(url("/level")).send().await.unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::OK);
+            assert_eq!(res.text().await.unwrap(), "5");
+
+            let res = client.put(url("/level")).send().await.unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+            let res = client.get(url("/broken")).send().await.unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+
+            let res = client.get(url("/text")).send().await.unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+            let res = client.post(url("/count")).send().await.unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+        };
+
+        serve_until(&servient, listener, checks).await;
+    }
+
+    #[tokio::test]
+    async fn serve_cbor() {
+        use axum::extract::State;
+        use reqwest::header::{ACCEPT, CONTENT_TYPE};
+
+        type Level = Arc<std::sync::Mutex<u8>>;
+
+        let (listener, addr) = local_listener();
+        let servient = Servient::stateful_builder("cbor")
+            .finish_extend()
+            .http_bind(addr)
+            .with_state(Level::default())
+            .http_media_type(MediaType::Cbor)
+            .property("level", |b| {
+                b.finish_extend_data_schema().integer().form(|f| {
+                    f.href("/level")
+                        .http_get(|State(level): State<Level>| async move {
+                            Payload(*level.lock().unwrap())
+                        })
+                        .http_put(
+                            |State(level): State<Level>, Payload(value): Payload<u8>| async move {
+                                *level.lock().unwrap() = value;
+                            },
+                        )
+                })
+            })
+            .build_servient()
+            .unwrap();
+
+        let forms = &servient.description.get()["properties"]["level"]["forms"];
+        assert_eq!(forms[0]["contentType"], "application/json");
+        assert_eq!(forms[1]["contentType"], "application/cbor");
+        assert_eq!(forms[1]["href"], "/level");
+
+        let checks = async {
+            let client = reqwest::Client::new();
+            let url = format!("http://{addr}/level");
+
+            let res = client
+                .put(&url)
+                .header(CONTENT_TYPE, "application/cbor")
+                .body(crate::cbor::to_vec(&serde_json::json!(42)))
+                .send()
+                .await
+                .unwrap();
+            assert!(res.status().is_success());
+
+            let res = client
+                .get(&url)
+                .header(ACCEPT, "application/cbor")
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.headers()[CONTENT_TYPE], "application/cbor");
+            let body = res.bytes().await.unwrap();
+            assert_eq!(crate::cbor::from_slice(&body), Ok(serde_json::json!(42)));
+
+            let res = client.get(&url).send().await.unwrap();
+            assert_eq!(res.headers()[CONTENT_TYPE], "application/json");
+            assert_eq!(res.text().await.unwrap(), "42");
+
+            let res = client
+                .get(&url)
+                .header(ACCEPT, "text/html")
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::NOT_ACCEPTABLE);
+        };
+
+        serve_until(&servient, listener, checks).await;
+    }
+
+    #[test]
+    fn cbor_generated_forms() {
+        let servient = Servient::builder("cbor")
+            .finish_extend()
+            .http_media_type(MediaType::Cbor)
+            .property_notifier(PropertyNotifier::new())
+            .property_history(PropertyHistory::new().historical("level", Retention::samples(4)))
+            .property("level", |b| {
+                b.finish_extend_data_schema().integer().form(|f| {
+                    f.href("/level")
+                        .http_get(|| async { Payload(1) })
+                        .op(FormOperation::ReadProperty)
+                })
+            })
+            .build_servient()
+            .unwrap();
+
+        // Only the plain Form is negotiated, the generated ones stream json or events.
+        let td = servient.description.get();
+        let forms = td["properties"]["level"]["forms"].as_array().unwrap();
+        let cbor: Vec<_> = forms
+            .iter()
+            .filter(|form| form["contentType"] == "application/cbor")
+            .collect();
+        assert_eq!(cbor.len(), 1);
+        assert_eq!(cbor[0]["href"], "/level");
+        assert!(forms.iter().any(|form| form["subprotocol"] == "longpoll"));
+        assert_eq!(td["links"][0]["type"], "application/json");
+    }
+
+    #[tokio::test]
+    async fn serve_coap() {
+        use crate::coap::{option, uint, Code, Message, MessageType};
+        use axum::{extract::State, Json};
+        use tokio::{net::UdpSocket, sync::broadcast};
+
+        let coap_addr = std::net::UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (tx, _) = broadcast::channel::<u8>(4);
+
+        let (listener, _) = local_listener();
+        let servient = Servient::stateful_builder("coap")
+            .finish_extend()
+            .coap_bind(coap_addr)
+            .with_state(tx.clone())
+            .property("level", |b| {
+                b.finish_extend_data_schema().integer().form(|f| {
+                    f.href("coap://localhost/level")
+                        .http_get(|| async { Json(5) })
+                        .op(FormOperation::ReadProperty)
+                })
+            })
+            .event("changed", |b| {
+                b.form(|f| {
+                    f.href("coap://localhost/changed").http_get(
+                        |State(tx): State<broadcast::Sender<u8>>| async move {
+                            Json(tx.subscribe().recv().await.unwrap())
+                        },
+                    )
+                })
+            })
+            .build_servient()
+            .unwrap();
+
+        let checks = async {
+            let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            client.connect(coap_addr).await.unwrap();
+            let client = &client;
+            let recv = || async move {
+                let mut buf = vec![0; 65535];
+                let len = client.recv(&mut buf).await.unwrap();
+                Message::decode(&buf[..len]).unwrap()
+            };
+            let get = |id: u16, path: &[&str]| {
+                path.iter().fold(
+                    Message::new(MessageType::Confirmable, Code::GET, id, vec![id as u8]),
+                    |m, segment| m.with_option(option::URI_PATH, segment.as_bytes()),
+                )
+            };
+
+            // The server may not be listening yet.
+            let mut response = None;
+            for id in 0..50 {
+                let request = get(id, &["level"]).encode();
+                client.send(&request).await.unwrap();
+                let received = tokio::time::timeout(std::time::Duration::from_millis(50), recv());
+                if let Ok(message) = received.await {
+                    response = Some(message);
+                    break;
+                }
+            }
+            let response = response.expect("the coap server should answer");
+            assert_eq!(response.ty, MessageType::Acknowledgement);
+            assert_eq!(response.code, Code::CONTENT);
+            assert_eq!(response.uint_option(option::CONTENT_FORMAT), Some(50));
+            assert_eq!(response.payload, b"5");
+
+            client
+                .send(&get(100, &[".well-known", "wot"]).encode())
+                .await
+                .unwrap();
+            let response = recv().await;
+            assert_eq!(response.uint_option(option::CONTENT_FORMAT), Some(432));
+            let td: Value = serde_json::from_slice(&response.payload).unwrap();
+            assert_eq!(td["title"], "coap");
+
+            client.send(&get(101, &["missing"]).encode()).await.unwrap();
+            let response = recv().await;
+            assert_eq!(response.code, Code::NOT_FOUND);
+
+            let observe = Message::new(MessageType::NonConfirmable, Code::GET, 102, vec![7])
+                .with_option(option::URI_PATH, "changed")
+                .with_option(option::OBSERVE, uint(0));
+            client.send(&observe.encode()).await.unwrap();
+            for value in [1u8, 2] {
+                // Wait for the observation to subscribe.
+                while tx.receiver_count() == 0 {
+                    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                }
+                tx.send(value).unwrap();
+
+                let notification = recv().await;
+                assert_eq!(notification.token, [7]);
+                assert_eq!(
+                    notification.uint_option(option::OBSERVE),
+                    Some(value as u32 - 1)
+                );
+                assert_eq!(notification.payload, value.to_string().as_bytes());
+            }
+        };
+
+        serve_until(&servient, listener, checks).await;
+    }
+
+    #[tokio::test]
+    async fn observe_properties() {
+        use futures_util::StreamExt;
+        use reqwest::header::{ACCEPT, CONTENT_TYPE};
+
+        let (listener, addr) = local_listener();
+        let notifier = PropertyNotifier::new();
+        let servient = Servient::builder("observed")
+            .finish_extend()
+            .http_bind(addr)
+            .property_notifier(notifier.clone())
+            .property("level", |b| {
+                b.finish_extend_data_schema().integer().form(|f| {
+                    f.href("/level")
+                        .http_get(|| async { "1" })
+                        .op(FormOperation::ReadProperty)
+                })
+            })
+            .property("secret", |b| {
+                b.finish_extend_data_schema()
+                    .integer()
+                    .write_only()
+                    .form(|f| {
+                        f.href("/secret")
+                            .http_put(|| async {})
+                            .op(FormOperation::WriteProperty)
+                    })
+            })
+            .build_servient()
+            .unwrap();
+
+        let td = servient.description.get();
+        let level = &td["properties"]["level"];
+        assert_eq!(level["observable"], true);
+        let subprotocols: Vec<_> = level["forms"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|form| {
+                form["op"] == serde_json::json!(["observeproperty", "unobserveproperty"])
+            })
+            .map(|form| {
+                assert_eq!(form["href"], "properties/level/observe");
+                form["subprotocol"].as_str().unwrap()
+            })
+            .collect();
+        assert_eq!(subprotocols, ["longpoll", "sse", "websocket"]);
+        assert!(td["properties"]["secret"].get("observable").is_none());
+
+        let notify = |value: u8| {
+            let notifier = notifier.clone();
+            async move {
+                while notifier.notify("level", value).unwrap() == 0 {
+                    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                }
+            }
+        };
+
+        let checks = async {
+            let client = reqwest::Client::new();
+            let url = format!("http://{addr}/properties/level/observe");
+
+            let (res, ()) = tokio::join!(client.get(&url).send(), notify(1));
+            assert_eq!(res.unwrap().text().await.unwrap(), "1");
+
+            let mut res = client
+                .get(&url)
+                .header(ACCEPT, "text/event-stream")
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.headers()[CONTENT_TYPE], "text/event-stream");
+            notify(2).await;
+            assert_eq!(res.chunk().await.unwrap().unwrap(), "data: 2\n\n");
+            drop(res);
+
+            let (mut socket, _) = tokio_tungstenite::connect_async(url.replace("http", "ws"))
+                .await
+                .unwrap();
+            notify(3).await;
+            let message = socket.next().await.unwrap().unwrap();
+            assert_eq!(message.into_text().unwrap(), "3");
+            socket.close(None).await.unwrap();
+
+            let res = client.delete(&url).send().await.unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::NO_CONTENT);
+        };
+
+        serve_until(&servient, listener, checks).await;
+    }
+
+    #[tokio::test]
+    async fn subscribe_events() {
+        use crate::thing::EventChannel;
+        use reqwest::{
+            header::{CONTENT_TYPE, LOCATION},
+            StatusCode,
+        };
+
+        #[derive(Clone, serde::Deserialize)]
+        struct Threshold {
+            threshold: f64,
+        }
+
+        let (listener, addr) = local_listener();
+        let channel = EventChannel::new();
+        let (cancelled, mut cancels) = tokio::sync::mpsc::unbounded_channel();
+        let subscriptions = EventSubscriptions::new(channel.clone())
+            .filter(|params: &Threshold, temperature: &f64| *temperature > params.threshold)
+            .on_cancel(move |params: Threshold, cancellation| {
+                let cancelled = cancelled.clone();
+                async move {
+                    cancelled.send((params.threshold, cancellation)).unwrap();
+                }
+            });
+
+        let servient = Servient::builder("subscribed")
+            .finish_extend()
+            .http_bind(addr)
+            .event("overheating", |b| {
+                b.subscription(|b| {
+                    b.finish_extend()
+                        .object()
+                        .property("threshold", true, |b| b.finish_extend().number())
+                })
+                .data(|b| b.finish_extend().number())
+                .cancellation(|b| {
+                    b.finish_extend()
+                        .object()
+                        .property("reason", false, |b| b.finish_extend().string())
+                })
+            })
+            .on_event_subscription("overheating", subscriptions)
+            .build_servient()
+            .unwrap();
+
+        let td = servient.description.get();
+        let forms = &td["events"]["overheating"]["forms"];
+        assert_eq!(forms[0]["href"], "events/overheating");
+        assert_eq!(forms[0]["op"], serde_json::json!(["subscribeevent"]));
+        assert_eq!(forms[0]["subprotocol"], "sse");
+        assert_eq!(forms[1]["href"], "events/overheating/{id}");
+        assert_eq!(forms[1]["op"], serde_json::json!(["unsubscribeevent"]));
+        assert_eq!(
+            td["events"]["overheating"]["uriVariables"]["id"]["type"],
+            "string"
+        );
+
+        let checks = async {
+            let client = reqwest::Client::new();
+            let url = format!("http://{addr}/events/overheating");
+
+            let res = client
+                .post(&url)
+                .json(&serde_json::json!({ "threshold": "high" }))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+            let mut res = client
+                .post(&url)
+                .json(&serde_json::json!({ "threshold": 50 }))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.headers()[CONTENT_TYPE], "text/event-stream");
+            let location = res.headers()[LOCATION].to_str().unwrap().to_string();
+            assert!(location.starts_with("/events/overheating/"));
+
+            channel.emit(40.);
+            channel.emit(60.);
+            assert_eq!(res.chunk().await.unwrap().unwrap(), "id: 2\ndata: 60.0\n\n");
+
+            let subscription = format!("http://{addr}{location}");
+            let res = client
+                .delete(&subscription)
+                .json(&serde_json::json!({ "reason": 1 }))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+            let res = client
+                .delete(&subscription)
+                .json(&serde_json::json!({ "reason": "done" }))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::NO_CONTENT);
+            assert_eq!(
+                cancels.recv().await.unwrap(),
+                (50., serde_json::json!({ "reason": "done" }))
+            );
+
+            let res = client.delete(&subscription).send().await.unwrap();
+            assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+            // A subscriber going away is cancelled without waiting for an event.
+            let res = client
+                .post(&url)
+                .json(&serde_json::json!({ "threshold": 70 }))
+                .send()
+                .await
+                .unwrap();
+            drop(res);
+            let cancelled =
+                tokio::time::timeout(std::time::Duration::from_secs(5), cancels.recv()).await;
+            assert_eq!(cancelled.unwrap().unwrap(), (70., serde_json::Value::Null));
+        };
+
+        serve_until(&servient, listener, checks).await;
+    }
+
+    #[tokio::test]
+    async fn deliver_webhooks() {
+        use crate::thing::EventChannel;
+        use axum::{http::StatusCode, routing::post, Json};
+        use reqwest::header::LOCATION;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let (listener, addr) = local_listener();
+        let channel = EventChannel::new();
+        let webhooks = EventWebhooks::new(channel.clone())
+            .retries(2)
+            .backoff(std::time::Duration::from_millis(10))
+            .max_failures(2)
+            .limit(1);
+
+        let servient = Servient::builder("hooked")
+            .finish_extend()
+            .http_bind(addr)
+            .event("changed", |b| b.data(|b| b.finish_extend().integer()))
+            .on_event_webhooks("changed", webhooks)
+            .build_servient()
+            .unwrap();
+
+        let td = servient.description.get();
+        let forms = &td["events"]["changed"]["forms"];
+        assert_eq!(forms[0]["href"], "events/changed/webhooks");
+        assert_eq!(forms[0]["subprotocol"], "webhook");
+        assert_eq!(forms[1]["href"], "events/changed/webhooks/{id}");
+        assert_eq!(forms[1]["op"], serde_json::json!(["unsubscribeevent"]));
+
+        // The receiver fails the first delivery.
+        let (receiver_listener, receiver) = local_listener();
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let (delivered, mut deliveries) = tokio::sync::mpsc::unbounded_channel();
+        let hook = {
+            let attempts = attempts.clone();
+            move |Json(data): Json<u8>| async move {
+                if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                    return StatusCode::SERVICE_UNAVAILABLE;
+                }
+                delivered.send(data).unwrap();
+                StatusCode::OK
+            }
+        };
+        let receiving = axum::Server::from_tcp(receiver_listener).unwrap().serve(
+            axum::Router::new()
+                .route("/hook", post(hook))
+                .into_make_service(),
+        );
+        tokio::spawn(receiving);
+
+        let checks = async {
+            let client = reqwest::Client::new();
+            let url = format!("http://{addr}/events/changed/webhooks");
+
+            let res = client
+                .post(&url)
+                .json(&serde_json::json!({ "callback": "not a url" }))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+
+            let res = client
+                .post(&url)
+                .json(&serde_json::json!({ "callback": format!("file://{receiver}/hook") }))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+
+            let register = |callback: String| {
+                client
+                    .post(&url)
+                    .json(&serde_json::json!({ "callback": callback }))
+                    .send()
+            };
+
+            let res = register(format!("http://{receiver}/hook")).await.unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::CREATED);
+            let location = res.headers()[LOCATION].to_str().unwrap().to_string();
+            assert!(location.starts_with("/events/changed/webhooks/"));
+
+            let res = register(format!("http://{receiver}/other")).await.unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+
+            channel.emit(1);
+            assert_eq!(deliveries.recv().await.unwrap(), 1);
+            assert_eq!(attempts.load(Ordering::SeqCst), 2);
+
+            let webhook = format!("http://{addr}{location}");
+            let res = client.delete(&webhook).send().await.unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::NO_CONTENT);
+
+            // The delivery task ends with the webhook.
+            while channel.emit(2) > 0 {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+
+            let res = client.delete(&webhook).send().await.unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::NOT_FOUND);
+
+            // A webhook failing its deliveries in a row is dropped, nothing listens on the
+            // port once the listener is dropped.
+            let unreachable = local_listener().1;
+            let res = register(format!("http://{unreachable}/hook"))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::CREATED);
+            let location = res.headers()[LOCATION].to_str().unwrap().to_string();
+            while channel.emit(3) > 0 {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+
+            let webhook = format!("http://{addr}{location}");
+            let res = client.delete(&webhook).send().await.unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::NOT_FOUND);
+        };
+
+        serve_until(&servient, listener, checks).await;
+    }
+
+    #[tokio::test]
+    async fn subscriptions_under_base() {
+        use crate::thing::EventChannel;
+        use axum::http::{header::LOCATION, Request, StatusCode};
+        use tower::ServiceExt;
+
+        let channel = EventChannel::<f64>::new();
+        let servient = Servient::builder("based")
+            .base("http://localhost/lamp/")
+            .finish_extend()
+            .event("overheating", |b| b.data(|b| b.finish_extend().number()))
+            .event("changed", |b| b.data(|b| b.finish_extend().number()))
+            .on_event_subscription(
+                "overheating",
+                EventSubscriptions::<Value, _>::new(channel.clone()),
+            )
+            .on_event_webhooks("changed", EventWebhooks::new(channel))
+            .build_servient()
+            .unwrap();
+
+        for (path, payload) in [
+            ("/lamp/events/overheating", serde_json::json!({})),
+            (
+                "/lamp/events/changed/webhooks",
+                serde_json::json!({ "callback": "http://localhost/hook" }),
+            ),
+        ] {
+            let request = Request::post(path)
+                .header("content-type", "application/json")
+                .body(axum::body::Body::from(payload.to_string()))
+                .unwrap();
+            let res = servient.router.clone().oneshot(request).await.unwrap();
+            assert!(res.status().is_success(), "{path}");
+
+            let location = res.headers()[LOCATION].to_str().unwrap().to_string();
+            assert!(location.starts_with(&format!("{path}/")), "{location}");
+
+            let request = Request::delete(location)
+                .body(axum::body::Body::empty())
+                .unwrap();
+            let res = servient.router.clone().oneshot(request).await.unwrap();
+            assert_eq!(res.status(), StatusCode::NO_CONTENT, "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn replay_events() {
+        use crate::thing::EventChannel;
+
+        let (listener, addr) = local_listener();
+        let channel = EventChannel::with_history(2);
+        let subscriptions =
+            EventSubscriptions::new(channel.clone()).filter(|(): &(), data: &u8| data % 2 == 1);
+
+        let servient = Servient::builder("replayed")
+            .finish_extend()
+            .http_bind(addr)
+            .event("counted", |b| b.data(|b| b.finish_extend().integer()))
+            .on_event_subscription("counted", subscriptions)
+            .build_servient()
+            .unwrap();
+
+        for data in 1..=5 {
+            channel.emit(data);
+        }
+
+        let checks = async {
+            let client = reqwest::Client::new();
+            let url = format!("http://{addr}/events/counted");
+
+            // Only the latest 2 events are kept, the even ones are filtered out.
+            let mut res = client
+                .post(&url)
+                .header("Last-Event-ID", "1")
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.chunk().await.unwrap().unwrap(), "id: 5\ndata: 5\n\n");
+            channel.emit(7);
+            assert_eq!(res.chunk().await.unwrap().unwrap(), "id: 6\ndata: 7\n\n");
+            drop(res);
+
+            let mut res = client.post(format!("{url}?since=5")).send().await.unwrap();
+            assert_eq!(res.chunk().await.unwrap().unwrap(), "id: 6\ndata: 7\n\n");
+            drop(res);
+        };
+
+        serve_until(&servient, listener, checks).await;
+    }
+
+    #[tokio::test]
+    async fn query_property_history() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let (listener, addr) = local_listener();
+        let history = PropertyHistory::new().historical("level", Retention::samples(10));
+
+        let servient = Servient::builder("recorded")
+            .finish_extend()
+            .http_bind(addr)
+            .property_history(history.clone())
+            .property("level", |b| {
+                b.finish_extend_data_schema().integer().form(|f| {
+                    f.href("/level")
+                        .http_get(|| async { "3" })
+                        .op(FormOperation::ReadProperty)
+                })
+            })
+            .build_servient()
+            .unwrap();
+
+        // The history is linked, the property keeps its only Form.
+        let td = servient.description.get();
+        assert_eq!(
+            td["properties"]["level"]["forms"].as_array().unwrap().len(),
+            1
+        );
+        let link = &td["links"][0];
+        let href = link["href"].as_str().unwrap();
+        assert_eq!(href, "properties/level/history{?from,to,interval}");
+        assert_eq!(link["rel"], "version-history");
+        assert_eq!(link["anchor"], "#/properties/level");
+
+        for (time, value) in [(1000, 1), (1200, 2), (2000, 3)] {
+            let time = UNIX_EPOCH + Duration::from_millis(time);
+            history.record_at("level", time, value).unwrap();
+        }
+
+        let checks = async {
+            let client = reqwest::Client::new();
+
+            // The query is built from the template the link describes.
+            let mut template = datta::UriTemplate::new(href);
+            template.set("from", "1100");
+            template.set("interval", "1000");
+            let samples: Vec<Sample> = client
+                .get(format!("http://{addr}/{}", template.build()))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            let expected = [(1200, 2), (2000, 3)].map(|(time, value)| Sample {
+                time,
+                value: value.into(),
+            });
+            assert_eq!(samples, expected);
+        };
+
+        serve_until(&servient, listener, checks).await;
+    }
+
+    #[tokio::test]
+    async fn persist_properties() {
+        use axum::{
+            body::Body,
+            http::{header::AUTHORIZATION, Request, StatusCode},
+            middleware::Next,
+            response::IntoResponse,
+            Json,
+        };
+        use std::sync::atomic::{AtomicU8, Ordering};
+
+        let dir = std::env::temp_dir().join(format!("wot-serve-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("properties.json");
+
+        // Only the authorized requests get through, the restore bypasses it.
+        let authorized =
+            axum::middleware::from_fn(|request: Request<Body>, next: Next<Body>| async move {
+                if !request.headers().contains_key(AUTHORIZATION) {
+                    return StatusCode::UNAUTHORIZED.into_response();
+                }
+                next.run(request).await
+            });
+
+        let servient = |level: Arc<AtomicU8>| {
+            let written = level.clone();
+            Servient::builder("persisted")
+                .finish_extend()
+                .http_layer(authorized.clone())
+                .persist_properties(JsonFile::new(&path))
+                .property("level", |b| {
+                    b.finish_extend_data_schema().integer().form(|f| {
+                        f.href("/level")
+                            .http_get(move || async move { Json(level.load(Ordering::SeqCst)) })
+                            .http_put(move |Json(value): Json<u8>| async move {
+                                written.store(value, Ordering::SeqCst);
+                            })
+                    })
+                })
+                .build_servient()
+                .unwrap()
+        };
+
+        async fn serve<O: ExtendableThing, F, Fut>(servient: &Servient<O>, checks: F)
+        where
+            F: FnOnce(reqwest::Client, String) -> Fut,
+            Fut: Future<Output = ()>,
+        {
+            let (listener, addr) = local_listener();
+            let checks = checks(reqwest::Client::new(), format!("http://{addr}/level"));
+            serve_until(servient, listener, checks).await;
+        }
+
+        let level = Arc::new(AtomicU8::new(1));
+        serve(&servient(level.clone()), |client, url| async move {
+            let res = client.put(&url).json(&7).send().await.unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+            let res = client
+                .put(&url)
+                .bearer_auth("token")
+                .json(&7)
+                .send()
+                .await
+                .unwrap();
+            assert!(res.status().is_success());
+            let res = client
+                .put(&url)
+                .bearer_auth("token")
+                .json(&"high")
+                .send()
+                .await
+                .unwrap();
+            assert!(!res.status().is_success());
+        })
+        .await;
+        assert_eq!(level.load(Ordering::SeqCst), 7);
+
+        let stored: Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(stored, serde_json::json!({ "level": 7 }));
+
+        // A new Servient starts from the persisted value.
+        let level = Arc::new(AtomicU8::new(1));
+        let restarted = servient(level.clone());
+        assert_eq!(
+            restarted.persisted.as_ref().unwrap().value("level"),
+            Some(7.into())
+        );
+        let check = |expected: u8| {
+            move |client: reqwest::Client, url: String| async move {
+                let res = client.get(&url).bearer_auth("token").send().await.unwrap();
+                assert_eq!(res.json::<u8>().await.unwrap(), expected);
+            }
+        };
+        serve(&restarted, check(7)).await;
+
+        // The values are restored once, serving again keeps the current ones.
+        level.store(3, Ordering::SeqCst);
+        serve(&restarted, check(3)).await;
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
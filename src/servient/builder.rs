@@ -1,114 +1,1851 @@
-use std::net::SocketAddr;
+use std::{
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    advertise::{Advertiser, ThingType},
+    coap::CoapServer,
+    directory::{self, Directory, Registration},
+    hlist::*,
+    model::TM_CONTEXT,
+    mqtt::{self, ControlPacket, MqttClient, Publication, Subscription},
+    schema::{DataSchema, ToDataSchema},
+    servient::{
+        content::negotiate_content,
+        events::EventSubscriptions,
+        history::{PropertyHistory, HISTORY},
+        notifier::{EVENT_STREAM, WEBSOCKET},
+        persistence::{PersistedProperties, PropertyStore},
+        validation::validate_responses,
+        webhooks::{EventWebhooks, WEBHOOK},
+        Description, Error, MediaType, PropertyNotifier, ResponseValidation, Servient,
+        TypedHandler,
+    },
+};
+use axum::{
+    body::Body,
+    handler::Handler,
+    http::Request,
+    response::{IntoResponse, Redirect},
+    routing::{MethodFilter, MethodRouter, Route},
+    Router,
+};
+use tower::{Layer, Service};
+use tower_http::cors::*;
+
+use datta::{Operator, UriTemplate};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+use wot_td::{
+    builder::{AffordanceType, Extended, FormBuilder, ThingBuilder},
+    extend::ExtendableThing,
+    protocol::http::Method,
+    thing::{
+        ActionAffordance, DefaultedFormOperations, EventAffordance, FormOperation,
+        PropertyAffordance, Thing, TD_CONTEXT_10, TD_CONTEXT_11,
+    },
+};
+
+#[doc(hidden)]
+/// ThingBuilder ExtendableThing used to build a Servient
+///
+/// It is not needed to know about it nor use it directly.
+/// Instantiate a correct builder by calling [`Servient::builder`].
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(bound = "")]
+pub struct ServientExtension<S = ()> {
+    /// Listening address
+    #[serde(skip)]
+    addr: Option<SocketAddr>,
+    /// Thing type
+    #[serde(skip)]
+    thing_type: ThingType,
+    #[serde(skip)]
+    permissive_cors: bool,
+    /// Validation of the responses against the affordance schemas
+    #[serde(skip)]
+    response_validation: Option<ResponseValidation>,
+    /// Media types served besides json
+    #[serde(skip)]
+    media_types: Vec<MediaType>,
+    /// CoAP listening address
+    #[serde(skip)]
+    coap_addr: Option<SocketAddr>,
+    /// MQTT broker url
+    #[serde(skip)]
+    mqtt_broker: Option<String>,
+    /// Notifier of the property changes, making the properties observable
+    #[serde(skip)]
+    notifier: Option<PropertyNotifier>,
+    /// Recorder of the values of the historical properties
+    #[serde(skip)]
+    history: Option<PropertyHistory>,
+    /// Storage of the property values across restarts
+    #[serde(skip)]
+    property_store: Option<Arc<dyn PropertyStore>>,
+    /// Thing Directory to register with
+    #[serde(skip)]
+    directory: Option<Directory>,
+    /// Time-to-live of the directory registration
+    #[serde(skip)]
+    registration_ttl: Duration,
+    /// Thing Description the affordances not defined by the builder are taken from
+    #[serde(skip)]
+    pub(crate) model: Option<Value>,
+    /// Handlers bound to the affordances by name
+    #[serde(skip)]
+    bindings: Vec<Binding<S>>,
+    /// Subscriptions to the events by name
+    #[serde(skip)]
+    subscriptions: Vec<SubscriptionBinding<S>>,
+    /// Application state shared by the handlers
+    #[serde(skip)]
+    pub(crate) state: Option<S>,
+    /// Additional routes by path
+    #[serde(skip)]
+    routes: Vec<(String, MethodRouter<S>)>,
+    /// Additional routers by the path they are nested under
+    #[serde(skip)]
+    nested: Vec<(String, Router<S>)>,
+    /// Middleware applied to the whole router
+    #[serde(skip)]
+    layers: Vec<Middleware<Router>>,
+    /// Middleware applied to the Forms of an affordance
+    #[serde(skip)]
+    affordance_layers: Vec<(AffordanceType, String, Middleware<MethodRouter<S>>)>,
+}
+
+/// Type-erased tower layer
+pub(crate) struct Middleware<T>(Arc<dyn Fn(T) -> T + Send + Sync>);
+
+impl<T> Middleware<T> {
+    pub(crate) fn new(f: impl Fn(T) -> T + Send + 'static) -> Self {
+        // The layers are only required to be Send, the Mutex makes them Sync.
+        let f = Mutex::new(f);
+
+        Self(Arc::new(move |t| (f.lock().unwrap())(t)))
+    }
+
+    pub(crate) fn apply(&self, t: T) -> T {
+        (self.0)(t)
+    }
+}
+
+impl<T> Clone for Middleware<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> std::fmt::Debug for Middleware<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Middleware").finish_non_exhaustive()
+    }
+}
+
+/// Interaction a Form belongs to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Target {
+    /// The Forms of the Thing itself, e.g. `readallproperties`.
+    Thing,
+    /// The Forms of the named affordance.
+    Affordance(AffordanceType, String),
+}
+
+/// Handler bound to an operation
+///
+/// The handler is routed once the http method of each Form is known.
+struct Binding<S> {
+    target: Target,
+    op: FormOperation,
+    route: Arc<dyn Fn(Method) -> MethodRouter<S> + Send + Sync>,
+}
+
+impl<S> Clone for Binding<S> {
+    fn clone(&self) -> Self {
+        Self {
+            target: self.target.clone(),
+            op: self.op,
+            route: self.route.clone(),
+        }
+    }
+}
+
+impl<S> std::fmt::Debug for Binding<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Binding")
+            .field("target", &self.target)
+            .field("op", &self.op)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Subscriptions bound to an event
+///
+/// Routed at the href once the subscription and cancellation schemas of the event are known.
+struct SubscriptionBinding<S> {
+    name: String,
+    href: String,
+    subprotocol: &'static str,
+    routes: SubscriptionRoutes<S>,
+}
+
+type SubscriptionRoutes<S> = Arc<
+    dyn Fn(&str, Option<Value>, Option<Value>) -> (MethodRouter<S>, MethodRouter<S>) + Send + Sync,
+>;
+
+impl<S> Clone for SubscriptionBinding<S> {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            href: self.href.clone(),
+            subprotocol: self.subprotocol,
+            routes: self.routes.clone(),
+        }
+    }
+}
+
+impl<S> std::fmt::Debug for SubscriptionBinding<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SubscriptionBinding")
+            .field("name", &self.name)
+            .field("href", &self.href)
+            .field("subprotocol", &self.subprotocol)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S> Default for ServientExtension<S> {
+    fn default() -> Self {
+        ServientExtension {
+            addr: None,
+            thing_type: ThingType::default(),
+            permissive_cors: true,
+            response_validation: None,
+            media_types: Vec::new(),
+            coap_addr: None,
+            mqtt_broker: None,
+            notifier: None,
+            history: None,
+            property_store: None,
+            directory: None,
+            registration_ttl: directory::DEFAULT_TTL,
+            model: None,
+            bindings: Vec::new(),
+            subscriptions: Vec::new(),
+            state: None,
+            routes: Vec::new(),
+            nested: Vec::new(),
+            layers: Vec::new(),
+            affordance_layers: Vec::new(),
+        }
+    }
+}
+
+#[doc(hidden)]
+/// Form Extension
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(bound = "S: Clone")]
+pub struct Form<S = ()> {
+    #[serde(skip)]
+    method_router: MethodRouter<S>,
+    /// Http method used for the operations of the Form, if not the default one
+    #[serde(
+        rename = "htv:methodName",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    method_name: Option<Method>,
+    /// MQTT topic the operations publish to
+    #[serde(rename = "mqv:topic", default, skip_serializing_if = "Option::is_none")]
+    mqtt_topic: Option<String>,
+    /// MQTT topic filter the operations subscribe to
+    #[serde(
+        rename = "mqv:filter",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    mqtt_filter: Option<String>,
+    /// MQTT control packet of the operations
+    #[serde(
+        rename = "mqv:controlPacket",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    mqtt_control_packet: Option<ControlPacket>,
+    /// Whether the MQTT messages are retained by the broker
+    #[serde(
+        rename = "mqv:retain",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    mqtt_retain: Option<bool>,
+    /// Http methods routed to a handler
+    #[serde(skip)]
+    methods: Vec<Method>,
+    /// Schema of the values replied by the typed handler, if any
+    #[serde(skip)]
+    schema: Option<DataSchema>,
+    /// Middleware applied to the route of the Form
+    #[serde(skip)]
+    layers: Vec<Middleware<MethodRouter<S>>>,
+}
+
+impl<S: Clone> Default for Form<S> {
+    fn default() -> Self {
+        Self {
+            method_router: Default::default(),
+            method_name: None,
+            mqtt_topic: None,
+            mqtt_filter: None,
+            mqtt_control_packet: None,
+            mqtt_retain: None,
+            methods: Vec::new(),
+            schema: None,
+            layers: Vec::new(),
+        }
+    }
+}
+
+impl<S> From<MethodRouter<S>> for Form<S> {
+    fn from(method_router: MethodRouter<S>) -> Self {
+        Self {
+            method_router,
+            method_name: None,
+            mqtt_topic: None,
+            mqtt_filter: None,
+            mqtt_control_packet: None,
+            mqtt_retain: None,
+            methods: Vec::new(),
+            schema: None,
+            layers: Vec::new(),
+        }
+    }
+}
+
+impl<S: Clone> ExtendableThing for ServientExtension<S> {
+    type InteractionAffordance = ();
+    type PropertyAffordance = ();
+    type ActionAffordance = ();
+    type EventAffordance = ();
+    type Form = Form<S>;
+    type ExpectedResponse = ();
+    type DataSchema = ();
+    type ObjectSchema = ();
+    type ArraySchema = ();
+}
+
+/// Extension trait for the [`Servient`] configuration.
+pub trait ServientSettings<S> {
+    /// Bind the http server to addr
+    fn http_bind(self, addr: SocketAddr) -> Self;
+    /// Serve the Forms with `coap://` hrefs over CoAP, binding to addr.
+    fn coap_bind(self, addr: SocketAddr) -> Self;
+    /// Expose the affordances over MQTT through the broker at `url`, e.g. `mqtt://localhost`.
+    ///
+    /// The handlers of the http Forms are called, see the [`mqtt`] module.
+    ///
+    /// [`mqtt`]: crate::mqtt
+    fn mqtt_broker(self, url: impl Into<String>) -> Self;
+    /// Make the properties observable, delivering the changes sent through `notifier`.
+    ///
+    /// The properties not observable already get `observeproperty` and `unobserveproperty`
+    /// Forms for long polling, Server-Sent Events and WebSocket.
+    fn property_notifier(self, notifier: PropertyNotifier) -> Self;
+    /// Serve the history of the properties `history` records.
+    ///
+    /// The Thing gets a `version-history` Link anchored at each historical property, queried
+    /// by time range and downsampled through the `from`, `to` and `interval` parameters.
+    fn property_history(self, history: PropertyHistory) -> Self;
+    /// Persist the values written to the properties in `store`.
+    ///
+    /// The stored values are loaded when the Servient is built and written back through the
+    /// `writeproperty` Forms before it serves.
+    fn persist_properties(self, store: impl PropertyStore) -> Self;
+    /// Set the thing type to be advertised.
+    fn thing_type(self, ty: ThingType) -> Self;
+    /// Disable the default CORS settings.
+    fn http_disable_permissive_cors(self) -> Self;
+    /// Check the json responses of the properties and actions against their schemas.
+    ///
+    /// The check happens before the layers of the Forms and of the affordances.
+    fn http_validate_responses(self, validation: ResponseValidation) -> Self;
+    /// Serve the payloads of the json Forms in `media_type` as well.
+    ///
+    /// The Forms are served in the media type the `Accept` and `Content-Type` headers of
+    /// the requests ask for, a Form per media type is added to the Thing Description.
+    fn http_media_type(self, media_type: MediaType) -> Self;
+    /// Register the Thing Description with the Thing Directory at `url`.
+    ///
+    /// The registration is refreshed while the [`Servient`] is served and removed
+    /// on graceful shutdown.
+    fn register_with(self, url: impl Into<String>) -> Self;
+    /// Register the Thing Description with the Thing Directories found through DNS-SD.
+    ///
+    /// The registration follows the most recently announced directory.
+    fn register_with_discovered(self) -> Self;
+    /// Set the time-to-live of the directory registration.
+    ///
+    /// It is advertised in whole seconds, rounded up. Building the Servient fails if it is zero.
+    fn registration_ttl(self, ttl: Duration) -> Self;
+    /// Set the application state the handlers access through the [`State`] extractor.
+    ///
+    /// [`State`]: axum::extract::State
+    fn with_state(self, state: S) -> Self;
+    /// Serve `route` at `path`, along the routes of the Forms.
+    ///
+    /// Building the Servient fails if `path` overlaps with the paths of the Forms, the
+    /// Thing Description ones or the other additional routes.
+    fn http_route(self, path: impl Into<String>, route: MethodRouter<S>) -> Self;
+    /// Serve the routes of `router` under `path`.
+    ///
+    /// Building the Servient fails if any other route is located under `path`.
+    fn http_nest(self, path: impl Into<String>, router: Router<S>) -> Self;
+    /// Apply a tower layer to every route, including the merged and nested ones.
+    ///
+    /// The layers wrap the routes in the order they are added, the CORS layer wraps them all.
+    fn http_layer<L>(self, layer: L) -> Self
+    where
+        L: Layer<Route> + Clone + Send + 'static,
+        L::Service: Service<Request<Body>> + Clone + Send + 'static,
+        <L::Service as Service<Request<Body>>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request<Body>>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request<Body>>>::Future: Send + 'static;
+    /// Apply a tower layer to the routes of every Form of an affordance.
+    fn http_affordance_layer<L>(
+        self,
+        affordance: AffordanceType,
+        name: impl Into<String>,
+        layer: L,
+    ) -> Self
+    where
+        L: Layer<Route> + Clone + Send + 'static,
+        L::Service: Service<Request<Body>> + Clone + Send + 'static,
+        <L::Service as Service<Request<Body>>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request<Body>>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request<Body>>>::Future: Send + 'static;
+}
+
+impl<O: ExtendableThing, S> ServientSettings<S> for ThingBuilder<O, wot_td::builder::Extended>
+where
+    O: Holder<ServientExtension<S>>,
+    S: Clone + Send + Sync + 'static,
+{
+    fn http_bind(mut self, addr: SocketAddr) -> Self {
+        self.other.field_mut().addr = Some(addr);
+        self
+    }
+
+    fn coap_bind(mut self, addr: SocketAddr) -> Self {
+        self.other.field_mut().coap_addr = Some(addr);
+        self
+    }
+
+    fn mqtt_broker(mut self, url: impl Into<String>) -> Self {
+        self.other.field_mut().mqtt_broker = Some(url.into());
+        self
+    }
+
+    fn property_notifier(mut self, notifier: PropertyNotifier) -> Self {
+        self.other.field_mut().notifier = Some(notifier);
+        self
+    }
+
+    fn property_history(mut self, history: PropertyHistory) -> Self {
+        self.other.field_mut().history = Some(history);
+        self
+    }
+
+    fn persist_properties(mut self, store: impl PropertyStore) -> Self {
+        self.other.field_mut().property_store = Some(Arc::new(store));
+        self
+    }
+
+    fn thing_type(mut self, ty: ThingType) -> Self {
+        self.other.field_mut().thing_type = ty;
+        self
+    }
+
+    fn http_disable_permissive_cors(mut self) -> Self {
+        self.other.field_mut().permissive_cors = false;
+        self
+    }
+
+    fn http_validate_responses(mut self, validation: ResponseValidation) -> Self {
+        self.other.field_mut().response_validation = Some(validation);
+        self
+    }
+
+    fn http_media_type(mut self, media_type: MediaType) -> Self {
+        let media_types = &mut self.other.field_mut().media_types;
+        if media_type != MediaType::Json && !media_types.contains(&media_type) {
+            media_types.push(media_type);
+        }
+        self
+    }
+
+    fn register_with(mut self, url: impl Into<String>) -> Self {
+        self.other.field_mut().directory = Some(Directory::Url(url.into()));
+        self
+    }
+
+    fn register_with_discovered(mut self) -> Self {
+        self.other.field_mut().directory = Some(Directory::Discover);
+        self
+    }
+
+    fn registration_ttl(mut self, ttl: Duration) -> Self {
+        self.other.field_mut().registration_ttl = ttl;
+        self
+    }
+
+    fn with_state(mut self, state: S) -> Self {
+        self.other.field_mut().state = Some(state);
+        self
+    }
+
+    fn http_route(mut self, path: impl Into<String>, route: MethodRouter<S>) -> Self {
+        self.other.field_mut().routes.push((path.into(), route));
+        self
+    }
+
+    fn http_nest(mut self, path: impl Into<String>, router: Router<S>) -> Self {
+        self.other.field_mut().nested.push((path.into(), router));
+        self
+    }
+
+    fn http_layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<Route> + Clone + Send + 'static,
+        L::Service: Service<Request<Body>> + Clone + Send + 'static,
+        <L::Service as Service<Request<Body>>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request<Body>>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+    {
+        let layer = Middleware::new(move |router: Router| router.layer(layer.clone()));
+
+        self.other.field_mut().layers.push(layer);
+        self
+    }
+
+    fn http_affordance_layer<L>(
+        mut self,
+        affordance: AffordanceType,
+        name: impl Into<String>,
+        layer: L,
+    ) -> Self
+    where
+        L: Layer<Route> + Clone + Send + 'static,
+        L::Service: Service<Request<Body>> + Clone + Send + 'static,
+        <L::Service as Service<Request<Body>>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request<Body>>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+    {
+        let layer = Middleware::new(move |route: MethodRouter<S>| route.layer(layer.clone()));
+
+        self.other
+            .field_mut()
+            .affordance_layers
+            .push((affordance, name.into(), layer));
+        self
+    }
+}
+
+/// Extension trait to route the operations of the affordances by name.
+///
+/// It is useful when the affordances are not defined through the builder, e.g. when they come
+/// from a [Thing Model](crate::model::ThingModel) or from [`Servient::from_td`].
+///
+/// The handler is bound to every Form of the affordance declaring the operation, using the
+/// Form `htv:methodName` or the default http method for the operation, once the [`Servient`]
+/// is built.
+pub trait AffordanceRouter<S> {
+    /// Route the `readproperty` operation of the property `name` to the handler.
+    fn on_read_property<H, T>(self, name: impl Into<String>, handler: H) -> Self
+    where
+        H: Handler<T, S, axum::body::Body>,
+        T: 'static;
+    /// Route the `writeproperty` operation of the property `name` to the handler.
+    fn on_write_property<H, T>(self, name: impl Into<String>, handler: H) -> Self
+    where
+        H: Handler<T, S, axum::body::Body>,
+        T: 'static;
+    /// Route the `observeproperty` operation of the property `name` to the handler.
+    fn on_observe_property<H, T>(self, name: impl Into<String>, handler: H) -> Self
+    where
+        H: Handler<T, S, axum::body::Body>,
+        T: 'static;
+    /// Route the `unobserveproperty` operation of the property `name` to the handler.
+    fn on_unobserve_property<H, T>(self, name: impl Into<String>, handler: H) -> Self
+    where
+        H: Handler<T, S, axum::body::Body>,
+        T: 'static;
+    /// Route the `invokeaction` operation of the action `name` to the handler.
+    fn on_invoke_action<H, T>(self, name: impl Into<String>, handler: H) -> Self
+    where
+        H: Handler<T, S, axum::body::Body>,
+        T: 'static;
+    /// Route the `queryaction` operation of the action `name` to the handler.
+    fn on_query_action<H, T>(self, name: impl Into<String>, handler: H) -> Self
+    where
+        H: Handler<T, S, axum::body::Body>,
+        T: 'static;
+    /// Route the `cancelaction` operation of the action `name` to the handler.
+    fn on_cancel_action<H, T>(self, name: impl Into<String>, handler: H) -> Self
+    where
+        H: Handler<T, S, axum::body::Body>,
+        T: 'static;
+    /// Route the `subscribeevent` operation of the event `name` to the handler.
+    fn on_subscribe_event<H, T>(self, name: impl Into<String>, handler: H) -> Self
+    where
+        H: Handler<T, S, axum::body::Body>,
+        T: 'static;
+    /// Route the `unsubscribeevent` operation of the event `name` to the handler.
+    fn on_unsubscribe_event<H, T>(self, name: impl Into<String>, handler: H) -> Self
+    where
+        H: Handler<T, S, axum::body::Body>,
+        T: 'static;
+    /// Route the subscriptions to the event `name` to `subscriptions`.
+    ///
+    /// The event gets a `subscribeevent` and an `unsubscribeevent` Form, validating the
+    /// payloads against its `subscription` and `cancellation` schemas.
+    fn on_event_subscription<P, D>(
+        self,
+        name: impl Into<String>,
+        subscriptions: EventSubscriptions<P, D>,
+    ) -> Self
+    where
+        P: serde::de::DeserializeOwned + Clone + Send + Sync + 'static,
+        D: Serialize + Clone + Send + 'static;
+    /// Deliver the event `name` to the webhooks registered with `webhooks`.
+    ///
+    /// The event gets a `subscribeevent` and an `unsubscribeevent` Form with the `webhook`
+    /// subprotocol.
+    fn on_event_webhooks<D>(self, name: impl Into<String>, webhooks: EventWebhooks<D>) -> Self
+    where
+        D: Serialize + Clone + Send + Sync + 'static;
+    /// Route the operation `op` of the Forms of the Thing itself to the handler.
+    ///
+    /// E.g. `readallproperties` or `subscribeallevents`.
+    fn on_thing_operation<H, T>(self, op: FormOperation, handler: H) -> Self
+    where
+        H: Handler<T, S, axum::body::Body>,
+        T: 'static;
+}
+
+fn bind<O, S, H, T>(
+    mut builder: ThingBuilder<O, wot_td::builder::Extended>,
+    target: Target,
+    op: FormOperation,
+    handler: H,
+) -> ThingBuilder<O, wot_td::builder::Extended>
+where
+    O: ExtendableThing + Holder<ServientExtension<S>>,
+    S: Clone + Send + Sync + 'static,
+    H: Handler<T, S, axum::body::Body>,
+    T: 'static,
+{
+    let handler = Mutex::new(handler);
+    let route = move |method| {
+        let handler = handler.lock().unwrap().clone();
+        axum::routing::on(method_filter(method), handler)
+    };
+
+    builder.other.field_mut().bindings.push(Binding {
+        target,
+        op,
+        route: Arc::new(route),
+    });
+    builder
+}
+
+impl<O: ExtendableThing, S> AffordanceRouter<S> for ThingBuilder<O, wot_td::builder::Extended>
+where
+    O: Holder<ServientExtension<S>>,
+    S: Clone + Send + Sync + 'static,
+{
+    fn on_read_property<H, T>(self, name: impl Into<String>, handler: H) -> Self
+    where
+        H: Handler<T, S, axum::body::Body>,
+        T: 'static,
+    {
+        let target = Target::Affordance(AffordanceType::Property, name.into());
+        bind(self, target, FormOperation::ReadProperty, handler)
+    }
+
+    fn on_write_property<H, T>(self, name: impl Into<String>, handler: H) -> Self
+    where
+        H: Handler<T, S, axum::body::Body>,
+        T: 'static,
+    {
+        let target = Target::Affordance(AffordanceType::Property, name.into());
+        bind(self, target, FormOperation::WriteProperty, handler)
+    }
+
+    fn on_observe_property<H, T>(self, name: impl Into<String>, handler: H) -> Self
+    where
+        H: Handler<T, S, axum::body::Body>,
+        T: 'static,
+    {
+        let target = Target::Affordance(AffordanceType::Property, name.into());
+        bind(self, target, FormOperation::ObserveProperty, handler)
+    }
+
+    fn on_unobserve_property<H, T>(self, name: impl Into<String>, handler: H) -> Self
+    where
+        H: Handler<T, S, axum::body::Body>,
+        T: 'static,
+    {
+        let target = Target::Affordance(AffordanceType::Property, name.into());
+        bind(self, target, FormOperation::UnobserveProperty, handler)
+    }
+
+    fn on_invoke_action<H, T>(self, name: impl Into<String>, handler: H) -> Self
+    where
+        H: Handler<T, S, axum::body::Body>,
+        T: 'static,
+    {
+        let target = Target::Affordance(AffordanceType::Action, name.into());
+        bind(self, target, FormOperation::InvokeAction, handler)
+    }
+
+    fn on_query_action<H, T>(self, name: impl Into<String>, handler: H) -> Self
+    where
+        H: Handler<T, S, axum::body::Body>,
+        T: 'static,
+    {
+        let target = Target::Affordance(AffordanceType::Action, name.into());
+        bind(self, target, FormOperation::QueryAction, handler)
+    }
+
+    fn on_cancel_action<H, T>(self, name: impl Into<String>, handler: H) -> Self
+    where
+        H: Handler<T, S, axum::body::Body>,
+        T: 'static,
+    {
+        let target = Target::Affordance(AffordanceType::Action, name.into());
+        bind(self, target, FormOperation::CancelAction, handler)
+    }
+
+    fn on_subscribe_event<H, T>(self, name: impl Into<String>, handler: H) -> Self
+    where
+        H: Handler<T, S, axum::body::Body>,
+        T: 'static,
+    {
+        let target = Target::Affordance(AffordanceType::Event, name.into());
+        bind(self, target, FormOperation::SubscribeEvent, handler)
+    }
+
+    fn on_unsubscribe_event<H, T>(self, name: impl Into<String>, handler: H) -> Self
+    where
+        H: Handler<T, S, axum::body::Body>,
+        T: 'static,
+    {
+        let target = Target::Affordance(AffordanceType::Event, name.into());
+        bind(self, target, FormOperation::UnsubscribeEvent, handler)
+    }
+
+    fn on_event_subscription<P, D>(
+        mut self,
+        name: impl Into<String>,
+        subscriptions: EventSubscriptions<P, D>,
+    ) -> Self
+    where
+        P: serde::de::DeserializeOwned + Clone + Send + Sync + 'static,
+        D: Serialize + Clone + Send + 'static,
+    {
+        let routes = move |path: &str, subscription, cancellation| {
+            subscriptions
+                .clone()
+                .routes(path, subscription, cancellation)
+        };
+
+        let name = name.into();
+        self.other
+            .field_mut()
+            .subscriptions
+            .push(SubscriptionBinding {
+                href: format!("events/{name}"),
+                name,
+                subprotocol: "sse",
+                routes: Arc::new(routes),
+            });
+        self
+    }
+
+    fn on_event_webhooks<D>(mut self, name: impl Into<String>, webhooks: EventWebhooks<D>) -> Self
+    where
+        D: Serialize + Clone + Send + Sync + 'static,
+    {
+        let routes = move |path: &str, _, _| webhooks.clone().routes(path);
+
+        let name = name.into();
+        self.other
+            .field_mut()
+            .subscriptions
+            .push(SubscriptionBinding {
+                href: format!("events/{name}/webhooks"),
+                name,
+                subprotocol: WEBHOOK,
+                routes: Arc::new(routes),
+            });
+        self
+    }
+
+    fn on_thing_operation<H, T>(self, op: FormOperation, handler: H) -> Self
+    where
+        H: Handler<T, S, axum::body::Body>,
+        T: 'static,
+    {
+        bind(self, Target::Thing, op, handler)
+    }
+}
+
+/// Default http method for the operation, according to the
+/// [HTTP Binding Template](https://w3c.github.io/wot-binding-templates/bindings/protocols/http/#http-default-vocabulary-terms).
+pub(crate) fn default_method(op: FormOperation) -> Method {
+    use FormOperation::*;
+
+    match op {
+        ReadProperty
+        | ReadAllProperties
+        | ReadMultipleProperties
+        | ObserveProperty
+        | ObserveAllProperties
+        | QueryAction
+        | QueryAllActions
+        | SubscribeEvent
+        | SubscribeAllEvents => Method::Get,
+        WriteProperty | WriteAllProperties => Method::Put,
+        WriteMultipleProperties => Method::Patch,
+        InvokeAction => Method::Post,
+        CancelAction
+        | UnobserveProperty
+        | UnobserveAllProperties
+        | UnsubscribeEvent
+        | UnsubscribeAllEvents => Method::Delete,
+    }
+}
+
+/// Name of the http method, as in the request line.
+pub(crate) fn method_str(method: Method) -> &'static str {
+    match method {
+        Method::Get => "GET",
+        Method::Put => "PUT",
+        Method::Post => "POST",
+        Method::Delete => "DELETE",
+        Method::Patch => "PATCH",
+    }
+}
+
+pub(crate) fn method_filter(method: Method) -> MethodFilter {
+    match method {
+        Method::Get => MethodFilter::GET,
+        Method::Put => MethodFilter::PUT,
+        Method::Post => MethodFilter::POST,
+        Method::Delete => MethodFilter::DELETE,
+        Method::Patch => MethodFilter::PATCH,
+    }
+}
+
+/// Operations of a Form, including the default ones for the affordance.
+///
+/// The Forms of a property read it unless it is `writeOnly` and write it unless it is
+/// `readOnly`, by default.
+pub(crate) fn form_ops(
+    ops: &DefaultedFormOperations,
+    affordance: AffordanceType,
+    read_only: bool,
+    write_only: bool,
+) -> Vec<FormOperation> {
+    use FormOperation::*;
+
+    match ops {
+        DefaultedFormOperations::Custom(ops) => ops.clone(),
+        DefaultedFormOperations::Default => match affordance {
+            AffordanceType::Property => {
+                let mut ops = Vec::new();
+                if !write_only {
+                    ops.push(ReadProperty);
+                }
+                if !read_only {
+                    ops.push(WriteProperty);
+                }
+                ops
+            }
+            AffordanceType::Action => vec![InvokeAction],
+            AffordanceType::Event => vec![SubscribeEvent, UnsubscribeEvent],
+        },
+    }
+}
+
+/// Operations of a Form of the target.
+///
+/// The Forms of the Thing itself have no default operations. The default operations of the
+/// read-only and write-only properties are declared by [`declare_property_ops`] beforehand.
+fn target_ops(ops: &DefaultedFormOperations, target: &Target) -> Vec<FormOperation> {
+    match (target, ops) {
+        (Target::Affordance(affordance, _), ops) => form_ops(ops, *affordance, false, false),
+        (Target::Thing, DefaultedFormOperations::Custom(ops)) => ops.clone(),
+        (Target::Thing, DefaultedFormOperations::Default) => Vec::new(),
+    }
+}
+
+/// Declare the default operations of the Forms of the read-only and write-only properties.
+fn declare_property_ops<O: ExtendableThing>(thing: &mut Thing<O>) {
+    for property in thing.properties.iter_mut().flat_map(|m| m.values_mut()) {
+        let (read_only, write_only) = (
+            property.data_schema.read_only,
+            property.data_schema.write_only,
+        );
+        if !read_only && !write_only {
+            continue;
+        }
+
+        for form in &mut property.interaction.forms {
+            if form.op == DefaultedFormOperations::Default {
+                let ops = form_ops(&form.op, AffordanceType::Property, read_only, write_only);
+                form.op = DefaultedFormOperations::Custom(ops);
+            }
+        }
+    }
+}
+
+fn forms_mut<'a, O: ExtendableThing>(
+    thing: &'a mut Thing<O>,
+    target: &Target,
+) -> Option<&'a mut Vec<wot_td::thing::Form<O>>> {
+    match target {
+        Target::Thing => thing.forms.as_mut(),
+        Target::Affordance(AffordanceType::Property, name) => thing
+            .properties
+            .as_mut()?
+            .get_mut(name)
+            .map(|a| &mut a.interaction.forms),
+        Target::Affordance(AffordanceType::Action, name) => thing
+            .actions
+            .as_mut()?
+            .get_mut(name)
+            .map(|a| &mut a.interaction.forms),
+        Target::Affordance(AffordanceType::Event, name) => thing
+            .events
+            .as_mut()?
+            .get_mut(name)
+            .map(|a| &mut a.interaction.forms),
+    }
+}
+
+/// The Thing itself and each of its affordances.
+fn targets<O: ExtendableThing>(thing: &Thing<O>) -> Vec<Target> {
+    fn names<A>(
+        affordances: &Option<HashMap<String, A>>,
+        affordance: AffordanceType,
+    ) -> impl Iterator<Item = Target> + '_ {
+        affordances
+            .iter()
+            .flat_map(|m| m.keys())
+            .map(move |name| Target::Affordance(affordance, name.clone()))
+    }
+
+    std::iter::once(Target::Thing)
+        .chain(names(&thing.properties, AffordanceType::Property))
+        .chain(names(&thing.actions, AffordanceType::Action))
+        .chain(names(&thing.events, AffordanceType::Event))
+        .collect()
+}
+
+/// Every Form of the Thing and of its affordances.
+fn forms_iter_mut<O: ExtendableThing>(
+    thing: &mut Thing<O>,
+) -> impl Iterator<Item = &mut wot_td::thing::Form<O>> {
+    let thing_forms = thing.forms.iter_mut().flatten();
+    let properties_forms = thing
+        .properties
+        .iter_mut()
+        .flat_map(|m| m.values_mut().flat_map(|a| a.interaction.forms.iter_mut()));
+    let actions_forms = thing
+        .actions
+        .iter_mut()
+        .flat_map(|m| m.values_mut().flat_map(|a| a.interaction.forms.iter_mut()));
+    let events_forms = thing
+        .events
+        .iter_mut()
+        .flat_map(|m| m.values_mut().flat_map(|a| a.interaction.forms.iter_mut()));
+
+    thing_forms
+        .chain(properties_forms)
+        .chain(actions_forms)
+        .chain(events_forms)
+}
+
+/// Protocol a Form is served through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Protocol {
+    Http,
+    Coap,
+}
+
+/// Split an absolute url into its scheme and what follows `://`.
+fn split_scheme(url: &str) -> Option<(&str, &str)> {
+    let is_scheme = |s: &str| {
+        !s.is_empty()
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    };
+
+    url.split_once("://")
+        .filter(|(scheme, _)| is_scheme(scheme))
+}
+
+/// Protocol the Form is served through, `None` if the protocol is not served.
+///
+/// Relative hrefs use http.
+fn route_protocol(href: &str) -> Option<Protocol> {
+    match split_scheme(href) {
+        Some((scheme, _)) => match scheme.to_ascii_lowercase().as_str() {
+            "http" | "https" => Some(Protocol::Http),
+            "coap" => Some(Protocol::Coap),
+            _ => None,
+        },
+        None => Some(Protocol::Http),
+    }
+}
+
+/// Path the relative hrefs are resolved against: the `base` path up to its last segment.
+///
+/// See: https://www.rfc-editor.org/rfc/rfc3986#section-5.2.3
+fn base_path(base: Option<&str>) -> String {
+    let base = base.unwrap_or("/");
+    let path = match split_scheme(base) {
+        Some((_, rest)) => rest.find('/').map_or("/", |idx| &rest[idx..]),
+        None => base,
+    };
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    let dir = path.rfind('/').map_or("", |idx| &path[..=idx]);
+
+    if dir.starts_with('/') {
+        dir.to_string()
+    } else {
+        format!("/{dir}")
+    }
+}
+
+/// Protocol and path the Form is routed to, `None` if the protocol is not served.
+///
+/// Absolute hrefs are routed by their path, relative ones are resolved against `base`, the
+/// [`base_path`].
+fn route_path(href: &str, base: &str) -> Option<(Protocol, String)> {
+    let protocol = route_protocol(href)?;
+
+    let path = match split_scheme(href) {
+        Some((_, rest)) => rest
+            .find('/')
+            .map_or_else(|| "/".into(), |idx| rest[idx..].into()),
+        None if href.starts_with('/') => href.into(),
+        None => format!("{base}{}", href.trim_start_matches("./")),
+    };
+
+    Some((protocol, path))
+}
+
+/// Path the Form is routed to, `None` if the href does not use http.
+fn http_path(href: &str, base: &str) -> Option<String> {
+    match route_path(href, base)? {
+        (Protocol::Http, path) => Some(path),
+        _ => None,
+    }
+}
+
+/// Check that the http methods routed by the Form match its operations.
+///
+/// A Form with a single operation handled through a method other than the default
+/// one gets its `htv:methodName` set accordingly. Only actions are invoked through
+/// any method, `GET` stays reserved to the reading operations otherwise.
+fn check_methods<O, S>(form: &mut wot_td::thing::Form<O>, target: &Target) -> Result<(), Error>
+where
+    O: ExtendableThing,
+    O::Form: Holder<Form<S>>,
+{
+    if route_protocol(&form.href) != Some(Protocol::Http) {
+        return Ok(());
+    }
+
+    let ops = target_ops(&form.op, target);
+    let route = form.other.field_mut();
+
+    let unbound = ops.iter().copied().find(|op| {
+        let method = route.method_name.unwrap_or_else(|| default_method(*op));
+        !route.methods.contains(&method)
+    });
+    if let Some(op) = unbound {
+        // Actions may be invoked through any method, the other operations read
+        // through GET and only through it.
+        let compatible = |method: Method| {
+            op == FormOperation::InvokeAction
+                || (default_method(op) == Method::Get) == (method == Method::Get)
+        };
+        match (ops.as_slice(), route.methods.as_slice()) {
+            ([_], [method]) if route.method_name.is_none() && compatible(*method) => {
+                route.method_name = Some(*method)
+            }
+            _ => return Err(Error::UnboundForm(form.href.clone(), op)),
+        }
+    }
+
+    let undeclared = route
+        .methods
+        .iter()
+        .copied()
+        .find(|method| match route.method_name {
+            Some(name) => name != *method,
+            None => ops.iter().all(|op| default_method(*op) != *method),
+        });
+    if let Some(method) = undeclared {
+        return Err(Error::UndeclaredMethod(form.href.clone(), method));
+    }
+
+    Ok(())
+}
+
+/// Set the schemas of the affordances served by typed handlers.
+///
+/// The schema of a property is updated in place, keeping its metadata.
+fn apply_schemas<O, S>(thing: &mut Thing<O>) -> Result<(), serde_json::Error>
+where
+    O: ExtendableThing,
+    O::Form: Holder<Form<S>>,
+{
+    for target in targets(thing) {
+        let Target::Affordance(affordance, name) = &target else {
+            continue;
+        };
+        let Some(schema) = forms_mut(thing, &target)
+            .into_iter()
+            .flatten()
+            .filter_map(|form| form.other.field_mut().schema.take())
+            .last()
+        else {
+            continue;
+        };
+        let schema = serde_json::to_value(schema)?;
+
+        match affordance {
+            AffordanceType::Property => {
+                let property = thing
+                    .properties
+                    .as_mut()
+                    .and_then(|p| p.get_mut(name))
+                    .expect("the target has just been listed");
+
+                let mut current = serde_json::to_value(&property.data_schema)?;
+                for (key, value) in schema.as_object().into_iter().flatten() {
+                    let keep = match key.as_str() {
+                        "readOnly" | "writeOnly" => true,
+                        "title" | "description" => current.get(key).is_some(),
+                        _ => false,
+                    };
+                    if !keep {
+                        current[key] = value.clone();
+                    }
+                }
+                property.data_schema = serde_json::from_value(current)?;
+            }
+            AffordanceType::Action => {
+                let action = thing
+                    .actions
+                    .as_mut()
+                    .and_then(|a| a.get_mut(name))
+                    .expect("the target has just been listed");
+                action.output = Some(serde_json::from_value(schema)?);
+            }
+            AffordanceType::Event => {
+                let event = thing
+                    .events
+                    .as_mut()
+                    .and_then(|e| e.get_mut(name))
+                    .expect("the target has just been listed");
+                event.data = Some(serde_json::from_value(schema)?);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Validate the responses of the Forms reading the properties and invoking the actions.
+fn validate_forms<O, S>(
+    thing: &mut Thing<O>,
+    validation: ResponseValidation,
+) -> Result<(), serde_json::Error>
+where
+    O: ExtendableThing,
+    O::Form: Holder<Form<S>>,
+    S: Clone + Send + Sync + 'static,
+{
+    for target in targets(thing) {
+        let (op, schema) = match &target {
+            Target::Affordance(AffordanceType::Property, name) => {
+                let property = &thing.properties.as_ref().expect("listed target")[name];
+                (
+                    FormOperation::ReadProperty,
+                    serde_json::to_value(&property.data_schema)?,
+                )
+            }
+            Target::Affordance(AffordanceType::Action, name) => {
+                let action = &thing.actions.as_ref().expect("listed target")[name];
+                let Some(output) = &action.output else {
+                    continue;
+                };
+                (FormOperation::InvokeAction, serde_json::to_value(output)?)
+            }
+            _ => continue,
+        };
+
+        for form in forms_mut(thing, &target).into_iter().flatten() {
+            if route_protocol(&form.href).is_none() || !target_ops(&form.op, &target).contains(&op)
+            {
+                continue;
+            }
+
+            let href = form.href.clone();
+            let route = form.other.field_mut();
+            let method = route.method_name.unwrap_or_else(|| default_method(op));
+            route.method_router = validate_responses(
+                std::mem::take(&mut route.method_router),
+                href,
+                method,
+                schema.clone(),
+                validation,
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the Form is served through http with json payloads.
+///
+/// The Forms with a subprotocol, e.g. the observations and subscriptions, stream their own
+/// payloads and are not negotiated.
+fn is_json<O: ExtendableThing>(form: &wot_td::thing::Form<O>) -> bool {
+    route_protocol(&form.href) == Some(Protocol::Http)
+        && form.subprotocol.is_none()
+        && form
+            .content_type
+            .as_deref()
+            .is_none_or(|ty| MediaType::parse(ty) == Some(MediaType::Json))
+}
+
+/// Describe the json Forms once per media type served.
+///
+/// The copies are added after routing, as they share the route of the original Form.
+fn add_media_forms<O>(
+    thing: &mut Thing<O>,
+    media_types: &[MediaType],
+) -> Result<(), serde_json::Error>
+where
+    O: ExtendableThing,
+{
+    for target in targets(thing) {
+        let Some(forms) = forms_mut(thing, &target) else {
+            continue;
+        };
+
+        let mut extra = Vec::new();
+        for form in forms.iter_mut().filter(|form| is_json(form)) {
+            form.content_type = Some(MediaType::Json.as_str().to_string());
+
+            // The Form extension is not Clone, the copies lose their routes.
+            let value = serde_json::to_value(&*form)?;
+            for media_type in media_types {
+                let mut copy: wot_td::thing::Form<O> = serde_json::from_value(value.clone())?;
+                copy.content_type = Some(media_type.as_str().to_string());
+                extra.push(copy);
+            }
+        }
+        forms.extend(extra);
+    }
+
+    Ok(())
+}
+
+/// Make the properties without an `observeproperty` Form observable through `notifier`.
+///
+/// Each property gets a long polling Form, routed to the observation handler, and returns
+/// the Forms describing its other transports, to add once routed as they share its route.
+fn add_observe_forms<O, S>(
+    thing: &mut Thing<O>,
+    notifier: &PropertyNotifier,
+) -> Result<Vec<(Target, Vec<Value>)>, serde_json::Error>
+where
+    O: ExtendableThing,
+    O::Form: Holder<Form<S>>,
+    S: Clone + Send + Sync + 'static,
+{
+    let mut described = Vec::new();
+
+    for (name, property) in thing.properties.iter_mut().flatten() {
+        let target = Target::Affordance(AffordanceType::Property, name.clone());
+        let observed =
+            property.interaction.forms.iter().any(|form| {
+                target_ops(&form.op, &target).contains(&FormOperation::ObserveProperty)
+            });
+        if observed || property.data_schema.write_only {
+            continue;
+        }
+
+        let href = format!("properties/{name}/observe");
+        let ops = ["observeproperty", "unobserveproperty"];
+
+        let mut form: wot_td::thing::Form<O> = serde_json::from_value(serde_json::json!({
+            "href": href,
+            "op": ops,
+            "subprotocol": "longpoll",
+        }))?;
+        let route = form.other.field_mut();
+        route.method_router = notifier.route(name);
+        route.methods = vec![Method::Get, Method::Delete];
+
+        property.interaction.forms.push(form);
+        property.observable = Some(true);
+
+        let forms = vec![
+            serde_json::json!({
+                "href": href,
+                "op": ops,
+                "subprotocol": "sse",
+                "contentType": EVENT_STREAM,
+            }),
+            serde_json::json!({
+                "href": href,
+                "op": ops,
+                "subprotocol": WEBSOCKET,
+                "contentType": MediaType::Json.as_str(),
+            }),
+        ];
+        described.push((target, forms));
+    }
+
+    Ok(described)
+}
+
+/// Route the subscriptions to the events, adding their Forms.
+fn add_subscription_forms<O, S>(
+    thing: &mut Thing<O>,
+    subscriptions: Vec<SubscriptionBinding<S>>,
+) -> Result<(), Error>
+where
+    O: ExtendableThing,
+    O::Form: Holder<Form<S>>,
+    S: Clone + Send + Sync + 'static,
+{
+    let base = base_path(thing.base.as_deref());
+
+    for SubscriptionBinding {
+        name,
+        href,
+        subprotocol,
+        routes,
+    } in subscriptions
+    {
+        let event = thing
+            .events
+            .as_mut()
+            .and_then(|events| events.get_mut(&name))
+            .ok_or_else(|| Error::UnknownAffordance(AffordanceType::Event, name.clone()))?;
+
+        let subscription = event
+            .subscription
+            .as_ref()
+            .map(serde_json::to_value)
+            .transpose()?;
+        let cancellation = event
+            .cancellation
+            .as_ref()
+            .map(serde_json::to_value)
+            .transpose()?;
+        // The subscriptions are located under the path the Form is routed to.
+        let path = http_path(&href, &base).unwrap_or_else(|| format!("/{href}"));
+        let (subscribe, cancel) = routes(&path, subscription, cancellation);
+
+        let mut form: wot_td::thing::Form<O> = serde_json::from_value(serde_json::json!({
+            "href": href,
+            "op": "subscribeevent",
+            "htv:methodName": "POST",
+            "subprotocol": subprotocol,
+        }))?;
+        let route = form.other.field_mut();
+        route.method_router = subscribe;
+        route.methods = vec![Method::Post];
+        event.interaction.forms.push(form);
+
+        let mut form: wot_td::thing::Form<O> = serde_json::from_value(serde_json::json!({
+            "href": format!("{href}/{{id}}"),
+            "op": "unsubscribeevent",
+            "subprotocol": subprotocol,
+        }))?;
+        let route = form.other.field_mut();
+        route.method_router = cancel;
+        route.methods = vec![Method::Delete];
+        event.interaction.forms.push(form);
+
+        let id = serde_json::json!({ "id": { "type": "string" } });
+        add_uri_variables(&mut event.interaction.uri_variables, id)?;
+    }
+
+    Ok(())
+}
+
+/// Persist the values written through the `writeproperty` Forms.
+///
+/// The values are restored through the routes as they are, before the layers wrap them.
+fn persist_forms<O, S>(thing: &mut Thing<O>, persisted: &mut PersistedProperties, state: &S)
+where
+    O: ExtendableThing,
+    O::Form: Holder<Form<S>>,
+    S: Clone + Send + Sync + 'static,
+{
+    let base = base_path(thing.base.as_deref());
+
+    for target in targets(thing) {
+        let Target::Affordance(AffordanceType::Property, name) = &target else {
+            continue;
+        };
+
+        for form in forms_mut(thing, &target).into_iter().flatten() {
+            let Some(path) = http_path(&form.href, &base) else {
+                continue;
+            };
+            if !target_ops(&form.op, &target).contains(&FormOperation::WriteProperty) {
+                continue;
+            }
+
+            let route = form.other.field_mut();
+            let method = route
+                .method_name
+                .unwrap_or_else(|| default_method(FormOperation::WriteProperty));
+            let method: axum::http::Method = method_str(method).parse().expect("valid http method");
+
+            if !path.contains('{') {
+                let unlayered = route.method_router.clone().with_state(state.clone());
+                persisted.restore_with(name, method.clone(), path, unlayered);
+            }
+            route.method_router =
+                persisted.persist_writes(std::mem::take(&mut route.method_router), name, method);
+        }
+    }
+}
+
+/// Link the history of the historical properties, returns their routes by property name.
+///
+/// The history is not a value of the property, it is linked instead of read through a Form.
+/// The href of the link is a URI template describing the [`HistoryQuery`].
+///
+/// [`HistoryQuery`]: crate::servient::HistoryQuery
+fn add_history_links<O, S>(
+    thing: &mut Thing<O>,
+    history: &PropertyHistory,
+) -> Result<Vec<(String, String, MethodRouter<S>)>, Error>
+where
+    O: ExtendableThing,
+    S: Clone + Send + Sync + 'static,
+{
+    let mut routes = Vec::new();
 
-use crate::{
-    advertise::{Advertiser, ThingType},
-    hlist::*,
-    servient::Servient,
-};
-use axum::{handler::Handler, response::Redirect, routing::MethodRouter, Router};
-use tower_http::cors::*;
+    for name in history.names() {
+        if !thing
+            .properties
+            .as_ref()
+            .is_some_and(|properties| properties.contains_key(&name))
+        {
+            return Err(Error::UnknownAffordance(AffordanceType::Property, name));
+        }
 
-use datta::{Operator, UriTemplate};
-use serde::{Deserialize, Serialize};
-use uuid::Uuid;
-use wot_td::{
-    builder::{FormBuilder, ThingBuilder},
-    extend::ExtendableThing,
-};
+        let href = format!("properties/{name}/history{{?from,to,interval}}");
+        let link = serde_json::from_value(serde_json::json!({
+            "href": href,
+            "type": MediaType::Json.as_str(),
+            "rel": HISTORY,
+            "anchor": format!("#/properties/{name}"),
+        }))?;
+        thing.links.get_or_insert_with(Vec::new).push(link);
 
-#[doc(hidden)]
-/// ThingBuilder ExtendableThing used to build a Servient
-///
-/// It is not needed to know about it nor use it directly.
-/// Instantiate a correct builder by calling [`Servient::builder`].
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct ServientExtension {
-    /// Listening address
-    #[serde(skip)]
-    addr: Option<SocketAddr>,
-    /// Thing type
-    #[serde(skip)]
-    thing_type: ThingType,
-    #[serde(skip)]
-    permissive_cors: bool,
+        routes.push((name.clone(), href, history.route(&name)));
+    }
+
+    Ok(routes)
 }
 
-impl Default for ServientExtension {
-    fn default() -> Self {
-        ServientExtension {
-            addr: None,
-            thing_type: ThingType::default(),
-            permissive_cors: true,
-        }
+/// Declare the uri variables of an affordance, keeping the ones already declared.
+fn add_uri_variables<V: serde::de::DeserializeOwned>(
+    variables: &mut Option<HashMap<String, V>>,
+    declared: Value,
+) -> Result<(), serde_json::Error> {
+    let declared: HashMap<String, V> = serde_json::from_value(declared)?;
+    let variables = variables.get_or_insert_with(HashMap::new);
+    for (name, schema) in declared {
+        variables.entry(name).or_insert(schema);
     }
+
+    Ok(())
 }
 
-#[doc(hidden)]
-/// Form Extension
-#[derive(Debug, Default, Serialize, Deserialize, Clone)]
-pub struct Form {
-    #[serde(skip)]
-    method_router: MethodRouter,
+/// The http request handling `op` on the Forms of the target, if routed to a fixed path.
+fn handler_route<O, S>(
+    thing: &mut Thing<O>,
+    target: &Target,
+    op: FormOperation,
+) -> Option<mqtt::Route>
+where
+    O: ExtendableThing,
+    O::Form: Holder<Form<S>>,
+{
+    let base = base_path(thing.base.as_deref());
+
+    forms_mut(thing, target)?.iter().find_map(|form| {
+        let path = http_path(&form.href, &base).filter(|path| !path.contains('{'))?;
+        // Only the plain http Forms, e.g. not the Server-Sent Events streams.
+        if !matches!(form.subprotocol.as_deref(), None | Some("longpoll")) {
+            return None;
+        }
+        if !target_ops(&form.op, target).contains(&op) {
+            return None;
+        }
+
+        let route = form.other.field_ref();
+        let method = route.method_name.unwrap_or_else(|| default_method(op));
+        route.methods.contains(&method).then(|| mqtt::Route {
+            path,
+            method: method_str(method).parse().expect("valid http method"),
+        })
+    })
 }
 
-impl From<MethodRouter> for Form {
-    fn from(method_router: MethodRouter) -> Self {
-        Self { method_router }
+/// Describe the affordances exposed over MQTT and list the topics to serve.
+///
+/// The topics are named after `name`, the Forms use the broker url as href.
+fn add_mqtt_forms<O, S>(
+    thing: &mut Thing<O>,
+    broker: &str,
+    name: &str,
+) -> Result<(Vec<Publication>, Vec<Subscription>), serde_json::Error>
+where
+    O: ExtendableThing,
+    O::Form: Holder<Form<S>>,
+{
+    let mut publications = Vec::new();
+    let mut subscriptions = Vec::new();
+
+    for target in targets(thing) {
+        let Target::Affordance(affordance, affordance_name) = &target else {
+            continue;
+        };
+
+        let mut form = match affordance {
+            AffordanceType::Property => {
+                let topic = format!("thing/{name}/properties/{affordance_name}");
+                let read = handler_route(thing, &target, FormOperation::ReadProperty);
+                let observe = handler_route(thing, &target, FormOperation::ObserveProperty);
+                let ops: Vec<_> = [
+                    read.as_ref().map(|_| "readproperty"),
+                    observe.as_ref().map(|_| "observeproperty"),
+                ]
+                .into_iter()
+                .flatten()
+                .collect();
+                if ops.is_empty() {
+                    continue;
+                }
+
+                publications.push(Publication {
+                    topic: topic.clone(),
+                    retain: true,
+                    read,
+                    observe,
+                });
+                serde_json::json!({
+                    "op": ops,
+                    "mqv:filter": topic,
+                    "mqv:controlPacket": ControlPacket::Subscribe,
+                    "mqv:retain": true,
+                })
+            }
+            AffordanceType::Event => {
+                let topic = format!("thing/{name}/events/{affordance_name}");
+                let Some(observe) = handler_route(thing, &target, FormOperation::SubscribeEvent)
+                else {
+                    continue;
+                };
+
+                publications.push(Publication {
+                    topic: topic.clone(),
+                    retain: false,
+                    read: None,
+                    observe: Some(observe),
+                });
+                serde_json::json!({
+                    "op": "subscribeevent",
+                    "mqv:filter": topic,
+                    "mqv:controlPacket": ControlPacket::Subscribe,
+                })
+            }
+            AffordanceType::Action => {
+                let topic = format!("thing/{name}/actions/{affordance_name}");
+                let Some(route) = handler_route(thing, &target, FormOperation::InvokeAction) else {
+                    continue;
+                };
+
+                subscriptions.push(Subscription {
+                    topic: topic.clone(),
+                    route,
+                });
+                serde_json::json!({
+                    "op": "invokeaction",
+                    "mqv:topic": topic,
+                    "mqv:controlPacket": ControlPacket::Publish,
+                })
+            }
+        };
+
+        form["href"] = broker.into();
+        form["contentType"] = MediaType::Json.as_str().into();
+        let form = serde_json::from_value(form)?;
+        forms_mut(thing, &target)
+            .expect("the target has just been listed")
+            .push(form);
+    }
+
+    if !publications.is_empty() || !subscriptions.is_empty() {
+        add_context(thing, "mqv", mqtt::MQV_CONTEXT);
     }
+
+    Ok((publications, subscriptions))
 }
 
-impl ExtendableThing for ServientExtension {
-    type InteractionAffordance = ();
-    type PropertyAffordance = ();
-    type ActionAffordance = ();
-    type EventAffordance = ();
-    type Form = Form;
-    type ExpectedResponse = ();
-    type DataSchema = ();
-    type ObjectSchema = ();
-    type ArraySchema = ();
+/// Add a prefix to the JSON-LD context of the Thing.
+fn add_context<O: ExtendableThing>(thing: &mut Thing<O>, prefix: &str, iri: &str) {
+    let contexts = match &mut thing.context {
+        Value::Array(contexts) => contexts,
+        context => {
+            *context = Value::Array(vec![context.take()]);
+            context.as_array_mut().expect("just set")
+        }
+    };
+
+    match contexts.iter_mut().find_map(Value::as_object_mut) {
+        Some(map) => {
+            map.entry(prefix).or_insert_with(|| iri.into());
+        }
+        None => contexts.push(serde_json::json!({ prefix: iri })),
+    }
 }
 
-/// Extension trait for the [`Servient`] configuration.
-pub trait ServientSettings {
-    /// Bind the http server to addr
-    fn http_bind(self, addr: SocketAddr) -> Self;
-    /// Set the thing type to be advertised.
-    fn thing_type(self, ty: ThingType) -> Self;
-    /// Disable the default CORS settings.
-    fn http_disable_permissive_cors(self) -> Self;
+/// Take what is missing from `thing` out of `model`.
+fn merge_model<O>(thing: &mut Thing<O>, model: Value) -> Result<(), serde_json::Error>
+where
+    O: ExtendableThing + serde::de::DeserializeOwned,
+{
+    fn merge<A: serde::de::DeserializeOwned>(
+        dest: &mut Option<HashMap<String, A>>,
+        src: Option<&Value>,
+    ) -> Result<(), serde_json::Error> {
+        let Some(src) = src else {
+            return Ok(());
+        };
+        let src: HashMap<String, A> = serde_json::from_value(src.clone())?;
+        let dest = dest.get_or_insert_with(Default::default);
+
+        for (name, a) in src {
+            dest.entry(name).or_insert(a);
+        }
+
+        Ok(())
+    }
+
+    fn fill<T: serde::de::DeserializeOwned>(
+        dest: &mut Option<T>,
+        src: Option<&Value>,
+    ) -> Result<(), serde_json::Error> {
+        if let (Some(src), None) = (src, &dest) {
+            *dest = Some(serde_json::from_value(src.clone())?);
+        }
+
+        Ok(())
+    }
+
+    fill(&mut thing.forms, model.get("forms"))?;
+    fill(&mut thing.titles, model.get("titles"))?;
+    fill(&mut thing.descriptions, model.get("descriptions"))?;
+    fill(&mut thing.support, model.get("support"))?;
+    fill(&mut thing.links, model.get("links"))?;
+    fill(&mut thing.uri_variables, model.get("uriVariables"))?;
+    fill(
+        &mut thing.schema_definitions,
+        model.get("schemaDefinitions"),
+    )?;
+
+    merge::<PropertyAffordance<O>>(&mut thing.properties, model.get("properties"))?;
+    merge::<ActionAffordance<O>>(&mut thing.actions, model.get("actions"))?;
+    merge::<EventAffordance<O>>(&mut thing.events, model.get("events"))?;
+
+    if let Some(defs) = model.get("securityDefinitions") {
+        let defs: HashMap<String, wot_td::thing::SecurityScheme> =
+            serde_json::from_value(defs.clone())?;
+        for (name, def) in defs {
+            thing.security_definitions.entry(name).or_insert(def);
+        }
+    }
+    if thing.security.is_empty() {
+        if let Some(security) = model.get("security") {
+            thing.security = match security {
+                Value::String(s) => vec![s.clone()],
+                _ => serde_json::from_value(security.clone())?,
+            };
+        }
+    }
+
+    Ok(())
 }
 
-impl<O: ExtendableThing> ServientSettings for ThingBuilder<O, wot_td::builder::Extended>
+/// Attach the handlers to the http Forms of the targets.
+fn bind_handlers<O, S>(thing: &mut Thing<O>, bindings: Vec<Binding<S>>) -> Result<(), Error>
 where
-    O: Holder<ServientExtension>,
+    O: ExtendableThing,
+    O::Form: Holder<Form<S>>,
+    S: Clone + Send + Sync + 'static,
 {
-    fn http_bind(mut self, addr: SocketAddr) -> Self {
-        self.other.field_mut().addr = Some(addr);
-        self
+    let mut methods = HashSet::new();
+    let base = base_path(thing.base.as_deref());
+
+    for Binding { target, op, route } in bindings {
+        let forms = forms_mut(thing, &target).ok_or_else(|| match &target {
+            Target::Thing => Error::UnknownThingOperation(op),
+            Target::Affordance(affordance, name) => {
+                Error::UnknownAffordance(*affordance, name.clone())
+            }
+        })?;
+
+        let mut found = false;
+        for form in forms.iter_mut() {
+            let Some((protocol, path)) = route_path(&form.href, &base) else {
+                continue;
+            };
+            if !target_ops(&form.op, &target).contains(&op) {
+                continue;
+            }
+
+            let form_route = form.other.field_mut();
+            let method = form_route.method_name.unwrap_or_else(|| default_method(op));
+            if !methods.insert((protocol, uritemplate_to_axum(&path), method)) {
+                return Err(Error::OverlappingOperation(form.href.clone(), op));
+            }
+
+            let current = std::mem::take(&mut form_route.method_router);
+            form_route.method_router = current.merge(route(method));
+            form_route.methods.push(method);
+            found = true;
+        }
+
+        if !found {
+            return Err(match target {
+                Target::Thing => Error::UnknownThingOperation(op),
+                Target::Affordance(affordance, name) => {
+                    Error::UnknownOperation(affordance, name, op)
+                }
+            });
+        }
     }
 
-    fn thing_type(mut self, ty: ThingType) -> Self {
-        self.other.field_mut().thing_type = ty;
-        self
+    Ok(())
+}
+
+/// Prepare a builder for the Thing Description `td`.
+///
+/// The metadata needed to build the Thing is set right away, everything else is taken
+/// from the description once the [`Servient`] is built. The application state is left unset.
+pub(crate) fn description_builder<S>(
+    td: Value,
+) -> Result<ThingBuilder<NilPlus<ServientExtension<S>>, Extended>, serde_json::Error>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let title = td.get("title").and_then(Value::as_str).unwrap_or_default();
+    let mut builder = Servient::stateful_builder::<S>(title);
+
+    let contexts = match td.get("@context") {
+        Some(Value::Array(contexts)) => contexts.as_slice(),
+        Some(context) => std::slice::from_ref(context),
+        None => &[],
+    };
+    for context in contexts {
+        match context {
+            Value::String(s) if s != TD_CONTEXT_10 && s != TD_CONTEXT_11 && s != TM_CONTEXT => {
+                builder = builder.context(s);
+            }
+            Value::Object(map) => {
+                let map: Vec<_> = map
+                    .iter()
+                    .filter_map(|(k, v)| Some((k, v.as_str()?)))
+                    .filter(|(_, v)| *v != TM_CONTEXT)
+                    .collect();
+                if !map.is_empty() {
+                    builder = builder.context_map(|b| {
+                        for (k, v) in map {
+                            b.context(k, v);
+                        }
+                        b
+                    });
+                }
+            }
+            _ => {}
+        }
     }
 
-    fn http_disable_permissive_cors(mut self) -> Self {
-        self.other.field_mut().permissive_cors = false;
-        self
+    let mut builder = builder.finish_extend();
+
+    if let Some(id) = td.get("id").and_then(Value::as_str) {
+        builder = builder.id(id);
     }
+    if let Some(description) = td.get("description").and_then(Value::as_str) {
+        builder = builder.description(description);
+    }
+    if let Some(base) = td.get("base").and_then(Value::as_str) {
+        builder = builder.base(base);
+    }
+    if let Some(version) = td.get("version") {
+        let version: wot_td::thing::VersionInfo = serde_json::from_value(version.clone())?;
+        builder = builder.version(version);
+    }
+    match td.get("@type") {
+        Some(Value::String(ty)) => builder = builder.attype(ty),
+        Some(Value::Array(tys)) => {
+            for ty in tys.iter().filter_map(Value::as_str) {
+                builder = builder.attype(ty);
+            }
+        }
+        _ => {}
+    }
+
+    builder.other.field_mut().model = Some(td);
+
+    Ok(builder)
 }
 
 /// Trait extension to build a [`Servient`] from an extended [`ThingBuilder`]
 ///
 /// TODO: Add an example
-pub trait BuildServient {
+pub trait BuildServient<S = ()> {
     /// Extension type for the [`Servient`] and underlying [`Thing`].
     ///
     /// [`Thing`]: wot_td::thing::Thing
     type Other: ExtendableThing;
     /// Build the configured [`Servient`].
-    fn build_servient(self) -> Result<Servient<Self::Other>, Box<dyn std::error::Error>>;
+    fn build_servient(self) -> Result<Servient<Self::Other, S>, Box<dyn std::error::Error>>;
 }
 
 fn uritemplate_to_axum(uri: &str) -> String {
@@ -146,96 +1883,357 @@ fn uritemplate_to_axum(uri: &str) -> String {
     path
 }
 
-impl<O: ExtendableThing> BuildServient for ThingBuilder<O, wot_td::builder::Extended>
+impl<O: ExtendableThing, S> BuildServient<S> for ThingBuilder<O, wot_td::builder::Extended>
 where
-    O: Holder<ServientExtension>,
-    O::Form: Holder<Form>,
-    O: Serialize,
+    O: Holder<ServientExtension<S>>,
+    O::Form: Holder<Form<S>>,
+    O: Serialize + serde::de::DeserializeOwned,
+    S: Clone + Send + Sync + 'static,
 {
     type Other = O;
 
     /// Build the configured Servient
-    fn build_servient(self) -> Result<Servient<Self::Other>, Box<dyn std::error::Error>> {
-        let thing = self.build()?;
+    fn build_servient(self) -> Result<Servient<Self::Other, S>, Box<dyn std::error::Error>> {
+        build_servient(self).map_err(|err| err as _)
+    }
+}
 
-        let mut router = Router::new();
+/// Build the Servient configured by `builder`, with an error that can cross threads.
+pub(crate) fn build_servient<O, S>(
+    builder: ThingBuilder<O, Extended>,
+) -> Result<Servient<O, S>, Box<dyn std::error::Error + Send + Sync>>
+where
+    O: ExtendableThing + Holder<ServientExtension<S>>,
+    O::Form: Holder<Form<S>>,
+    O: Serialize + serde::de::DeserializeOwned,
+    S: Clone + Send + Sync + 'static,
+{
+    let mut thing = builder.build()?;
 
-        let thing_forms = thing.forms.iter().flat_map(|o| o.iter());
-        let properties_forms = thing
-            .properties
-            .iter()
-            .flat_map(|m| m.values().flat_map(|a| a.interaction.forms.iter()));
-        let actions_forms = thing
-            .actions
-            .iter()
-            .flat_map(|m| m.values().flat_map(|a| a.interaction.forms.iter()));
-        let events_forms = thing
-            .events
-            .iter()
-            .flat_map(|m| m.values().flat_map(|a| a.interaction.forms.iter()));
+    let state = thing
+        .other
+        .field_mut()
+        .state
+        .take()
+        .ok_or(Error::MissingState)?;
 
-        for form in thing_forms
-            .chain(properties_forms)
-            .chain(actions_forms)
-            .chain(events_forms)
-        {
-            let route = form.other.field_ref();
+    let model = thing.other.field_mut().model.take();
+    let bindings = std::mem::take(&mut thing.other.field_mut().bindings);
+
+    if let Some(model) = model {
+        merge_model(&mut thing, model)?;
+    }
 
-            let href = uritemplate_to_axum(&form.href);
+    declare_property_ops(&mut thing);
+    bind_handlers(&mut thing, bindings)?;
 
-            router = router.route(&href, route.method_router.clone());
+    for target in targets(&thing) {
+        for form in forms_mut(&mut thing, &target).into_iter().flatten() {
+            check_methods(form, &target)?;
         }
+    }
 
-        // TODO: Figure out how to share the thing description and if we want to.
-        let json = serde_json::to_value(&thing)?;
+    apply_schemas(&mut thing)?;
 
-        // We serve The thing from the root
-        router = router.route("/", axum::routing::get(move || async { axum::Json(json) }));
+    if let Some(validation) = thing.other.field_ref().response_validation {
+        validate_forms(&mut thing, validation)?;
+    }
 
-        // We redirect this path to / to support relative Forms with empty base
-        // See: https://www.rfc-editor.org/rfc/rfc3986#section-5.1.3
-        router = router.route(
-            "/.well-known/wot",
-            axum::routing::get(move || async { Redirect::to("/") }),
-        );
+    let persisted = match thing.other.field_mut().property_store.take() {
+        Some(store) => {
+            let mut persisted = PersistedProperties::load(store).map_err(Error::Persistence)?;
+            persist_forms(&mut thing, &mut persisted, &state);
+            Some(persisted)
+        }
+        None => None,
+    };
 
-        if thing.other.field_ref().permissive_cors {
-            let cors = CorsLayer::new()
-                .allow_methods(tower_http::cors::Any)
-                .allow_origin(tower_http::cors::Any);
-            router = router.layer(cors);
+    let media_types = std::mem::take(&mut thing.other.field_mut().media_types);
+    if !media_types.is_empty() {
+        for form in forms_iter_mut(&mut thing).filter(|form| is_json(form)) {
+            let route = form.other.field_mut();
+            route.method_router =
+                negotiate_content(std::mem::take(&mut route.method_router), &media_types);
         }
+    }
 
-        let sd = Advertiser::new()?;
+    for form in forms_iter_mut(&mut thing) {
+        let route = form.other.field_mut();
+        for layer in std::mem::take(&mut route.layers) {
+            route.method_router = layer.apply(std::mem::take(&mut route.method_router));
+        }
+    }
 
-        let name = {
-            let name = thing
-                .title
-                .split_whitespace()
-                .next()
-                .unwrap_or("")
-                .to_lowercase();
-            let uuid = Uuid::new_v4();
+    let observe_forms = match thing.other.field_ref().notifier.clone() {
+        Some(notifier) => add_observe_forms(&mut thing, &notifier)?,
+        None => Vec::new(),
+    };
 
-            format!("{}{}", name, uuid.as_simple())
-        };
+    let subscriptions = std::mem::take(&mut thing.other.field_mut().subscriptions);
+    add_subscription_forms(&mut thing, subscriptions)?;
+
+    let mut history_routes = match thing.other.field_ref().history.clone() {
+        Some(history) => add_history_links(&mut thing, &history)?,
+        None => Vec::new(),
+    };
+
+    let affordance_layers = std::mem::take(&mut thing.other.field_mut().affordance_layers);
+    for (affordance, name, layer) in affordance_layers {
+        if affordance == AffordanceType::Property {
+            for (_, _, route) in history_routes.iter_mut().filter(|(n, ..)| *n == name) {
+                *route = layer.apply(std::mem::take(route));
+            }
+        }
+
+        let target = Target::Affordance(affordance, name.clone());
+        let forms =
+            forms_mut(&mut thing, &target).ok_or(Error::UnknownAffordance(affordance, name))?;
+        for form in forms {
+            let route = form.other.field_mut();
+            route.method_router = layer.apply(std::mem::take(&mut route.method_router));
+        }
+    }
+
+    let mut router = Router::new();
+    let mut paths = Vec::new();
+    let mut coap_router = Router::new();
+    let mut observable = Vec::new();
+    let base = base_path(thing.base.as_deref());
+    let coap_addr = thing.other.field_ref().coap_addr;
+
+    for target in targets(&thing) {
+        for form in forms_mut(&mut thing, &target).into_iter().flatten() {
+            let Some((protocol, path)) = route_path(&form.href, &base) else {
+                continue;
+            };
+            let route = form.other.field_ref().method_router.clone();
+            let href = uritemplate_to_axum(&path);
+
+            match protocol {
+                Protocol::Http => {
+                    router = router.route(&href, route);
+                    paths.push(href);
+                }
+                // Without handlers the Form only describes the affordance.
+                Protocol::Coap if coap_addr.is_none() => {
+                    if !form.other.field_ref().methods.is_empty() {
+                        return Err(Error::UnboundCoap(form.href.clone()).into());
+                    }
+                }
+                Protocol::Coap => {
+                    let observes = target_ops(&form.op, &target).iter().any(|op| {
+                        matches!(
+                            op,
+                            FormOperation::ObserveProperty
+                                | FormOperation::ObserveAllProperties
+                                | FormOperation::SubscribeEvent
+                                | FormOperation::SubscribeAllEvents
+                        )
+                    });
+                    if observes {
+                        observable.push(href.clone());
+                    }
+                    coap_router = coap_router.route(&href, route);
+                }
+            }
+        }
+    }
+
+    for (_, href, route) in history_routes {
+        if let Some(path) = http_path(&href, &base) {
+            let path = uritemplate_to_axum(&path);
+            router = router.route(&path, route);
+            paths.push(path);
+        }
+    }
+
+    let coap = coap_addr
+        .map(|addr| CoapServer::new(addr, coap_router.with_state(state.clone()), observable));
+
+    if !media_types.is_empty() {
+        add_media_forms(&mut thing, &media_types)?;
+    }
+
+    for (target, forms) in observe_forms {
+        if let Some(described) = forms_mut(&mut thing, &target) {
+            for form in forms {
+                described.push(serde_json::from_value(form)?);
+            }
+        }
+    }
+
+    let uuid = Uuid::new_v4();
+    let name = {
+        let name = thing
+            .title
+            .split_whitespace()
+            .next()
+            .unwrap_or("")
+            .to_lowercase();
+
+        format!("{}{}", name, uuid.as_simple())
+    };
+
+    let mqtt_topics = match thing.other.field_ref().mqtt_broker.clone() {
+        Some(broker) => {
+            let addr = mqtt::broker_addr(&broker)?;
+            let topic_name = mqtt::topic_name(thing.id.as_deref(), &thing.title);
+            let topics = add_mqtt_forms(&mut thing, broker.trim_end_matches('/'), &topic_name)?;
+            Some((addr, topics))
+        }
+        None => None,
+    };
+
+    let description = Description::new(serde_json::to_value(&thing)?);
+
+    // We serve The thing from the root
+    let td = description.clone();
+    router = router.route(
+        "/",
+        axum::routing::get(move || async move { axum::Json(td.get()) }),
+    );
+
+    // We redirect this path to / to support relative Forms with empty base
+    // See: https://www.rfc-editor.org/rfc/rfc3986#section-5.1.3
+    router = router.route(
+        "/.well-known/wot",
+        axum::routing::get(move || async { Redirect::to("/") }),
+    );
+    paths.extend(["/".to_string(), "/.well-known/wot".to_string()]);
+
+    let routes = std::mem::take(&mut thing.other.field_mut().routes);
+    let nested = std::mem::take(&mut thing.other.field_mut().nested);
+    router = add_extra_routes(router, paths, routes, nested)?;
+
+    let mut router = router.with_state(state.clone());
+
+    for layer in std::mem::take(&mut thing.other.field_mut().layers) {
+        router = layer.apply(router);
+    }
 
-        let http_addr = thing
-            .other
-            .field_ref()
-            .addr
-            .unwrap_or_else(|| "0.0.0.0:8080".parse().unwrap());
-
-        let thing_type = thing.other.field_ref().thing_type;
-
-        Ok(Servient {
-            name,
-            thing,
-            router,
-            sd,
-            http_addr,
-            thing_type,
+    if thing.other.field_ref().permissive_cors {
+        let cors = CorsLayer::new()
+            .allow_methods(tower_http::cors::Any)
+            .allow_origin(tower_http::cors::Any);
+        router = router.layer(cors);
+    }
+
+    let mqtt = mqtt_topics.map(|(addr, (publications, subscriptions))| {
+        MqttClient::new(
+            addr,
+            name.clone(),
+            router.clone(),
+            publications,
+            subscriptions,
+        )
+    });
+
+    let sd = Advertiser::new()?;
+
+    let http_addr = thing
+        .other
+        .field_ref()
+        .addr
+        .unwrap_or_else(|| "0.0.0.0:8080".parse().unwrap());
+
+    let thing_type = thing.other.field_ref().thing_type;
+
+    let registration = thing
+        .other
+        .field_ref()
+        .directory
+        .clone()
+        .map(|directory| {
+            let id = thing
+                .id
+                .clone()
+                .unwrap_or_else(|| format!("urn:uuid:{}", uuid.as_hyphenated()));
+            let base = if http_addr.ip().is_unspecified() {
+                format!("http://{}:{}/", sd.hostname, http_addr.port())
+            } else {
+                format!("http://{http_addr}/")
+            };
+
+            Registration::new(
+                directory,
+                id,
+                base,
+                thing.other.field_ref().registration_ttl,
+            )
         })
+        .transpose()?;
+
+    Ok(Servient {
+        name,
+        thing,
+        router,
+        sd,
+        http_addr,
+        thing_type,
+        description,
+        registration,
+        coap,
+        mqtt,
+        persisted,
+        state,
+    })
+}
+
+/// Add the additional routes and nested routers to `router`, serving `paths` already.
+///
+/// axum panics on overlapping routes, so they are detected beforehand.
+fn add_extra_routes<S>(
+    mut router: Router<S>,
+    mut paths: Vec<String>,
+    routes: Vec<(String, MethodRouter<S>)>,
+    nested: Vec<(String, Router<S>)>,
+) -> Result<Router<S>, Error>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    for (path, route) in routes {
+        if paths.iter().any(|other| overlaps(&path, other, false)) {
+            return Err(Error::OverlappingRoute(path));
+        }
+
+        router = router.route(&path, route);
+        paths.push(path);
+    }
+
+    let mut prefixes: Vec<String> = Vec::new();
+    for (prefix, nested) in nested {
+        let overlapping = paths.iter().any(|path| overlaps(path, &prefix, true))
+            || prefixes
+                .iter()
+                .any(|other| overlaps(other, &prefix, true) || overlaps(&prefix, other, true));
+        if overlapping {
+            return Err(Error::OverlappingRoute(prefix));
+        }
+
+        router = router.nest(&prefix, nested);
+        prefixes.push(prefix);
+    }
+
+    Ok(router)
+}
+
+/// Whether a request path matches both the axum paths, or `path` and any path under `other`
+/// if `nested`.
+///
+/// Parameters match any segment and wildcards any remainder.
+fn overlaps(path: &str, other: &str, nested: bool) -> bool {
+    let mut path = path.split('/').filter(|s| !s.is_empty());
+    let mut other = other.split('/').filter(|s| !s.is_empty());
+
+    loop {
+        match (path.next(), other.next()) {
+            (None, None) => return true,
+            (Some(_), None) => return nested,
+            (None, Some(_)) => return false,
+            (Some(a), Some(b)) if a.starts_with('*') || b.starts_with('*') => return true,
+            (Some(a), Some(b)) if a == b || a.starts_with(':') || b.starts_with(':') => {}
+            (Some(_), Some(_)) => return false,
+        }
     }
 }
 
@@ -244,91 +2242,186 @@ where
 ///
 /// [`Form`]: wot_td::thing::Form
 /// [`FormBuilder`]: wot_td::builder::FormBuilder
-pub trait HttpRouter {
+pub trait HttpRouter<S> {
     /// Specialisation of [wot_td::builder::FormBuilder]
     type Target;
     /// Route GET requests to the given handler.
     fn http_get<H, T>(self, handler: H) -> Self::Target
     where
-        H: Handler<T, (), axum::body::Body>,
+        H: Handler<T, S, axum::body::Body>,
         T: 'static;
     /// Route PUT requests to the given handler.
     fn http_put<H, T>(self, handler: H) -> Self::Target
     where
-        H: Handler<T, (), axum::body::Body>,
+        H: Handler<T, S, axum::body::Body>,
         T: 'static;
     /// Route POST requests to the given handler.
     fn http_post<H, T>(self, handler: H) -> Self::Target
     where
-        H: Handler<T, (), axum::body::Body>,
+        H: Handler<T, S, axum::body::Body>,
         T: 'static;
     /// Route PATCH requests to the given handler.
     fn http_patch<H, T>(self, handler: H) -> Self::Target
     where
-        H: Handler<T, (), axum::body::Body>,
+        H: Handler<T, S, axum::body::Body>,
         T: 'static;
     /// Route DELETE requests to the given handler.
     fn http_delete<H, T>(self, handler: H) -> Self::Target
     where
-        H: Handler<T, (), axum::body::Body>,
+        H: Handler<T, S, axum::body::Body>,
+        T: 'static;
+    /// Route GET requests to a handler replying with json.
+    ///
+    /// The [`DataSchema`] of the replies becomes the schema of the property, the output of
+    /// the action or the data of the event the Form belongs to.
+    fn http_get_typed<H, T, D>(self, handler: H) -> Self::Target
+    where
+        H: TypedHandler<T, S, D>,
+        D: ToDataSchema;
+    /// Route POST requests to a handler replying with json.
+    ///
+    /// The [`DataSchema`] of the replies becomes the schema of the output of the action the
+    /// Form belongs to.
+    fn http_post_typed<H, T, D>(self, handler: H) -> Self::Target
+    where
+        H: TypedHandler<T, S, D>,
+        D: ToDataSchema;
+    /// Add the operation `op` to the Form and route its default http method to the handler.
+    ///
+    /// The methods follow the defaults of the
+    /// [HTTP binding](https://w3c.github.io/wot-binding-templates/bindings/protocols/http/#default-mappings),
+    /// e.g. `GET` for `readproperty` and `POST` for `invokeaction`.
+    fn http_handler<H, T>(self, op: FormOperation, handler: H) -> Self::Target
+    where
+        H: Handler<T, S, axum::body::Body>,
         T: 'static;
+    /// Apply a tower layer to the route of the Form.
+    ///
+    /// The layer wraps every handler of the Form, including the ones bound through
+    /// [`AffordanceRouter`], once the [`Servient`] is built.
+    fn layer<L>(self, layer: L) -> Self::Target
+    where
+        L: Layer<Route> + Clone + Send + 'static,
+        L::Service: Service<Request<Body>> + Clone + Send + 'static,
+        <L::Service as Service<Request<Body>>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request<Body>>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request<Body>>>::Future: Send + 'static;
 }
 
-impl<Other, Href, OtherForm> HttpRouter for FormBuilder<Other, Href, OtherForm>
+impl<Other, Href, OtherForm, S> HttpRouter<S> for FormBuilder<Other, Href, OtherForm>
 where
-    Other: ExtendableThing + Holder<ServientExtension>,
-    OtherForm: Holder<Form>,
+    Other: ExtendableThing + Holder<ServientExtension<S>>,
+    OtherForm: Holder<Form<S>>,
+    S: Clone + Send + Sync + 'static,
 {
     type Target = FormBuilder<Other, Href, OtherForm>;
 
     /// Route GET requests to the given handler.
     fn http_get<H, T>(mut self, handler: H) -> Self::Target
     where
-        H: Handler<T, (), axum::body::Body>,
+        H: Handler<T, S, axum::body::Body>,
         T: 'static,
     {
-        let method_router = std::mem::take(&mut self.other.field_mut().method_router);
-        self.other.field_mut().method_router = method_router.get(handler);
+        let route = self.other.field_mut();
+        route.method_router = std::mem::take(&mut route.method_router).get(handler);
+        route.methods.push(Method::Get);
         self
     }
     /// Route PUT requests to the given handler.
     fn http_put<H, T>(mut self, handler: H) -> Self::Target
     where
-        H: Handler<T, (), axum::body::Body>,
+        H: Handler<T, S, axum::body::Body>,
         T: 'static,
     {
-        let method_router = std::mem::take(&mut self.other.field_mut().method_router);
-        self.other.field_mut().method_router = method_router.put(handler);
+        let route = self.other.field_mut();
+        route.method_router = std::mem::take(&mut route.method_router).put(handler);
+        route.methods.push(Method::Put);
         self
     }
     /// Route POST requests to the given handler.
     fn http_post<H, T>(mut self, handler: H) -> Self::Target
     where
-        H: Handler<T, (), axum::body::Body>,
+        H: Handler<T, S, axum::body::Body>,
         T: 'static,
     {
-        let method_router = std::mem::take(&mut self.other.field_mut().method_router);
-        self.other.field_mut().method_router = method_router.post(handler);
+        let route = self.other.field_mut();
+        route.method_router = std::mem::take(&mut route.method_router).post(handler);
+        route.methods.push(Method::Post);
         self
     }
     /// Route PATCH requests to the given handler.
     fn http_patch<H, T>(mut self, handler: H) -> Self::Target
     where
-        H: Handler<T, (), axum::body::Body>,
+        H: Handler<T, S, axum::body::Body>,
         T: 'static,
     {
-        let method_router = std::mem::take(&mut self.other.field_mut().method_router);
-        self.other.field_mut().method_router = method_router.patch(handler);
+        let route = self.other.field_mut();
+        route.method_router = std::mem::take(&mut route.method_router).patch(handler);
+        route.methods.push(Method::Patch);
         self
     }
     /// Route DELETE requests to the given handler.
     fn http_delete<H, T>(mut self, handler: H) -> Self::Target
     where
-        H: Handler<T, (), axum::body::Body>,
+        H: Handler<T, S, axum::body::Body>,
+        T: 'static,
+    {
+        let route = self.other.field_mut();
+        route.method_router = std::mem::take(&mut route.method_router).delete(handler);
+        route.methods.push(Method::Delete);
+        self
+    }
+    /// Route GET requests to the given typed handler.
+    fn http_get_typed<H, T, D>(mut self, handler: H) -> Self::Target
+    where
+        H: TypedHandler<T, S, D>,
+        D: ToDataSchema,
+    {
+        let route = self.other.field_mut();
+        route.method_router =
+            std::mem::take(&mut route.method_router).merge(handler.route(MethodFilter::GET));
+        route.methods.push(Method::Get);
+        route.schema = Some(D::data_schema());
+        self
+    }
+    /// Route POST requests to the given typed handler.
+    fn http_post_typed<H, T, D>(mut self, handler: H) -> Self::Target
+    where
+        H: TypedHandler<T, S, D>,
+        D: ToDataSchema,
+    {
+        let route = self.other.field_mut();
+        route.method_router =
+            std::mem::take(&mut route.method_router).merge(handler.route(MethodFilter::POST));
+        route.methods.push(Method::Post);
+        route.schema = Some(D::data_schema());
+        self
+    }
+    /// Add the operation and route its default http method to the given handler.
+    fn http_handler<H, T>(mut self, op: FormOperation, handler: H) -> Self::Target
+    where
+        H: Handler<T, S, axum::body::Body>,
         T: 'static,
     {
-        let method_router = std::mem::take(&mut self.other.field_mut().method_router);
-        self.other.field_mut().method_router = method_router.delete(handler);
+        let method = default_method(op);
+        let route = self.other.field_mut();
+        route.method_router =
+            std::mem::take(&mut route.method_router).on(method_filter(method), handler);
+        route.methods.push(method);
+        self.op(op)
+    }
+    /// Apply a tower layer to the route of the Form.
+    fn layer<L>(mut self, layer: L) -> Self::Target
+    where
+        L: Layer<Route> + Clone + Send + 'static,
+        L::Service: Service<Request<Body>> + Clone + Send + 'static,
+        <L::Service as Service<Request<Body>>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request<Body>>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+    {
+        let layer = Middleware::new(move |route: MethodRouter<S>| route.layer(layer.clone()));
+
+        self.other.field_mut().layers.push(layer);
         self
     }
 }
@@ -362,4 +2455,37 @@ mod test {
     fn query_uri() {
         uritemplate("/weather/{?lat,long}", "/weather/");
     }
+
+    fn resolve(base: Option<&str>, href: &str, path: &str) {
+        let base = base_path(base);
+
+        assert_eq!(route_path(href, &base), Some((Protocol::Http, path.into())));
+    }
+
+    #[test]
+    fn relative_href() {
+        resolve(None, "properties/on", "/properties/on");
+        resolve(
+            Some("http://localhost:8080"),
+            "properties/on",
+            "/properties/on",
+        );
+        resolve(
+            Some("http://localhost:8080/things/lamp/"),
+            "properties/on",
+            "/things/lamp/properties/on",
+        );
+        resolve(
+            Some("http://localhost:8080/things/lamp?x=1"),
+            "./properties/on",
+            "/things/properties/on",
+        );
+    }
+
+    #[test]
+    fn absolute_href() {
+        let base = Some("http://localhost:8080/things/lamp/");
+        resolve(base, "/properties/on", "/properties/on");
+        resolve(base, "http://localhost:8080/on", "/on");
+    }
 }
@@ -0,0 +1,348 @@
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    body::{boxed, Body, Bytes, HttpBody},
+    extract::{FromRequest, State},
+    http::{
+        header::{ACCEPT, CONTENT_LENGTH, CONTENT_TYPE, VARY},
+        HeaderMap, HeaderValue, Request, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::MethodRouter,
+    BoxError,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::cbor;
+
+/// Media types the payloads of the Forms are encoded with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MediaType {
+    /// `application/json`, the default one
+    Json,
+    /// `application/cbor`
+    Cbor,
+}
+
+impl MediaType {
+    /// The media type as used in the `contentType` of the Forms.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MediaType::Json => "application/json",
+            MediaType::Cbor => "application/cbor",
+        }
+    }
+
+    /// The media type of a `Content-Type`, ignoring its parameters.
+    pub fn parse(content_type: &str) -> Option<Self> {
+        let essence = content_type.split(';').next()?.trim();
+
+        [MediaType::Json, MediaType::Cbor]
+            .into_iter()
+            .find(|media_type| media_type.as_str().eq_ignore_ascii_case(essence))
+    }
+
+    /// Whether the media type falls in an `Accept` media range.
+    fn accepted_by(self, range: &str) -> bool {
+        let (ty, _) = self.as_str().split_once('/').expect("type/subtype");
+
+        match range.split_once('/') {
+            Some(("*", "*")) => true,
+            Some((range_ty, "*")) => range_ty.eq_ignore_ascii_case(ty),
+            _ => range.eq_ignore_ascii_case(self.as_str()),
+        }
+    }
+
+    fn encode(self, value: &Value) -> Vec<u8> {
+        match self {
+            MediaType::Json => serde_json::to_vec(value).expect("json values serialize"),
+            MediaType::Cbor => cbor::to_vec(value),
+        }
+    }
+
+    fn decode(self, bytes: &[u8]) -> Result<Value, String> {
+        match self {
+            MediaType::Json => serde_json::from_slice(bytes).map_err(|err| err.to_string()),
+            MediaType::Cbor => cbor::from_slice(bytes).map_err(|err| err.to_string()),
+        }
+    }
+}
+
+fn content_type(headers: &HeaderMap) -> Option<&str> {
+    headers.get(CONTENT_TYPE)?.to_str().ok()
+}
+
+/// The media type preferred by the `Accept` header among the `available` ones.
+///
+/// Without an `Accept` header the first available media type is picked.
+fn preferred(headers: &HeaderMap, available: &[MediaType]) -> Option<MediaType> {
+    let accept: Vec<_> = headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect();
+
+    if accept.is_empty() {
+        return available.first().copied();
+    }
+
+    let mut ranges: Vec<(&str, f32)> = accept
+        .into_iter()
+        .map(|range| {
+            let mut params = range.split(';');
+            let range = params.next().unwrap_or_default().trim();
+            let quality = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            (range, quality)
+        })
+        .collect();
+    // The sort is stable, the ranges with the same quality keep their order.
+    ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    ranges
+        .into_iter()
+        .filter(|(_, quality)| *quality > 0.0)
+        .find_map(|(range, _)| available.iter().copied().find(|m| m.accepted_by(range)))
+}
+
+/// Most bytes of a request body collected by the middleware, as axum's default body limit
+pub(crate) const BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+/// Collect a response body.
+pub(crate) async fn body_bytes<B>(mut body: B) -> Result<Bytes, B::Error>
+where
+    B: HttpBody<Data = Bytes> + Unpin,
+{
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        bytes.extend_from_slice(&chunk?);
+    }
+
+    Ok(bytes.into())
+}
+
+/// Collect a request body of up to `limit` bytes.
+///
+/// The rejection is `413 Payload Too Large` past the limit, `400 Bad Request` if the body
+/// cannot be read.
+pub(crate) async fn request_bytes<B>(mut body: B, limit: usize) -> Result<Bytes, Response>
+where
+    B: HttpBody<Data = Bytes> + Unpin,
+{
+    let too_large = || StatusCode::PAYLOAD_TOO_LARGE.into_response();
+
+    if body.size_hint().lower() > limit as u64 {
+        return Err(too_large());
+    }
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|_| StatusCode::BAD_REQUEST.into_response())?;
+        if bytes.len() + chunk.len() > limit {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(bytes.into())
+}
+
+fn with_body(headers: &mut HeaderMap, media_type: MediaType, body: &[u8]) -> Body {
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(media_type.as_str()));
+    headers.remove(CONTENT_LENGTH);
+
+    Body::from(body.to_vec())
+}
+
+/// Payload of a request or of a response, encoded in the negotiated media type
+///
+/// As a request extractor it decodes the body according to its `Content-Type`, json when
+/// missing. As a response it is encoded as json, the Forms serving more media types
+/// re-encode it according to the `Accept` header of the request.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Payload<T>(pub T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for Payload<T>
+where
+    T: DeserializeOwned,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let media_type = match content_type(req.headers()) {
+            None => MediaType::Json,
+            Some(content_type) => MediaType::parse(content_type)
+                .ok_or_else(|| StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response())?,
+        };
+
+        let bytes = Bytes::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let value = media_type
+            .decode(&bytes)
+            .map_err(|err| (StatusCode::BAD_REQUEST, err).into_response())?;
+
+        serde_json::from_value(value)
+            .map(Payload)
+            .map_err(|err| (StatusCode::UNPROCESSABLE_ENTITY, err.to_string()).into_response())
+    }
+}
+
+impl<T: Serialize> IntoResponse for Payload<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+/// Media types served by a Form, json first
+type MediaTypes = Arc<[MediaType]>;
+
+/// Translate the payloads between json and the media types of the request.
+///
+/// The handlers exchange json. The requests in other media types are decoded, up to
+/// [`BODY_LIMIT`] bytes, and the json responses are encoded in the media type preferred by
+/// the `Accept` header.
+async fn negotiate(
+    State(media_types): State<MediaTypes>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let Some(reply_type) = preferred(request.headers(), &media_types) else {
+        return StatusCode::NOT_ACCEPTABLE.into_response();
+    };
+
+    let request_type = content_type(request.headers()).and_then(MediaType::parse);
+    let request = match request_type {
+        Some(media_type) if media_type != MediaType::Json && media_types.contains(&media_type) => {
+            let (mut parts, body) = request.into_parts();
+            let bytes = match request_bytes(body, BODY_LIMIT).await {
+                Ok(bytes) => bytes,
+                Err(rejection) => return rejection,
+            };
+            let value = match media_type.decode(&bytes) {
+                Ok(value) => value,
+                Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+            };
+            let body = with_body(
+                &mut parts.headers,
+                MediaType::Json,
+                &MediaType::Json.encode(&value),
+            );
+            Request::from_parts(parts, body)
+        }
+        _ => request,
+    };
+
+    let mut response = next.run(request).await;
+    response
+        .headers_mut()
+        .append(VARY, HeaderValue::from_static("accept"));
+
+    let is_json = content_type(response.headers()).and_then(MediaType::parse);
+    if reply_type == MediaType::Json || is_json != Some(MediaType::Json) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = body_bytes(body).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let body = match MediaType::Json.decode(&bytes) {
+        Ok(value) => with_body(&mut parts.headers, reply_type, &reply_type.encode(&value)),
+        // Not actually json, left as it is.
+        Err(_) => Body::from(bytes),
+    };
+
+    Response::from_parts(parts, boxed(body))
+}
+
+/// Negotiate the media type of the payloads of the route.
+pub(crate) fn negotiate_content<S>(
+    route: MethodRouter<S>,
+    media_types: &[MediaType],
+) -> MethodRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let media_types: MediaTypes = std::iter::once(MediaType::Json)
+        .chain(media_types.iter().copied())
+        .collect();
+
+    route.layer(axum::middleware::from_fn_with_state(media_types, negotiate))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn accept(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn preferred_media_type() {
+        let both = [MediaType::Json, MediaType::Cbor];
+
+        assert_eq!(preferred(&HeaderMap::new(), &both), Some(MediaType::Json));
+        assert_eq!(preferred(&accept("*/*"), &both), Some(MediaType::Json));
+        assert_eq!(
+            preferred(&accept("application/cbor"), &both),
+            Some(MediaType::Cbor)
+        );
+        assert_eq!(
+            preferred(&accept("application/json;q=0.5, application/*"), &both),
+            Some(MediaType::Json)
+        );
+        assert_eq!(
+            preferred(&accept("application/json;q=0.5, application/cbor"), &both),
+            Some(MediaType::Cbor)
+        );
+        assert_eq!(
+            preferred(&accept("application/cbor"), &[MediaType::Json]),
+            None
+        );
+        assert_eq!(preferred(&accept("text/html, */*;q=0"), &both), None);
+        assert_eq!(
+            MediaType::parse("Application/CBOR; charset=utf-8"),
+            Some(MediaType::Cbor)
+        );
+    }
+
+    #[tokio::test]
+    async fn limit_request_bodies() {
+        let status = |rejection: Response| rejection.status();
+
+        let bytes = request_bytes(Body::from(vec![0; 4]), 4).await.unwrap();
+        assert_eq!(bytes.len(), 4);
+        assert_eq!(
+            request_bytes(Body::from(vec![0; 5]), 4)
+                .await
+                .map_err(status)
+                .unwrap_err(),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+
+        // A streamed body is cut once past the limit.
+        let (sender, body) = crate::servient::notifier::body_channel();
+        tokio::spawn(
+            async move { while sender.send(Bytes::from_static(b"chunk")).await.is_ok() {} },
+        );
+        assert_eq!(
+            request_bytes(body, 12).await.map_err(status).unwrap_err(),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+}
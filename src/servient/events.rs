@@ -0,0 +1,270 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Query},
+    http::{header::LOCATION, HeaderMap, HeaderValue, StatusCode},
+    response::IntoResponse,
+    routing::MethodRouter,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::{broadcast::error::RecvError, oneshot};
+use uuid::Uuid;
+
+use crate::{schema, thing::EventChannel};
+
+use super::notifier::{body_channel, event_stream_response, server_sent_event};
+
+type Filter<P, T> = Arc<dyn Fn(&P, &T) -> bool + Send + Sync>;
+type Task = Pin<Box<dyn Future<Output = ()> + Send>>;
+type Setup<P> = Arc<dyn Fn(P) -> Task + Send + Sync>;
+type Cleanup<P> = Arc<dyn Fn(P, Value) -> Task + Send + Sync>;
+
+/// Subscriptions to an event, each with its own parameters
+///
+/// Route it with [`AffordanceRouter::on_event_subscription`]: the event gets a `subscribeevent`
+/// Form, a `POST` whose payload is checked against the `subscription` schema of the event
+/// and parsed as `P`. The data emitted through the [`EventChannel`] the subscription filter
+/// accepts are streamed to the subscriber as Server-Sent Events.
+///
+/// The events are streamed with their ids: a subscriber reconnecting with the `Last-Event-ID`
+/// header or the `since` query parameter receives first the events it missed the channel kept,
+/// see [`EventChannel::with_history`].
+///
+/// The response `Location` is the subscription, cancelled by a `DELETE` through the
+/// `unsubscribeevent` Form, whose payload if any is checked against the `cancellation` schema.
+/// The cancellation hook runs once per subscription, with a `null` payload if the subscriber
+/// goes away instead.
+///
+/// [`AffordanceRouter::on_event_subscription`]: crate::servient::AffordanceRouter::on_event_subscription
+pub struct EventSubscriptions<P, T> {
+    channel: EventChannel<T>,
+    filter: Filter<P, T>,
+    subscribe: Setup<P>,
+    cancel: Cleanup<P>,
+}
+
+impl<P, T: Clone> Clone for EventSubscriptions<P, T> {
+    fn clone(&self) -> Self {
+        Self {
+            channel: self.channel.clone(),
+            filter: self.filter.clone(),
+            subscribe: self.subscribe.clone(),
+            cancel: self.cancel.clone(),
+        }
+    }
+}
+
+impl<P, T> std::fmt::Debug for EventSubscriptions<P, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventSubscriptions").finish_non_exhaustive()
+    }
+}
+
+/// Active subscription
+struct Active<P> {
+    parameters: P,
+    stop: oneshot::Sender<()>,
+}
+
+type Subscribers<P> = Arc<Mutex<HashMap<String, Active<P>>>>;
+
+/// Query parameters of a subscription resuming a previous one
+#[derive(Debug, Deserialize)]
+struct Resume {
+    since: Option<u64>,
+}
+
+/// Id of the last event received by a reconnecting subscriber.
+fn last_event_id(headers: &HeaderMap, resume: Resume) -> Option<u64> {
+    headers
+        .get("last-event-id")
+        .and_then(|id| id.to_str().ok()?.trim().parse().ok())
+        .or(resume.since)
+}
+
+impl<P, T> EventSubscriptions<P, T>
+where
+    P: DeserializeOwned + Clone + Send + Sync + 'static,
+    T: Serialize + Clone + Send + 'static,
+{
+    /// Deliver every event emitted through `channel` to every subscriber.
+    pub fn new(channel: EventChannel<T>) -> Self {
+        Self {
+            channel,
+            filter: Arc::new(|_, _| true),
+            subscribe: Arc::new(|_| Box::pin(async {})),
+            cancel: Arc::new(|_, _| Box::pin(async {})),
+        }
+    }
+
+    /// Deliver the events to the subscribers whose parameters `filter` accepts them for.
+    pub fn filter(mut self, filter: impl Fn(&P, &T) -> bool + Send + Sync + 'static) -> Self {
+        self.filter = Arc::new(filter);
+        self
+    }
+
+    /// Run `hook` with the parameters of each new subscription.
+    pub fn on_subscribe<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(P) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.subscribe = Arc::new(move |parameters| Box::pin(hook(parameters)));
+        self
+    }
+
+    /// Run `hook` with the parameters and the cancellation payload of each ending subscription.
+    pub fn on_cancel<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(P, Value) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.cancel =
+            Arc::new(move |parameters, cancellation| Box::pin(hook(parameters, cancellation)));
+        self
+    }
+
+    /// Routes subscribing to the event at `path` and cancelling the subscriptions.
+    pub(crate) fn routes<S>(
+        self,
+        path: &str,
+        subscription: Option<Value>,
+        cancellation: Option<Value>,
+    ) -> (MethodRouter<S>, MethodRouter<S>)
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let subscribers: Subscribers<P> = Arc::default();
+        let path = path.to_string();
+
+        let cancel = {
+            let subscribers = subscribers.clone();
+            let cleanup = self.cancel.clone();
+            move |Path(id): Path<String>, body: Bytes| async move {
+                // The cancellation payload is optional.
+                let schema = cancellation.as_ref().filter(|_| !body.is_empty());
+                let cancellation = match payload::<Value>(&body, schema) {
+                    Ok(cancellation) => cancellation,
+                    Err(rejection) => return rejection.into_response(),
+                };
+
+                let ended = subscribers.lock().unwrap().remove(&id);
+                let Some(Active { parameters, stop }) = ended else {
+                    return StatusCode::NOT_FOUND.into_response();
+                };
+                let _ = stop.send(());
+                cleanup(parameters, cancellation).await;
+
+                StatusCode::NO_CONTENT.into_response()
+            }
+        };
+
+        let subscribe = move |headers: HeaderMap, Query(resume): Query<Resume>, body: Bytes| {
+            async move {
+                let parameters = match payload::<P>(&body, subscription.as_ref()) {
+                    Ok(parameters) => parameters,
+                    Err(rejection) => return rejection.into_response(),
+                };
+                (self.subscribe)(parameters.clone()).await;
+
+                let id = Uuid::new_v4().as_simple().to_string();
+                let (stop, mut stopped) = oneshot::channel();
+                let active = Active {
+                    parameters: parameters.clone(),
+                    stop,
+                };
+                subscribers.lock().unwrap().insert(id.clone(), active);
+
+                let location = format!("{path}/{id}");
+                let last_id = last_event_id(&headers, resume).unwrap_or(u64::MAX);
+                let (missed, mut next_id, mut events) = self.channel.replay(last_id);
+                let (sender, body) = body_channel();
+                tokio::spawn(async move {
+                    // The missed events first, then the ones emitted from now on.
+                    let mut missed = missed.into_iter();
+                    loop {
+                        let (event_id, data) = match missed.next() {
+                            Some(event) => event,
+                            None => {
+                                // The subscriber may go away while no event is delivered.
+                                let data = tokio::select! {
+                                    _ = &mut stopped => break,
+                                    _ = sender.closed() => break,
+                                    data = events.recv() => data,
+                                };
+                                match data {
+                                    Ok(data) => {
+                                        next_id += 1;
+                                        (next_id - 1, data)
+                                    }
+                                    Err(RecvError::Lagged(skipped)) => {
+                                        next_id += skipped;
+                                        continue;
+                                    }
+                                    Err(RecvError::Closed) => break,
+                                }
+                            }
+                        };
+
+                        if !(self.filter)(&parameters, &data) {
+                            continue;
+                        }
+                        let Ok(data) = serde_json::to_value(data) else {
+                            continue;
+                        };
+                        if sender
+                            .send(server_sent_event(Some(event_id), &data))
+                            .await
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+
+                    // Not cancelled through a request, e.g. the subscriber went away.
+                    let ended = subscribers.lock().unwrap().remove(&id);
+                    if let Some(Active { parameters, .. }) = ended {
+                        (self.cancel)(parameters, Value::Null).await;
+                    }
+                });
+
+                let mut response = event_stream_response(body);
+                if let Ok(location) = HeaderValue::try_from(location) {
+                    response.headers_mut().insert(LOCATION, location);
+                }
+                response
+            }
+        };
+
+        (
+            axum::routing::post(subscribe),
+            axum::routing::delete(cancel),
+        )
+    }
+}
+
+/// Parse the json payload, `null` if empty, checking it against the schema if any.
+fn payload<D: DeserializeOwned>(
+    body: &[u8],
+    schema: Option<&Value>,
+) -> Result<D, (StatusCode, String)> {
+    let value = if body.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_slice(body).map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?
+    };
+
+    if let Some(schema) = schema {
+        schema::validate(schema, &value)
+            .map_err(|violation| (StatusCode::UNPROCESSABLE_ENTITY, violation.to_string()))?;
+    }
+
+    serde_json::from_value(value).map_err(|err| (StatusCode::UNPROCESSABLE_ENTITY, err.to_string()))
+}
@@ -0,0 +1,248 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{extract::Query, routing::MethodRouter, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Relation type of the Links to the history of a property
+pub(crate) const HISTORY: &str = "version-history";
+
+/// How much of the history of a property is kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retention {
+    samples: usize,
+    max_age: Option<Duration>,
+}
+
+impl Retention {
+    /// Keep up to the latest `samples` values.
+    pub fn samples(samples: usize) -> Self {
+        Self {
+            samples,
+            max_age: None,
+        }
+    }
+
+    /// Drop the values older than `max_age`.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+}
+
+/// Value of a property at a point in time
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    /// Milliseconds since the unix epoch
+    pub time: u64,
+    /// Value of the property
+    pub value: Value,
+}
+
+/// Query of the history of a property
+///
+/// The times are in milliseconds since the unix epoch, both bounds included.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct HistoryQuery {
+    /// Earliest sample
+    pub from: Option<u64>,
+    /// Latest sample
+    pub to: Option<u64>,
+    /// Keep only the latest sample of each interval of this many milliseconds
+    pub interval: Option<u64>,
+}
+
+#[derive(Debug)]
+struct Series {
+    retention: Retention,
+    samples: VecDeque<Sample>,
+}
+
+impl Series {
+    fn prune(&mut self, now: u64) {
+        while self.samples.len() > self.retention.samples {
+            self.samples.pop_front();
+        }
+
+        if let Some(max_age) = self.retention.max_age {
+            let oldest = now.saturating_sub(max_age.as_millis() as u64);
+            while self
+                .samples
+                .front()
+                .is_some_and(|sample| sample.time < oldest)
+            {
+                self.samples.pop_front();
+            }
+        }
+    }
+}
+
+/// Records the values of the historical properties
+///
+/// Set it with [`ServientSettings::property_history`] and call [`PropertyHistory::record`]
+/// whenever a property changes: the Thing links the history of each historical property, a
+/// `GET` returning the recorded [`Sample`]s filtered by a [`HistoryQuery`].
+///
+/// [`ServientSettings::property_history`]: crate::servient::ServientSettings::property_history
+#[derive(Debug, Clone, Default)]
+pub struct PropertyHistory {
+    properties: Arc<Mutex<HashMap<String, Series>>>,
+}
+
+fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+impl PropertyHistory {
+    /// Create a recorder without historical properties.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the values of the property `name`, keeping them according to `retention`.
+    pub fn historical(self, name: impl Into<String>, retention: Retention) -> Self {
+        let series = Series {
+            retention,
+            samples: VecDeque::new(),
+        };
+        self.properties.lock().unwrap().insert(name.into(), series);
+        self
+    }
+
+    /// The names of the historical properties.
+    pub(crate) fn names(&self) -> Vec<String> {
+        self.properties.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Record the current value of the property `name`, returns whether it is historical.
+    pub fn record(&self, name: &str, value: impl Serialize) -> Result<bool, serde_json::Error> {
+        self.record_at(name, SystemTime::now(), value)
+    }
+
+    /// Record the value of the property `name` at `time`, returns whether it is historical.
+    ///
+    /// The samples are expected in chronological order.
+    pub fn record_at(
+        &self,
+        name: &str,
+        time: SystemTime,
+        value: impl Serialize,
+    ) -> Result<bool, serde_json::Error> {
+        let mut properties = self.properties.lock().unwrap();
+        let Some(series) = properties.get_mut(name) else {
+            return Ok(false);
+        };
+
+        let sample = Sample {
+            time: millis(time),
+            value: serde_json::to_value(value)?,
+        };
+        series.samples.push_back(sample);
+        series.prune(millis(SystemTime::now()));
+
+        Ok(true)
+    }
+
+    /// The recorded values of the property `name` matching `query`, `None` if not historical.
+    pub fn query(&self, name: &str, query: &HistoryQuery) -> Option<Vec<Sample>> {
+        let mut properties = self.properties.lock().unwrap();
+        let series = properties.get_mut(name)?;
+        series.prune(millis(SystemTime::now()));
+
+        let in_range = series.samples.iter().filter(|sample| {
+            query.from.is_none_or(|from| sample.time >= from)
+                && query.to.is_none_or(|to| sample.time <= to)
+        });
+
+        let mut samples: Vec<Sample> = Vec::new();
+        for sample in in_range {
+            let same_interval = match (query.interval, samples.last()) {
+                (Some(interval), Some(last)) if interval > 0 => {
+                    last.time / interval == sample.time / interval
+                }
+                _ => false,
+            };
+            if same_interval {
+                samples.pop();
+            }
+            samples.push(sample.clone());
+        }
+
+        Some(samples)
+    }
+
+    /// Route querying the history of the property `name`.
+    pub(crate) fn route<S>(&self, name: &str) -> MethodRouter<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let history = self.clone();
+        let name = name.to_string();
+
+        axum::routing::get(move |Query(query): Query<HistoryQuery>| async move {
+            Json(history.query(&name, &query).unwrap_or_default())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn query_history() {
+        let history = PropertyHistory::new().historical("level", Retention::samples(4));
+        let at = |ms| UNIX_EPOCH + Duration::from_millis(ms);
+
+        for (time, value) in [(1000, 1), (1500, 2), (2000, 3), (2100, 4), (3000, 5)] {
+            assert!(history.record_at("level", at(time), value).unwrap());
+        }
+        assert!(!history.record("other", 1).unwrap());
+
+        let values = |query| -> Vec<Value> {
+            history
+                .query("level", &query)
+                .unwrap()
+                .into_iter()
+                .map(|sample| sample.value)
+                .collect()
+        };
+
+        // The first sample is out of the retention.
+        assert_eq!(values(HistoryQuery::default()), [2, 3, 4, 5]);
+
+        let range = HistoryQuery {
+            from: Some(2000),
+            to: Some(2500),
+            interval: None,
+        };
+        assert_eq!(values(range), [3, 4]);
+
+        let downsampled = HistoryQuery {
+            interval: Some(1000),
+            ..Default::default()
+        };
+        assert_eq!(values(downsampled), [2, 4, 5]);
+
+        assert!(history.query("other", &HistoryQuery::default()).is_none());
+    }
+
+    #[test]
+    fn drop_old_samples() {
+        let retention = Retention::samples(10).max_age(Duration::from_secs(60));
+        let history = PropertyHistory::new().historical("level", retention);
+
+        let old = SystemTime::now() - Duration::from_secs(120);
+        history.record_at("level", old, 1).unwrap();
+        history.record("level", 2).unwrap();
+
+        let samples = history.query("level", &HistoryQuery::default()).unwrap();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].value, 2);
+    }
+}
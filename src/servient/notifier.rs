@@ -0,0 +1,284 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use axum::{
+    body::{boxed, Bytes, HttpBody},
+    extract::ws::{Message, WebSocket, WebSocketUpgrade},
+    http::{
+        header::{ACCEPT, CACHE_CONTROL, CONTENT_TYPE},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    routing::MethodRouter,
+    BoxError, Json,
+};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    mpsc,
+};
+
+/// Media type of the Server-Sent Events streams
+pub(crate) const EVENT_STREAM: &str = "text/event-stream";
+
+/// Subprotocol of the Forms observing a property through a WebSocket
+pub(crate) const WEBSOCKET: &str = "websocket";
+
+/// Changes buffered for each observer
+const CAPACITY: usize = 16;
+
+/// Notifies the observers of the properties of their new values
+///
+/// Set it with [`ServientSettings::property_notifier`] and call [`PropertyNotifier::notify`]
+/// whenever a property changes: the properties without an `observeproperty` Form become
+/// observable through long polling, Server-Sent Events and WebSocket, and over MQTT if
+/// configured.
+///
+/// [`ServientSettings::property_notifier`]: crate::servient::ServientSettings::property_notifier
+#[derive(Debug, Clone)]
+pub struct PropertyNotifier {
+    all: broadcast::Sender<(String, Value)>,
+    properties: Arc<Mutex<HashMap<String, broadcast::Sender<Value>>>>,
+}
+
+impl PropertyNotifier {
+    /// Create a notifier buffering up to 16 changes of each property for each observer.
+    pub fn new() -> Self {
+        let (all, _) = broadcast::channel(CAPACITY);
+
+        Self {
+            all,
+            properties: Arc::default(),
+        }
+    }
+
+    /// Notify the new value of the property `name`, returns how many observers receive it,
+    /// the ones of every property included.
+    pub fn notify(&self, name: &str, value: impl Serialize) -> Result<usize, serde_json::Error> {
+        let value = serde_json::to_value(value)?;
+
+        let observers = match self.properties.lock().unwrap().get(name) {
+            Some(tx) => tx.send(value.clone()).unwrap_or(0),
+            None => 0,
+        };
+
+        Ok(observers + self.all.send((name.to_string(), value)).unwrap_or(0))
+    }
+
+    /// Receive the changes of every property from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<(String, Value)> {
+        self.all.subscribe()
+    }
+
+    /// Receive the changes of the property `name` from now on.
+    ///
+    /// The changes of the other properties do not count against its buffer.
+    pub fn subscribe_property(&self, name: &str) -> broadcast::Receiver<Value> {
+        self.properties
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_insert_with(|| broadcast::channel(CAPACITY).0)
+            .subscribe()
+    }
+
+    /// Wait for the next value of a property, `None` once the notifier is gone.
+    pub(crate) async fn next(changes: &mut broadcast::Receiver<Value>) -> Option<Value> {
+        loop {
+            match changes.recv().await {
+                Ok(value) => return Some(value),
+                Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// Route observing the property `name`.
+    ///
+    /// `GET` upgrades to a WebSocket if requested, streams Server-Sent Events if accepted and
+    /// waits for the next value otherwise. `DELETE` acknowledges the end of an observation.
+    pub(crate) fn route<S>(&self, name: &str) -> MethodRouter<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let notifier = self.clone();
+        let name = name.to_string();
+
+        let observe = move |ws: Option<WebSocketUpgrade>, headers: HeaderMap| async move {
+            let changes = notifier.subscribe_property(&name);
+
+            if let Some(ws) = ws {
+                return ws.on_upgrade(move |socket| websocket(socket, changes));
+            }
+
+            let streams = headers
+                .get_all(ACCEPT)
+                .iter()
+                .filter_map(|accept| accept.to_str().ok())
+                .any(|accept| accept.contains(EVENT_STREAM));
+            if streams {
+                return event_stream(changes);
+            }
+
+            long_poll(changes).await
+        };
+
+        axum::routing::get(observe).delete(|| async { StatusCode::NO_CONTENT })
+    }
+}
+
+impl Default for PropertyNotifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+async fn long_poll(mut changes: broadcast::Receiver<Value>) -> Response {
+    match PropertyNotifier::next(&mut changes).await {
+        Some(value) => Json(value).into_response(),
+        None => StatusCode::NO_CONTENT.into_response(),
+    }
+}
+
+/// Server-Sent Event carrying `value`, with the event id if any.
+pub(super) fn server_sent_event(id: Option<u64>, value: &Value) -> Bytes {
+    match id {
+        Some(id) => format!("id: {id}\ndata: {value}\n\n").into(),
+        None => format!("data: {value}\n\n").into(),
+    }
+}
+
+/// Body streamed through a channel
+///
+/// Unlike a [`Body::channel`](axum::body::Body::channel), the sender notices when the client goes away through
+/// [`mpsc::Sender::closed`], even without anything to send.
+pub(super) struct Streamed {
+    chunks: mpsc::Receiver<Bytes>,
+}
+
+impl HttpBody for Streamed {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Infallible>>> {
+        self.chunks.poll_recv(cx).map(|chunk| chunk.map(Ok))
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Infallible>> {
+        Poll::Ready(Ok(None))
+    }
+}
+
+/// Channel of a streamed body.
+pub(super) fn body_channel() -> (mpsc::Sender<Bytes>, Streamed) {
+    let (sender, chunks) = mpsc::channel(1);
+
+    (sender, Streamed { chunks })
+}
+
+/// Response streaming the Server-Sent Events sent through `body`.
+pub(super) fn event_stream_response<B>(body: B) -> Response
+where
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    Response::builder()
+        .header(CONTENT_TYPE, HeaderValue::from_static(EVENT_STREAM))
+        .header(CACHE_CONTROL, HeaderValue::from_static("no-cache"))
+        .body(boxed(body))
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+}
+
+fn event_stream(mut changes: broadcast::Receiver<Value>) -> Response {
+    let (sender, body) = body_channel();
+
+    tokio::spawn(async move {
+        loop {
+            let value = tokio::select! {
+                _ = sender.closed() => break,
+                value = PropertyNotifier::next(&mut changes) => value,
+            };
+            let Some(value) = value else {
+                break;
+            };
+            if sender.send(server_sent_event(None, &value)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    event_stream_response(body)
+}
+
+async fn websocket(mut socket: WebSocket, mut changes: broadcast::Receiver<Value>) {
+    loop {
+        tokio::select! {
+            value = PropertyNotifier::next(&mut changes) => {
+                let Some(value) = value else {
+                    break;
+                };
+                if socket.send(Message::Text(value.to_string())).await.is_err() {
+                    return;
+                }
+            }
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            }
+        }
+    }
+
+    let _ = socket.send(Message::Close(None)).await;
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn notify_observers() {
+        let notifier = PropertyNotifier::new();
+        let mut level = notifier.subscribe_property("level");
+        let _all = notifier.subscribe();
+
+        // The changes of the other properties do not lag the observers of the level.
+        for value in 0..2 * CAPACITY {
+            assert_eq!(notifier.notify("other", value).unwrap(), 1);
+        }
+        assert_eq!(notifier.notify("level", 1).unwrap(), 2);
+        assert_eq!(level.try_recv().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn close_event_streams() {
+        let notifier = PropertyNotifier::new();
+        let observers = || notifier.properties.lock().unwrap()["level"].receiver_count();
+
+        let response = event_stream(notifier.subscribe_property("level"));
+        assert_eq!(observers(), 1);
+
+        // The stream ends with its client, without waiting for the next change.
+        drop(response);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while observers() > 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+}
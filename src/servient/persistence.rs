@@ -0,0 +1,312 @@
+use std::{
+    collections::HashMap,
+    fmt, io,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use axum::{
+    body::Body,
+    http::{header::CONTENT_TYPE, Request},
+    middleware::Next,
+    response::Response,
+    routing::MethodRouter,
+};
+use serde_json::{Map, Value};
+use tower::ServiceExt;
+
+use super::content::{request_bytes, BODY_LIMIT};
+
+/// Storage of the property values across restarts
+///
+/// Set it with [`ServientSettings::persist_properties`].
+///
+/// [`ServientSettings::persist_properties`]: crate::servient::ServientSettings::persist_properties
+pub trait PropertyStore: fmt::Debug + Send + Sync + 'static {
+    /// Load the stored values, by property name.
+    fn load(&self) -> io::Result<Map<String, Value>>;
+
+    /// Replace the stored values.
+    fn save(&self, values: &Map<String, Value>) -> io::Result<()>;
+}
+
+/// Property values stored as a json object in a file
+///
+/// The file is replaced atomically: the values are written to a temporary file next to it,
+/// renamed over it once synced. The directory is synced as well, so the rename survives a
+/// crash.
+#[derive(Debug, Clone)]
+pub struct JsonFile {
+    path: PathBuf,
+}
+
+impl JsonFile {
+    /// Store the values in the file at `path`, created on the first write.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn temporary_path(&self) -> PathBuf {
+        let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+        name.push(".tmp");
+        self.path.with_file_name(name)
+    }
+}
+
+impl PropertyStore for JsonFile {
+    fn load(&self) -> io::Result<Map<String, Value>> {
+        let json = match std::fs::read(&self.path) {
+            Ok(json) => json,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Map::new()),
+            Err(err) => return Err(err),
+        };
+
+        serde_json::from_slice(&json).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    fn save(&self, values: &Map<String, Value>) -> io::Result<()> {
+        let json = serde_json::to_vec_pretty(values)?;
+        let temporary = self.temporary_path();
+
+        {
+            let mut file = std::fs::File::create(&temporary)?;
+            io::Write::write_all(&mut file, &json)?;
+            file.sync_all()?;
+        }
+
+        std::fs::rename(&temporary, &self.path)?;
+
+        // Only unix allows opening and syncing a directory.
+        #[cfg(unix)]
+        {
+            let dir = match self.path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => std::path::Path::new("."),
+            };
+            std::fs::File::open(dir)?.sync_all()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Persisted values, with the sequence number of the write each one comes from
+#[derive(Debug)]
+struct Stored {
+    values: Map<String, Value>,
+    sequences: HashMap<String, u64>,
+}
+
+/// Property name, http method, path and route of a write restoring it
+type Write = (String, axum::http::Method, String, MethodRouter);
+
+/// Property values persisted through a [`PropertyStore`]
+///
+/// Loaded when the [`Servient`] is built and written again to the properties through their
+/// `writeproperty` Forms the first time it serves, bypassing the layers of the routes.
+///
+/// [`Servient`]: crate::servient::Servient
+#[derive(Debug, Clone)]
+pub struct PersistedProperties {
+    store: Arc<dyn PropertyStore>,
+    values: Arc<Mutex<Stored>>,
+    /// Sequence number of the next persisted write
+    sequence: Arc<AtomicU64>,
+    /// Unlayered route writing each property
+    writes: Arc<Mutex<Vec<Write>>>,
+    restored: Arc<AtomicBool>,
+}
+
+impl PersistedProperties {
+    /// Load the values from `store`.
+    pub(crate) fn load(store: Arc<dyn PropertyStore>) -> io::Result<Self> {
+        let values = store.load()?;
+
+        Ok(Self {
+            store,
+            values: Arc::new(Mutex::new(Stored {
+                values,
+                sequences: HashMap::new(),
+            })),
+            sequence: Arc::default(),
+            writes: Arc::default(),
+            restored: Arc::default(),
+        })
+    }
+
+    /// Current value of the property `name`.
+    pub fn value(&self, name: &str) -> Option<Value> {
+        self.values.lock().unwrap().values.get(name).cloned()
+    }
+
+    /// Store the new value of the property `name`, off the async runtime as it syncs to disk.
+    ///
+    /// The values are persisted in the order of their `sequence` numbers, a value older than
+    /// the persisted one is dropped.
+    async fn persist(&self, name: &str, value: Value, sequence: u64) -> io::Result<()> {
+        let persisted = self.clone();
+        let name = name.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            let mut stored = persisted.values.lock().unwrap();
+            if stored
+                .sequences
+                .get(&name)
+                .is_some_and(|&latest| latest > sequence)
+            {
+                return Ok(());
+            }
+
+            stored.sequences.insert(name.clone(), sequence);
+            stored.values.insert(name, value);
+            persisted.store.save(&stored.values)
+        })
+        .await
+        .map_err(io::Error::other)?
+    }
+
+    /// Restore the property `name` through a `method` request to `path`, handled by `route`.
+    pub(crate) fn restore_with(
+        &mut self,
+        name: &str,
+        method: axum::http::Method,
+        path: String,
+        route: MethodRouter,
+    ) {
+        let mut writes = self.writes.lock().unwrap();
+        if !writes.iter().any(|(n, ..)| n == name) {
+            writes.push((name.to_string(), method, path, route));
+        }
+    }
+
+    /// Write the persisted values to the properties, once.
+    pub(crate) async fn restore(&self) {
+        if self.restored.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let writes = self.writes.lock().unwrap().clone();
+        for (name, method, path, route) in writes {
+            let Some(value) = self.value(&name) else {
+                continue;
+            };
+
+            let request = Request::builder()
+                .method(method)
+                .uri(path)
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(value.to_string()));
+            let Ok(request) = request else {
+                continue;
+            };
+
+            match route.clone().oneshot(request).await {
+                Ok(response) if response.status().is_success() => {}
+                Ok(response) => {
+                    tracing::warn!(%name, status = %response.status(), "property not restored");
+                }
+                Err(err) => match err {},
+            }
+        }
+    }
+
+    /// Persist the values of the property `name` written through the `method` requests of
+    /// the route.
+    pub(crate) fn persist_writes<S>(
+        &self,
+        route: MethodRouter<S>,
+        name: &str,
+        method: axum::http::Method,
+    ) -> MethodRouter<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let persisted = self.clone();
+        let name = Arc::new(name.to_string());
+
+        route.layer(axum::middleware::from_fn(
+            move |request: Request<Body>, next: Next<Body>| {
+                let persisted = persisted.clone();
+                let name = name.clone();
+                let method = method.clone();
+
+                async move {
+                    if request.method() != method {
+                        return next.run(request).await;
+                    }
+
+                    let (parts, body) = request.into_parts();
+                    let bytes = match request_bytes(body, BODY_LIMIT).await {
+                        Ok(bytes) => bytes,
+                        Err(rejection) => return rejection,
+                    };
+                    let request = Request::from_parts(parts, Body::from(bytes.clone()));
+
+                    let response: Response = next.run(request).await;
+                    if !response.status().is_success() {
+                        return response;
+                    }
+
+                    // Numbered as the handlers complete, the persisting tasks may run out of order.
+                    let sequence = persisted.sequence.fetch_add(1, Ordering::SeqCst);
+
+                    // Only the json payloads are persisted.
+                    if let Ok(value) = serde_json::from_slice(&bytes) {
+                        if let Err(err) = persisted.persist(&name, value, sequence).await {
+                            tracing::error!(%name, %err, "property value not persisted");
+                        }
+                    }
+
+                    response
+                }
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn replace_json_file() {
+        let dir = std::env::temp_dir().join(format!("wot-serve-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let file = JsonFile::new(dir.join("values.json"));
+
+        assert!(file.load().unwrap().is_empty());
+
+        let mut values = Map::new();
+        values.insert("level".into(), 3.into());
+        file.save(&values).unwrap();
+        values.insert("name".into(), "lamp".into());
+        file.save(&values).unwrap();
+
+        assert_eq!(file.load().unwrap(), values);
+        assert!(!file.temporary_path().exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn persist_in_order() {
+        let dir = std::env::temp_dir().join(format!("wot-serve-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let file = JsonFile::new(dir.join("values.json"));
+        let persisted = PersistedProperties::load(Arc::new(file.clone())).unwrap();
+
+        // The later write reaches the store first.
+        persisted.persist("level", 2.into(), 1).await.unwrap();
+        persisted.persist("level", 1.into(), 0).await.unwrap();
+        persisted.persist("name", "lamp".into(), 2).await.unwrap();
+
+        assert_eq!(persisted.value("level"), Some(2.into()));
+        assert_eq!(file.load().unwrap()["level"], 2);
+        assert_eq!(file.load().unwrap()["name"], "lamp");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,66 @@
+use std::future::Future;
+
+use axum::{
+    body::Body,
+    extract::{FromRequest, FromRequestParts},
+    routing::{MethodFilter, MethodRouter},
+    Json,
+};
+use serde::Serialize;
+
+use crate::schema::ToDataSchema;
+
+/// Handler replying with a json value whose [`DataSchema`] is known
+///
+/// It is implemented for the async functions taking up to 8 extractors, as the axum
+/// [`Handler`]s, and returning a value implementing [`ToDataSchema`].
+///
+/// [`DataSchema`]: crate::schema::DataSchema
+/// [`Handler`]: axum::handler::Handler
+pub trait TypedHandler<T, S, D>: Clone + Send + Sized + 'static {
+    /// Route the requests for `filter` to the handler.
+    fn route(self, filter: MethodFilter) -> MethodRouter<S>;
+}
+
+impl<F, Fut, S, D> TypedHandler<(), S, D> for F
+where
+    F: FnOnce() -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = D> + Send,
+    D: Serialize + ToDataSchema,
+    S: Clone + Send + Sync + 'static,
+{
+    fn route(self, filter: MethodFilter) -> MethodRouter<S> {
+        axum::routing::on(filter, move || async move { Json(self().await) })
+    }
+}
+
+macro_rules! impl_typed_handler {
+    ([$($ty:ident),*], $last:ident) => {
+        #[allow(non_snake_case)]
+        impl<F, Fut, S, D, M, $($ty,)* $last> TypedHandler<(M, $($ty,)* $last,), S, D> for F
+        where
+            F: FnOnce($($ty,)* $last) -> Fut + Clone + Send + 'static,
+            Fut: Future<Output = D> + Send,
+            D: Serialize + ToDataSchema,
+            S: Clone + Send + Sync + 'static,
+            $($ty: FromRequestParts<S> + Send + 'static,)*
+            $last: FromRequest<S, Body, M> + Send + 'static,
+            M: 'static,
+        {
+            fn route(self, filter: MethodFilter) -> MethodRouter<S> {
+                axum::routing::on(filter, move |$($ty: $ty,)* $last: $last| async move {
+                    Json(self($($ty,)* $last).await)
+                })
+            }
+        }
+    };
+}
+
+impl_typed_handler!([], T1);
+impl_typed_handler!([T1], T2);
+impl_typed_handler!([T1, T2], T3);
+impl_typed_handler!([T1, T2, T3], T4);
+impl_typed_handler!([T1, T2, T3, T4], T5);
+impl_typed_handler!([T1, T2, T3, T4, T5], T6);
+impl_typed_handler!([T1, T2, T3, T4, T5, T6], T7);
+impl_typed_handler!([T1, T2, T3, T4, T5, T6, T7], T8);
@@ -0,0 +1,94 @@
+use std::sync::Arc;
+
+use axum::{
+    body::{boxed, Full},
+    http::{header::CONTENT_TYPE, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::MethodRouter,
+};
+use serde_json::Value;
+use wot_td::protocol::http::Method;
+
+use crate::schema::{self, Violation};
+
+use super::{builder::method_str, content::body_bytes};
+
+/// Handling of the responses not matching the schema of their affordance
+///
+/// The json responses of the `readproperty` and `invokeaction` operations are checked
+/// against the property schema and the action output schema.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseValidation {
+    /// Log the violations and send the responses as they are, useful while developing.
+    Log,
+    /// Log the violations and replace the responses with `500 Internal Server Error`.
+    Strict,
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|media| {
+            let media = media.trim();
+            media.eq_ignore_ascii_case("application/json") || media.ends_with("+json")
+        })
+}
+
+/// Check the body of the response against the schema.
+fn check(schema: &Value, body: &[u8]) -> Result<(), Violation> {
+    let value = serde_json::from_slice(body).map_err(|err| Violation {
+        path: String::new(),
+        reason: format!("is not valid json: {err}"),
+    })?;
+
+    schema::validate(schema, &value)
+}
+
+/// Validate the responses of the route to the requests using `method`.
+pub(crate) fn validate_responses<S>(
+    route: MethodRouter<S>,
+    href: String,
+    method: Method,
+    schema: Value,
+    validation: ResponseValidation,
+) -> MethodRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let href = Arc::new(href);
+    let schema = Arc::new(schema);
+
+    route.layer(axum::middleware::map_response(
+        move |request_method: axum::http::Method, response: Response| {
+            let href = href.clone();
+            let schema = schema.clone();
+
+            async move {
+                if request_method.as_str() != method_str(method)
+                    || !response.status().is_success()
+                    || !is_json(response.headers())
+                {
+                    return response;
+                }
+
+                let (parts, body) = response.into_parts();
+                let Ok(bytes) = body_bytes(body).await else {
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                };
+
+                if !bytes.is_empty() {
+                    if let Err(violation) = check(&schema, &bytes) {
+                        tracing::warn!(%href, %violation, "response not matching the schema");
+                        if validation == ResponseValidation::Strict {
+                            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                        }
+                    }
+                }
+
+                Response::from_parts(parts, boxed(Full::new(bytes)))
+            }
+        },
+    ))
+}
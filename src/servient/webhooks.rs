@@ -0,0 +1,199 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    extract::Path,
+    http::{header::LOCATION, HeaderValue, StatusCode},
+    response::IntoResponse,
+    routing::MethodRouter,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast::error::RecvError, oneshot};
+use uuid::Uuid;
+
+use crate::thing::EventChannel;
+
+/// Subprotocol of the Forms subscribing to an event through a webhook
+pub(crate) const WEBHOOK: &str = "webhook";
+
+/// Webhooks the data of an event is delivered to
+///
+/// Route it with [`AffordanceRouter::on_event_webhooks`]: the event gets a `subscribeevent`
+/// Form with the `webhook` subprotocol, a `POST` of `{"callback": "<url>"}` with an http or
+/// https url. The data emitted through the [`EventChannel`] is then `POST`ed as json to the
+/// callback url, retried with an exponential backoff if the delivery fails. A webhook whose
+/// deliveries keep failing is dropped, and the registrations past the limit are rejected.
+///
+/// The response `Location` is the webhook, removed by a `DELETE` through the
+/// `unsubscribeevent` Form.
+///
+/// [`AffordanceRouter::on_event_webhooks`]: crate::servient::AffordanceRouter::on_event_webhooks
+#[derive(Debug, Clone)]
+pub struct EventWebhooks<T> {
+    channel: EventChannel<T>,
+    client: reqwest::Client,
+    retries: u32,
+    backoff: Duration,
+    max_failures: u32,
+    limit: usize,
+}
+
+/// Payload registering a webhook
+#[derive(Debug, Deserialize)]
+struct Webhook {
+    callback: String,
+}
+
+type Webhooks = Arc<Mutex<HashMap<String, oneshot::Sender<()>>>>;
+
+impl<T> EventWebhooks<T>
+where
+    T: Serialize + Clone + Send + Sync + 'static,
+{
+    /// Deliver the events emitted through `channel`, retrying 3 times from 100ms on.
+    ///
+    /// Up to 64 webhooks are registered, each dropped after 3 failed deliveries in a row.
+    pub fn new(channel: EventChannel<T>) -> Self {
+        Self {
+            channel,
+            client: reqwest::Client::new(),
+            retries: 3,
+            backoff: Duration::from_millis(100),
+            max_failures: 3,
+            limit: 64,
+        }
+    }
+
+    /// Retry a failed delivery up to `retries` times.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Wait `backoff` before the first retry, doubling it for each of the next ones.
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Drop a webhook once `max_failures` deliveries in a row failed, retries included.
+    pub fn max_failures(mut self, max_failures: u32) -> Self {
+        self.max_failures = max_failures.max(1);
+        self
+    }
+
+    /// Register up to `limit` webhooks at a time.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Deliver `data` to `callback`, returns false once out of retries.
+    async fn deliver(&self, callback: &reqwest::Url, data: &T) -> bool {
+        let mut backoff = self.backoff;
+
+        for retry in 0..=self.retries {
+            if retry > 0 {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+
+            let res = self.client.post(callback.clone()).json(data).send().await;
+            if res.is_ok_and(|res| res.status().is_success()) {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Routes registering the webhooks at `path` and removing them.
+    pub(crate) fn routes<S>(self, path: &str) -> (MethodRouter<S>, MethodRouter<S>)
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let webhooks: Webhooks = Arc::default();
+        let path = path.to_string();
+
+        let unsubscribe = {
+            let webhooks = webhooks.clone();
+            move |Path(id): Path<String>| async move {
+                match webhooks.lock().unwrap().remove(&id) {
+                    Some(stop) => {
+                        let _ = stop.send(());
+                        StatusCode::NO_CONTENT
+                    }
+                    None => StatusCode::NOT_FOUND,
+                }
+            }
+        };
+
+        let subscribe = move |Json(Webhook { callback }): Json<Webhook>| async move {
+            let callback = match reqwest::Url::parse(&callback) {
+                Ok(callback) if matches!(callback.scheme(), "http" | "https") => callback,
+                Ok(callback) => {
+                    let err = format!("unsupported callback scheme {}", callback.scheme());
+                    return (StatusCode::UNPROCESSABLE_ENTITY, err).into_response();
+                }
+                Err(err) => {
+                    return (StatusCode::UNPROCESSABLE_ENTITY, err.to_string()).into_response()
+                }
+            };
+
+            let id = Uuid::new_v4().as_simple().to_string();
+            let (stop, mut stopped) = oneshot::channel();
+            {
+                let mut webhooks = webhooks.lock().unwrap();
+                if webhooks.len() >= self.limit {
+                    return (StatusCode::SERVICE_UNAVAILABLE, "too many webhooks").into_response();
+                }
+                webhooks.insert(id.clone(), stop);
+            }
+
+            let mut events = self.channel.subscribe();
+            let webhooks = webhooks.clone();
+            let webhook = id.clone();
+            tokio::spawn(async move {
+                let mut failures = 0;
+                loop {
+                    let data = tokio::select! {
+                        _ = &mut stopped => break,
+                        data = events.recv() => data,
+                    };
+                    match data {
+                        Ok(data) => {
+                            // Removing the webhook cancels the pending delivery too.
+                            let delivered = tokio::select! {
+                                _ = &mut stopped => break,
+                                delivered = self.deliver(&callback, &data) => delivered,
+                            };
+                            failures = if delivered { 0 } else { failures + 1 };
+                            if failures >= self.max_failures {
+                                tracing::warn!(%callback, "webhook dropped after failed deliveries");
+                                webhooks.lock().unwrap().remove(&webhook);
+                                break;
+                            }
+                        }
+                        Err(RecvError::Lagged(_)) => {}
+                        Err(RecvError::Closed) => break,
+                    }
+                }
+            });
+
+            let mut response = StatusCode::CREATED.into_response();
+            if let Ok(location) = HeaderValue::try_from(format!("{path}/{id}")) {
+                response.headers_mut().insert(LOCATION, location);
+            }
+            response
+        };
+
+        (
+            axum::routing::post(subscribe),
+            axum::routing::delete(unsubscribe),
+        )
+    }
+}
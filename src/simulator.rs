@@ -0,0 +1,921 @@
+//! Simulated Things
+//!
+//! A [`Simulator`] serves a Thing Description without writing any handler, useful to test
+//! Consumers against fake Things:
+//!
+//! - property reads return values conforming to the property schema, writes are validated
+//!   against it, stored and notified to the observers.
+//! - actions reply with values conforming to their output schema.
+//! - events fire periodically, delivered through long polling.
+//!
+//! ```no_run
+//! use wot_serve::{servient::*, simulator::Simulator};
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let td = std::fs::read_to_string("lamp.td.json")?;
+//! let servient = Simulator::from_td(&td)?
+//!     .random()
+//!     .builder()?
+//!     .build_servient()?;
+//!
+//! servient.serve().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    body::Bytes,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tokio::{sync::broadcast, time::Instant};
+use uuid::Uuid;
+use wot_td::{
+    builder::{AffordanceType, Extended, ThingBuilder},
+    extend::ExtendableThing,
+    thing::{FormOperation, Thing},
+};
+
+use crate::{
+    hlist::NilPlus,
+    schema,
+    servient::{
+        description_builder, AffordanceRouter, PropertyNotifier, ServientExtension,
+        ServientSettings,
+    },
+};
+
+/// Error type for the module
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// The Thing Description cannot be parsed.
+    #[error("invalid thing description {0}")]
+    Description(#[from] serde_json::Error),
+}
+
+/// Result type for the module
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Default period of the simulated events.
+pub const DEFAULT_EVENT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Handlers for every Form of a Thing Description
+///
+/// Call [`Simulator::builder`] to get a [`Servient`] builder with all the operations routed.
+///
+/// [`Servient`]: crate::Servient
+#[derive(Debug, Clone)]
+pub struct Simulator {
+    td: Value,
+    random: Option<u64>,
+    event_interval: Duration,
+    values: Arc<Mutex<Map<String, Value>>>,
+}
+
+impl Simulator {
+    /// Simulate the Thing Description `td`.
+    pub fn from_td(td: &str) -> Result<Self> {
+        Self::from_json(serde_json::from_str(td)?)
+    }
+
+    /// Simulate the given Thing.
+    pub fn from_thing<O>(thing: &Thing<O>) -> Result<Self>
+    where
+        O: ExtendableThing + Serialize,
+    {
+        Self::from_json(serde_json::to_value(thing)?)
+    }
+
+    fn from_json(td: Value) -> Result<Self> {
+        // Check the description is well formed before taking it apart.
+        serde_json::from_value::<Thing>(td.clone())?;
+
+        Ok(Self {
+            td,
+            random: None,
+            event_interval: DEFAULT_EVENT_INTERVAL,
+            values: Default::default(),
+        })
+    }
+
+    /// Produce random values instead of the schema defaults.
+    pub fn random(self) -> Self {
+        let seed = Uuid::new_v4().as_u64_pair().0;
+
+        self.random_with_seed(seed)
+    }
+
+    /// Produce random values from a given seed, to reproduce a simulation.
+    pub fn random_with_seed(mut self, seed: u64) -> Self {
+        self.random = Some(seed);
+        self
+    }
+
+    /// Set how often the events fire.
+    pub fn event_interval(mut self, interval: Duration) -> Self {
+        self.event_interval = interval;
+        self
+    }
+
+    /// Current value of the property `name`.
+    ///
+    /// The values are available once the builder is produced.
+    pub fn value(&self, name: &str) -> Option<Value> {
+        self.values.lock().unwrap().get(name).cloned()
+    }
+
+    /// Prepare a [`Servient`] builder with every operation of the description routed to
+    /// a simulated handler.
+    ///
+    /// [`Servient`]: crate::Servient
+    pub fn builder(&self) -> Result<ThingBuilder<NilPlus<ServientExtension>, Extended>> {
+        let state = Arc::new(State {
+            td: self.td.clone(),
+            values: self.values.clone(),
+            changes: PropertyNotifier::new(),
+            rng: Mutex::new(Rng::new(self.random)),
+            event_interval: self.event_interval,
+            start: Instant::now(),
+        });
+
+        {
+            let mut values = state.values.lock().unwrap();
+            values.clear();
+            for (name, schema) in affordances(&state.td, "properties") {
+                values.insert(name.clone(), state.sample(schema));
+            }
+        }
+
+        let mut builder = description_builder(self.td.clone())?
+            .with_state(())
+            .property_notifier(state.changes.clone());
+
+        for op in declared_ops(self.td.get("forms"), &[]) {
+            builder = bind_thing(builder, op, state.clone());
+        }
+
+        for (affordance, key, default) in DEFAULT_OPS {
+            for (name, a) in affordances(&self.td, key) {
+                for op in declared_ops(a.get("forms"), &default_ops(a, default)) {
+                    builder = bind_affordance(builder, affordance, name, op, state.clone());
+                }
+            }
+        }
+
+        Ok(builder)
+    }
+}
+
+/// Affordance types, their key in the description and the operations of the Forms declaring none
+pub(crate) const DEFAULT_OPS: [(AffordanceType, &str, &[&str]); 3] = [
+    (
+        AffordanceType::Property,
+        "properties",
+        &["readproperty", "writeproperty"],
+    ),
+    (AffordanceType::Action, "actions", &["invokeaction"]),
+    (
+        AffordanceType::Event,
+        "events",
+        &["subscribeevent", "unsubscribeevent"],
+    ),
+];
+
+/// Operations of the Forms of the affordance `a` declaring none.
+///
+/// The properties flagged `writeOnly` are not read and the ones flagged `readOnly` not written.
+pub(crate) fn default_ops(a: &Value, default: &[&'static str]) -> Vec<&'static str> {
+    let flag = |name| a.get(name) == Some(&Value::Bool(true));
+
+    default
+        .iter()
+        .copied()
+        .filter(|op| match *op {
+            "readproperty" => !flag("writeOnly"),
+            "writeproperty" => !flag("readOnly"),
+            _ => true,
+        })
+        .collect()
+}
+
+/// Affordances of type `key` of the description, with their names.
+pub(crate) fn affordances<'a>(
+    td: &'a Value,
+    key: &str,
+) -> impl Iterator<Item = (&'a String, &'a Value)> {
+    td.get(key)
+        .and_then(Value::as_object)
+        .into_iter()
+        .flat_map(|m| m.iter())
+}
+
+/// Operations of the `forms`, `default` for the Forms declaring none.
+pub(crate) fn declared_ops(forms: Option<&Value>, default: &[&str]) -> HashSet<FormOperation> {
+    forms
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .flat_map(|form| match form.get("op") {
+            Some(Value::String(op)) => vec![op.as_str()],
+            Some(Value::Array(ops)) => ops.iter().filter_map(Value::as_str).collect(),
+            _ => default.to_vec(),
+        })
+        .filter_map(|op| serde_json::from_value(op.into()).ok())
+        .collect()
+}
+
+type Builder = ThingBuilder<NilPlus<ServientExtension>, Extended>;
+
+fn bind_affordance(
+    builder: Builder,
+    affordance: AffordanceType,
+    name: &str,
+    op: FormOperation,
+    state: Arc<State>,
+) -> Builder {
+    use FormOperation::*;
+
+    let n = name.to_string();
+
+    match (affordance, op) {
+        (AffordanceType::Property, ReadProperty) => {
+            builder.on_read_property(name, move || async move { state.read(&n) })
+        }
+        (AffordanceType::Property, WriteProperty) => builder.on_write_property(
+            name,
+            move |body: Bytes| async move { state.write(&n, &body) },
+        ),
+        (AffordanceType::Property, ObserveProperty) => {
+            builder.on_observe_property(name, move || async move { state.observe(Some(&n)).await })
+        }
+        (AffordanceType::Property, UnobserveProperty) => {
+            builder.on_unobserve_property(name, no_content)
+        }
+        (AffordanceType::Action, InvokeAction) => {
+            builder.on_invoke_action(name, move || async move { state.invoke(&n) })
+        }
+        (AffordanceType::Action, QueryAction) => builder.on_query_action(name, || async {
+            Json(serde_json::json!({ "status": "completed" }))
+        }),
+        (AffordanceType::Action, CancelAction) => builder.on_cancel_action(name, no_content),
+        (AffordanceType::Event, SubscribeEvent) => {
+            builder.on_subscribe_event(name, move || async move { state.next_event(&n).await })
+        }
+        (AffordanceType::Event, UnsubscribeEvent) => builder.on_unsubscribe_event(name, no_content),
+        // Leave the inconsistent operations to the Servient checks.
+        _ => builder,
+    }
+}
+
+fn bind_thing(builder: Builder, op: FormOperation, state: Arc<State>) -> Builder {
+    use FormOperation::*;
+
+    match op {
+        ReadAllProperties | ReadMultipleProperties => builder
+            .on_thing_operation(op, move || async move {
+                Json(Value::Object(state.values.lock().unwrap().clone()))
+            }),
+        WriteAllProperties | WriteMultipleProperties => {
+            builder.on_thing_operation(op, move |body: Bytes| async move { state.write_all(&body) })
+        }
+        ObserveAllProperties => {
+            builder.on_thing_operation(op, move || async move { state.observe(None).await })
+        }
+        SubscribeAllEvents => {
+            builder.on_thing_operation(op, move || async move { state.next_events().await })
+        }
+        QueryAllActions => builder.on_thing_operation(op, || async { Json(serde_json::json!({})) }),
+        UnobserveAllProperties | UnsubscribeAllEvents => builder.on_thing_operation(op, no_content),
+        _ => builder,
+    }
+}
+
+async fn no_content() -> StatusCode {
+    StatusCode::NO_CONTENT
+}
+
+struct State {
+    td: Value,
+    values: Arc<Mutex<Map<String, Value>>>,
+    changes: PropertyNotifier,
+    rng: Mutex<Rng>,
+    event_interval: Duration,
+    start: Instant,
+}
+
+impl State {
+    fn sample(&self, schema: &Value) -> Value {
+        sample(schema, &mut self.rng.lock().unwrap())
+    }
+
+    fn read(&self, name: &str) -> Json<Value> {
+        Json(
+            self.values
+                .lock()
+                .unwrap()
+                .get(name)
+                .cloned()
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Check that the property `name` exists, is writable and accepts `value`.
+    fn check_write(&self, name: &str, value: &Value) -> std::result::Result<(), String> {
+        let Some(schema) = self.td["properties"].get(name) else {
+            return Err(format!("no property named {name}"));
+        };
+        if schema["readOnly"] == true {
+            return Err(format!("the property {name} is read-only"));
+        }
+
+        schema::validate(schema, value).map_err(|violation| format!("property {name}: {violation}"))
+    }
+
+    fn write(&self, name: &str, body: &[u8]) -> Response {
+        let Ok(value) = serde_json::from_slice::<Value>(body) else {
+            return StatusCode::BAD_REQUEST.into_response();
+        };
+        if let Err(reason) = self.check_write(name, &value) {
+            return (StatusCode::UNPROCESSABLE_ENTITY, reason).into_response();
+        }
+
+        self.values
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), value.clone());
+        let _ = self.changes.notify(name, value);
+
+        StatusCode::NO_CONTENT.into_response()
+    }
+
+    fn write_all(&self, body: &[u8]) -> Response {
+        let Ok(Value::Object(values)) = serde_json::from_slice::<Value>(body) else {
+            return StatusCode::BAD_REQUEST.into_response();
+        };
+
+        // Nothing is written unless every value is.
+        for (name, value) in &values {
+            if let Err(reason) = self.check_write(name, value) {
+                return (StatusCode::UNPROCESSABLE_ENTITY, reason).into_response();
+            }
+        }
+
+        for (name, value) in values {
+            self.values
+                .lock()
+                .unwrap()
+                .insert(name.clone(), value.clone());
+            let _ = self.changes.notify(&name, value);
+        }
+
+        StatusCode::NO_CONTENT.into_response()
+    }
+
+    /// Wait for the next write of the property `name`, or of any property.
+    async fn observe(&self, name: Option<&str>) -> Json<Value> {
+        let mut changes = self.changes.subscribe();
+
+        loop {
+            match changes.recv().await {
+                Ok((n, value)) if name.is_none() => {
+                    return Json(serde_json::json!({ n: value }));
+                }
+                Ok((n, value)) if Some(n.as_str()) == name => return Json(value),
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => return Json(Value::Null),
+            }
+        }
+    }
+
+    fn invoke(&self, name: &str) -> Response {
+        match self.td["actions"][name].get("output") {
+            Some(output) => Json(self.sample(output)).into_response(),
+            None => StatusCode::NO_CONTENT.into_response(),
+        }
+    }
+
+    /// Wait for the next time the events fire.
+    async fn tick(&self) {
+        let elapsed = self.start.elapsed().as_nanos();
+        let interval = self.event_interval.as_nanos().max(1);
+        let next = (elapsed / interval + 1) * interval;
+
+        tokio::time::sleep_until(self.start + Duration::from_nanos(next as u64)).await;
+    }
+
+    fn event_data(&self, name: &str) -> Value {
+        self.td["events"][name]
+            .get("data")
+            .map(|data| self.sample(data))
+            .unwrap_or_default()
+    }
+
+    async fn next_event(&self, name: &str) -> Json<Value> {
+        self.tick().await;
+
+        Json(self.event_data(name))
+    }
+
+    async fn next_events(&self) -> Json<Value> {
+        self.tick().await;
+
+        let events = affordances(&self.td, "events")
+            .map(|(name, _)| (name.clone(), self.event_data(name)))
+            .collect();
+
+        Json(Value::Object(events))
+    }
+}
+
+/// Pseudo-random generator for the simulated values
+///
+/// A xorshift64* generator is plenty to produce test values.
+#[derive(Debug)]
+struct Rng {
+    state: Option<u64>,
+}
+
+impl Rng {
+    /// Generator from `seed`, or producing the schema defaults if `None`.
+    fn new(seed: Option<u64>) -> Self {
+        // The state of a xorshift generator must not be zero.
+        Self {
+            state: seed.map(|seed| seed | 1),
+        }
+    }
+
+    fn next(&mut self) -> Option<u64> {
+        let x = self.state.as_mut()?;
+
+        *x ^= *x >> 12;
+        *x ^= *x << 25;
+        *x ^= *x >> 27;
+
+        Some(x.wrapping_mul(0x2545_f491_4f6c_dd1d))
+    }
+
+    /// A number in `[0, 1)`.
+    fn unit(&mut self) -> Option<f64> {
+        self.next().map(|x| (x >> 11) as f64 / (1u64 << 53) as f64)
+    }
+
+    /// An index in `[0, len)`.
+    fn index(&mut self, len: usize) -> usize {
+        match self.next() {
+            Some(x) if len > 0 => (x % len as u64) as usize,
+            _ => 0,
+        }
+    }
+}
+
+/// The tighter of an inclusive and an exclusive bound, along with whether it is exclusive.
+fn bound(
+    inclusive: Option<f64>,
+    exclusive: Option<f64>,
+    tighter: impl Fn(f64, f64) -> bool,
+) -> Option<(f64, bool)> {
+    match (inclusive, exclusive) {
+        (Some(i), Some(e)) if tighter(i, e) => Some((i, false)),
+        (_, Some(e)) => Some((e, true)),
+        (Some(i), None) => Some((i, false)),
+        (None, None) => None,
+    }
+}
+
+/// Produce a value conforming to the data schema.
+///
+/// Without randomness the schema `const`, `default` or first `enum` value are used, then the
+/// smallest value fitting the constraints.
+fn sample(schema: &Value, rng: &mut Rng) -> Value {
+    let Some(schema) = schema.as_object() else {
+        return Value::Null;
+    };
+
+    if let Some(value) = schema.get("const") {
+        return value.clone();
+    }
+    if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+        if !values.is_empty() {
+            return values[rng.index(values.len())].clone();
+        }
+    }
+    if rng.state.is_none() {
+        if let Some(value) = schema.get("default") {
+            return value.clone();
+        }
+    }
+    if let Some(schemas) = schema.get("oneOf").and_then(Value::as_array) {
+        if !schemas.is_empty() {
+            return sample(&schemas[rng.index(schemas.len())], rng);
+        }
+    }
+
+    let number = |key| schema.get(key).and_then(Value::as_f64);
+    let length = |key| schema.get(key).and_then(Value::as_u64).map(|l| l as usize);
+
+    match schema.get("type").and_then(Value::as_str) {
+        Some("boolean") => Value::Bool(rng.next().is_some_and(|x| x & 1 == 1)),
+        Some(ty @ ("integer" | "number")) => {
+            let lower = bound(number("minimum"), number("exclusiveMinimum"), |i, e| i > e);
+            let upper = bound(number("maximum"), number("exclusiveMaximum"), |i, e| i < e);
+
+            let (min, exclusive_min) = lower.unwrap_or(match upper {
+                Some((max, _)) if max <= 0.0 => (max - 100.0, false),
+                _ => (0.0, false),
+            });
+            let (max, exclusive_max) = upper.unwrap_or((min.max(0.0) + 100.0, false));
+            let unit = rng.unit();
+
+            let step = number("multipleOf")
+                .filter(|step| *step > 0.0)
+                .or((ty == "integer").then_some(1.0));
+
+            let value = match step {
+                // The multiples of the step within the bounds.
+                Some(step) => {
+                    let mut first = (min / step).ceil();
+                    if exclusive_min && first * step <= min {
+                        first += 1.0;
+                    }
+                    let mut last = (max / step).floor();
+                    if exclusive_max && last * step >= max {
+                        last -= 1.0;
+                    }
+                    // Unsatisfiable bounds, the lower one wins.
+                    let count = (last - first).max(0.0);
+
+                    let k = match unit {
+                        Some(unit) => first + (unit * (count + 1.0)).floor().min(count),
+                        None => first,
+                    };
+                    k * step
+                }
+                None => {
+                    let value = min + unit.unwrap_or(0.0) * (max - min);
+                    let excluded =
+                        (exclusive_min && value <= min) || (exclusive_max && value >= max);
+                    if excluded {
+                        min + (max - min) / 2.0
+                    } else {
+                        value
+                    }
+                }
+            };
+
+            if ty == "integer" {
+                Value::from(value.round() as i64)
+            } else {
+                Value::from(value)
+            }
+        }
+        Some("string") => {
+            const CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+
+            let min = length("minLength").unwrap_or(0);
+            let max = length("maxLength").unwrap_or(min + 8).max(min);
+            let len = match rng.state {
+                Some(_) => min + rng.index(max - min + 1),
+                None => min,
+            };
+
+            (0..len)
+                .map(|_| CHARS[rng.index(CHARS.len())] as char)
+                .collect::<String>()
+                .into()
+        }
+        Some("array") => {
+            let min = length("minItems").unwrap_or(0);
+            let max = length("maxItems").unwrap_or(min + 3).max(min);
+            let len = match rng.state {
+                Some(_) => min + rng.index(max - min + 1),
+                None => min,
+            };
+
+            let items = schema.get("items").unwrap_or(&Value::Null);
+            let items = match items {
+                Value::Array(items) => (0..len)
+                    .map(|idx| sample(items.get(idx).unwrap_or(&Value::Null), rng))
+                    .collect(),
+                items => (0..len).map(|_| sample(items, rng)).collect(),
+            };
+
+            Value::Array(items)
+        }
+        Some("object") => {
+            let properties = schema
+                .get("properties")
+                .and_then(Value::as_object)
+                .into_iter()
+                .flatten()
+                .map(|(name, schema)| (name.clone(), sample(schema, rng)))
+                .collect();
+
+            Value::Object(properties)
+        }
+        _ => Value::Null,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::servient::{BuildServient, ServientSettings};
+    use crate::test_util::{local_listener, serve_until};
+
+    #[test]
+    fn sample_defaults() {
+        let mut rng = Rng::new(None);
+
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "on": { "type": "boolean" },
+                "level": { "type": "integer", "minimum": 10, "maximum": 20 },
+                "color": { "type": "string", "enum": ["red", "green"] },
+                "name": { "type": "string", "default": "lamp" },
+                "tags": { "type": "array", "minItems": 2, "items": { "type": "string", "minLength": 1 } }
+            }
+        });
+
+        assert_eq!(
+            sample(&schema, &mut rng),
+            json!({
+                "on": false,
+                "level": 10,
+                "color": "red",
+                "name": "lamp",
+                "tags": ["a", "a"],
+            })
+        );
+    }
+
+    #[test]
+    fn sample_random() {
+        let mut rng = Rng::new(Some(42));
+
+        for _ in 0..100 {
+            let v = sample(
+                &json!({ "type": "number", "minimum": -1.5, "maximum": 1.5 }),
+                &mut rng,
+            );
+            let v = v.as_f64().unwrap();
+            assert!((-1.5..=1.5).contains(&v));
+
+            let v = sample(
+                &json!({ "type": "integer", "minimum": 0, "maximum": 100, "multipleOf": 5 }),
+                &mut rng,
+            );
+            assert_eq!(v.as_i64().unwrap() % 5, 0);
+
+            let v = sample(
+                &json!({ "type": "string", "minLength": 2, "maxLength": 4 }),
+                &mut rng,
+            );
+            assert!((2..=4).contains(&v.as_str().unwrap().len()));
+        }
+
+        let a = sample(&json!({ "type": "number" }), &mut Rng::new(Some(7)));
+        let b = sample(&json!({ "type": "number" }), &mut Rng::new(Some(7)));
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn sample_bounds() {
+        let deterministic = |schema| sample(&schema, &mut Rng::new(None));
+
+        assert_eq!(
+            deterministic(json!({ "type": "integer", "exclusiveMinimum": 3 })),
+            4
+        );
+        assert_eq!(
+            deterministic(json!({ "type": "integer", "exclusiveMinimum": 2.5 })),
+            3
+        );
+        assert_eq!(
+            deterministic(json!({ "type": "integer", "minimum": 3, "exclusiveMinimum": 3 })),
+            4
+        );
+        assert_eq!(
+            deterministic(json!({ "type": "integer", "maximum": -10 })),
+            -110
+        );
+        assert_eq!(
+            deterministic(json!({ "type": "number", "exclusiveMinimum": 0, "maximum": 1 })),
+            0.5
+        );
+        assert_eq!(
+            deterministic(json!({ "type": "number", "minimum": 1, "multipleOf": 0.5 })),
+            1.0
+        );
+
+        let mut rng = Rng::new(Some(3));
+        for _ in 0..1000 {
+            let v = sample(
+                &json!({ "type": "integer", "exclusiveMinimum": 0, "exclusiveMaximum": 2 }),
+                &mut rng,
+            );
+            assert_eq!(v, 1);
+
+            let v = sample(
+                &json!({ "type": "integer", "minimum": 1, "maximum": 9, "multipleOf": 4 }),
+                &mut rng,
+            );
+            assert!([4, 8].contains(&v.as_i64().unwrap()));
+
+            let v = sample(
+                &json!({ "type": "number", "exclusiveMinimum": -1, "exclusiveMaximum": 1 }),
+                &mut rng,
+            )
+            .as_f64()
+            .unwrap();
+            assert!(v > -1.0 && v < 1.0);
+
+            let v = sample(
+                &json!({ "type": "number", "minimum": 0.5, "exclusiveMaximum": 2, "multipleOf": 0.5 }),
+                &mut rng,
+            )
+            .as_f64()
+            .unwrap();
+            assert!([0.5, 1.0, 1.5].contains(&v));
+        }
+    }
+
+    #[test]
+    fn flagged_defaults() {
+        let td = json!({
+            "title": "Simulated",
+            "securityDefinitions": { "nosec_sc": { "scheme": "nosec" } },
+            "security": "nosec_sc",
+            "properties": {
+                "temperature": {
+                    "type": "number",
+                    "readOnly": true,
+                    "forms": [{ "href": "/properties/temperature" }]
+                },
+                "target": {
+                    "type": "number",
+                    "writeOnly": true,
+                    "forms": [{ "href": "/properties/target" }]
+                }
+            }
+        });
+
+        let servient = Simulator::from_td(&td.to_string())
+            .unwrap()
+            .builder()
+            .unwrap()
+            .build_servient()
+            .unwrap();
+
+        let td = servient.description.get();
+        assert_eq!(
+            td["properties"]["temperature"]["forms"][0]["op"],
+            json!(["readproperty"])
+        );
+        assert_eq!(
+            td["properties"]["target"]["forms"][0]["op"],
+            json!(["writeproperty"])
+        );
+    }
+
+    #[tokio::test]
+    async fn serve_simulated() {
+        let (listener, addr) = local_listener();
+
+        let td = json!({
+            "title": "Simulated",
+            "securityDefinitions": { "nosec_sc": { "scheme": "nosec" } },
+            "security": "nosec_sc",
+            "forms": [{ "href": "/properties", "op": "readallproperties" }],
+            "properties": {
+                "brightness": {
+                    "type": "integer",
+                    "default": 50,
+                    "forms": [{ "href": "/properties/brightness" }]
+                }
+            },
+            "actions": {
+                "toggle": {
+                    "output": { "type": "boolean", "const": true },
+                    "forms": [{ "href": "/actions/toggle" }]
+                }
+            },
+            "events": {
+                "overheat": {
+                    "data": { "type": "string", "const": "hot" },
+                    "forms": [{ "href": "/events/overheat", "op": "subscribeevent" }]
+                }
+            }
+        });
+
+        let simulator = Simulator::from_td(&td.to_string())
+            .unwrap()
+            .event_interval(Duration::from_millis(50));
+        let servient = simulator
+            .builder()
+            .unwrap()
+            .http_bind(addr)
+            .build_servient()
+            .unwrap();
+
+        let checks = async {
+            let client = reqwest::Client::new();
+            let url = |path: &str| format!("http://{addr}{path}");
+            let get = |path: &str| {
+                let req = client.get(url(path));
+                async move { req.send().await.unwrap().json::<Value>().await.unwrap() }
+            };
+
+            assert_eq!(get("/properties/brightness").await, 50);
+
+            let res = client
+                .put(url("/properties/brightness"))
+                .body("75")
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::NO_CONTENT);
+            assert_eq!(get("/properties/brightness").await, 75);
+            assert_eq!(simulator.value("brightness"), Some(json!(75)));
+            assert_eq!(get("/properties").await, json!({ "brightness": 75 }));
+
+            let res = client.post(url("/actions/toggle")).send().await.unwrap();
+            assert_eq!(res.json::<Value>().await.unwrap(), true);
+
+            assert_eq!(get("/events/overheat").await, "hot");
+        };
+
+        serve_until(&servient, listener, checks).await;
+    }
+
+    #[tokio::test]
+    async fn validate_writes() {
+        use axum::{body::Body, http::Request};
+        use tower::ServiceExt;
+
+        let td = json!({
+            "title": "Simulated",
+            "securityDefinitions": { "nosec_sc": { "scheme": "nosec" } },
+            "security": "nosec_sc",
+            "forms": [{ "href": "/properties", "op": "writemultipleproperties" }],
+            "properties": {
+                "brightness": {
+                    "type": "integer",
+                    "maximum": 100,
+                    "default": 50,
+                    "forms": [{ "href": "/properties/brightness" }]
+                },
+                "temperature": {
+                    "type": "number",
+                    "readOnly": true,
+                    "default": 20,
+                    "forms": [{ "href": "/properties/temperature" }]
+                }
+            }
+        });
+
+        let simulator = Simulator::from_td(&td.to_string()).unwrap();
+        let servient = simulator.builder().unwrap().build_servient().unwrap();
+        let write = |method: &str, path: &str, body: Value| {
+            let request = Request::builder()
+                .method(method)
+                .uri(path)
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            servient.router.clone().oneshot(request)
+        };
+
+        let res = write("PUT", "/properties/brightness", json!(150))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        for values in [
+            json!({ "brightness": 75, "missing": 1 }),
+            json!({ "brightness": 75, "temperature": 30 }),
+            json!({ "brightness": "bright" }),
+        ] {
+            let res = write("PATCH", "/properties", values).await.unwrap();
+            assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        }
+        assert_eq!(simulator.value("brightness"), Some(json!(50)));
+        assert_eq!(simulator.value("missing"), None);
+
+        let res = write("PATCH", "/properties", json!({ "brightness": 75 }))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(simulator.value("brightness"), Some(json!(75)));
+    }
+}
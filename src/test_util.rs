@@ -0,0 +1,40 @@
+//! Helpers shared by the tests
+
+use std::{
+    future::Future,
+    net::{SocketAddr, TcpListener},
+};
+
+use wot_td::extend::ExtendableThing;
+
+use crate::Servient;
+
+/// A listener on a free local port, with its address.
+///
+/// The connections wait in its backlog until it is served, no request has to be retried.
+pub(crate) fn local_listener() -> (TcpListener, SocketAddr) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    (listener, addr)
+}
+
+/// Serve `servient` through `listener` until `checks` completes.
+pub(crate) async fn serve_until<O: ExtendableThing, S>(
+    servient: &Servient<O, S>,
+    listener: TcpListener,
+    checks: impl Future<Output = ()>,
+) {
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+
+    let checks = async {
+        checks.await;
+        stop.send(()).unwrap();
+    };
+
+    let shutdown = async move {
+        let _ = stopped.await;
+    };
+    let (served, ()) = tokio::join!(servient.serve_listener(listener, shutdown), checks);
+    served.unwrap();
+}
@@ -0,0 +1,595 @@
+//! Rust types exposed as Things
+//!
+//! `#[derive(Thing)]` describes a struct as a Thing and serves it:
+//!
+//! - the fields are properties, read and written through http.
+//! - the fields marked `#[event]` are [`EventChannel`]s, their values are delivered through
+//!   long polling.
+//! - the methods marked `#[action]` in an impl block marked [`#[actions]`](actions) are
+//!   actions, the struct opts in with `#[thing(actions)]`.
+//!
+//! The data schemas are derived from the types of the fields and of the methods signatures
+//! through [`ToDataSchema`], the descriptions from the doc comments.
+//!
+//! [`ToDataSchema`]: crate::schema::ToDataSchema
+//!
+//! ```no_run
+//! use wot_serve::{
+//!     servient::*,
+//!     thing::{actions, EventChannel, Thing},
+//! };
+//!
+//! /// A dimmable lamp
+//! #[derive(Thing)]
+//! #[thing(title = "Lamp", actions)]
+//! struct Lamp {
+//!     /// Brightness in percent
+//!     brightness: u8,
+//!     #[thing(read_only)]
+//!     on: bool,
+//!     /// Temperature reached when overheating
+//!     #[event]
+//!     overheating: EventChannel<f64>,
+//! }
+//!
+//! #[actions]
+//! impl Lamp {
+//!     /// Switch the lamp on or off
+//!     #[action]
+//!     fn toggle(&mut self) -> bool {
+//!         self.on = !self.on;
+//!         self.on
+//!     }
+//! }
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let lamp = Lamp {
+//!     brightness: 100,
+//!     on: false,
+//!     overheating: EventChannel::new(),
+//! };
+//!
+//! let servient = lamp.builder().build_servient()?;
+//!
+//! servient.serve().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{collections::VecDeque, sync::Arc};
+
+use serde_json::Value;
+use tokio::sync::{broadcast, Mutex};
+use wot_td::builder::{Extended, ThingBuilder};
+
+use crate::{
+    hlist::NilPlus,
+    servient::{description_builder, ServientExtension, ServientSettings},
+};
+
+pub use wot_serve_derive::{actions, Thing};
+
+/// Value of a Thing, shared by its handlers and the application
+pub type Shared<T> = Arc<Mutex<T>>;
+
+/// [`Servient`] builder exposing the Thing `T`
+///
+/// [`Servient`]: crate::Servient
+pub type Builder<T> = ThingBuilder<NilPlus<ServientExtension<Shared<T>>>, Extended>;
+
+/// A Rust type exposed as a Thing
+///
+/// Derive it with `#[derive(Thing)]`.
+pub trait Thing: Sized + Send + 'static {
+    /// Thing Description of the type, without the handlers.
+    fn description() -> Value;
+
+    /// Route the operations of the description to the value.
+    fn route(builder: Builder<Self>) -> Builder<Self>;
+
+    /// Prepare a [`Servient`] builder exposing the value.
+    ///
+    /// [`Servient`]: crate::Servient
+    fn builder(self) -> Builder<Self> {
+        Self::shared_builder(Arc::new(Mutex::new(self)))
+    }
+
+    /// Prepare a [`Servient`] builder exposing a value the application keeps using.
+    ///
+    /// [`Servient`]: crate::Servient
+    fn shared_builder(thing: Shared<Self>) -> Builder<Self> {
+        let builder = description_builder(Self::description())
+            .expect("the description has no version to parse")
+            .with_state(thing);
+
+        Self::route(builder)
+    }
+}
+
+/// The actions of a [`Thing`]
+///
+/// Implement it with [`#[actions]`](actions).
+pub trait Actions: Thing {
+    /// Add the actions to the Thing Description.
+    fn describe_actions(td: &mut Value);
+
+    /// Route the actions to the methods of the value.
+    fn route_actions(builder: Builder<Self>) -> Builder<Self>;
+}
+
+/// Channel the data of an event is emitted through
+///
+/// The events are numbered from 1 on, the channel can keep the latest ones to replay them to
+/// the subscribers reconnecting.
+#[derive(Debug, Clone)]
+pub struct EventChannel<T> {
+    tx: broadcast::Sender<T>,
+    log: Arc<std::sync::Mutex<EventLog<T>>>,
+}
+
+/// Latest events of a channel
+#[derive(Debug)]
+struct EventLog<T> {
+    next_id: u64,
+    retention: usize,
+    events: VecDeque<(u64, T)>,
+}
+
+impl<T: Clone> EventChannel<T> {
+    /// Create a channel buffering up to 16 events for each subscriber.
+    pub fn new() -> Self {
+        Self::with_history(0)
+    }
+
+    /// Create a channel keeping the latest `retention` events.
+    pub fn with_history(retention: usize) -> Self {
+        let (tx, _) = broadcast::channel(16);
+        let log = EventLog {
+            next_id: 1,
+            retention,
+            events: VecDeque::with_capacity(retention),
+        };
+
+        Self {
+            tx,
+            log: Arc::new(std::sync::Mutex::new(log)),
+        }
+    }
+
+    /// Emit an event, returns how many subscribers receive it.
+    pub fn emit(&self, data: T) -> usize {
+        let mut log = self.log.lock().unwrap();
+        let id = log.next_id;
+        log.next_id += 1;
+
+        if log.retention > 0 {
+            if log.events.len() == log.retention {
+                log.events.pop_front();
+            }
+            log.events.push_back((id, data.clone()));
+        }
+
+        self.tx.send(data).unwrap_or(0)
+    }
+
+    /// Receive the events emitted from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<T> {
+        self.tx.subscribe()
+    }
+
+    /// The kept events emitted after the event `last_id`, with the id of the next event and
+    /// a receiver of the events emitted from now on.
+    pub(crate) fn replay(&self, last_id: u64) -> (Vec<(u64, T)>, u64, broadcast::Receiver<T>) {
+        let log = self.log.lock().unwrap();
+        let missed = log
+            .events
+            .iter()
+            .filter(|(id, _)| *id > last_id)
+            .cloned()
+            .collect();
+
+        (missed, log.next_id, self.tx.subscribe())
+    }
+}
+
+impl<T: Clone> Default for EventChannel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[doc(hidden)]
+/// Runtime support of the derived code
+pub mod __private {
+    use std::future::Future;
+
+    use axum::{
+        body::Bytes,
+        extract::State,
+        http::StatusCode,
+        response::{IntoResponse, Response},
+        Json,
+    };
+    use serde::{de::DeserializeOwned, Serialize};
+    use serde_json::{json, Value};
+    use tokio::sync::broadcast::error::RecvError;
+
+    use super::{Builder, EventChannel, Shared};
+    use crate::{schema::ToDataSchema, servient::AffordanceRouter};
+
+    pub use serde_json;
+
+    pub fn schema<T: ToDataSchema + ?Sized>() -> Value {
+        // The data schemas always serialize the readOnly and writeOnly flags, drop them
+        // where they are false as in the handwritten descriptions.
+        fn strip(value: &mut Value) {
+            match value {
+                Value::Object(map) => {
+                    for flag in ["readOnly", "writeOnly"] {
+                        if map.get(flag) == Some(&Value::Bool(false)) {
+                            map.remove(flag);
+                        }
+                    }
+                    map.values_mut().for_each(strip);
+                }
+                Value::Array(values) => values.iter_mut().for_each(strip),
+                _ => {}
+            }
+        }
+
+        let mut schema =
+            serde_json::to_value(T::data_schema()).expect("data schemas serialize to json");
+        strip(&mut schema);
+
+        schema
+    }
+
+    fn describe(td: &mut Value, kind: &str, name: &str, mut affordance: Value, forms: Value) {
+        affordance["forms"] = forms;
+        td[kind][name] = affordance;
+    }
+
+    pub fn describe_property(
+        td: &mut Value,
+        name: &str,
+        mut schema: Value,
+        description: Option<&str>,
+        read_only: bool,
+    ) {
+        let op = if read_only {
+            json!(["readproperty"])
+        } else {
+            json!(["readproperty", "writeproperty"])
+        };
+        if read_only {
+            schema["readOnly"] = true.into();
+        }
+        if let Some(description) = description {
+            schema["description"] = description.into();
+        }
+
+        let forms = json!([{ "href": format!("properties/{name}"), "op": op }]);
+        describe(td, "properties", name, schema, forms);
+    }
+
+    pub fn describe_action(
+        td: &mut Value,
+        name: &str,
+        input: Option<Value>,
+        output: Option<Value>,
+        description: Option<&str>,
+    ) {
+        let mut action = json!({});
+        if let Some(description) = description {
+            action["description"] = description.into();
+        }
+        if let Some(input) = input {
+            action["input"] = input;
+        }
+        if let Some(output) = output {
+            action["output"] = output;
+        }
+
+        let forms = json!([{ "href": format!("actions/{name}"), "op": ["invokeaction"] }]);
+        describe(td, "actions", name, action, forms);
+    }
+
+    pub fn describe_event(td: &mut Value, name: &str, data: Value, description: Option<&str>) {
+        let mut event = json!({ "data": data });
+        if let Some(description) = description {
+            event["description"] = description.into();
+        }
+
+        let forms = json!([{
+            "href": format!("events/{name}"),
+            "op": ["subscribeevent"],
+            "subprotocol": "longpoll",
+        }]);
+        describe(td, "events", name, event, forms);
+    }
+
+    pub fn read_property<T, V>(builder: Builder<T>, name: &str, read: fn(&T) -> V) -> Builder<T>
+    where
+        T: Send + 'static,
+        V: Serialize + Send + 'static,
+    {
+        builder.on_read_property(name, move |State(thing): State<Shared<T>>| async move {
+            Json(read(&*thing.lock().await))
+        })
+    }
+
+    pub fn write_property<T, V>(builder: Builder<T>, name: &str, write: fn(&mut T, V)) -> Builder<T>
+    where
+        T: Send + 'static,
+        V: DeserializeOwned + Send + 'static,
+    {
+        builder.on_write_property(
+            name,
+            move |State(thing): State<Shared<T>>, body: Bytes| async move {
+                let Ok(value) = serde_json::from_slice(&body) else {
+                    return StatusCode::BAD_REQUEST;
+                };
+                write(&mut *thing.lock().await, value);
+
+                StatusCode::NO_CONTENT
+            },
+        )
+    }
+
+    pub fn event<T, E>(
+        builder: Builder<T>,
+        name: &str,
+        channel: fn(&T) -> &EventChannel<E>,
+    ) -> Builder<T>
+    where
+        T: Send + 'static,
+        E: Clone + Serialize + Send + 'static,
+    {
+        builder.on_subscribe_event(name, move |State(thing): State<Shared<T>>| async move {
+            let mut events = channel(&*thing.lock().await).subscribe();
+
+            loop {
+                match events.recv().await {
+                    Ok(data) => return Json(data).into_response(),
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return StatusCode::NO_CONTENT.into_response(),
+                }
+            }
+        })
+    }
+
+    pub fn action<T, I, O, F, Fut>(builder: Builder<T>, name: &str, invoke: F) -> Builder<T>
+    where
+        T: Send + 'static,
+        I: DeserializeOwned + Send + 'static,
+        O: Serialize + Send + 'static,
+        F: Fn(Shared<T>, I) -> Fut + Clone + Send + Sync + 'static,
+        Fut: Future<Output = O> + Send + 'static,
+    {
+        builder.on_invoke_action(
+            name,
+            move |State(thing): State<Shared<T>>, body: Bytes| async move {
+                let input = if body.is_empty() {
+                    Ok(Value::Null)
+                } else {
+                    serde_json::from_slice(&body)
+                };
+                let Ok(input) = input.and_then(serde_json::from_value::<I>) else {
+                    return StatusCode::BAD_REQUEST.into_response();
+                };
+
+                output(invoke(thing, input).await)
+            },
+        )
+    }
+
+    fn output(output: impl Serialize) -> Response {
+        match serde_json::to_value(output) {
+            Ok(Value::Null) => StatusCode::NO_CONTENT.into_response(),
+            Ok(output) => Json(output).into_response(),
+            Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    }
+}
+
+#[cfg(all(test, not(miri)))]
+mod test {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::servient::BuildServient;
+    use crate::test_util::{local_listener, serve_until};
+
+    /// A dimmable lamp
+    #[derive(Thing)]
+    #[thing(title = "Lamp", actions)]
+    struct Lamp {
+        /// Brightness in percent
+        brightness: u8,
+        #[thing(read_only)]
+        on: bool,
+        #[thing(rename = "label")]
+        name: Option<String>,
+        #[thing(skip)]
+        #[allow(dead_code)]
+        secret: (),
+        /// Temperature reached when overheating
+        #[event]
+        overheating: EventChannel<f64>,
+    }
+
+    #[actions]
+    impl Lamp {
+        /// Switch the lamp on or off
+        #[action]
+        fn toggle(&mut self) -> bool {
+            self.on = !self.on;
+            self.on
+        }
+
+        #[action]
+        async fn fade(&mut self, to: u8) {
+            self.brightness = to;
+        }
+    }
+
+    #[test]
+    fn describe_derived() {
+        assert_eq!(
+            Lamp::description(),
+            json!({
+                "title": "Lamp",
+                "description": "A dimmable lamp",
+                "properties": {
+                    "brightness": {
+                        "type": "integer",
+                        "minimum": 0,
+                        "maximum": 255,
+                        "description": "Brightness in percent",
+                        "forms": [{
+                            "href": "properties/brightness",
+                            "op": ["readproperty", "writeproperty"],
+                        }],
+                    },
+                    "on": {
+                        "type": "boolean",
+                        "readOnly": true,
+                        "forms": [{ "href": "properties/on", "op": ["readproperty"] }],
+                    },
+                    "label": {
+                        "oneOf": [{ "type": "string" }, { "type": "null" }],
+                        "forms": [{
+                            "href": "properties/label",
+                            "op": ["readproperty", "writeproperty"],
+                        }],
+                    },
+                },
+                "actions": {
+                    "toggle": {
+                        "description": "Switch the lamp on or off",
+                        "output": { "type": "boolean" },
+                        "forms": [{ "href": "actions/toggle", "op": ["invokeaction"] }],
+                    },
+                    "fade": {
+                        "input": { "type": "integer", "minimum": 0, "maximum": 255 },
+                        "forms": [{ "href": "actions/fade", "op": ["invokeaction"] }],
+                    },
+                },
+                "events": {
+                    "overheating": {
+                        "description": "Temperature reached when overheating",
+                        "data": { "type": "number" },
+                        "forms": [{
+                            "href": "events/overheating",
+                            "op": ["subscribeevent"],
+                            "subprotocol": "longpoll",
+                        }],
+                    },
+                },
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn serve_derived() {
+        let (listener, addr) = local_listener();
+
+        let lamp = Arc::new(Mutex::new(Lamp {
+            brightness: 50,
+            on: false,
+            name: None,
+            secret: (),
+            overheating: EventChannel::new(),
+        }));
+
+        let servient = Lamp::shared_builder(lamp.clone())
+            .http_bind(addr)
+            .build_servient()
+            .unwrap();
+
+        let checks = async {
+            let client = reqwest::Client::new();
+            let url = |path: &str| format!("http://{addr}{path}");
+
+            let res = client
+                .put(url("/properties/brightness"))
+                .body("80")
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::NO_CONTENT);
+
+            let res = client
+                .put(url("/properties/brightness"))
+                .body("-1")
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+
+            let brightness: Value = client
+                .get(url("/properties/brightness"))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            assert_eq!(brightness, json!(80));
+
+            let res = client.put(url("/properties/on")).body("true").send().await;
+            assert_eq!(
+                res.unwrap().status(),
+                reqwest::StatusCode::METHOD_NOT_ALLOWED
+            );
+
+            let on: Value = client
+                .post(url("/actions/toggle"))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            assert_eq!(on, json!(true));
+
+            let res = client
+                .post(url("/actions/fade"))
+                .body("10")
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::NO_CONTENT);
+            assert_eq!(lamp.lock().await.brightness, 10);
+
+            let event = client.get(url("/events/overheating")).send();
+            let emit = async {
+                while lamp.lock().await.overheating.emit(90.5) == 0 {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            };
+            let (event, ()) = tokio::join!(event, emit);
+            let data: Value = event.unwrap().json().await.unwrap();
+            assert_eq!(data, json!(90.5));
+        };
+
+        serve_until(&servient, listener, checks).await;
+    }
+
+    #[test]
+    fn event_history() {
+        let channel = EventChannel::with_history(2);
+        for data in ["a", "b", "c"] {
+            channel.emit(data);
+        }
+
+        let (missed, next_id, _) = channel.replay(0);
+        assert_eq!(missed, [(2, "b"), (3, "c")]);
+        assert_eq!(next_id, 4);
+
+        let (missed, _, _) = channel.replay(u64::MAX);
+        assert!(missed.is_empty());
+    }
+}
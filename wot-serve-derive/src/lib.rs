@@ -0,0 +1,376 @@
+//! Derive macros for wot-serve
+//!
+//! Use them through the `wot_serve::thing` module, the expanded code refers to it.
+
+mod schema;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, ToTokens};
+use syn::{
+    parse_macro_input, spanned::Spanned, Attribute, Data, DeriveInput, Expr, ExprLit, Fields,
+    FnArg, GenericArgument, ImplItem, ItemImpl, Lit, LitStr, Meta, PathArguments, ReturnType, Type,
+};
+
+/// Describe a struct as a Thing and serve its fields.
+///
+/// Every field is a property, read and written through http, unless marked:
+///
+/// - `#[thing(read_only)]`: the property cannot be written.
+/// - `#[thing(skip)]`: the field is not exposed.
+/// - `#[thing(rename = "name")]`: the property is named differently.
+/// - `#[event]`: the field is an `EventChannel` whose values are the event data.
+///
+/// On the struct, `#[thing(title = "Title")]` sets the title of the Thing, the name of the
+/// struct otherwise, and `#[thing(actions)]` exposes the methods marked by `#[actions]`.
+///
+/// The doc comments become the descriptions of the Thing and of its affordances.
+#[proc_macro_derive(Thing, attributes(thing, event))]
+pub fn derive_thing(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    thing(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Describe the values of a type as a data schema.
+///
+/// Structs are objects, their fields not wrapped in an `Option` are required. Enums of unit
+/// variants are strings, the other enums are `oneOf` their variants in the default serde
+/// representation. The `rename`, `skip` and `default` serde attributes are taken into account.
+///
+/// The doc comments become the descriptions of the schemas.
+#[proc_macro_derive(ToDataSchema, attributes(serde))]
+pub fn derive_to_data_schema(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    schema::to_data_schema(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Expose the methods marked `#[action]` as actions of the Thing.
+///
+/// The methods take `&self` or `&mut self` and at most one input argument, the value they
+/// return is the output of the action. They may be `async`.
+#[proc_macro_attribute]
+pub fn actions(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let attr = TokenStream2::from(attr);
+    if !attr.is_empty() {
+        return syn::Error::new(attr.span(), "no argument is expected")
+            .into_compile_error()
+            .into();
+    }
+    let item = parse_macro_input!(item as ItemImpl);
+
+    actions_impl(item)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Path to the runtime support of the expanded code
+fn private() -> TokenStream2 {
+    quote!(::wot_serve::thing::__private)
+}
+
+fn thing(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let private = private();
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let mut title = ident.to_string();
+    let mut actions = false;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("thing")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("title") {
+                title = meta.value()?.parse::<LitStr>()?.value();
+                Ok(())
+            } else if meta.path.is_ident("actions") {
+                actions = true;
+                Ok(())
+            } else {
+                Err(meta.error("unsupported thing attribute"))
+            }
+        })?;
+    }
+
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new(
+            input.span(),
+            "only structs can be derived as Things",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(syn::Error::new(
+            data.fields.span(),
+            "the fields of the Thing must be named",
+        ));
+    };
+
+    let mut describe = Vec::new();
+    let mut route = Vec::new();
+
+    for field in &fields.named {
+        let member = field.ident.as_ref().expect("named fields");
+        let ty = &field.ty;
+
+        let mut name = member.to_string().trim_start_matches("r#").to_string();
+        let mut read_only = false;
+        let mut skip = false;
+        for attr in field.attrs.iter().filter(|a| a.path().is_ident("thing")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("read_only") {
+                    read_only = true;
+                    Ok(())
+                } else if meta.path.is_ident("skip") {
+                    skip = true;
+                    Ok(())
+                } else if meta.path.is_ident("rename") {
+                    name = meta.value()?.parse::<LitStr>()?.value();
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported thing attribute"))
+                }
+            })?;
+        }
+        if skip {
+            continue;
+        }
+
+        let description = option(docs(&field.attrs));
+
+        if field.attrs.iter().any(|a| a.path().is_ident("event")) {
+            let Some(data) = generic_argument(ty, "EventChannel") else {
+                return Err(syn::Error::new(
+                    ty.span(),
+                    "an event must be an EventChannel",
+                ));
+            };
+            let schema = schema(data);
+
+            describe.push(quote! {
+                #private::describe_event(&mut td, #name, #schema, #description);
+            });
+            route.push(quote! {
+                let builder = #private::event(builder, #name, |thing: &Self| &thing.#member);
+            });
+        } else {
+            let schema = schema(ty);
+
+            describe.push(quote! {
+                #private::describe_property(&mut td, #name, #schema, #description, #read_only);
+            });
+            route.push(quote! {
+                let builder = #private::read_property(builder, #name, |thing: &Self| {
+                    ::std::clone::Clone::clone(&thing.#member)
+                });
+            });
+            if !read_only {
+                route.push(quote! {
+                    let builder = #private::write_property(
+                        builder,
+                        #name,
+                        |thing: &mut Self, value: #ty| thing.#member = value,
+                    );
+                });
+            }
+        }
+    }
+
+    if actions {
+        describe.push(quote! {
+            <Self as ::wot_serve::thing::Actions>::describe_actions(&mut td);
+        });
+        route.push(quote! {
+            let builder = <Self as ::wot_serve::thing::Actions>::route_actions(builder);
+        });
+    }
+
+    let description = docs(&input.attrs).map(|doc| {
+        quote! {
+            td["description"] = #private::serde_json::Value::from(#doc);
+        }
+    });
+
+    Ok(quote! {
+        impl #impl_generics ::wot_serve::thing::Thing for #ident #ty_generics #where_clause {
+            fn description() -> #private::serde_json::Value {
+                let mut td = #private::serde_json::json!({ "title": #title });
+                #description
+                #(#describe)*
+                td
+            }
+
+            fn route(
+                builder: ::wot_serve::thing::Builder<Self>,
+            ) -> ::wot_serve::thing::Builder<Self> {
+                #(#route)*
+                builder
+            }
+        }
+    })
+}
+
+fn actions_impl(mut item: ItemImpl) -> syn::Result<TokenStream2> {
+    let private = private();
+
+    let mut describe = Vec::new();
+    let mut route = Vec::new();
+
+    for impl_item in &mut item.items {
+        let ImplItem::Fn(method) = impl_item else {
+            continue;
+        };
+        let len = method.attrs.len();
+        method.attrs.retain(|a| !a.path().is_ident("action"));
+        if method.attrs.len() == len {
+            continue;
+        }
+
+        let sig = &method.sig;
+        let ident = &sig.ident;
+        let name = ident.to_string().trim_start_matches("r#").to_string();
+
+        let mut inputs = sig.inputs.iter();
+        let receiver = match inputs.next() {
+            Some(FnArg::Receiver(r)) if r.reference.is_some() => r,
+            _ => {
+                return Err(syn::Error::new(
+                    sig.span(),
+                    "an action takes either &self or &mut self",
+                ))
+            }
+        };
+        let input = match (inputs.next(), inputs.next()) {
+            (None, _) => None,
+            (Some(FnArg::Typed(arg)), None) => Some(&*arg.ty),
+            (_, Some(arg)) => {
+                return Err(syn::Error::new(
+                    arg.span(),
+                    "an action takes at most one input",
+                ))
+            }
+            (Some(arg), None) => return Err(syn::Error::new(arg.span(), "unexpected argument")),
+        };
+        let output = match &sig.output {
+            ReturnType::Type(_, ty) if !is_unit(ty) => Some(&**ty),
+            _ => None,
+        };
+
+        let description = option(docs(&method.attrs));
+        let input_schema = option(input.map(schema));
+        let output_schema = option(output.map(schema));
+        describe.push(quote! {
+            #private::describe_action(
+                td,
+                #name,
+                #input_schema,
+                #output_schema,
+                #description,
+            );
+        });
+
+        let guard = match receiver.mutability {
+            Some(_) => quote!(let mut thing = thing.lock().await;),
+            None => quote!(let thing = thing.lock().await;),
+        };
+        let (arg, input_ty) = match input {
+            Some(ty) => (quote!(input), ty.to_token_stream()),
+            None => (quote!(), quote!(())),
+        };
+        let call = match sig.asyncness {
+            Some(_) => quote!(thing.#ident(#arg).await),
+            None => quote!(thing.#ident(#arg)),
+        };
+        route.push(quote! {
+            let builder = #private::action(
+                builder,
+                #name,
+                |thing: ::wot_serve::thing::Shared<Self>, input: #input_ty| async move {
+                    #guard
+                    let output = #call;
+                    output
+                },
+            );
+        });
+    }
+
+    let self_ty = &item.self_ty;
+    let (impl_generics, _, where_clause) = item.generics.split_for_impl();
+
+    Ok(quote! {
+        #item
+
+        impl #impl_generics ::wot_serve::thing::Actions for #self_ty #where_clause {
+            fn describe_actions(td: &mut #private::serde_json::Value) {
+                #(#describe)*
+            }
+
+            #[allow(clippy::let_and_return, unused_variables)]
+            fn route_actions(
+                builder: ::wot_serve::thing::Builder<Self>,
+            ) -> ::wot_serve::thing::Builder<Self> {
+                #(#route)*
+                builder
+            }
+        }
+    })
+}
+
+/// The doc comments, as a description.
+pub(crate) fn docs(attrs: &[Attribute]) -> Option<String> {
+    let lines: Vec<_> = attrs
+        .iter()
+        .filter_map(|attr| match &attr.meta {
+            Meta::NameValue(nv) if nv.path.is_ident("doc") => match &nv.value {
+                Expr::Lit(ExprLit {
+                    lit: Lit::Str(s), ..
+                }) => Some(s.value().trim().to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+
+    let doc = lines.join("\n").trim().to_string();
+
+    (!doc.is_empty()).then_some(doc)
+}
+
+pub(crate) fn option(value: Option<impl ToTokens>) -> TokenStream2 {
+    match value {
+        Some(value) => quote!(::std::option::Option::Some(#value)),
+        None => quote!(::std::option::Option::None),
+    }
+}
+
+fn is_unit(ty: &Type) -> bool {
+    matches!(ty, Type::Tuple(t) if t.elems.is_empty())
+}
+
+/// The type argument of `ty`, if it is the generic type `name`.
+pub(crate) fn generic_argument<'a>(ty: &'a Type, name: &str) -> Option<&'a Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != name {
+        return None;
+    }
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+
+    args.args.iter().find_map(|arg| match arg {
+        GenericArgument::Type(ty) => Some(ty),
+        _ => None,
+    })
+}
+
+/// Data schema of a Rust type, as a json value.
+fn schema(ty: &Type) -> TokenStream2 {
+    let private = private();
+
+    quote!(#private::schema::<#ty>())
+}
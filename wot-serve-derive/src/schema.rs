@@ -0,0 +1,289 @@
+//! `ToDataSchema` derive
+
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    meta::ParseNestedMeta, parse_quote, spanned::Spanned, Attribute, Data, DeriveInput, Expr,
+    Fields, LitStr, Token,
+};
+
+use crate::{docs, generic_argument, option};
+
+/// Path to the runtime support of the expanded code
+fn private() -> TokenStream2 {
+    quote!(::wot_serve::schema::__private)
+}
+
+/// The serde attributes changing the representation of a value
+#[derive(Default)]
+struct Serde {
+    rename: Option<String>,
+    rename_all: Option<String>,
+    skip: bool,
+    default: bool,
+    untagged: bool,
+}
+
+impl Serde {
+    fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut serde = Serde::default();
+
+        for attr in attrs.iter().filter(|a| a.path().is_ident("serde")) {
+            attr.parse_nested_meta(|meta| {
+                let path = &meta.path;
+                if path.is_ident("rename") || path.is_ident("rename_all") {
+                    let value = serialized_name(&meta)?;
+                    if path.is_ident("rename") {
+                        serde.rename = value;
+                    } else {
+                        serde.rename_all = value;
+                    }
+                } else if path.is_ident("skip") || path.is_ident("skip_serializing") {
+                    serde.skip = true;
+                } else if path.is_ident("default") || path.is_ident("skip_serializing_if") {
+                    serde.default = true;
+                    ignore(&meta)?;
+                } else if path.is_ident("untagged") {
+                    serde.untagged = true;
+                } else if path.is_ident("tag") || path.is_ident("content") {
+                    return Err(meta.error("only the external and untagged enums are supported"));
+                } else {
+                    ignore(&meta)?;
+                }
+                Ok(())
+            })?;
+        }
+
+        Ok(serde)
+    }
+}
+
+/// The value of `rename = "..."` or of `rename(serialize = "...")`.
+fn serialized_name(meta: &ParseNestedMeta) -> syn::Result<Option<String>> {
+    if meta.input.peek(Token![=]) {
+        return Ok(Some(meta.value()?.parse::<LitStr>()?.value()));
+    }
+
+    let mut name = None;
+    meta.parse_nested_meta(|meta| {
+        let value = meta.value()?.parse::<LitStr>()?.value();
+        if meta.path.is_ident("serialize") {
+            name = Some(value);
+        }
+        Ok(())
+    })?;
+
+    Ok(name)
+}
+
+/// Consume the arguments of an attribute not affecting the schema.
+fn ignore(meta: &ParseNestedMeta) -> syn::Result<()> {
+    if meta.input.peek(Token![=]) {
+        meta.value()?.parse::<Expr>()?;
+    } else if meta.input.peek(syn::token::Paren) {
+        meta.parse_nested_meta(|meta| ignore(&meta))?;
+    }
+
+    Ok(())
+}
+
+fn words(name: &str) -> Vec<String> {
+    let mut words: Vec<String> = Vec::new();
+
+    for part in name.split('_').filter(|p| !p.is_empty()) {
+        let mut word = String::new();
+        for c in part.chars() {
+            if c.is_uppercase() && !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            word.extend(c.to_lowercase());
+        }
+        words.push(word);
+    }
+
+    words
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    chars
+        .next()
+        .map(|c| c.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+/// Apply a serde `rename_all` rule to a field or variant name.
+fn rename_all(rule: &str, name: &str) -> syn::Result<String> {
+    let words = words(name);
+    let upper = || words.iter().map(|w| w.to_uppercase()).collect::<Vec<_>>();
+
+    Ok(match rule {
+        "lowercase" => words.concat(),
+        "UPPERCASE" => upper().concat(),
+        "PascalCase" => words.iter().map(|w| capitalize(w)).collect(),
+        "camelCase" => {
+            let pascal: String = words.iter().map(|w| capitalize(w)).collect();
+            let mut chars = pascal.chars();
+            chars
+                .next()
+                .map(|c| c.to_lowercase().chain(chars).collect())
+                .unwrap_or_default()
+        }
+        "snake_case" => words.join("_"),
+        "SCREAMING_SNAKE_CASE" => upper().join("_"),
+        "kebab-case" => words.join("-"),
+        "SCREAMING-KEBAB-CASE" => upper().join("-"),
+        _ => {
+            return Err(syn::Error::new(
+                proc_macro2::Span::call_site(),
+                format!("unknown rename rule {rule}"),
+            ))
+        }
+    })
+}
+
+/// Serialized name of a field or variant.
+fn name(ident: &syn::Ident, serde: &Serde, container: &Serde) -> syn::Result<String> {
+    let ident = ident.to_string().trim_start_matches("r#").to_string();
+
+    match (&serde.rename, &container.rename_all) {
+        (Some(rename), _) => Ok(rename.clone()),
+        (None, Some(rule)) => rename_all(rule, &ident),
+        (None, None) => Ok(ident),
+    }
+}
+
+/// Schema of the fields of a struct or of a variant.
+fn fields(fields: &Fields, container: &Serde) -> syn::Result<TokenStream2> {
+    let private = private();
+
+    Ok(match fields {
+        Fields::Named(named) => {
+            let mut properties = Vec::new();
+            for field in &named.named {
+                let serde = Serde::parse(&field.attrs)?;
+                if serde.skip {
+                    continue;
+                }
+
+                let ty = &field.ty;
+                let name = name(
+                    field.ident.as_ref().expect("named field"),
+                    &serde,
+                    container,
+                )?;
+                let description = option(docs(&field.attrs));
+                let required = !serde.default
+                    && !container.default
+                    && generic_argument(ty, "Option").is_none();
+
+                properties.push(quote! {
+                    (
+                        #name,
+                        #private::described(
+                            <#ty as ::wot_serve::schema::ToDataSchema>::data_schema(),
+                            #description,
+                        ),
+                        #required,
+                    )
+                });
+            }
+
+            quote!(#private::object(::std::vec![#(#properties),*]))
+        }
+        Fields::Unnamed(unnamed) if unnamed.unnamed.len() == 1 => {
+            let ty = &unnamed.unnamed[0].ty;
+
+            quote!(<#ty as ::wot_serve::schema::ToDataSchema>::data_schema())
+        }
+        Fields::Unnamed(unnamed) => {
+            let items = unnamed.unnamed.iter().map(|field| {
+                let ty = &field.ty;
+                quote!(<#ty as ::wot_serve::schema::ToDataSchema>::data_schema())
+            });
+
+            quote!(#private::tuple(::std::vec![#(#items),*]))
+        }
+        Fields::Unit => quote!(<() as ::wot_serve::schema::ToDataSchema>::data_schema()),
+    })
+}
+
+pub(crate) fn to_data_schema(mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    let private = private();
+    let container = Serde::parse(&input.attrs)?;
+
+    let schema = match &input.data {
+        Data::Struct(data) => fields(&data.fields, &container)?,
+        Data::Enum(data) => {
+            let mut variants = Vec::new();
+            for variant in &data.variants {
+                let serde = Serde::parse(&variant.attrs)?;
+                if !serde.skip {
+                    variants.push((variant, serde));
+                }
+            }
+
+            let unit = variants
+                .iter()
+                .all(|(v, _)| matches!(v.fields, Fields::Unit));
+
+            if unit && !container.untagged {
+                let names = variants
+                    .iter()
+                    .map(|(v, serde)| name(&v.ident, serde, &container))
+                    .collect::<syn::Result<Vec<_>>>()?;
+
+                quote!(#private::string_enum(&[#(#names),*]))
+            } else {
+                let mut alternatives = Vec::new();
+                for (variant, serde) in variants {
+                    let name = name(&variant.ident, &serde, &container)?;
+                    let description = option(docs(&variant.attrs));
+                    let fields = fields(&variant.fields, &serde)?;
+
+                    let alternative = match (&variant.fields, container.untagged) {
+                        (_, true) => fields,
+                        (Fields::Unit, false) => quote!(#private::constant(#name)),
+                        (_, false) => {
+                            quote!(#private::object(::std::vec![(#name, #fields, true)]))
+                        }
+                    };
+                    alternatives.push(quote!(#private::described(#alternative, #description)));
+                }
+
+                quote!(#private::one_of(::std::vec![#(#alternatives),*]))
+            }
+        }
+        Data::Union(data) => {
+            return Err(syn::Error::new(
+                data.union_token.span(),
+                "unions have no data schema",
+            ))
+        }
+    };
+
+    let description = option(docs(&input.attrs));
+
+    let type_params: Vec<_> = input
+        .generics
+        .type_params()
+        .map(|param| param.ident.clone())
+        .collect();
+    let where_clause = input.generics.make_where_clause();
+    for param in type_params {
+        where_clause
+            .predicates
+            .push(parse_quote!(#param: ::wot_serve::schema::ToDataSchema));
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::wot_serve::schema::ToDataSchema for #ident #ty_generics #where_clause {
+            fn data_schema() -> ::wot_serve::schema::DataSchema {
+                #private::described(#schema, #description)
+            }
+        }
+    })
+}